@@ -0,0 +1,301 @@
+//! Alerts on feed outages and grade drops, delivered to webhooks, email and
+//! local commands.
+//!
+//! Rules and sinks are read from [`ALERTING_FILE`] in the sampler's output
+//! directory. The sampler reports every poll to the [`Alerter`], which fires
+//! `feed_down` rules, and after each day's aggregation evaluates
+//! `grade_below` and `field_drop` rules against the new indexes and
+//! [`ChangesReport`]. The [`AlertEngine`](rules::AlertEngine) deduplicates
+//! firing alerts, repeats them only after a cool-down, and sends a recovery
+//! notification once the condition clears.
+
+pub mod command;
+pub mod rules;
+pub mod smtp;
+pub mod webhook;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::{debug, error, info};
+
+use crate::analyzers::analyzer::index_key;
+use crate::analyzers::changes::{CHANGES_KEY, ChangesReport};
+use crate::analyzers::types::FeedIndex;
+use crate::feed_type::FeedType;
+use crate::object_store::ObjectStore;
+
+pub use command::CommandSink;
+pub use rules::{AlertEngine, Condition, Rule};
+pub use smtp::SmtpSink;
+pub use webhook::WebhookSink;
+
+/// File name of the alerting settings inside the output directory.
+pub const ALERTING_FILE: &str = "alerting.toml";
+
+/// Minutes before a still-firing alert is repeated, unless a rule sets its own.
+pub const DEFAULT_COOLDOWN_MINUTES: i64 = 60;
+
+/// Alert rules and where their notifications go, as read from [`ALERTING_FILE`].
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct AlertConfig {
+    pub cooldown_minutes: i64,
+    pub rules: Vec<Rule>,
+    pub sinks: Vec<SinkConfig>,
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        Self {
+            cooldown_minutes: DEFAULT_COOLDOWN_MINUTES,
+            rules: Vec::new(),
+            sinks: Vec::new(),
+        }
+    }
+}
+
+impl AlertConfig {
+    /// Parses and validates an alerting TOML file.
+    pub fn parse(toml: &str) -> Result<Self> {
+        let config: Self = toml::from_str(toml)?;
+        let mut names = HashSet::new();
+        for rule in &config.rules {
+            if !names.insert(rule.name.as_str()) {
+                anyhow::bail!("duplicate alert rule '{}'", rule.name);
+            }
+            rule.condition
+                .validate()
+                .with_context(|| format!("alert rule '{}'", rule.name))?;
+        }
+        Ok(config)
+    }
+}
+
+/// Where notifications are delivered, tagged by `kind`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SinkConfig {
+    Webhook(WebhookSink),
+    Smtp(SmtpSink),
+    Command(CommandSink),
+}
+
+impl SinkConfig {
+    fn into_sink(self) -> Box<dyn AlertSink> {
+        match self {
+            SinkConfig::Webhook(sink) => Box::new(sink),
+            SinkConfig::Smtp(sink) => Box::new(sink),
+            SinkConfig::Command(sink) => Box::new(sink),
+        }
+    }
+}
+
+/// Whether a notification announces an alert or its recovery.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertStatus {
+    Firing,
+    Resolved,
+}
+
+/// One alert or recovery, as delivered to every sink.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Notification {
+    pub rule: String,
+    pub status: AlertStatus,
+    pub feed_id: String,
+    /// Unset for `feed_down`, which covers every type the feed serves.
+    pub feed_type: Option<FeedType>,
+    pub message: String,
+    /// When the condition started.
+    pub since: DateTime<Utc>,
+    pub at: DateTime<Utc>,
+    /// Whether the alert was already sent and is repeated after its cool-down.
+    pub repeat: bool,
+}
+
+impl Notification {
+    /// One-line summary, used as the email subject.
+    pub fn subject(&self) -> String {
+        let status = match self.status {
+            AlertStatus::Firing => "FIRING",
+            AlertStatus::Resolved => "RESOLVED",
+        };
+        format!("[{}] {}: {}", status, self.rule, self.feed_id)
+    }
+
+    /// Plain-text body: the subject line followed by the message.
+    pub fn text(&self) -> String {
+        format!("{}\n{}", self.subject(), self.message)
+    }
+}
+
+/// A destination for notifications.
+#[async_trait]
+pub trait AlertSink: Send + Sync {
+    /// Short description for logs, e.g. the webhook URL.
+    fn describe(&self) -> String;
+
+    /// Delivers one notification.
+    async fn send(&self, notification: &Notification) -> Result<()>;
+}
+
+/// Evaluates alert rules and delivers their notifications to every sink.
+pub struct Alerter {
+    engine: Mutex<AlertEngine>,
+    sinks: Vec<Box<dyn AlertSink>>,
+}
+
+impl Alerter {
+    pub fn new(config: AlertConfig) -> Self {
+        Self {
+            engine: Mutex::new(AlertEngine::new(config.rules, config.cooldown_minutes)),
+            sinks: config
+                .sinks
+                .into_iter()
+                .map(SinkConfig::into_sink)
+                .collect(),
+        }
+    }
+
+    /// Loads [`ALERTING_FILE`] from `output_dir`, or returns `None` if there is none.
+    pub fn load(output_dir: &str) -> Result<Option<Self>> {
+        let path = Path::new(output_dir).join(ALERTING_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let toml = std::fs::read_to_string(&path)?;
+        let config =
+            AlertConfig::parse(&toml).with_context(|| format!("reading {}", path.display()))?;
+        info!(
+            rules = config.rules.len(),
+            sinks = config.sinks.len(),
+            "Alerting enabled"
+        );
+        Ok(Some(Self::new(config)))
+    }
+
+    /// Records whether a poll of `feed_id` succeeded.
+    pub async fn record_poll(&self, feed_id: &str, up: bool) {
+        let notifications = self
+            .engine
+            .lock()
+            .unwrap()
+            .record_poll(feed_id, up, Utc::now());
+        self.deliver(notifications).await;
+    }
+
+    /// Fires `feed_down` alerts for feeds that have been down long enough
+    /// since their last poll.
+    pub async fn check_down(&self) {
+        let notifications = self.engine.lock().unwrap().check_down(Utc::now());
+        self.deliver(notifications).await;
+    }
+
+    /// Evaluates the aggregate rules against the latest indexes and changes
+    /// report in `store`.
+    pub async fn evaluate_aggregates(&self, store: &dyn ObjectStore) -> Result<()> {
+        let mut indexes = Vec::new();
+        for feed_type in FeedType::ALL {
+            if let Some(bytes) = store.get(&index_key(feed_type)).await? {
+                indexes.push(serde_json::from_slice::<FeedIndex>(&bytes)?);
+            }
+        }
+        let changes = match store.get(CHANGES_KEY).await? {
+            Some(bytes) => serde_json::from_slice(&bytes)?,
+            None => ChangesReport::new(0, Vec::new()),
+        };
+        let notifications =
+            self.engine
+                .lock()
+                .unwrap()
+                .evaluate_aggregates(&indexes, &changes, Utc::now());
+        self.deliver(notifications).await;
+        Ok(())
+    }
+
+    /// Sends each notification to every sink. A failing sink is logged and
+    /// doesn't stop delivery to the others.
+    async fn deliver(&self, notifications: Vec<Notification>) {
+        for notification in &notifications {
+            info!(
+                rule = %notification.rule,
+                feed_id = %notification.feed_id,
+                status = ?notification.status,
+                "{}", notification.message
+            );
+            for sink in &self.sinks {
+                match sink.send(notification).await {
+                    Ok(()) => debug!(sink = %sink.describe(), "Alert delivered"),
+                    Err(e) => {
+                        error!(sink = %sink.describe(), error = %e, "Failed to deliver alert")
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = AlertConfig::parse(
+            r#"
+cooldown_minutes = 30
+
+[[rules]]
+name = "down"
+kind = "feed_down"
+minutes = 10
+
+[[rules]]
+name = "poor"
+kind = "grade_below"
+grade = "C"
+feeds = ["a"]
+notify_recovery = false
+
+[[sinks]]
+kind = "webhook"
+url = "http://localhost:8080/hook"
+
+[[sinks]]
+kind = "smtp"
+host = "localhost"
+port = 1025
+from = "rater@example.com"
+to = ["ops@example.com"]
+
+[[sinks]]
+kind = "command"
+program = "notify-send"
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.cooldown_minutes, 30);
+        assert_eq!(config.rules.len(), 2);
+        assert_eq!(
+            config.rules[0].condition,
+            Condition::FeedDown { minutes: 10 }
+        );
+        assert_eq!(config.rules[1].feeds, vec!["a"]);
+        assert!(!config.rules[1].notify_recovery);
+        assert!(matches!(config.sinks[1], SinkConfig::Smtp(_)));
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_rules() {
+        let duplicate = "[[rules]]\nname = \"x\"\nkind = \"feed_down\"\nminutes = 1\n";
+        assert!(AlertConfig::parse(&duplicate.repeat(2)).is_err());
+        let grade = "[[rules]]\nname = \"x\"\nkind = \"grade_below\"\ngrade = \"E\"\n";
+        assert!(AlertConfig::parse(grade).is_err());
+    }
+}
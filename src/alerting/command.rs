@@ -0,0 +1,131 @@
+//! Sink running a local command for each notification.
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::alerting::{AlertSink, Notification};
+
+/// Runs `program` with `args` for each notification.
+///
+/// The notification is written to the command's stdin as JSON, and its main
+/// fields are also set as `ALERT_RULE`, `ALERT_STATUS`, `ALERT_FEED_ID` and
+/// `ALERT_MESSAGE` environment variables. A non-zero exit fails the delivery.
+#[derive(Debug, Deserialize)]
+pub struct CommandSink {
+    pub program: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Seconds before the command is killed.
+    #[serde(default = "default_timeout")]
+    pub timeout_secs: u64,
+}
+
+fn default_timeout() -> u64 {
+    30
+}
+
+#[async_trait]
+impl AlertSink for CommandSink {
+    fn describe(&self) -> String {
+        format!("command {}", self.program)
+    }
+
+    async fn send(&self, notification: &Notification) -> Result<()> {
+        let status = serde_json::to_value(notification.status)?;
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .env("ALERT_RULE", &notification.rule)
+            .env("ALERT_STATUS", status.as_str().unwrap_or_default())
+            .env("ALERT_FEED_ID", &notification.feed_id)
+            .env("ALERT_MESSAGE", &notification.message)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("starting {}", self.program))?;
+
+        let mut stdin = child.stdin.take().context("command stdin unavailable")?;
+        let json = serde_json::to_vec(notification)?;
+        let timeout = Duration::from_secs(self.timeout_secs);
+        let output = tokio::time::timeout(timeout, async move {
+            // A command that ignores its input may exit before reading it
+            let _ = stdin.write_all(&json).await;
+            drop(stdin);
+            child.wait_with_output().await
+        })
+        .await
+        .with_context(|| format!("{} timed out", self.program))??;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "{} exited with {}: {}",
+                self.program,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::alerting::AlertStatus;
+    use chrono::Utc;
+
+    fn notification() -> Notification {
+        Notification {
+            rule: "down".to_string(),
+            status: AlertStatus::Firing,
+            feed_id: "a".to_string(),
+            feed_type: None,
+            message: "Feed a has been down for 10 minutes".to_string(),
+            since: Utc::now(),
+            at: Utc::now(),
+            repeat: false,
+        }
+    }
+
+    fn shell(script: &str, path: &str) -> CommandSink {
+        CommandSink {
+            program: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string(), path.to_string()],
+            timeout_secs: 5,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_runs_command_with_alert() {
+        let path = format!(
+            "{}/gtfs_rt_rater_test_alert_command.txt",
+            std::env::temp_dir().display()
+        );
+        let sink = shell(
+            r#"echo "$ALERT_STATUS $ALERT_FEED_ID" > "$0"; cat >> "$0""#,
+            &path,
+        );
+
+        sink.send(&notification()).await.unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        let (env, json) = written.split_once('\n').unwrap();
+        assert_eq!(env, "firing a");
+        let json: serde_json::Value = serde_json::from_str(json).unwrap();
+        assert_eq!(json["rule"], "down");
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn test_failing_command_fails() {
+        let sink = shell("echo nope >&2; exit 3", "");
+        let error = sink.send(&notification()).await.unwrap_err();
+        assert!(error.to_string().contains("nope"));
+    }
+}
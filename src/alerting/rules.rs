@@ -0,0 +1,458 @@
+//! Alert rules and the state machine that turns them into notifications.
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::alerting::{AlertStatus, Notification};
+use crate::analyzers::changes::ChangesReport;
+use crate::analyzers::grade::grade_rank;
+use crate::analyzers::types::{FeedIndex, GradeStatus};
+use crate::feed_type::FeedType;
+
+/// A named condition, optionally limited to some feeds.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Rule {
+    pub name: String,
+    #[serde(flatten)]
+    pub condition: Condition,
+    /// Feed ids the rule applies to; every feed when empty.
+    #[serde(default)]
+    pub feeds: Vec<String>,
+    /// Overrides the config's `cooldown_minutes` for this rule.
+    pub cooldown_minutes: Option<i64>,
+    /// Whether to notify when the condition clears.
+    #[serde(default = "notify_recovery")]
+    pub notify_recovery: bool,
+}
+
+fn notify_recovery() -> bool {
+    true
+}
+
+impl Rule {
+    fn applies_to(&self, feed_id: &str) -> bool {
+        self.feeds.is_empty() || self.feeds.iter().any(|f| f == feed_id)
+    }
+}
+
+/// What a [`Rule`] alerts on, tagged by `kind`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Condition {
+    /// Every poll of the feed has failed for at least `minutes`.
+    FeedDown { minutes: i64 },
+    /// The feed's latest grade is worse than `grade`.
+    GradeBelow { grade: String },
+    /// The feed's support for `field` fell by at least `points` percentage
+    /// points since the previous aggregate, as listed in the changes report.
+    FieldDrop { field: String, points: f64 },
+}
+
+impl Condition {
+    pub(crate) fn validate(&self) -> Result<()> {
+        match self {
+            Condition::GradeBelow { grade } if grade_rank(grade).is_none() => {
+                anyhow::bail!("unknown grade '{}'", grade)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Identifies one alert: a rule firing for a feed (and feed type, for the
+/// aggregate rules).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct AlertKey {
+    rule: String,
+    feed_id: String,
+    feed_type: Option<FeedType>,
+}
+
+/// A condition that currently holds.
+struct Firing {
+    since: DateTime<Utc>,
+    message: String,
+}
+
+#[derive(Default)]
+struct AlertState {
+    active: bool,
+    since: Option<DateTime<Utc>>,
+    message: String,
+    /// Whether the current episode has been notified.
+    notified: bool,
+    last_notified: Option<DateTime<Utc>>,
+}
+
+/// Evaluates [`Rule`]s and decides which notifications to send.
+///
+/// An alert is sent when its condition starts holding, then repeated while it
+/// still holds only once per cool-down. A condition that clears and holds
+/// again within the cool-down of the last notification stays silent, so a
+/// flapping feed doesn't flood the sinks. When a notified condition clears, a
+/// recovery notification is sent.
+pub struct AlertEngine {
+    rules: Vec<Rule>,
+    cooldown: Duration,
+    /// When each currently failing feed started failing.
+    down_since: HashMap<String, DateTime<Utc>>,
+    alerts: HashMap<AlertKey, AlertState>,
+}
+
+impl AlertEngine {
+    pub fn new(rules: Vec<Rule>, cooldown_minutes: i64) -> Self {
+        Self {
+            rules,
+            cooldown: Duration::minutes(cooldown_minutes),
+            down_since: HashMap::new(),
+            alerts: HashMap::new(),
+        }
+    }
+
+    /// Records whether a poll of `feed_id` at `now` succeeded, and evaluates
+    /// the feed's `feed_down` rules.
+    pub fn record_poll(
+        &mut self,
+        feed_id: &str,
+        up: bool,
+        now: DateTime<Utc>,
+    ) -> Vec<Notification> {
+        if up {
+            self.down_since.remove(feed_id);
+        } else {
+            self.down_since.entry(feed_id.to_string()).or_insert(now);
+        }
+        self.evaluate_down(feed_id, now)
+    }
+
+    /// Evaluates `feed_down` rules for every failing feed.
+    pub fn check_down(&mut self, now: DateTime<Utc>) -> Vec<Notification> {
+        let mut feed_ids: Vec<String> = self.down_since.keys().cloned().collect();
+        feed_ids.sort();
+        feed_ids
+            .iter()
+            .flat_map(|feed_id| self.evaluate_down(feed_id, now))
+            .collect()
+    }
+
+    fn evaluate_down(&mut self, feed_id: &str, now: DateTime<Utc>) -> Vec<Notification> {
+        let since = self.down_since.get(feed_id).copied();
+        let mut notifications = Vec::new();
+        for rule in &self.rules {
+            let Condition::FeedDown { minutes } = rule.condition else {
+                continue;
+            };
+            if !rule.applies_to(feed_id) {
+                continue;
+            }
+            let firing = since
+                .filter(|since| now - *since >= Duration::minutes(minutes))
+                .map(|since| Firing {
+                    since,
+                    message: format!(
+                        "Feed {} has been down for {} minutes",
+                        feed_id,
+                        (now - since).num_minutes()
+                    ),
+                });
+            let key = AlertKey {
+                rule: rule.name.clone(),
+                feed_id: feed_id.to_string(),
+                feed_type: None,
+            };
+            notifications.extend(observe(
+                &mut self.alerts,
+                self.cooldown,
+                rule,
+                key,
+                firing,
+                now,
+            ));
+        }
+        notifications
+    }
+
+    /// Evaluates `grade_below` and `field_drop` rules for every feed in
+    /// `indexes`, the latest aggregation's feed indexes, with `changes` its
+    /// changes report.
+    pub fn evaluate_aggregates(
+        &mut self,
+        indexes: &[FeedIndex],
+        changes: &ChangesReport,
+        now: DateTime<Utc>,
+    ) -> Vec<Notification> {
+        let mut notifications = Vec::new();
+        for rule in &self.rules {
+            for index in indexes {
+                for entry in index.feeds.iter().filter(|e| rule.applies_to(&e.feed_id)) {
+                    let firing = match &rule.condition {
+                        Condition::FeedDown { .. } => continue,
+                        Condition::GradeBelow { grade } => {
+                            let below = entry.status == GradeStatus::Graded
+                                && grade_rank(&entry.overall_grade) > grade_rank(grade);
+                            below.then(|| {
+                                format!(
+                                    "Feed {} ({}) is graded {}, below {}",
+                                    entry.feed_id, index.feed_type, entry.overall_grade, grade
+                                )
+                            })
+                        }
+                        Condition::FieldDrop { field, points } => changes
+                            .regressions
+                            .iter()
+                            .find(|c| {
+                                c.feed_id == entry.feed_id
+                                    && c.feed_type == index.feed_type
+                                    && c.component == *field
+                                    && -c.delta * 100.0 >= *points
+                            })
+                            .map(|c| {
+                                format!(
+                                    "Feed {} ({}) {} support dropped {:.0} points, from {:.0}% to {:.0}%",
+                                    entry.feed_id,
+                                    index.feed_type,
+                                    field,
+                                    -c.delta * 100.0,
+                                    c.previous * 100.0,
+                                    c.current * 100.0
+                                )
+                            }),
+                    };
+                    let key = AlertKey {
+                        rule: rule.name.clone(),
+                        feed_id: entry.feed_id.clone(),
+                        feed_type: Some(index.feed_type),
+                    };
+                    let firing = firing.map(|message| Firing {
+                        since: now,
+                        message,
+                    });
+                    notifications.extend(observe(
+                        &mut self.alerts,
+                        self.cooldown,
+                        rule,
+                        key,
+                        firing,
+                        now,
+                    ));
+                }
+            }
+        }
+        notifications
+    }
+}
+
+/// Updates the alert at `key` with whether its condition holds, returning
+/// the notification to send, if any.
+fn observe(
+    alerts: &mut HashMap<AlertKey, AlertState>,
+    default_cooldown: Duration,
+    rule: &Rule,
+    key: AlertKey,
+    firing: Option<Firing>,
+    now: DateTime<Utc>,
+) -> Option<Notification> {
+    let cooldown = rule
+        .cooldown_minutes
+        .map_or(default_cooldown, Duration::minutes);
+    let state = alerts.entry(key.clone()).or_default();
+    let notification = |status, message: String, since, repeat| Notification {
+        rule: key.rule.clone(),
+        status,
+        feed_id: key.feed_id.clone(),
+        feed_type: key.feed_type,
+        message,
+        since,
+        at: now,
+        repeat,
+    };
+
+    match firing {
+        Some(firing) => {
+            if !state.active {
+                state.active = true;
+                state.notified = false;
+                state.since = Some(firing.since);
+            }
+            state.message = firing.message;
+            if state
+                .last_notified
+                .is_some_and(|last| now - last < cooldown)
+            {
+                return None;
+            }
+            let repeat = state.notified;
+            state.notified = true;
+            state.last_notified = Some(now);
+            let since = state.since.unwrap_or(now);
+            Some(notification(
+                AlertStatus::Firing,
+                state.message.clone(),
+                since,
+                repeat,
+            ))
+        }
+        None => {
+            if !state.active {
+                return None;
+            }
+            state.active = false;
+            if !(state.notified && rule.notify_recovery) {
+                return None;
+            }
+            let since = state.since.unwrap_or(now);
+            Some(notification(
+                AlertStatus::Resolved,
+                format!("Resolved: {}", state.message),
+                since,
+                false,
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzers::changes::FeedChange;
+    use crate::analyzers::types::FeedIndexEntry;
+
+    fn rule(name: &str, condition: Condition) -> Rule {
+        Rule {
+            name: name.to_string(),
+            condition,
+            feeds: Vec::new(),
+            cooldown_minutes: None,
+            notify_recovery: true,
+        }
+    }
+
+    fn at(minutes: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000, 0).unwrap() + Duration::minutes(minutes)
+    }
+
+    fn statuses(notifications: &[Notification]) -> Vec<(AlertStatus, bool)> {
+        notifications.iter().map(|n| (n.status, n.repeat)).collect()
+    }
+
+    #[test]
+    fn test_feed_down_fires_once_then_recovers() {
+        let mut engine =
+            AlertEngine::new(vec![rule("down", Condition::FeedDown { minutes: 10 })], 60);
+        assert!(engine.record_poll("a", false, at(0)).is_empty());
+        assert!(engine.record_poll("a", false, at(5)).is_empty());
+
+        let fired = engine.check_down(at(10));
+        assert_eq!(statuses(&fired), vec![(AlertStatus::Firing, false)]);
+        assert_eq!(fired[0].since, at(0));
+        assert_eq!(fired[0].message, "Feed a has been down for 10 minutes");
+
+        // Deduplicated until the cool-down has passed
+        assert!(engine.record_poll("a", false, at(11)).is_empty());
+        assert!(engine.check_down(at(69)).is_empty());
+        assert_eq!(
+            statuses(&engine.check_down(at(70))),
+            vec![(AlertStatus::Firing, true)]
+        );
+
+        let resolved = engine.record_poll("a", true, at(75));
+        assert_eq!(statuses(&resolved), vec![(AlertStatus::Resolved, false)]);
+        assert!(engine.record_poll("a", true, at(76)).is_empty());
+    }
+
+    #[test]
+    fn test_flapping_feed_waits_for_cooldown() {
+        let mut engine =
+            AlertEngine::new(vec![rule("down", Condition::FeedDown { minutes: 0 })], 30);
+        assert_eq!(engine.record_poll("a", false, at(0)).len(), 1);
+        assert_eq!(engine.record_poll("a", true, at(1)).len(), 1);
+        // Down again within the cool-down: silent, and so is its recovery
+        assert!(engine.record_poll("a", false, at(2)).is_empty());
+        assert!(engine.record_poll("a", true, at(3)).is_empty());
+        assert_eq!(engine.record_poll("a", false, at(40)).len(), 1);
+    }
+
+    #[test]
+    fn test_rule_limited_to_feeds() {
+        let mut down = rule("down", Condition::FeedDown { minutes: 0 });
+        down.feeds = vec!["b".to_string()];
+        let mut engine = AlertEngine::new(vec![down], 60);
+        assert!(engine.record_poll("a", false, at(0)).is_empty());
+        assert_eq!(engine.record_poll("b", false, at(0)).len(), 1);
+    }
+
+    fn index(grades: &[(&str, &str)]) -> FeedIndex {
+        FeedIndex {
+            generated_at: at(0),
+            feed_type: FeedType::VehiclePositions,
+            feeds: grades
+                .iter()
+                .map(|(feed_id, grade)| FeedIndexEntry {
+                    feed_id: feed_id.to_string(),
+                    overall_grade: grade.to_string(),
+                    overall_score: 0.0,
+                    status: GradeStatus::Graded,
+                    uptime_percent: 1.0,
+                })
+                .collect(),
+            agencies: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_grade_below() {
+        let mut engine = AlertEngine::new(
+            vec![rule(
+                "poor",
+                Condition::GradeBelow {
+                    grade: "C".to_string(),
+                },
+            )],
+            60,
+        );
+        let no_changes = ChangesReport::new(0, Vec::new());
+        let fired =
+            engine.evaluate_aggregates(&[index(&[("a", "C"), ("b", "D")])], &no_changes, at(0));
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].feed_id, "b");
+        assert_eq!(fired[0].feed_type, Some(FeedType::VehiclePositions));
+
+        let resolved = engine.evaluate_aggregates(&[index(&[("b", "B")])], &no_changes, at(1440));
+        assert_eq!(statuses(&resolved), vec![(AlertStatus::Resolved, false)]);
+    }
+
+    #[test]
+    fn test_field_drop() {
+        let mut engine = AlertEngine::new(
+            vec![rule(
+                "route",
+                Condition::FieldDrop {
+                    field: "route_id".to_string(),
+                    points: 30.0,
+                },
+            )],
+            60,
+        );
+        let drop = |feed_id: &str, previous: f64, current: f64| FeedChange {
+            feed_id: feed_id.to_string(),
+            feed_type: FeedType::VehiclePositions,
+            component: "route_id".to_string(),
+            previous,
+            current,
+            delta: current - previous,
+            previous_grade: String::new(),
+            current_grade: String::new(),
+            previous_updated: at(0),
+        };
+        let changes = ChangesReport::new(2, vec![drop("a", 0.9, 0.5), drop("b", 0.9, 0.7)]);
+        let fired =
+            engine.evaluate_aggregates(&[index(&[("a", "A"), ("b", "A")])], &changes, at(0));
+        assert_eq!(fired.len(), 1);
+        assert_eq!(
+            fired[0].message,
+            "Feed a (vp) route_id support dropped 40 points, from 90% to 50%"
+        );
+    }
+}
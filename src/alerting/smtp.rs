@@ -0,0 +1,227 @@
+//! Email sink speaking plain SMTP.
+//!
+//! Meant for a local relay or a test server such as MailHog: the connection
+//! is unencrypted, with optional `AUTH PLAIN`. Point it at a relay (e.g. a
+//! local Postfix) to deliver through a provider that requires TLS.
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use crate::alerting::{AlertSink, Notification};
+
+/// How long a whole delivery may take.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Emails each notification to `to`.
+#[derive(Debug, Deserialize)]
+pub struct SmtpSink {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    pub from: String,
+    pub to: Vec<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+fn default_port() -> u16 {
+    25
+}
+
+#[async_trait]
+impl AlertSink for SmtpSink {
+    fn describe(&self) -> String {
+        format!("smtp {}:{}", self.host, self.port)
+    }
+
+    async fn send(&self, notification: &Notification) -> Result<()> {
+        let stream = tokio::time::timeout(TIMEOUT, async {
+            TcpStream::connect((self.host.as_str(), self.port)).await
+        })
+        .await??;
+        tokio::time::timeout(TIMEOUT, self.deliver(stream, notification))
+            .await
+            .context("SMTP delivery timed out")?
+    }
+}
+
+impl SmtpSink {
+    /// Runs one SMTP session over `stream` sending `notification`.
+    async fn deliver(
+        &self,
+        stream: impl AsyncRead + AsyncWrite + Unpin,
+        notification: &Notification,
+    ) -> Result<()> {
+        let mut stream = BufReader::new(stream);
+        expect(&mut stream, 220).await?;
+        command(&mut stream, "EHLO gtfs-rt-rater", 250).await?;
+        if let (Some(username), Some(password)) = (&self.username, &self.password) {
+            let credentials = STANDARD.encode(format!("\0{}\0{}", username, password));
+            command(&mut stream, &format!("AUTH PLAIN {}", credentials), 235).await?;
+        }
+        command(&mut stream, &format!("MAIL FROM:<{}>", self.from), 250).await?;
+        for to in &self.to {
+            command(&mut stream, &format!("RCPT TO:<{}>", to), 250).await?;
+        }
+        command(&mut stream, "DATA", 354).await?;
+        stream
+            .write_all(self.message(notification).as_bytes())
+            .await?;
+        command(&mut stream, ".", 250).await?;
+        command(&mut stream, "QUIT", 221).await?;
+        Ok(())
+    }
+
+    /// Headers and dot-stuffed body of the email, with CRLF line endings.
+    fn message(&self, notification: &Notification) -> String {
+        let mut message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\n\
+             Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n",
+            self.from,
+            self.to.join(", "),
+            notification.subject(),
+            notification.at.to_rfc2822(),
+        );
+        for line in notification.text().lines() {
+            if line.starts_with('.') {
+                message.push('.');
+            }
+            message.push_str(line);
+            message.push_str("\r\n");
+        }
+        message
+    }
+}
+
+/// Sends one command line and expects a reply in the same class as `code`.
+async fn command<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut BufReader<S>,
+    line: &str,
+    code: u16,
+) -> Result<()> {
+    stream.write_all(format!("{}\r\n", line).as_bytes()).await?;
+    stream.flush().await?;
+    expect(stream, code).await
+}
+
+/// Reads a possibly multi-line reply and checks it is in the same class
+/// (2xx, 3xx) as `code`.
+async fn expect<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut BufReader<S>,
+    code: u16,
+) -> Result<()> {
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await? == 0 {
+            anyhow::bail!("SMTP server closed the connection");
+        }
+        let reply: u16 = line
+            .get(..3)
+            .and_then(|c| c.parse().ok())
+            .with_context(|| format!("malformed SMTP reply '{}'", line.trim_end()))?;
+        // "250-" continues a multi-line reply, "250 " ends it
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+        if reply / 100 != code / 100 {
+            anyhow::bail!("unexpected SMTP reply '{}'", line.trim_end());
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerting::AlertStatus;
+    use chrono::Utc;
+    use tokio::net::TcpListener;
+
+    /// A minimal MailHog-style server: accepts one message and returns the
+    /// commands it received and the message data.
+    async fn receive_one(listener: TcpListener) -> (Vec<String>, String) {
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut socket = BufReader::new(socket);
+        socket.write_all(b"220 test ESMTP\r\n").await.unwrap();
+        let (mut commands, mut data) = (Vec::new(), String::new());
+        loop {
+            let mut line = String::new();
+            if socket.read_line(&mut line).await.unwrap() == 0 {
+                break;
+            }
+            let line = line.trim_end().to_string();
+            let reply: &[u8] = match line.as_str() {
+                l if l.starts_with("EHLO") => b"250-test\r\n250 AUTH PLAIN\r\n",
+                l if l.starts_with("AUTH") => b"235 ok\r\n",
+                "DATA" => {
+                    socket.write_all(b"354 go ahead\r\n").await.unwrap();
+                    loop {
+                        let mut line = String::new();
+                        socket.read_line(&mut line).await.unwrap();
+                        if line == ".\r\n" {
+                            break;
+                        }
+                        data.push_str(&line);
+                    }
+                    b"250 queued\r\n"
+                }
+                "QUIT" => {
+                    socket.write_all(b"221 bye\r\n").await.unwrap();
+                    commands.push(line);
+                    break;
+                }
+                _ => b"250 ok\r\n",
+            };
+            socket.write_all(reply).await.unwrap();
+            commands.push(line);
+        }
+        (commands, data)
+    }
+
+    #[tokio::test]
+    async fn test_sends_email() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let sink = SmtpSink {
+            host: "127.0.0.1".to_string(),
+            port: listener.local_addr().unwrap().port(),
+            from: "rater@example.com".to_string(),
+            to: vec!["ops@example.com".to_string()],
+            username: Some("user".to_string()),
+            password: Some("secret".to_string()),
+        };
+        let server = tokio::spawn(receive_one(listener));
+        let notification = Notification {
+            rule: "poor".to_string(),
+            status: AlertStatus::Resolved,
+            feed_id: "a".to_string(),
+            feed_type: None,
+            message: ".hidden line".to_string(),
+            since: Utc::now(),
+            at: Utc::now(),
+            repeat: false,
+        };
+
+        sink.send(&notification).await.unwrap();
+
+        let (commands, data) = server.await.unwrap();
+        assert_eq!(
+            commands,
+            vec![
+                "EHLO gtfs-rt-rater",
+                "AUTH PLAIN AHVzZXIAc2VjcmV0",
+                "MAIL FROM:<rater@example.com>",
+                "RCPT TO:<ops@example.com>",
+                "DATA",
+                "QUIT",
+            ]
+        );
+        assert!(data.contains("Subject: [RESOLVED] poor: a\r\n"));
+        assert!(data.ends_with("\r\n..hidden line\r\n"));
+    }
+}
@@ -0,0 +1,127 @@
+//! Webhook sink posting Slack-compatible JSON.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::alerting::{AlertSink, Notification};
+
+/// How long a webhook may take to answer.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Posts each notification to `url` as `{"text": ..., "alert": {...}}`.
+///
+/// `text` is what Slack (and compatible receivers such as Mattermost)
+/// display; `alert` carries the [`Notification`] fields for other receivers.
+#[derive(Debug, Deserialize)]
+pub struct WebhookSink {
+    pub url: String,
+    #[serde(skip)]
+    client: reqwest::Client,
+}
+
+impl WebhookSink {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl AlertSink for WebhookSink {
+    fn describe(&self) -> String {
+        format!("webhook {}", self.url)
+    }
+
+    async fn send(&self, notification: &Notification) -> Result<()> {
+        let body = serde_json::json!({
+            "text": notification.text(),
+            "alert": notification,
+        });
+        self.client
+            .post(&self.url)
+            .timeout(TIMEOUT)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerting::AlertStatus;
+    use chrono::Utc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn notification() -> Notification {
+        Notification {
+            rule: "down".to_string(),
+            status: AlertStatus::Firing,
+            feed_id: "a".to_string(),
+            feed_type: None,
+            message: "Feed a has been down for 10 minutes".to_string(),
+            since: Utc::now(),
+            at: Utc::now(),
+            repeat: false,
+        }
+    }
+
+    /// Accepts one HTTP request, answers with `status` and returns the body.
+    async fn receive_one(listener: TcpListener, status: &'static str) -> String {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            let n = socket.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request);
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|l| {
+                        l.to_ascii_lowercase()
+                            .strip_prefix("content-length: ")
+                            .map(|v| v.parse::<usize>().unwrap())
+                    })
+                    .unwrap_or(0);
+                if body.len() >= length {
+                    let response = format!("HTTP/1.1 {}\r\ncontent-length: 0\r\n\r\n", status);
+                    socket.write_all(response.as_bytes()).await.unwrap();
+                    return body.to_string();
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_posts_slack_compatible_json() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let sink = WebhookSink::new(&format!("http://{}/hook", listener.local_addr().unwrap()));
+        let receiver = tokio::spawn(receive_one(listener, "200 OK"));
+
+        sink.send(&notification()).await.unwrap();
+
+        let body: serde_json::Value = serde_json::from_str(&receiver.await.unwrap()).unwrap();
+        assert_eq!(
+            body["text"],
+            "[FIRING] down: a\nFeed a has been down for 10 minutes"
+        );
+        assert_eq!(body["alert"]["status"], "firing");
+    }
+
+    #[tokio::test]
+    async fn test_error_status_fails() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let sink = WebhookSink::new(&format!("http://{}/hook", listener.local_addr().unwrap()));
+        tokio::spawn(receive_one(listener, "500 Internal Server Error"));
+
+        assert!(sink.send(&notification()).await.is_err());
+    }
+}
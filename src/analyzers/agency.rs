@@ -0,0 +1,407 @@
+//! Agency-level rollup across an agency's VehiclePositions, TripUpdates and
+//! ServiceAlerts feeds.
+//!
+//! The sampler records which catalog feeds belong to the same agency in an
+//! [`AgencyDirectory`] (`agencies.json` in the output directory), and appends
+//! per-round cross-feed id matches to `agencies/agency={id}/date=*.csv`.
+//! [`aggregate_agency`] combines the member feeds' scores with those
+//! consistency rows into a single [`AgencyAggregate`].
+
+use crate::analyzers::aggregate::ALGORITHM_VERSION;
+use crate::analyzers::grade::GradeThresholds;
+use crate::analyzers::profile::GradingProfile;
+use crate::analyzers::types::{
+    AgencyAggregate, AgencyFeedScore, ConsistencyAggregate, ConsistencyRow, GradeStatus,
+    OverallAggregate,
+};
+use crate::analyzers::utility::mean;
+use crate::feed_type::FeedType;
+use crate::object_store::ObjectStore;
+use anyhow::Result;
+use chrono::Utc;
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// File name of the agency directory written alongside the feed CSVs.
+pub const AGENCY_DIRECTORY_FILE: &str = "agencies.json";
+
+/// Sub-directory holding per-agency consistency CSVs.
+pub const AGENCY_SUBDIR: &str = "agencies";
+
+/// Weights used to combine an agency's feed types and cross-feed consistency.
+static AGENCY_WEIGHTS: &[(&str, f64)] =
+    &[("vp", 3.0), ("tu", 3.0), ("sa", 1.0), ("consistency", 2.0)];
+
+/// A catalog feed belonging to an agency.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgencyFeed {
+    pub feed_id: String,
+    pub feed_type: FeedType,
+}
+
+/// Display name and member feeds of one agency.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AgencyMembers {
+    pub name: String,
+    /// IANA timezone the agency operates in, when the catalog location gives one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    pub feeds: Vec<AgencyFeed>,
+}
+
+/// Mapping from agency id to its member feeds, derived from catalog relationships.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AgencyDirectory {
+    pub agencies: BTreeMap<String, AgencyMembers>,
+}
+
+impl AgencyDirectory {
+    /// Records that `feed_id` serves `feed_type` for the given agency.
+    pub fn add(
+        &mut self,
+        agency_id: &str,
+        name: &str,
+        timezone: Option<&str>,
+        feed_id: &str,
+        feed_type: FeedType,
+    ) {
+        let members = self.agencies.entry(agency_id.to_string()).or_default();
+        if members.name.is_empty() {
+            members.name = name.to_string();
+        }
+        if members.timezone.is_none() {
+            members.timezone = timezone.map(str::to_string);
+        }
+        members.feeds.push(AgencyFeed {
+            feed_id: feed_id.to_string(),
+            feed_type,
+        });
+    }
+
+    /// Returns the agency id a feed belongs to, if any.
+    pub fn agency_of(&self, feed_id: &str) -> Option<&str> {
+        self.agencies
+            .iter()
+            .find(|(_, m)| m.feeds.iter().any(|f| f.feed_id == feed_id))
+            .map(|(id, _)| id.as_str())
+    }
+
+    /// Timezone of the agency a feed belongs to, or UTC when unknown.
+    pub fn timezone_of(&self, feed_id: &str) -> Tz {
+        self.agency_of(feed_id)
+            .and_then(|id| self.agencies[id].timezone.as_deref())
+            .and_then(|tz| tz.parse().ok())
+            .unwrap_or(Tz::UTC)
+    }
+
+    /// Reads `agencies.json` from a store over the output directory,
+    /// returning `None` when it does not exist.
+    pub async fn fetch(store: &dyn ObjectStore) -> Result<Option<Self>> {
+        match store.get(AGENCY_DIRECTORY_FILE).await? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Writes `agencies.json` into `base_dir`.
+    pub fn save(&self, base_dir: &str) -> Result<()> {
+        let path = Path::new(base_dir).join(AGENCY_DIRECTORY_FILE);
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Directory holding the consistency CSVs for one agency.
+pub fn consistency_dir(base_dir: &str, agency_id: &str) -> String {
+    format!(
+        "{}/{}",
+        base_dir,
+        consistency_prefix(agency_id).trim_end_matches('/')
+    )
+}
+
+/// Key prefix of the consistency CSVs for one agency, relative to the output directory.
+pub fn consistency_prefix(agency_id: &str) -> String {
+    format!("{}/agency={}/", AGENCY_SUBDIR, agency_id)
+}
+
+/// Derives a stable agency id from catalog relationships.
+///
+/// Feeds that reference the same static GTFS feed share an agency; otherwise
+/// feeds are grouped by provider name.
+pub fn agency_id_for(feed_references: &[String], provider: &str) -> String {
+    if let Some(static_id) = feed_references.iter().min() {
+        return static_id.clone();
+    }
+
+    let slug: String = provider
+        .to_ascii_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    let slug = slug
+        .split('-')
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    format!("provider-{}", slug)
+}
+
+/// Combines an agency's member feed scores and consistency rows into one grade.
+///
+/// `feed_scores` maps `(feed type, feed id)` to that feed's overall aggregate.
+/// Feed types without any aggregated member are listed as missing and left out
+/// of the weighted score, as are feeds with insufficient data. When no feed
+/// had enough data, the agency has insufficient data too. Grades use
+/// `profile`'s thresholds. Returns `None` when none of the agency's feeds
+/// were aggregated.
+pub fn aggregate_agency(
+    agency_id: &str,
+    members: &AgencyMembers,
+    feed_scores: &HashMap<(FeedType, String), OverallAggregate>,
+    consistency_rows: &[ConsistencyRow],
+    profile: &GradingProfile,
+) -> Option<AgencyAggregate> {
+    let thresholds = &profile.thresholds;
+    let weights: HashMap<&str, f64> = AGENCY_WEIGHTS.iter().copied().collect();
+
+    let mut feeds = Vec::new();
+    let mut by_type: BTreeMap<FeedType, Vec<f64>> = BTreeMap::new();
+
+    for member in &members.feeds {
+        let Some(overall) = feed_scores.get(&(member.feed_type, member.feed_id.clone())) else {
+            continue;
+        };
+        if overall.status == GradeStatus::Graded {
+            by_type
+                .entry(member.feed_type)
+                .or_default()
+                .push(overall.score);
+        }
+        feeds.push(AgencyFeedScore {
+            feed_id: member.feed_id.clone(),
+            feed_type: member.feed_type,
+            score: overall.score,
+            grade: overall.grade.clone(),
+        });
+    }
+
+    if feeds.is_empty() {
+        return None;
+    }
+
+    let mut weighted_total = 0.0;
+    let mut weight_sum = 0.0;
+
+    let mut feed_types = BTreeMap::new();
+    for (feed_type, scores) in &by_type {
+        let score = mean(scores);
+        let weight = *weights.get(feed_type.catalog_code()).unwrap_or(&1.0);
+        weighted_total += score * weight;
+        weight_sum += weight;
+        feed_types.insert(
+            *feed_type,
+            OverallAggregate {
+                score,
+                grade: thresholds.grade(score),
+                status: GradeStatus::Graded,
+            },
+        );
+    }
+
+    let missing_feed_types = FeedType::ALL
+        .into_iter()
+        .filter(|t| !by_type.contains_key(t))
+        .collect();
+
+    let consistency = aggregate_consistency(consistency_rows, thresholds);
+    if let Some(c) = &consistency {
+        let weight = *weights.get("consistency").unwrap_or(&2.0);
+        weighted_total += c.score * weight;
+        weight_sum += weight;
+    }
+
+    let overall_score = if weight_sum == 0.0 {
+        0.0
+    } else {
+        weighted_total / weight_sum
+    };
+
+    let profile_ref = profile.reference();
+    let status = if by_type.is_empty() {
+        GradeStatus::InsufficientData
+    } else {
+        GradeStatus::Graded
+    };
+
+    Some(AgencyAggregate {
+        schema_version: 1,
+        algorithm_version: ALGORITHM_VERSION,
+        agency_id: agency_id.to_string(),
+        name: members.name.clone(),
+        last_updated: Utc::now(),
+        feeds,
+        feed_types,
+        missing_feed_types,
+        consistency,
+        overall: OverallAggregate {
+            score: overall_score,
+            grade: profile_ref.grade(overall_score, status),
+            status,
+        },
+        profile: profile_ref,
+    })
+}
+
+/// Averages per-round trip and vehicle id match rates.
+///
+/// Rounds where VehiclePositions carried no ids of a kind are skipped for
+/// that kind. Returns `None` when no round had anything to compare.
+fn aggregate_consistency(
+    rows: &[ConsistencyRow],
+    thresholds: &GradeThresholds,
+) -> Option<ConsistencyAggregate> {
+    let trip_series: Vec<f64> = rows
+        .iter()
+        .filter(|r| r.vp_trip_ids > 0)
+        .map(|r| r.matched_trip_ids as f64 / r.vp_trip_ids as f64)
+        .collect();
+    let vehicle_series: Vec<f64> = rows
+        .iter()
+        .filter(|r| r.vp_vehicle_ids > 0)
+        .map(|r| r.matched_vehicle_ids as f64 / r.vp_vehicle_ids as f64)
+        .collect();
+
+    if trip_series.is_empty() && vehicle_series.is_empty() {
+        return None;
+    }
+
+    let trip_id_match = mean(&trip_series);
+    let vehicle_id_match = mean(&vehicle_series);
+
+    let parts: Vec<f64> = [
+        (&trip_series, trip_id_match),
+        (&vehicle_series, vehicle_id_match),
+    ]
+    .into_iter()
+    .filter(|(series, _)| !series.is_empty())
+    .map(|(_, value)| value)
+    .collect();
+    let score = mean(&parts);
+
+    Some(ConsistencyAggregate {
+        samples: rows.len(),
+        trip_id_match,
+        vehicle_id_match,
+        score,
+        grade: thresholds.grade(score),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzers::grade::grade;
+
+    fn members() -> AgencyMembers {
+        let mut dir = AgencyDirectory::default();
+        dir.add("mdb-1", "Metro", None, "vp-1", FeedType::VehiclePositions);
+        dir.add("mdb-1", "Metro", None, "tu-1", FeedType::TripUpdates);
+        dir.agencies.remove("mdb-1").unwrap()
+    }
+
+    fn overall(score: f64) -> OverallAggregate {
+        OverallAggregate {
+            score,
+            grade: grade(score),
+            status: GradeStatus::Graded,
+        }
+    }
+
+    fn consistency_row(vp_trips: usize, matched: usize) -> ConsistencyRow {
+        ConsistencyRow {
+            timestamp: chrono::Utc::now(),
+            vp_trip_ids: vp_trips,
+            matched_trip_ids: matched,
+            vp_vehicle_ids: 0,
+            matched_vehicle_ids: 0,
+        }
+    }
+
+    #[test]
+    fn test_agency_id_prefers_static_feed_reference() {
+        let refs = vec!["mdb-20".to_string(), "mdb-10".to_string()];
+        assert_eq!(agency_id_for(&refs, "Metro Transit"), "mdb-10");
+        assert_eq!(
+            agency_id_for(&[], "Metro  Transit (MN)"),
+            "provider-metro-transit-mn"
+        );
+    }
+
+    #[test]
+    fn test_agency_of() {
+        let mut dir = AgencyDirectory::default();
+        dir.add("mdb-1", "Metro", None, "vp-1", FeedType::VehiclePositions);
+        dir.add(
+            "mdb-1",
+            "Metro",
+            Some("America/Chicago"),
+            "tu-1",
+            FeedType::TripUpdates,
+        );
+        assert_eq!(dir.agency_of("vp-1"), Some("mdb-1"));
+        assert_eq!(dir.agency_of("other"), None);
+        assert_eq!(dir.timezone_of("vp-1"), chrono_tz::America::Chicago);
+        assert_eq!(dir.timezone_of("other"), Tz::UTC);
+    }
+
+    #[test]
+    fn test_no_aggregated_members_returns_none() {
+        let result = aggregate_agency(
+            "mdb-1",
+            &members(),
+            &HashMap::new(),
+            &[],
+            &GradingProfile::default(),
+        );
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn test_combines_feed_types_and_consistency() {
+        let mut scores = HashMap::new();
+        scores.insert(
+            (FeedType::VehiclePositions, "vp-1".to_string()),
+            overall(1.0),
+        );
+        scores.insert((FeedType::TripUpdates, "tu-1".to_string()), overall(0.5));
+
+        // Half of the VP trip ids appear in TU → consistency 0.5
+        let rows = vec![consistency_row(4, 2)];
+        let result = aggregate_agency(
+            "mdb-1",
+            &members(),
+            &scores,
+            &rows,
+            &GradingProfile::default(),
+        )
+        .unwrap();
+
+        // (1.0*3 + 0.5*3 + 0.5*2) / (3+3+2) = 0.6875
+        assert!((result.overall.score - 0.6875).abs() < 1e-10);
+        assert_eq!(result.feeds.len(), 2);
+        assert_eq!(result.algorithm_version, ALGORITHM_VERSION);
+        assert_eq!(result.missing_feed_types, vec![FeedType::ServiceAlerts]);
+        let consistency = result.consistency.unwrap();
+        assert!((consistency.trip_id_match - 0.5).abs() < 1e-10);
+        assert_eq!(consistency.vehicle_id_match, 0.0);
+    }
+
+    #[test]
+    fn test_consistency_without_ids_is_none() {
+        let rows = vec![consistency_row(0, 0)];
+        assert!(aggregate_consistency(&rows, &GradeThresholds::default()).is_none());
+    }
+}
@@ -1,181 +1,530 @@
-use crate::analyzers::grade::grade;
+use crate::analyzers::breakdown::BreakdownTally;
+use crate::analyzers::grade::NOT_APPLICABLE;
+use crate::analyzers::mode::{Mode, is_mode_field, mode_weight};
+use crate::analyzers::outage::OutageTracker;
+use crate::analyzers::profile::GradingProfile;
+use crate::analyzers::score::ScoreBreakdown;
 use crate::analyzers::types::{
-    EntityStats, FeedAggregate, FeedStats, FieldAggregate, OverallAggregate,
+    CatalogAccuracy, EntityStats, Evidence, FeedAggregate, FieldAggregate, OverallAggregate,
+    RaterCoverage,
 };
-use crate::analyzers::utility::{mean, stddev};
-use chrono::Utc;
-use std::collections::HashMap;
-
-/// Weights used in the weighted average for each field and uptime.
-/// Higher weight means the field contributes more to the overall score.
-static WEIGHTS: &[(&str, f64)] = &[
-    ("route_id", 3.0),
-    ("direction_id", 3.0),
-    ("stop_id", 3.0),
-    ("stop_sequence", 3.0),
-    ("trip_id", 2.0),
-    ("vehicle_id", 0.0),
-    ("vehicle_label", 0.0),
-    ("license_plate", 0.0),
-    ("wheelchair_accessible", 0.0),
-    ("bearing", 0.0),
-    ("speed", 0.0),
-    ("occupancy", 1.0),
-    ("multi_carriage", 0.0),
-    ("odometer", 0.0),
-    ("current_status", 1.0),
-    ("timestamp", 1.0),
-    ("congestion_level", 0.0),
-    ("occupancy_percentage", 1.0),
-    ("uptime", 3.0),
-    ("service_time", 3.0),
-];
-
-/// Aggregates a series of [`FeedStats`] rows into a single [`FeedAggregate`].
+use crate::analyzers::utility::{Summary, wilson_interval};
+use crate::feed_type::FeedType;
+use crate::observer::{self, OBSERVER_OUTAGE};
+use crate::stats::fields::{Category, FieldRegistry, category_of};
+use crate::stats::modes::ModeCounts;
+use crate::stats::{FeedStats, Stats};
+use chrono::{DateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use std::collections::{BTreeMap, HashMap};
+use std::marker::PhantomData;
+
+/// Version of the feed scoring algorithm, recorded in every [`FeedAggregate`].
+///
+/// Bump it whenever a change would grade the same samples differently, then
+/// `recompute` past days to publish them under the new version.
+pub const ALGORITHM_VERSION: u8 = 6;
+
+/// A per-sample CSV row that can be aggregated into a [`FeedAggregate`].
+pub trait SampleRow {
+    /// Weights of each graded field plus `uptime` and `service_time` in the
+    /// overall score. Higher weight means the field contributes more.
+    fn weights() -> Vec<(&'static str, f64)>;
+    /// When the sample was taken.
+    fn timestamp(&self) -> DateTime<Utc>;
+    /// Error category, if the sample failed.
+    fn error_type(&self) -> Option<&str>;
+    /// Error details, if the sample failed.
+    fn error_message(&self) -> Option<&str>;
+    /// The feed header's timestamp (POSIX seconds), if the sample recorded one.
+    fn header_timestamp(&self) -> Option<u64>;
+    /// Number of primary entities (vehicles, trip updates, or alerts) in the sample.
+    fn entity_count(&self) -> usize;
+    /// Count of primary entities populating each graded field, keyed by field name.
+    fn field_counts(&self) -> Vec<(&'static str, usize)>;
+    /// Primary entities by the transit mode of their route.
+    fn mode_counts(&self) -> &ModeCounts;
+    /// Entity types the catalog declared for the feed (comma-separated, may be empty).
+    fn declared_entity_types(&self) -> &str;
+    /// Entity types present in the sample (comma-separated, may be empty).
+    fn observed_entity_types(&self) -> &str;
+    /// Whether the observed entity types contradicted the declared ones.
+    fn entity_type_mismatch(&self) -> bool;
+}
+
+impl<F: FieldRegistry> SampleRow for Stats<F> {
+    fn weights() -> Vec<(&'static str, f64)> {
+        F::weights()
+    }
+
+    fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    fn error_type(&self) -> Option<&str> {
+        self.error_type.as_deref()
+    }
+
+    fn error_message(&self) -> Option<&str> {
+        self.error_message.as_deref()
+    }
+
+    fn header_timestamp(&self) -> Option<u64> {
+        self.header_timestamp
+    }
+
+    fn entity_count(&self) -> usize {
+        Stats::entity_count(self)
+    }
+
+    fn field_counts(&self) -> Vec<(&'static str, usize)> {
+        self.fields
+            .iter()
+            .filter(|(metric, _)| metric.weight.is_some())
+            .map(|(metric, count)| (metric.name, count))
+            .collect()
+    }
+
+    fn mode_counts(&self) -> &ModeCounts {
+        &self.modes
+    }
+
+    fn declared_entity_types(&self) -> &str {
+        &self.declared_entity_types
+    }
+
+    fn observed_entity_types(&self) -> &str {
+        &self.observed_entity_types
+    }
+
+    fn entity_type_mismatch(&self) -> bool {
+        self.entity_type_mismatch
+    }
+}
+
+/// Aggregates a series of vehicle position [`FeedStats`] rows into a single [`FeedAggregate`].
 ///
 /// Computes per-field support averages, standard deviations, letter grades,
-/// and an overall weighted score incorporating uptime.
+/// and an overall weighted score incorporating uptime, with the default
+/// [`GradingProfile`]. Breakdowns are in UTC.
 pub fn aggregate_feed(feed_id: &str, rows: Vec<FeedStats>) -> anyhow::Result<FeedAggregate> {
-    let now = Utc::now();
-
-    let window_minutes = if rows.len() < 2 {
-        0
-    } else {
-        let first = rows.first().unwrap().timestamp;
-        let last = rows.last().unwrap().timestamp;
-        (last - first).num_minutes()
-    };
-
-    // Uptime: fraction of polling attempts where the API responded without error.
-    let successful_polls = rows
-        .iter()
-        .filter(|r| r.error_type.as_deref().map_or(true, |s| s.is_empty()))
-        .count();
-    let uptime_percent = if rows.is_empty() {
-        0.0
-    } else {
-        successful_polls as f64 / rows.len() as f64
-    };
-
-    // Service time: fraction of polling attempts where at least one vehicle was present.
-    let service_polls = rows.iter().filter(|r| r.vehicles > 0).count();
-    let service_time_percent = if rows.is_empty() {
-        0.0
-    } else {
-        service_polls as f64 / rows.len() as f64
-    };
-
-    let mut vehicle_counts = Vec::new();
-
-    let mut field_series: HashMap<&str, Vec<f64>> = HashMap::new();
-
-    for row in &rows {
-        if row.vehicles == 0 {
+    aggregate_rows(
+        feed_id,
+        FeedType::VehiclePositions,
+        rows,
+        Tz::UTC,
+        &GradingProfile::default(),
+        None,
+    )
+}
+
+/// Aggregates a series of sample rows of any [`FeedType`] into a single [`FeedAggregate`],
+/// scoring fields with the row type's [`SampleRow::weights`] as overridden by `profile`.
+///
+/// Rows must be in time order; they are fed through a [`FeedTally`] one at a
+/// time. Mode-specific fields are judged on the entities whose route's mode
+/// they apply to, taking entities of no known mode to be of the feed's
+/// configured `mode` (see [`ModeCounts::applicable`]). Fields that apply to
+/// none of them or that the profile marks not applicable are reported with an
+/// `N/A` grade and left out of the score, as are fields seen in fewer than the
+/// profile's `min_samples` samples. Hourly and weekday breakdowns are computed
+/// in the agency's timezone `tz`.
+pub fn aggregate_rows<R: SampleRow>(
+    feed_id: &str,
+    feed_type: FeedType,
+    rows: impl IntoIterator<Item = R>,
+    tz: Tz,
+    profile: &GradingProfile,
+    mode: Option<Mode>,
+) -> anyhow::Result<FeedAggregate> {
+    let mut tally = FeedTally::<R>::new(tz, mode);
+    for row in rows {
+        tally.push(&row);
+    }
+    tally.finish(feed_id, feed_type, profile)
+}
+
+/// Mean `avg_support` of the applicable fields in each category of
+/// `feed_type`'s registry. Fields graded `N/A` are left out.
+pub(crate) fn category_support(
+    feed_type: FeedType,
+    fields: &HashMap<String, FieldAggregate>,
+) -> BTreeMap<Category, f64> {
+    let mut totals: BTreeMap<Category, (f64, usize)> = BTreeMap::new();
+    for (name, field) in fields {
+        if field.grade == NOT_APPLICABLE {
             continue;
         }
+        if let Some(category) = category_of(feed_type, name) {
+            let (sum, n) = totals.entry(category).or_default();
+            *sum += field.avg_support;
+            *n += 1;
+        }
+    }
+    totals
+        .into_iter()
+        .map(|(category, (sum, n))| (category, sum / n as f64))
+        .collect()
+}
+
+/// Running totals of a feed's sample rows, fed one row at a time in time
+/// order, so aggregating a long window never holds all of its rows in memory.
+///
+/// Field support and entity counts go into streaming [`Summary`]s, and the
+/// breakdowns and outage episodes are tracked as rows arrive.
+pub struct FeedTally<R> {
+    mode: Option<Mode>,
+    first: Option<DateTime<Utc>>,
+    last: Option<DateTime<Utc>>,
+    observer_outage_samples: usize,
+    // Time leading up to each observer outage row, since the previous row
+    unobserved_seconds: i64,
+    // Everything below leaves out observer outage rows
+    samples: usize,
+    successful_polls: usize,
+    service_polls: usize,
+    vehicle_counts: Summary,
+    field_series: HashMap<&'static str, Summary>,
+    mode_series: HashMap<&'static str, ModeSeries>,
+    mode_entities: BTreeMap<Mode, usize>,
+    entities: usize,
+    catalog: CatalogTally,
+    breakdowns: BreakdownTally,
+    outages: OutageTracker,
+    rows: PhantomData<fn(&R)>,
+}
+
+impl<R: SampleRow> FeedTally<R> {
+    /// An empty tally, breaking samples down by local time in `tz`, for a
+    /// feed whose vehicles are of mode `mode` unless their route says otherwise.
+    pub fn new(tz: Tz, mode: Option<Mode>) -> Self {
+        Self {
+            mode,
+            first: None,
+            last: None,
+            observer_outage_samples: 0,
+            unobserved_seconds: 0,
+            samples: 0,
+            successful_polls: 0,
+            service_polls: 0,
+            vehicle_counts: Summary::default(),
+            field_series: HashMap::new(),
+            mode_series: HashMap::new(),
+            mode_entities: BTreeMap::new(),
+            entities: 0,
+            catalog: CatalogTally::default(),
+            breakdowns: BreakdownTally::new(tz),
+            outages: OutageTracker::default(),
+            rows: PhantomData,
+        }
+    }
 
-        vehicle_counts.push(row.vehicles as f64);
+    /// Returns true when no row has been added.
+    pub fn is_empty(&self) -> bool {
+        self.first.is_none()
+    }
 
-        macro_rules! push_field {
-            ($name:expr, $value:expr) => {
-                field_series
-                    .entry($name)
-                    .or_default()
-                    .push($value as f64 / row.vehicles as f64);
-            };
+    /// Adds the next row.
+    pub fn push(&mut self, row: &R) {
+        let ts = row.timestamp();
+        let previous = self.last.replace(ts);
+        self.first.get_or_insert(ts);
+
+        // Samples lost on the rater's side say nothing about the feed
+        if row.error_type() == Some(OBSERVER_OUTAGE) {
+            self.observer_outage_samples += 1;
+            // Restart markers record when the sampler stopped; count from
+            // there, clipped to the marker's day, which has its own marker
+            let day_start = ts.date_naive().and_time(NaiveTime::MIN).and_utc();
+            let gap_start = row
+                .error_message()
+                .and_then(observer::gap_start)
+                .map(|since| since.max(day_start));
+            if let Some(since) = gap_start.max(previous) {
+                let since = since.min(ts);
+                self.unobserved_seconds += (ts - since).num_seconds();
+                self.first = self.first.min(Some(since));
+            }
+            return;
         }
 
-        push_field!("trip_id", row.with_trip_id);
-        push_field!("route_id", row.with_route_id);
-        push_field!("direction_id", row.with_direction_id);
-        push_field!("vehicle_id", row.with_vehicle_id);
-        push_field!("vehicle_label", row.with_vehicle_label);
-        push_field!("license_plate", row.with_license_plate);
-        push_field!("wheelchair_accessible", row.with_wheelchair_accessible);
-        push_field!("bearing", row.with_bearing);
-        push_field!("speed", row.with_speed);
-        push_field!("occupancy", row.with_occupancy);
-        push_field!("stop_sequence", row.with_current_stop_sequence);
-        push_field!("multi_carriage", row.with_multi_carriage_details);
-        push_field!("odometer", row.with_odometer);
-        push_field!("stop_id", row.with_stop_id);
-        push_field!("current_status", row.with_current_status);
-        push_field!("timestamp", row.with_timestamp);
-        push_field!("congestion_level", row.with_congestion_level);
-        push_field!("occupancy_percentage", row.with_occupancy_percentage);
-    }
-
-    let avg_vehicles = mean(&vehicle_counts);
-
-    let weights: HashMap<&str, f64> = WEIGHTS.iter().copied().collect();
-
-    let mut fields = HashMap::new();
-    let mut weighted_total = 0.0;
-    let mut weight_sum = 0.0;
-
-    for (name, series) in field_series {
-        if series.is_empty() {
-            continue;
+        self.samples += 1;
+        if row.error_type().is_none_or(|s| s.is_empty()) {
+            self.successful_polls += 1;
+        }
+        self.catalog.push(row);
+        self.breakdowns.push(row);
+        self.outages.push(row);
+
+        let entities = row.entity_count();
+        if entities == 0 {
+            return;
+        }
+        self.service_polls += 1;
+        self.vehicle_counts.push(entities as f64);
+        let modes = row.mode_counts();
+        self.entities += entities;
+        let mut known = 0;
+        for (mode, count) in modes.entities() {
+            *self.mode_entities.entry(mode).or_default() += count;
+            known += count;
+        }
+        if let Some(mode) = self.mode {
+            *self.mode_entities.entry(mode).or_default() += entities.saturating_sub(known);
+        }
+        for (name, count) in row.field_counts() {
+            self.field_series
+                .entry(name)
+                .or_default()
+                .push(count as f64 / entities as f64);
+            if is_mode_field(name)
+                && let Some(applicable) = modes.applicable(name, count, entities, self.mode)
+            {
+                let series = self.mode_series.entry(name).or_default();
+                let series = if applicable.mode_known {
+                    &mut series.known
+                } else {
+                    &mut series.unknown
+                };
+                series.push(applicable.populated as f64 / applicable.entities as f64);
+            }
+        }
+    }
+
+    /// Grades the rows added so far into a [`FeedAggregate`].
+    pub fn finish(
+        mut self,
+        feed_id: &str,
+        feed_type: FeedType,
+        profile: &GradingProfile,
+    ) -> anyhow::Result<FeedAggregate> {
+        let thresholds = &profile.thresholds;
+        let now = Utc::now();
+
+        let window_seconds = match (self.first, self.last) {
+            (Some(first), Some(last)) => (last - first).num_seconds(),
+            _ => 0,
+        };
+        let window_minutes = window_seconds / 60;
+        let rater_coverage = self.rater_coverage(window_seconds);
+
+        // Uptime: fraction of polling attempts where the API responded without error.
+        // Service time: fraction of polling attempts where at least one entity was present.
+        let per_sample = |n: usize| {
+            if self.samples == 0 {
+                0.0
+            } else {
+                n as f64 / self.samples as f64
+            }
+        };
+        let uptime_percent = per_sample(self.successful_polls);
+        let service_time_percent = per_sample(self.service_polls);
+
+        let avg_vehicles = self.vehicle_counts.mean();
+
+        let weights: HashMap<&str, f64> = R::weights().into_iter().collect();
+
+        let mut fields = HashMap::new();
+        // Weighted inputs of the overall score: (name, value, weight)
+        let mut components = Vec::new();
+
+        for (name, series) in self.field_series {
+            if series.count() == 0 {
+                continue;
+            }
+
+            let built_in = *weights.get(name).unwrap_or(&1.0);
+            // Mode-specific fields are judged on the entities they apply to
+            let (series, weight) = match self.mode_series.remove(name) {
+                Some(ModeSeries { known, unknown }) => {
+                    // Samples without route data would dilute those with
+                    // modes, so they only count when no sample had a mode
+                    let (applicable, weight) = if known.count() > 0 {
+                        (known, mode_weight(name).unwrap_or(built_in))
+                    } else {
+                        (unknown, built_in)
+                    };
+                    (applicable, Some(weight))
+                }
+                None if is_mode_field(name) => (series, None),
+                None => (series, Some(built_in)),
+            };
+            let weight = weight.and_then(|weight| profile.weight(name, weight));
+
+            let avg = series.mean();
+            let sd = series.stddev();
+            if let Some(weight) = weight
+                && series.count() >= profile.min_samples
+            {
+                components.push((name.to_string(), avg, weight));
+            }
+            let grade = match weight {
+                Some(_) => thresholds.grade(avg),
+                None => NOT_APPLICABLE.to_string(),
+            };
+
+            fields.insert(
+                name.to_string(),
+                FieldAggregate {
+                    avg_support: avg,
+                    stddev: sd,
+                    distribution: series.distribution(),
+                    // One trial per sample: a sample's entities are mostly the
+                    // same vehicles as the last one's, so they aren't independent
+                    confidence: wilson_interval(avg, series.count() as f64),
+                    grade,
+                },
+            );
         }
 
-        let avg = mean(&series);
-        let sd = stddev(&series, avg);
+        // Factor uptime and service time into overall score
+        let mut service_time_scored = false;
+        for (name, value) in [
+            ("uptime", uptime_percent),
+            ("service_time", service_time_percent),
+        ] {
+            if let Some(weight) = profile.weight(name, *weights.get(name).unwrap_or(&3.0)) {
+                components.push((name.to_string(), value, weight));
+                service_time_scored |= name == "service_time" && weight > 0.0;
+            }
+        }
 
-        let weight = *weights.get(name).unwrap_or(&1.0);
+        // Feeds with entities only now and then, like alerts, are judged on the whole window
+        let window_hours = window_minutes as f64 / 60.0;
+        let evidence = Evidence {
+            samples: self.samples,
+            service_hours: if service_time_scored {
+                window_hours * service_time_percent
+            } else {
+                window_hours
+            },
+        };
+        let profile_ref = profile.reference();
+        let status = profile_ref.status(&evidence);
 
-        weighted_total += avg * weight;
-        weight_sum += weight;
+        let score_breakdown = ScoreBreakdown::new(components, thresholds);
+        let overall_score = score_breakdown.score();
 
-        fields.insert(
-            name.to_string(),
-            FieldAggregate {
-                avg_support: avg,
-                stddev: sd,
-                grade: grade(avg),
+        Ok(FeedAggregate {
+            schema_version: 1,
+            algorithm_version: ALGORITHM_VERSION,
+            feed_id: feed_id.to_string(),
+            feed_type,
+            last_updated: now,
+            window_minutes,
+            entity_stats: EntityStats {
+                avg_vehicles,
+                vehicle_distribution: self.vehicle_counts.distribution(),
+                uptime_percent,
+                uptime_confidence: wilson_interval(uptime_percent, self.samples as f64),
+                service_time_percent,
             },
-        );
+            categories: category_support(feed_type, &fields),
+            fields,
+            catalog_accuracy: self.catalog.finish(),
+            rater_coverage,
+            breakdowns: self.breakdowns.finish(),
+            outages: self.outages.finish(),
+            score_breakdown,
+            mode: self.mode,
+            modes: self
+                .mode_entities
+                .iter()
+                .map(|(mode, n)| (*mode, *n as f64 / self.entities as f64))
+                .collect(),
+            evidence,
+            overall: OverallAggregate {
+                score: overall_score,
+                grade: profile_ref.grade(overall_score, status),
+                status,
+            },
+            profile: profile_ref,
+        })
     }
 
-    // Factor uptime and service time into overall score
-    let uptime_weight = *weights.get("uptime").unwrap_or(&3.0);
-    weighted_total += uptime_percent * uptime_weight;
-    weight_sum += uptime_weight;
-
-    let service_time_weight = *weights.get("service_time").unwrap_or(&3.0);
-    weighted_total += service_time_percent * service_time_weight;
-    weight_sum += service_time_weight;
-
-    let overall_score = if weight_sum == 0.0 {
-        0.0
-    } else {
-        weighted_total / weight_sum
-    };
-
-    Ok(FeedAggregate {
-        schema_version: 1,
-        algorithm_version: 3,
-        feed_id: feed_id.to_string(),
-        last_updated: now,
-        window_minutes,
-        entity_stats: EntityStats {
-            avg_vehicles,
-            uptime_percent,
-            service_time_percent,
-        },
-        fields,
-        overall: OverallAggregate {
-            score: overall_score,
-            grade: grade(overall_score),
-        },
-    })
+    /// Measures how much of the sampled window the rater could observe.
+    ///
+    /// The time leading up to each observer outage row (since the previous row) is
+    /// counted as unobserved: for a network outage that is the failed poll's
+    /// interval, and for a sampler restart marker it is the downtime itself.
+    fn rater_coverage(&self, window_seconds: i64) -> RaterCoverage {
+        let coverage_percent = if window_seconds > 0 {
+            1.0 - self.unobserved_seconds as f64 / window_seconds as f64
+        } else if self.observer_outage_samples > 0 {
+            0.0
+        } else {
+            1.0
+        };
+
+        RaterCoverage {
+            observer_outage_samples: self.observer_outage_samples,
+            coverage_percent,
+        }
+    }
+}
+
+/// Support of a mode-specific field over the entities it applies to, kept
+/// apart for samples whose entities had a mode and samples where none did.
+#[derive(Default)]
+struct ModeSeries {
+    known: Summary,
+    unknown: Summary,
+}
+
+/// Compares the catalog's declared entity types with those observed in each sample.
+#[derive(Default)]
+struct CatalogTally {
+    samples: usize,
+    mismatched_samples: usize,
+    declared_entity_types: String,
+    observed_entity_types: Vec<FeedType>,
+}
+
+impl CatalogTally {
+    fn push<R: SampleRow>(&mut self, row: &R) {
+        let (declared, observed) = (row.declared_entity_types(), row.observed_entity_types());
+        if declared.is_empty() || observed.is_empty() {
+            return;
+        }
+        self.samples += 1;
+        if row.entity_type_mismatch() {
+            self.mismatched_samples += 1;
+        }
+        if self.declared_entity_types != declared {
+            self.declared_entity_types = declared.to_string();
+        }
+        for feed_type in FeedType::parse_list(observed) {
+            if !self.observed_entity_types.contains(&feed_type) {
+                self.observed_entity_types.push(feed_type);
+            }
+        }
+    }
+
+    /// Returns `None` when no sample recorded both declared and observed types.
+    fn finish(mut self) -> Option<CatalogAccuracy> {
+        if self.samples == 0 {
+            return None;
+        }
+        self.observed_entity_types.sort();
+        Some(CatalogAccuracy {
+            declared_entity_types: self.declared_entity_types,
+            observed_entity_types: self.observed_entity_types,
+            samples: self.samples,
+            mismatched_samples: self.mismatched_samples,
+            mismatch_percent: self.mismatched_samples as f64 / self.samples as f64,
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::analyzers::grade::INSUFFICIENT_DATA;
+    use crate::analyzers::types::{ConfidenceInterval, GradeStatus};
+    use crate::stats::{AlertStats, TripUpdateStats};
     use chrono::Utc;
 
-    /// Builds a minimal `FeedStats` row with all counts zeroed out.
+    /// Builds a minimal `FeedStats` row with all field counts zeroed out.
     /// Override only the fields relevant to each test.
     fn make_row(vehicles: usize, error: bool) -> FeedStats {
         FeedStats {
@@ -186,24 +535,7 @@ mod tests {
             } else {
                 None
             },
-            with_trip_id: 0,
-            with_route_id: 0,
-            with_direction_id: 0,
-            with_vehicle_id: 0,
-            with_vehicle_label: 0,
-            with_license_plate: 0,
-            with_wheelchair_accessible: 0,
-            with_bearing: 0,
-            with_speed: 0,
-            with_odometer: 0,
-            with_current_stop_sequence: 0,
-            with_stop_id: 0,
-            with_current_status: 0,
-            with_timestamp: 0,
-            with_congestion_level: 0,
-            with_occupancy: 0,
-            with_occupancy_percentage: 0,
-            with_multi_carriage_details: 0,
+            ..Default::default()
         }
     }
 
@@ -236,6 +568,99 @@ mod tests {
         assert!((result.entity_stats.uptime_percent - 0.75).abs() < 1e-10);
     }
 
+    #[test]
+    fn test_observer_outage_excluded_from_uptime() {
+        let start = Utc::now();
+        let mut rows: Vec<FeedStats> = (0..4)
+            .map(|i| {
+                let mut row = make_row(1, i == 3);
+                row.timestamp = start + chrono::Duration::minutes(i);
+                row
+            })
+            .collect();
+        // The rater lost connectivity for the last two minutes
+        for row in &mut rows[2..] {
+            row.error_type = Some(OBSERVER_OUTAGE.to_string());
+        }
+
+        let result = aggregate_feed("feed", rows).unwrap();
+        assert_eq!(result.entity_stats.uptime_percent, 1.0);
+        assert_eq!(result.rater_coverage.observer_outage_samples, 2);
+        assert!((result.rater_coverage.coverage_percent - 1.0 / 3.0).abs() < 1e-10);
+    }
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    /// The restart marker rows written for sampler downtime from `since` to `until`.
+    fn restart_markers(since: &str, until: &str) -> Vec<FeedStats> {
+        let gap = observer::SamplerGap {
+            since: at(since),
+            until: at(until),
+        };
+        gap.markers()
+            .into_iter()
+            .map(|ts| FeedStats::from_error(OBSERVER_OUTAGE, &gap.message()).with_timestamp(ts))
+            .collect()
+    }
+
+    fn sample_at(ts: &str) -> FeedStats {
+        make_row(1, false).with_timestamp(at(ts))
+    }
+
+    #[test]
+    fn test_restart_marker_counts_from_gap_start() {
+        // Down from 22:00 the previous day until 06:00, sampled until noon
+        let mut rows = restart_markers("2024-01-01T22:00:00Z", "2024-01-02T06:00:00Z");
+        let marker = rows.pop().unwrap();
+        let day = vec![marker, sample_at("2024-01-02T12:00:00Z")];
+
+        let result = aggregate_feed("feed", day).unwrap();
+        assert_eq!(result.rater_coverage.observer_outage_samples, 1);
+        assert!((result.rater_coverage.coverage_percent - 0.5).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_restart_marker_covers_end_of_previous_day() {
+        // Sampled from 20:00, down from 22:00 past midnight
+        let mut day = vec![sample_at("2024-01-01T20:00:00Z")];
+        day.extend(
+            restart_markers("2024-01-01T22:00:00Z", "2024-01-02T06:00:00Z")
+                .into_iter()
+                .filter(|row| {
+                    row.timestamp.date_naive() == at("2024-01-01T00:00:00Z").date_naive()
+                }),
+        );
+
+        let result = aggregate_feed("feed", day).unwrap();
+        assert_eq!(result.rater_coverage.observer_outage_samples, 1);
+        assert!((result.rater_coverage.coverage_percent - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_category_support() {
+        let mut row = make_row(10, false);
+        row.fields.set("trip_id", 10);
+        row.fields.set("route_id", 5);
+        row.fields.set("bearing", 10);
+        let result = aggregate_feed("feed", vec![row]).unwrap();
+
+        // trip_id 1.0, route_id 0.5, direction_id 0.0
+        assert!((result.categories[&Category::Trip] - 0.5).abs() < 1e-10);
+        // bearing 1.0, speed 0.0, odometer 0.0
+        assert!((result.categories[&Category::Position] - 1.0 / 3.0).abs() < 1e-10);
+        assert!(!result.categories.contains_key(&Category::Text));
+    }
+
+    #[test]
+    fn test_full_rater_coverage_without_outages() {
+        let rows = vec![make_row(1, false), make_row(0, true)];
+        let result = aggregate_feed("feed", rows).unwrap();
+        assert_eq!(result.rater_coverage.observer_outage_samples, 0);
+        assert_eq!(result.rater_coverage.coverage_percent, 1.0);
+    }
+
     #[test]
     fn test_service_time_fraction() {
         // 2 rows with vehicles, 2 without → service_time = 0.5
@@ -253,7 +678,7 @@ mod tests {
     fn test_field_avg_support() {
         // 1 vehicle, route_id present → route_id avg_support should be 1.0
         let mut row = make_row(1, false);
-        row.with_route_id = 1;
+        row.fields.set("route_id", 1);
         let result = aggregate_feed("test-feed", vec![row]).unwrap();
         let route = result.fields.get("route_id").unwrap();
         assert!((route.avg_support - 1.0).abs() < 1e-10);
@@ -272,7 +697,7 @@ mod tests {
     fn test_partial_field_support() {
         // 4 vehicles, only 2 have route_id → avg_support = 0.5
         let mut row = make_row(4, false);
-        row.with_route_id = 2;
+        row.fields.set("route_id", 2);
         let result = aggregate_feed("test-feed", vec![row]).unwrap();
         let route = result.fields.get("route_id").unwrap();
         assert!((route.avg_support - 0.5).abs() < 1e-10);
@@ -308,11 +733,232 @@ mod tests {
     #[test]
     fn test_overall_score_full_uptime_no_vehicle_data() {
         // No vehicle rows → uptime=1.0 but service_time=0.0.
-        // score = (1.0*3 + 0.0*3) / (3+3) = 0.5 → D
+        // score = (1.0*3 + 0.0*3) / (3+3) = 0.5 → D, but two samples are too few for a grade
         let rows = vec![make_row(0, false), make_row(0, false)];
         let result = aggregate_feed("test-feed", rows).unwrap();
         assert!((result.overall.score - 0.5).abs() < 1e-10);
-        assert_eq!(result.overall.grade, "D");
+        assert_eq!(result.overall.status, GradeStatus::InsufficientData);
+        assert_eq!(result.overall.grade, INSUFFICIENT_DATA);
+    }
+
+    #[test]
+    fn test_grade_needs_samples_and_service_hours() {
+        let start = Utc::now();
+        // One sample every 10 minutes
+        let rows_over = |minutes: i64| -> Vec<FeedStats> {
+            (0..=minutes / 10)
+                .map(|i| FeedStats {
+                    timestamp: start + chrono::TimeDelta::minutes(i * 10),
+                    ..make_row(4, false)
+                })
+                .collect()
+        };
+
+        // 13 samples over two hours, but only 6 with vehicles: under one service hour
+        let mut rows = rows_over(120);
+        for row in rows.iter_mut().skip(6) {
+            row.vehicles = 0;
+        }
+        let result = aggregate_feed("test-feed", rows).unwrap();
+        assert_eq!(result.evidence.samples, 13);
+        assert!(result.evidence.service_hours < 1.0);
+        assert_eq!(result.overall.status, GradeStatus::InsufficientData);
+
+        let result = aggregate_feed("test-feed", rows_over(120)).unwrap();
+        assert!((result.evidence.service_hours - 2.0).abs() < 1e-10);
+        assert_eq!(result.overall.status, GradeStatus::Graded);
+        assert_eq!(result.overall.grade, "F");
+    }
+
+    #[test]
+    fn test_confidence_counts_samples_not_entities() {
+        // Many vehicles in one sample are still one observation of the feed
+        let mut row = make_row(500, false);
+        row.fields.set("route_id", 250);
+        let result = aggregate_feed("test-feed", vec![row]).unwrap();
+        let confidence = &result.fields["route_id"].confidence;
+        assert!(confidence.lower < 0.2 && confidence.upper > 0.8);
+    }
+
+    #[test]
+    fn test_confidence_intervals_narrow_with_more_data() {
+        let mut row = make_row(4, false);
+        row.fields.set("route_id", 2);
+        let few = aggregate_feed("test-feed", vec![row.clone()]).unwrap();
+        let many = aggregate_feed("test-feed", vec![row; 50]).unwrap();
+
+        let width = |c: &ConfidenceInterval| c.upper - c.lower;
+        let (few_route, many_route) = (
+            &few.fields["route_id"].confidence,
+            &many.fields["route_id"].confidence,
+        );
+        assert!(few_route.lower < 0.5 && few_route.upper > 0.5);
+        assert!(width(many_route) < width(few_route));
+        assert!(
+            width(&many.entity_stats.uptime_confidence)
+                < width(&few.entity_stats.uptime_confidence)
+        );
+    }
+
+    #[test]
+    fn test_score_breakdown_explains_overall_score() {
+        let rows = vec![make_row(0, false), make_row(0, false)];
+        let result = aggregate_feed("test-feed", rows).unwrap();
+        let breakdown = &result.score_breakdown;
+
+        let points: f64 = breakdown.components.iter().map(|c| c.points).sum();
+        assert!((points - result.overall.score).abs() < 1e-10);
+        assert_eq!(breakdown.components[0].name, "service_time");
+        assert!((breakdown.components[0].points_lost - 0.5).abs() < 1e-10);
+        assert_eq!(breakdown.improvements.len(), 1);
+        assert_eq!(breakdown.improvements[0].grade_after, "A+");
+    }
+
+    #[test]
+    fn test_profile_weights_and_not_applicable_fields() {
+        let mut row = make_row(4, false);
+        row.fields.set("route_id", 4);
+        let profile = GradingProfile {
+            name: "partner".to_string(),
+            weights: [("uptime".to_string(), 0.0)].into(),
+            not_applicable: vec!["bearing".to_string()],
+            ..Default::default()
+        };
+        let result = aggregate_rows(
+            "test-feed",
+            FeedType::VehiclePositions,
+            vec![row],
+            Tz::UTC,
+            &profile,
+            None,
+        )
+        .unwrap();
+
+        // Reported, but left out of the score
+        assert_eq!(result.fields["bearing"].avg_support, 0.0);
+        assert!(
+            !result
+                .score_breakdown
+                .components
+                .iter()
+                .any(|c| c.name == "bearing")
+        );
+        assert_eq!(result.profile.name, "partner");
+    }
+
+    #[test]
+    fn test_mode_decides_field_applicability() {
+        let aggregate = |mode| {
+            let row = make_row(4, false);
+            aggregate_rows(
+                "test-feed",
+                FeedType::VehiclePositions,
+                vec![row],
+                Tz::UTC,
+                &GradingProfile::default(),
+                mode,
+            )
+            .unwrap()
+        };
+        let weight_of = |result: &FeedAggregate, name: &str| {
+            result
+                .score_breakdown
+                .components
+                .iter()
+                .find(|c| c.name == name)
+                .map(|c| c.weight)
+        };
+
+        let bus = aggregate(Some(Mode::Bus));
+        assert_eq!(bus.fields["multi_carriage"].grade, NOT_APPLICABLE);
+        assert_eq!(weight_of(&bus, "multi_carriage"), None);
+        assert_eq!(bus.fields["route_id"].grade, "F");
+
+        let rail = aggregate(Some(Mode::Rail));
+        assert_eq!(rail.fields["multi_carriage"].grade, "F");
+        assert_eq!(weight_of(&rail, "multi_carriage"), Some(1.0));
+        assert!(rail.overall.score < bus.overall.score);
+
+        // Unknown mode: graded, but weighted 0 as before
+        let unknown = aggregate(None);
+        assert_eq!(unknown.fields["multi_carriage"].grade, "F");
+        assert_eq!(weight_of(&unknown, "multi_carriage"), Some(0.0));
+    }
+
+    #[test]
+    fn test_mixed_modes_grade_fields_per_entity() {
+        // 10 buses without carriage details, 4 trams of which 3 report them
+        let mut row = make_row(14, false);
+        row.fields.set("multi_carriage", 3);
+        row.modes = "bus=10;tram=4,multi_carriage=3".parse().unwrap();
+        let result = aggregate_rows(
+            "test-feed",
+            FeedType::VehiclePositions,
+            vec![row],
+            Tz::UTC,
+            &GradingProfile::default(),
+            Some(Mode::Bus),
+        )
+        .unwrap();
+
+        let carriages = &result.fields["multi_carriage"];
+        assert_eq!(carriages.avg_support, 0.75);
+        assert_ne!(carriages.grade, NOT_APPLICABLE);
+        // Only trains run on rails with odometers
+        assert_eq!(result.fields["odometer"].grade, NOT_APPLICABLE);
+        assert!(
+            result
+                .score_breakdown
+                .components
+                .iter()
+                .any(|c| c.name == "multi_carriage" && c.weight == 1.0)
+        );
+        assert_eq!(result.modes[&Mode::Bus], 10.0 / 14.0);
+        assert_eq!(result.modes[&Mode::Tram], 4.0 / 14.0);
+    }
+
+    #[test]
+    fn test_samples_without_route_data_left_out_of_mode_fields() {
+        // Trams reporting carriages, then a sample before route data loaded
+        let mut with_modes = make_row(4, false);
+        with_modes.fields.set("multi_carriage", 4);
+        with_modes.modes = "tram=4,multi_carriage=4".parse().unwrap();
+        let without_modes = make_row(10, false);
+        let result = aggregate_rows(
+            "test-feed",
+            FeedType::VehiclePositions,
+            vec![with_modes, without_modes],
+            Tz::UTC,
+            &GradingProfile::default(),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(result.fields["multi_carriage"].avg_support, 1.0);
+    }
+
+    #[test]
+    fn test_profile_min_samples() {
+        let mut row = make_row(4, false);
+        row.fields.set("route_id", 4);
+        let profile = GradingProfile {
+            min_samples: 2,
+            ..Default::default()
+        };
+        let result = aggregate_rows(
+            "test-feed",
+            FeedType::VehiclePositions,
+            vec![row],
+            Tz::UTC,
+            &profile,
+            None,
+        )
+        .unwrap();
+
+        // One sample isn't enough to grade fields; only uptime and service time count
+        assert!(result.fields.contains_key("route_id"));
+        assert_eq!(result.score_breakdown.components.len(), 2);
+        assert!((result.overall.score - 1.0).abs() < 1e-10);
     }
 
     #[test]
@@ -327,10 +973,107 @@ mod tests {
         // Row 2: 0 of 4 have route_id → support = 0.0
         // mean = 0.5, population stddev = 0.5
         let mut row1 = make_row(4, false);
-        row1.with_route_id = 4;
+        row1.fields.set("route_id", 4);
         let row2 = make_row(4, false);
         let result = aggregate_feed("test-feed", vec![row1, row2]).unwrap();
         let route = result.fields.get("route_id").unwrap();
         assert!((route.stddev - 0.5).abs() < 1e-10);
     }
+
+    #[test]
+    fn test_field_and_vehicle_distributions() {
+        let mut row1 = make_row(4, false);
+        row1.fields.set("route_id", 4);
+        let row2 = make_row(2, false);
+        let result = aggregate_feed("test-feed", vec![row1, row2, make_row(0, true)]).unwrap();
+
+        let route = &result.fields["route_id"].distribution;
+        assert_eq!(route.count, 2);
+        assert_eq!((route.min, route.max), (0.0, 1.0));
+        assert!((route.median - 0.5).abs() < 1e-10);
+
+        let vehicles = &result.entity_stats.vehicle_distribution;
+        assert_eq!(vehicles.count, 2);
+        assert_eq!((vehicles.min, vehicles.max), (2.0, 4.0));
+    }
+
+    #[test]
+    fn test_trip_update_rows_use_trip_update_fields() {
+        let mut row = TripUpdateStats {
+            timestamp: Utc::now(),
+            trip_updates: 4,
+            ..Default::default()
+        };
+        for (name, count) in [
+            ("trip_id", 4),
+            ("route_id", 2),
+            ("stop_time_updates", 4),
+            ("stop_id", 4),
+            ("arrival", 4),
+            ("timestamp", 4),
+        ] {
+            row.fields.set(name, count);
+        }
+        let result = aggregate_rows(
+            "tu-feed",
+            FeedType::TripUpdates,
+            vec![row],
+            Tz::UTC,
+            &GradingProfile::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(result.feed_type, FeedType::TripUpdates);
+        assert!((result.fields.get("route_id").unwrap().avg_support - 0.5).abs() < 1e-10);
+        assert!(result.fields.contains_key("stop_time_updates"));
+        assert!(!result.fields.contains_key("bearing"));
+        assert!((result.entity_stats.avg_vehicles - 4.0).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_empty_alert_feed_not_penalized_for_service_time() {
+        let row = AlertStats {
+            timestamp: Utc::now(),
+            ..Default::default()
+        };
+        let result = aggregate_rows(
+            "sa-feed",
+            FeedType::ServiceAlerts,
+            vec![row],
+            Tz::UTC,
+            &GradingProfile::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(result.entity_stats.service_time_percent, 0.0);
+        // Only uptime carries weight, so a healthy empty alerts feed scores 1.0.
+        assert!((result.overall.score - 1.0).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_catalog_accuracy_counts_mismatches() {
+        let mut ok = make_row(5, false);
+        ok.declared_entity_types = "vp".to_string();
+        ok.observed_entity_types = "vp".to_string();
+        let mut bad = make_row(0, false);
+        bad.declared_entity_types = "vp".to_string();
+        bad.observed_entity_types = "tu".to_string();
+        bad.entity_type_mismatch = true;
+
+        let result = aggregate_feed("test-feed", vec![ok, bad]).unwrap();
+        let accuracy = result.catalog_accuracy.unwrap();
+        assert_eq!(accuracy.samples, 2);
+        assert_eq!(accuracy.mismatched_samples, 1);
+        assert!((accuracy.mismatch_percent - 0.5).abs() < 1e-10);
+        assert_eq!(
+            accuracy.observed_entity_types,
+            vec![FeedType::VehiclePositions, FeedType::TripUpdates]
+        );
+    }
+
+    #[test]
+    fn test_catalog_accuracy_absent_for_legacy_rows() {
+        let result = aggregate_feed("test-feed", vec![make_row(5, false)]).unwrap();
+        assert!(result.catalog_accuracy.is_none());
+    }
 }
@@ -1,198 +1,1285 @@
-use crate::analyzers::aggregate::aggregate_feed;
-use crate::analyzers::types::{FeedIndex, FeedIndexEntry, FeedStats};
-use crate::analyzers::writetos3::write_json_to_s3;
+use crate::analyzers::agency::{
+    AGENCY_SUBDIR, AgencyDirectory, aggregate_agency, consistency_prefix,
+};
+use crate::analyzers::aggregate::{FeedTally, SampleRow};
+use crate::analyzers::changes::{CHANGES_KEY, ChangesReport, compare};
+use crate::analyzers::history::{daily_key, record_feed_day, rolling_prefix};
+use crate::analyzers::mode::Mode;
+use crate::analyzers::profile::{GradingProfile, GradingProfiles};
+use crate::analyzers::types::{
+    AgencyAggregate, AgencyIndexEntry, CatalogIssue, ConsistencyRow, FeedAggregate, FeedIndex,
+    FeedIndexEntry, OverallAggregate,
+};
+use crate::feed_type::FeedType;
+use crate::object_store::{ObjectStore, PrefixStore, put_json};
+use crate::output::{daily_file_part, parquet, read_csv_records};
+use crate::stats::{AlertStats, FeedStats, TripUpdateStats};
+use crate::storage::TimeRange;
+use crate::storage::sqlite::{SqliteReader, SqliteStore};
 use anyhow::Result;
 use chrono::NaiveDate;
-use std::fs;
-use std::fs::File;
+use chrono_tz::Tz;
+use serde::de::DeserializeOwned;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
 use tracing::{debug, info, warn};
 
-/// Aggregates all local feed CSVs, uploads per-feed JSON and an index to S3,
-/// then deletes the processed CSVs.
-#[tracing::instrument(fields(bucket, base_dir))]
-pub async fn analyze(bucket: &str, base_dir: &str) -> anyhow::Result<()> {
-    let config = aws_config::load_from_env().await;
-    let s3 = aws_sdk_s3::Client::new(&config);
+/// Aggregates all collected feed CSVs in `files`, uploads per-feed JSON and
+/// an index to `dest`, then deletes the processed CSVs.
+///
+/// `files` is a store over the sampler's output directory. Each [`FeedType`]
+/// present in it is aggregated into its own stream of per-feed JSON files and
+/// index.
+#[tracing::instrument(skip(files, dest))]
+pub async fn analyze(files: &dyn ObjectStore, dest: &dyn ObjectStore) -> Result<()> {
+    let source = RowSource::Files {
+        files,
+        date_str: None,
+        range: TimeRange::default(),
+        cleanup: Cleanup::DeleteAll,
+    };
+    aggregate_and_upload(files, dest, &source, None, None).await
+}
+
+/// Key prefix that runs over a [`Selection::is_partial`] selection write under,
+/// so they never replace the indexes, changes report or latest aggregates.
+pub const PARTIAL_PREFIX: &str = "partial/";
+
+/// Which samples an aggregation run covers.
+#[derive(Debug, Clone, Default)]
+pub struct Selection {
+    /// Only aggregate this feed. Agency rollups need every member feed, so
+    /// they are skipped.
+    pub feed_id: Option<String>,
+    /// Only aggregate samples taken within this range.
+    pub range: TimeRange,
+}
+
+impl Selection {
+    /// The day whose history this run records: set when every feed is
+    /// aggregated over exactly one whole UTC day.
+    fn history_day(&self) -> Option<NaiveDate> {
+        self.feed_id
+            .is_none()
+            .then(|| self.range.whole_day())
+            .flatten()
+    }
+
+    /// Returns true when the run covers only one feed, or a window other than
+    /// everything or one whole day.
+    pub fn is_partial(&self) -> bool {
+        self.history_day().is_none()
+            && (self.feed_id.is_some() || self.range != TimeRange::default())
+    }
+}
+
+/// Aggregates the files in `files` matching `selection` and writes per-feed
+/// JSON and the indexes to `dest` (under [`PARTIAL_PREFIX`] for a partial
+/// selection), leaving the files untouched.
+///
+/// `dest` can be any store, e.g. a [`LocalStore`](crate::object_store::LocalStore)
+/// to grade feeds without S3.
+#[tracing::instrument(skip(files, dest))]
+pub async fn analyze_selected(
+    files: &dyn ObjectStore,
+    dest: &dyn ObjectStore,
+    selection: &Selection,
+) -> Result<()> {
+    let source = RowSource::Files {
+        files,
+        date_str: None,
+        range: selection.range,
+        cleanup: Cleanup::Keep,
+    };
+    let partial = PrefixStore::new(dest, PARTIAL_PREFIX);
+    let dest: &dyn ObjectStore = if selection.is_partial() {
+        &partial
+    } else {
+        dest
+    };
+    aggregate_and_upload(
+        files,
+        dest,
+        &source,
+        selection.feed_id.as_deref(),
+        selection.history_day(),
+    )
+    .await
+}
+
+/// Aggregates the samples stored in SQLite matching `selection`, uploads
+/// per-feed and agency JSON plus the indexes to `dest` (under
+/// [`PARTIAL_PREFIX`] for a partial selection), and records every
+/// aggregate in the database. Unlike file storage, the samples themselves are kept.
+///
+/// `files` is a store over the sampler's output directory, which holds `agencies.json`.
+#[tracing::instrument(skip(files, dest, store))]
+pub async fn analyze_sqlite(
+    files: &dyn ObjectStore,
+    dest: &dyn ObjectStore,
+    store: &SqliteStore,
+    selection: &Selection,
+) -> Result<()> {
+    info!("Starting aggregation of SQLite samples");
+
+    // Scans go through their own connection, leaving the store's to the sampler
+    let source = RowSource::Sqlite {
+        store,
+        reader: Arc::new(store.reader()?),
+        range: selection.range,
+    };
+    let partial = PrefixStore::new(dest, PARTIAL_PREFIX);
+    let dest: &dyn ObjectStore = if selection.is_partial() {
+        &partial
+    } else {
+        dest
+    };
+    aggregate_and_upload(
+        files,
+        dest,
+        &source,
+        selection.feed_id.as_deref(),
+        selection.history_day(),
+    )
+    .await?;
+
+    info!("Aggregation complete");
+    Ok(())
+}
+
+/// Where aggregation reads sample rows from, and what it does with them afterwards.
+enum RowSource<'a> {
+    /// Per-feed CSV or Parquet files in a store over the output directory,
+    /// optionally only one day's, keeping only rows within `range`.
+    Files {
+        files: &'a dyn ObjectStore,
+        date_str: Option<&'a str>,
+        range: TimeRange,
+        cleanup: Cleanup<'a>,
+    },
+    /// Samples in a SQLite store within `range`, read through `reader`.
+    /// Aggregates are saved back to the store.
+    Sqlite {
+        store: &'a SqliteStore,
+        reader: Arc<SqliteReader>,
+        range: TimeRange,
+    },
+}
 
-    let feed_ids = load_feed_ids(base_dir)?;
+/// What happens to sample files once they have been aggregated.
+#[derive(Clone, Copy)]
+enum Cleanup<'a> {
+    DeleteAll,
+    /// Delete only these keys, e.g. the files confirmed uploaded.
+    DeleteOnly(&'a BTreeSet<String>),
+    Keep,
+}
+
+impl RowSource<'_> {
+    /// Feed ids with data of `feed_type`, or `None` when there is no data of that type at all.
+    async fn feed_ids(&self, feed_type: FeedType) -> Result<Option<Vec<String>>> {
+        match self {
+            RowSource::Files { files, .. } => {
+                let keys = files.list(&feed_type.key("agency_id=")).await?;
+                let ids: BTreeSet<String> = keys
+                    .iter()
+                    .filter_map(|key| feed_type.parse_key(key))
+                    .map(|(feed_id, _)| feed_id.to_string())
+                    .collect();
+                // Vehicle positions always get an index, since it also lists agencies
+                let any = !ids.is_empty() || feed_type == FeedType::VehiclePositions;
+                Ok(any.then(|| ids.into_iter().collect()))
+            }
+            RowSource::Sqlite { store, range, .. } => {
+                let ids = store.feed_ids(feed_type, range)?;
+                Ok((!ids.is_empty()).then_some(ids))
+            }
+        }
+    }
+
+    /// Adds a feed's rows to `tally`, oldest first, reading one file at a
+    /// time. A SQLite scan runs on a blocking thread.
+    async fn tally_feed_rows<R: SampleRow + DeserializeOwned + Send + 'static>(
+        &self,
+        feed_type: FeedType,
+        feed_id: &str,
+        mut tally: FeedTally<R>,
+    ) -> Result<FeedTally<R>> {
+        match self {
+            RowSource::Files {
+                files,
+                date_str,
+                range,
+                ..
+            } => {
+                let prefix = feed_prefix(feed_type, feed_id);
+                for key in row_file_keys(*files, &prefix, *date_str, range).await? {
+                    for row in read_row_file::<R>(*files, &key).await? {
+                        if range.contains(row.timestamp()) {
+                            tally.push(&row);
+                        }
+                    }
+                }
+                Ok(tally)
+            }
+            RowSource::Sqlite { reader, range, .. } => {
+                let (reader, range, feed_id) = (reader.clone(), *range, feed_id.to_string());
+                tokio::task::spawn_blocking(move || {
+                    reader
+                        .for_each_sample(feed_type, &feed_id, &range, |row: R| tally.push(&row))?;
+                    Ok(tally)
+                })
+                .await?
+            }
+        }
+    }
+
+    async fn consistency_rows(&self, agency_id: &str) -> Result<Vec<ConsistencyRow>> {
+        match self {
+            RowSource::Files {
+                files,
+                date_str,
+                range,
+                ..
+            } => {
+                let prefix = consistency_prefix(agency_id);
+                let mut rows: Vec<ConsistencyRow> =
+                    load_rows(*files, &prefix, *date_str, range).await?;
+                rows.retain(|r| range.contains(r.timestamp));
+                Ok(rows)
+            }
+            RowSource::Sqlite { store, range, .. } => store.consistency(agency_id, range),
+        }
+    }
+
+    /// Called once a feed has been aggregated and uploaded.
+    async fn finish_feed(
+        &self,
+        feed_type: FeedType,
+        feed_id: &str,
+        aggregate: &FeedAggregate,
+    ) -> Result<()> {
+        match self {
+            RowSource::Files {
+                files,
+                date_str,
+                range,
+                cleanup,
+            } => {
+                let prefix = feed_prefix(feed_type, feed_id);
+                delete_rows(*files, &prefix, *date_str, range, *cleanup).await
+            }
+            RowSource::Sqlite { store, range, .. } => {
+                store.save_aggregate(feed_type.catalog_code(), feed_id, range, aggregate)
+            }
+        }
+    }
+
+    /// Called once an agency has been aggregated and uploaded.
+    async fn finish_agency(&self, agency_id: &str, aggregate: &AgencyAggregate) -> Result<()> {
+        match self {
+            RowSource::Files {
+                files,
+                date_str,
+                range,
+                cleanup,
+            } => {
+                let prefix = consistency_prefix(agency_id);
+                delete_rows(*files, &prefix, *date_str, range, *cleanup).await
+            }
+            RowSource::Sqlite { store, range, .. } => {
+                store.save_aggregate(AGENCY_SUBDIR, agency_id, range, aggregate)
+            }
+        }
+    }
+}
 
-    let mut index_entries = Vec::new();
+/// Aggregates every feed type in `source` (or only feed `only_feed`), rolls
+/// feeds up into agencies, uploads all JSON to `dest`, then lets `source`
+/// clean up the processed rows.
+///
+/// When the run covers the whole of `day`, every JSON is also kept under that
+/// day's dated prefix, and each feed's history and rolling aggregates are
+/// updated (see [`history`](crate::analyzers::history)). A feed that already
+/// has a newer day keeps its latest aggregate and index entry, as does an
+/// agency with such a feed, so the indexes always match the latest per-feed
+/// files. Feeds and agencies are graded with the [`GradingProfiles`] in
+/// `files`. Each feed is compared with the aggregate it replaces in a
+/// [`ChangesReport`].
+async fn aggregate_and_upload(
+    files: &dyn ObjectStore,
+    dest: &dyn ObjectStore,
+    source: &RowSource<'_>,
+    only_feed: Option<&str>,
+    day: Option<NaiveDate>,
+) -> Result<()> {
+    let directory = AgencyDirectory::fetch(files).await?;
+    let profiles = GradingProfiles::fetch(files).await?;
+    // The day's index entries and the latest ones, per feed type
+    let mut indexes = Vec::new();
+    // Rolling index entries per window length and feed type
+    let mut rolling_entries: BTreeMap<(u32, FeedType), Vec<FeedIndexEntry>> = BTreeMap::new();
+    let mut feed_scores: HashMap<(FeedType, String), OverallAggregate> = HashMap::new();
+    let mut changes = Vec::new();
+    let mut feeds_compared = 0;
+    let mut feeds_replaced = 0;
+    // Feeds that already have a newer day than this run's
+    let mut stale: HashSet<(FeedType, String)> = HashSet::new();
 
-    for feed_id in feed_ids {
-        // Load local CSVs for feed
-        let rows = load_feed_rows(base_dir, &feed_id)?;
-        if rows.is_empty() {
-            warn!(feed_id = %feed_id, "No rows found for feed, skipping aggregation");
+    for feed_type in FeedType::ALL {
+        let Some(feed_ids) = source.feed_ids(feed_type).await? else {
+            debug!(feed_type = %feed_type, "No data for feed type, skipping");
             continue;
+        };
+
+        let mut index_entries = Vec::new();
+        let mut latest_entries = Vec::new();
+
+        let feed_ids = feed_ids
+            .into_iter()
+            .filter(|id| only_feed.is_none_or(|only| only == id));
+        for feed_id in feed_ids {
+            // Load the feed's rows and aggregate with its profile, broken down in the agency's local time
+            let tz = directory
+                .as_ref()
+                .map_or(Tz::UTC, |d| d.timezone_of(&feed_id));
+            let agency_id = directory.as_ref().and_then(|d| d.agency_of(&feed_id));
+            let profile = profiles.for_feed(&feed_id, agency_id);
+            let mode = profiles.mode_of(&feed_id, agency_id);
+            let Some(aggregate) =
+                aggregate_feed_rows(source, &feed_id, feed_type, tz, profile, mode).await?
+            else {
+                warn!(feed_id = %feed_id, "No rows found for feed, skipping aggregation");
+                continue;
+            };
+
+            // Keep the day's dated copy, then compare with the aggregate about
+            // to be replaced and upload JSON, unless the feed has a newer day
+            let key = aggregate_key(feed_type, &feed_id);
+            let latest = match day {
+                Some(day) => {
+                    put_json(dest, &daily_key(day, &key), &aggregate).await?;
+                    let recorded =
+                        record_feed_day(dest, day, feed_type, &feed_id, &aggregate, &key).await?;
+                    for (days, combined) in &recorded.rolling {
+                        rolling_entries
+                            .entry((*days, feed_type))
+                            .or_default()
+                            .push(index_entry(&feed_id, combined));
+                    }
+                    recorded.latest
+                }
+                None => true,
+            };
+            let previous = previous_aggregate(dest, &key).await?;
+            if latest {
+                if let Some(previous) = &previous {
+                    changes.extend(compare(previous, &aggregate));
+                    feeds_compared += 1;
+                }
+                put_json(dest, &key, &aggregate).await?;
+                debug!(feed_id = %feed_id, key = %key, "Uploaded feed aggregate");
+                feeds_replaced += 1;
+                latest_entries.push(index_entry(&feed_id, &aggregate));
+            } else {
+                warn!(feed_id = %feed_id, "Newer day already aggregated, keeping its latest aggregate");
+                stale.insert((feed_type, feed_id.clone()));
+                if let Some(previous) = &previous {
+                    latest_entries.push(index_entry(&feed_id, previous));
+                }
+            }
+
+            // Add to index
+            index_entries.push(index_entry(&feed_id, &aggregate));
+            source.finish_feed(feed_type, &feed_id, &aggregate).await?;
+            feed_scores.insert((feed_type, feed_id.clone()), aggregate.overall);
         }
 
-        debug!(feed_id = %feed_id, row_count = rows.len(), "Aggregating feed rows");
+        indexes.push((feed_type, index_entries, latest_entries));
+    }
 
-        // Aggregate
-        let aggregate = aggregate_feed(&feed_id, rows)?;
+    let (day_agencies, latest_agencies) = match only_feed {
+        Some(_) => (Vec::new(), Vec::new()),
+        None => {
+            aggregate_agencies(
+                directory.as_ref(),
+                &profiles,
+                dest,
+                source,
+                &feed_scores,
+                day,
+                &stale,
+            )
+            .await?
+        }
+    };
 
-        // Upload JSON to S3
-        let s3_key = format!("aggregates/feeds/{}.json", feed_id);
-        write_json_to_s3(&s3, bucket, &s3_key, &aggregate).await?;
-        debug!(feed_id = %feed_id, key = %s3_key, "Uploaded feed aggregate to S3");
+    // Write homepage index JSON; agency rollups are listed in the vehicle positions index
+    let mut agencies = Some((day_agencies, latest_agencies));
+    for (feed_type, index_entries, latest_entries) in indexes {
+        let (day_agencies, latest_agencies) = match feed_type {
+            FeedType::VehiclePositions => agencies.take().unwrap_or_default(),
+            _ => Default::default(),
+        };
+        let key = index_key(feed_type);
+        if let Some(day) = day {
+            let key = daily_key(day, &key);
+            upsert_index(dest, &key, feed_type, index_entries, day_agencies).await?;
+        }
+        upsert_index(dest, &key, feed_type, latest_entries, latest_agencies).await?;
+    }
 
-        // Add to index
-        index_entries.push(FeedIndexEntry {
-            feed_id: feed_id.to_string(),
-            overall_grade: aggregate.overall.grade.clone(),
-            overall_score: aggregate.overall.score,
-            uptime_percent: aggregate.entity_stats.uptime_percent,
-        });
+    // Only feeds whose latest aggregate was replaced are compared
+    let report = ChangesReport::new(feeds_compared, changes);
+    let latest = feeds_replaced > 0 || stale.is_empty();
+    publish(dest, day, latest, CHANGES_KEY, &report).await?;
 
-        // Delete local CSVs
-        delete_feed_csvs(base_dir, &feed_id)?;
+    for ((days, feed_type), feeds) in rolling_entries {
+        let key = format!("{}/{}", rolling_prefix(days), feed_type.key("feeds.json"));
+        upsert_index(dest, &key, feed_type, feeds, Vec::new()).await?;
     }
 
-    // Write homepage index JSON
+    Ok(())
+}
+
+/// Writes the `feed_type` index at `key`, upserting `feeds` and `agencies`
+/// by id into the index already there. A run may cover only some feeds (e.g.
+/// a late day), and the others keep their entries, as they keep their files.
+async fn upsert_index(
+    dest: &dyn ObjectStore,
+    key: &str,
+    feed_type: FeedType,
+    feeds: Vec<FeedIndexEntry>,
+    agencies: Vec<AgencyIndexEntry>,
+) -> Result<()> {
+    let previous = match dest.get(key).await? {
+        Some(bytes) => serde_json::from_slice::<FeedIndex>(&bytes)
+            .inspect_err(|e| warn!(key = %key, error = %e, "Unreadable index, rebuilding it"))
+            .ok(),
+        None => None,
+    };
+    let (previous_feeds, previous_agencies) =
+        previous.map_or_else(Default::default, |p| (p.feeds, p.agencies));
+
+    let feeds: BTreeMap<String, FeedIndexEntry> = previous_feeds
+        .into_iter()
+        .chain(feeds)
+        .map(|entry| (entry.feed_id.clone(), entry))
+        .collect();
+    let agencies: BTreeMap<String, AgencyIndexEntry> = previous_agencies
+        .into_iter()
+        .chain(agencies)
+        .map(|entry| (entry.agency_id.clone(), entry))
+        .collect();
     let index = FeedIndex {
         generated_at: chrono::Utc::now(),
-        feeds: index_entries,
+        feed_type,
+        feeds: feeds.into_values().collect(),
+        agencies: agencies.into_values().collect(),
+    };
+    put_json(dest, key, &index).await
+}
+
+/// The feed aggregate currently at `key`, if any. One that can't be read,
+/// e.g. written by an incompatible version, is skipped with a warning.
+async fn previous_aggregate(dest: &dyn ObjectStore, key: &str) -> Result<Option<FeedAggregate>> {
+    let Some(bytes) = dest.get(key).await? else {
+        return Ok(None);
     };
-    write_json_to_s3(&s3, bucket, "aggregates/feeds.json", &index).await?;
+    match serde_json::from_slice(&bytes) {
+        Ok(aggregate) => Ok(Some(aggregate)),
+        Err(e) => {
+            warn!(key = %key, error = %e, "Unreadable previous aggregate, not comparing");
+            Ok(None)
+        }
+    }
+}
 
+/// Writes `value` to its dated copy for `day` when given, and to `key` when `latest`.
+async fn publish(
+    dest: &dyn ObjectStore,
+    day: Option<NaiveDate>,
+    latest: bool,
+    key: &str,
+    value: &impl serde::Serialize,
+) -> Result<()> {
+    if latest {
+        put_json(dest, key, value).await?;
+    }
+    if let Some(day) = day {
+        put_json(dest, &daily_key(day, key), value).await?;
+    }
     Ok(())
 }
 
-fn load_feed_ids(base_dir: &str) -> Result<Vec<String>> {
-    let mut feed_ids = Vec::new();
+/// Builds and uploads an [`AgencyAggregate`](crate::analyzers::types::AgencyAggregate)
+/// for every agency listed in `agencies.json`, then lets `source` clean up the
+/// consumed consistency rows.
+///
+/// An agency with a feed in `stale` (one with a newer day than `day`) only gets
+/// its dated copy, and keeps its entry in the latest index. Returns the day's
+/// index entries and the latest ones.
+async fn aggregate_agencies(
+    directory: Option<&AgencyDirectory>,
+    profiles: &GradingProfiles,
+    dest: &dyn ObjectStore,
+    source: &RowSource<'_>,
+    feed_scores: &HashMap<(FeedType, String), OverallAggregate>,
+    day: Option<NaiveDate>,
+    stale: &HashSet<(FeedType, String)>,
+) -> Result<(Vec<AgencyIndexEntry>, Vec<AgencyIndexEntry>)> {
+    let Some(directory) = directory else {
+        debug!("No agency directory found, skipping agency rollup");
+        return Ok((Vec::new(), Vec::new()));
+    };
 
-    for entry in fs::read_dir(base_dir)? {
-        let entry = entry?;
-        if entry.file_type()?.is_dir() {
-            if let Some(dir_name) = entry.file_name().to_str() {
-                if let Some(feed_id) = dir_name.strip_prefix("agency_id=") {
-                    feed_ids.push(feed_id.to_string());
-                }
-            }
+    // Entries of the agencies that keep their latest rollup
+    let mut previous: Vec<AgencyIndexEntry> = Vec::new();
+    if !stale.is_empty()
+        && let Some(bytes) = dest.get(&index_key(FeedType::VehiclePositions)).await?
+    {
+        previous = serde_json::from_slice::<FeedIndex>(&bytes)
+            .map(|index| index.agencies)
+            .unwrap_or_default();
+    }
+
+    let mut entries = Vec::new();
+    let mut latest_entries = Vec::new();
+
+    for (agency_id, members) in &directory.agencies {
+        let rows = source.consistency_rows(agency_id).await?;
+
+        let profile = profiles.for_agency(agency_id);
+        let Some(aggregate) = aggregate_agency(agency_id, members, feed_scores, &rows, profile)
+        else {
+            continue;
+        };
+
+        let latest = !members
+            .feeds
+            .iter()
+            .any(|f| stale.contains(&(f.feed_type, f.feed_id.clone())));
+        let key = format!("aggregates/agencies/{}.json", agency_id);
+        publish(dest, day, latest, &key, &aggregate).await?;
+        debug!(agency_id = %agency_id, key = %key, latest, "Uploaded agency aggregate");
+
+        let entry = || AgencyIndexEntry {
+            agency_id: agency_id.clone(),
+            name: aggregate.name.clone(),
+            overall_grade: aggregate.overall.grade.clone(),
+            overall_score: aggregate.overall.score,
+            status: aggregate.overall.status,
+            feed_count: aggregate.feeds.len(),
+        };
+        entries.push(entry());
+        if latest {
+            latest_entries.push(entry());
+        } else if let Some(i) = previous.iter().position(|e| &e.agency_id == agency_id) {
+            latest_entries.push(previous.swap_remove(i));
         }
+
+        source.finish_agency(agency_id, &aggregate).await?;
     }
 
-    Ok(feed_ids)
+    Ok((entries, latest_entries))
 }
 
-fn load_feed_rows(base_dir: &str, feed_id: &str) -> Result<Vec<FeedStats>> {
-    let mut rows = Vec::new();
-    let feed_dir = format!("{}/agency_id={}", base_dir, feed_id);
-
-    for entry in fs::read_dir(&feed_dir)? {
-        let entry = entry?;
-        let path = entry.path();
+/// Lists feeds in `files` whose observed entity types contradict the
+/// catalog's declared types in at least `min_mismatch` (0.0–1.0) of samples.
+///
+/// Reads all collected CSVs without deleting them. A feed sampled as more than
+/// one feed type is reported once.
+pub async fn catalog_report(
+    files: &dyn ObjectStore,
+    min_mismatch: f64,
+) -> Result<Vec<CatalogIssue>> {
+    let mut issues: Vec<CatalogIssue> = Vec::new();
+    let source = RowSource::Files {
+        files,
+        date_str: None,
+        range: TimeRange::default(),
+        cleanup: Cleanup::Keep,
+    };
 
-        if path.extension().and_then(|e| e.to_str()) != Some("csv") {
+    for feed_type in FeedType::ALL {
+        let Some(feed_ids) = source.feed_ids(feed_type).await? else {
             continue;
+        };
+
+        for feed_id in feed_ids {
+            if issues.iter().any(|i| i.feed_id == feed_id) {
+                continue;
+            }
+
+            let profile = GradingProfile::default();
+            let Some(accuracy) =
+                aggregate_feed_rows(&source, &feed_id, feed_type, Tz::UTC, &profile, None)
+                    .await?
+                    .and_then(|aggregate| aggregate.catalog_accuracy)
+            else {
+                continue;
+            };
+
+            if accuracy.mismatched_samples > 0 && accuracy.mismatch_percent >= min_mismatch {
+                issues.push(CatalogIssue {
+                    feed_id,
+                    declared_entity_types: accuracy.declared_entity_types,
+                    observed_entity_types: accuracy.observed_entity_types,
+                    samples: accuracy.samples,
+                    mismatch_percent: accuracy.mismatch_percent,
+                });
+            }
         }
+    }
+
+    Ok(issues)
+}
 
-        let file = File::open(path)?;
-        let mut rdr = csv::Reader::from_reader(file);
+/// Key of the per-feed aggregate JSON for a feed of the given type.
+fn aggregate_key(feed_type: FeedType, feed_id: &str) -> String {
+    format!(
+        "aggregates/{}",
+        feed_type.key(&format!("feeds/{}.json", feed_id))
+    )
+}
+
+/// Key of the feed index JSON for the given feed type.
+pub fn index_key(feed_type: FeedType) -> String {
+    format!("aggregates/{}", feed_type.key("feeds.json"))
+}
+
+fn index_entry(feed_id: &str, aggregate: &FeedAggregate) -> FeedIndexEntry {
+    FeedIndexEntry {
+        feed_id: feed_id.to_string(),
+        overall_grade: aggregate.overall.grade.clone(),
+        overall_score: aggregate.overall.score,
+        status: aggregate.overall.status,
+        uptime_percent: aggregate.entity_stats.uptime_percent,
+    }
+}
 
-        for result in rdr.deserialize() {
-            let record: FeedStats = result?;
-            rows.push(record);
+/// Loads a feed's rows using the row type for `feed_type` and aggregates them.
+///
+/// Returns `None` when there are no rows.
+async fn aggregate_feed_rows(
+    source: &RowSource<'_>,
+    feed_id: &str,
+    feed_type: FeedType,
+    tz: Tz,
+    profile: &GradingProfile,
+    mode: Option<Mode>,
+) -> Result<Option<FeedAggregate>> {
+    match feed_type {
+        FeedType::VehiclePositions => {
+            aggregate_typed::<FeedStats>(source, feed_id, feed_type, tz, profile, mode).await
+        }
+        FeedType::TripUpdates => {
+            aggregate_typed::<TripUpdateStats>(source, feed_id, feed_type, tz, profile, mode).await
+        }
+        FeedType::ServiceAlerts => {
+            aggregate_typed::<AlertStats>(source, feed_id, feed_type, tz, profile, mode).await
         }
     }
+}
+
+async fn aggregate_typed<R: SampleRow + DeserializeOwned + Send + 'static>(
+    source: &RowSource<'_>,
+    feed_id: &str,
+    feed_type: FeedType,
+    tz: Tz,
+    profile: &GradingProfile,
+    mode: Option<Mode>,
+) -> Result<Option<FeedAggregate>> {
+    let tally = source
+        .tally_feed_rows(feed_type, feed_id, FeedTally::<R>::new(tz, mode))
+        .await?;
+    if tally.is_empty() {
+        return Ok(None);
+    }
+
+    debug!(feed_id = %feed_id, feed_type = %feed_type, "Aggregated feed rows");
+    Ok(Some(tally.finish(feed_id, feed_type, profile)?))
+}
+
+/// Key prefix of a feed's sample files.
+fn feed_prefix(feed_type: FeedType, feed_id: &str) -> String {
+    feed_type.key(&format!("agency_id={}/", feed_id))
+}
+
+/// Keys of the CSV and Parquet files under `prefix` for days overlapping
+/// `range`, or only those of `date={date_str}` when a date is given.
+///
+/// Keys are in time order: by day, then a day's rotated files by rotation
+/// index, then its current file.
+async fn row_file_keys(
+    files: &dyn ObjectStore,
+    prefix: &str,
+    date_str: Option<&str>,
+    range: &TimeRange,
+) -> Result<Vec<String>> {
+    let mut keys: Vec<((String, u32), String)> = files
+        .list(prefix)
+        .await?
+        .into_iter()
+        .filter_map(|key| {
+            // Includes CSVs rotated on a schema change, and days converted to Parquet
+            let (date, rotation) = daily_file_part(&key[prefix.len()..])?;
+            let included = date_str.is_none_or(|d| d == date)
+                && date
+                    .parse::<NaiveDate>()
+                    .is_ok_and(|day| range.overlaps_day(day));
+            let order = (date.to_string(), rotation.unwrap_or(u32::MAX));
+            included.then_some((order, key))
+        })
+        .collect();
+    keys.sort();
+    Ok(keys.into_iter().map(|(_, key)| key).collect())
+}
 
+/// Reads every CSV or Parquet file under `prefix` (optionally only one day's) as rows of type `R`.
+async fn load_rows<R: DeserializeOwned>(
+    files: &dyn ObjectStore,
+    prefix: &str,
+    date_str: Option<&str>,
+    range: &TimeRange,
+) -> Result<Vec<R>> {
+    let mut rows = Vec::new();
+    for key in row_file_keys(files, prefix, date_str, range).await? {
+        rows.extend(read_row_file::<R>(files, &key).await?);
+    }
     Ok(rows)
 }
 
-fn delete_feed_csvs(base_dir: &str, feed_id: &str) -> Result<()> {
-    let feed_dir = format!("{}/agency_id={}", base_dir, feed_id);
+/// Reads one CSV or Parquet file as rows of type `R`; a missing file has none.
+///
+/// CSVs of every schema version are accepted (see [`read_csv_records`]).
+async fn read_row_file<R: DeserializeOwned>(files: &dyn ObjectStore, key: &str) -> Result<Vec<R>> {
+    let Some(bytes) = files.get(key).await? else {
+        return Ok(Vec::new());
+    };
+    if key.ends_with(".parquet") {
+        parquet::read_rows::<R>(bytes)
+    } else {
+        read_csv_records::<R>(&bytes)
+    }
+}
 
-    for entry in fs::read_dir(&feed_dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.extension().and_then(|e| e.to_str()) == Some("csv") {
-            fs::remove_file(path)?;
+/// Deletes the files [`load_rows`] would read, as far as `cleanup` allows.
+async fn delete_rows(
+    files: &dyn ObjectStore,
+    prefix: &str,
+    date_str: Option<&str>,
+    range: &TimeRange,
+    cleanup: Cleanup<'_>,
+) -> Result<()> {
+    if matches!(cleanup, Cleanup::Keep) {
+        return Ok(());
+    }
+
+    for key in row_file_keys(files, prefix, date_str, range).await? {
+        if let Cleanup::DeleteOnly(keys) = cleanup
+            && !keys.contains(&key)
+        {
+            warn!(key = %key, "Keeping local file that was not confirmed uploaded");
+            continue;
         }
+        files.delete(&key).await?;
+        debug!(key = %key, "Deleted local file after aggregation");
     }
 
     Ok(())
 }
 
-/// Analyze and aggregate feeds for a specific date, upload JSON to S3, then delete local CSVs.
-#[tracing::instrument(skip(s3), fields(bucket, base_dir, date = %date))]
+/// Analyze and aggregate feeds for a specific date, upload JSON to `dest`, then
+/// delete that day's files from `files`.
+///
+/// With `uploaded` (the [`UploadManifest::uploaded_sources`](crate::upload::UploadManifest::uploaded_sources)
+/// of the day's upload), only those files are deleted; the rest are kept for the next attempt.
+/// Every [`FeedType`] present in `files` gets its own aggregates and index.
+#[tracing::instrument(skip(files, dest, uploaded), fields(date = %date))]
 pub async fn analyze_for_date(
-    s3: &aws_sdk_s3::Client,
-    bucket: &str,
-    base_dir: &str,
+    files: &dyn ObjectStore,
+    dest: &dyn ObjectStore,
     date: NaiveDate,
+    uploaded: Option<&BTreeSet<String>>,
 ) -> Result<()> {
     let date_str = date.format("%Y-%m-%d").to_string();
     info!(date = %date_str, "Starting aggregation");
 
-    let feed_ids = load_feed_ids(base_dir)?;
-    let mut index_entries = Vec::new();
+    let source = RowSource::Files {
+        files,
+        date_str: Some(&date_str),
+        range: TimeRange::default(),
+        cleanup: uploaded.map_or(Cleanup::DeleteAll, Cleanup::DeleteOnly),
+    };
+    aggregate_and_upload(files, dest, &source, None, Some(date)).await?;
 
-    for feed_id in feed_ids {
-        let rows = load_feed_rows_for_date(base_dir, &feed_id, &date_str)?;
-        if rows.is_empty() {
-            warn!(feed_id = %feed_id, "No rows found for feed, skipping aggregation");
-            continue;
+    info!(date = %date_str, "Aggregation complete");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::MemoryStore;
+    use chrono::Utc;
+
+    /// Serializes sampler rows into the CSV the sampler would have written.
+    fn sample_csv(rows: &[crate::stats::FeedStats]) -> Vec<u8> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        for row in rows {
+            writer.serialize(row).unwrap();
         }
+        writer.into_inner().unwrap()
+    }
 
-        debug!(feed_id = %feed_id, row_count = rows.len(), "Aggregating feed rows");
-        let aggregate = aggregate_feed(&feed_id, rows)?;
+    /// Drops the named columns from a CSV, as an older schema would have written it.
+    fn without_columns(csv: &[u8], columns: &[&str]) -> Vec<u8> {
+        let mut rdr = csv::Reader::from_reader(csv);
+        let headers = rdr.headers().unwrap().clone();
+        let keep: Vec<usize> = (0..headers.len())
+            .filter(|i| !columns.contains(&&headers[*i]))
+            .collect();
 
-        let s3_key = format!("aggregates/feeds/{}.json", feed_id);
-        write_json_to_s3(s3, bucket, &s3_key, &aggregate).await?;
-        debug!(feed_id = %feed_id, key = %s3_key, "Uploaded feed aggregate to S3");
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer
+            .write_record(keep.iter().map(|i| &headers[*i]))
+            .unwrap();
+        for record in rdr.records() {
+            let record = record.unwrap();
+            writer
+                .write_record(keep.iter().map(|i| &record[*i]))
+                .unwrap();
+        }
+        writer.into_inner().unwrap()
+    }
 
-        index_entries.push(FeedIndexEntry {
-            feed_id: feed_id.to_string(),
-            overall_grade: aggregate.overall.grade.clone(),
-            overall_score: aggregate.overall.score,
-            uptime_percent: aggregate.entity_stats.uptime_percent,
-        });
+    fn sample(vehicles: usize) -> crate::stats::FeedStats {
+        let mut row = crate::stats::FeedStats {
+            timestamp: Utc::now(),
+            vehicles,
+            total_entities: vehicles,
+            ..Default::default()
+        };
+        row.fields.set("position", vehicles);
+        row
+    }
+
+    #[tokio::test]
+    async fn test_analyze_for_date_offline() {
+        let files = MemoryStore::new();
+        let dest = MemoryStore::new();
+        let csv = sample_csv(&[sample(4), sample(5)]);
+        files
+            .put("agency_id=a/date=2024-01-01.csv", csv.clone(), None)
+            .await
+            .unwrap();
+        files
+            .put("agency_id=a/date=2024-01-02.csv", csv, None)
+            .await
+            .unwrap();
+        // Rotated earlier the same day, written before the catalog conformance columns
+        let legacy = without_columns(
+            &sample_csv(&[sample(3)]),
+            &[
+                "declared_entity_types",
+                "observed_entity_types",
+                "entity_type_mismatch",
+            ],
+        );
+        files
+            .put("agency_id=a/date=2024-01-01.1.csv", legacy, None)
+            .await
+            .unwrap();
 
-        delete_feed_csv_for_date(base_dir, &feed_id, &date_str)?;
+        let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        analyze_for_date(&files, &dest, date, None).await.unwrap();
+
+        // The latest aggregates, plus the day's dated copies, history and rolling windows
+        assert_eq!(
+            dest.keys(),
+            vec![
+                "aggregates/changes.json",
+                "aggregates/daily/date=2024-01-01/changes.json",
+                "aggregates/daily/date=2024-01-01/feeds.json",
+                "aggregates/daily/date=2024-01-01/feeds/a.json",
+                "aggregates/feeds.json",
+                "aggregates/feeds/a.json",
+                "aggregates/history/feeds/a.json",
+                "aggregates/rolling/30d/feeds.json",
+                "aggregates/rolling/30d/feeds/a.json",
+                "aggregates/rolling/7d/feeds.json",
+                "aggregates/rolling/7d/feeds/a.json",
+            ]
+        );
+        let index: serde_json::Value =
+            serde_json::from_slice(&dest.get("aggregates/feeds.json").await.unwrap().unwrap())
+                .unwrap();
+        assert_eq!(index["feeds"][0]["feed_id"], "a");
+
+        // Only the aggregated day is removed
+        assert_eq!(files.keys(), vec!["agency_id=a/date=2024-01-02.csv"]);
     }
 
-    let index = FeedIndex {
-        generated_at: chrono::Utc::now(),
-        feeds: index_entries,
-    };
-    write_json_to_s3(s3, bucket, "aggregates/feeds.json", &index).await?;
+    #[tokio::test]
+    async fn test_row_file_keys_in_rotation_order() {
+        let files = MemoryStore::new();
+        for key in [
+            "agency_id=a/date=2024-01-01.10.csv",
+            "agency_id=a/date=2024-01-01.2.csv",
+            "agency_id=a/date=2024-01-01.csv",
+            "agency_id=a/date=2024-01-02.1.csv",
+        ] {
+            files.put(key, Vec::new(), None).await.unwrap();
+        }
 
-    info!(date = %date_str, "Aggregation complete");
-    Ok(())
-}
+        let keys = row_file_keys(&files, "agency_id=a/", None, &TimeRange::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            keys,
+            vec![
+                "agency_id=a/date=2024-01-01.2.csv",
+                "agency_id=a/date=2024-01-01.10.csv",
+                "agency_id=a/date=2024-01-01.csv",
+                "agency_id=a/date=2024-01-02.1.csv",
+            ]
+        );
+    }
 
-fn load_feed_rows_for_date(
-    base_dir: &str,
-    feed_id: &str,
-    date_str: &str,
-) -> Result<Vec<FeedStats>> {
-    let csv_path = format!("{}/agency_id={}/date={}.csv", base_dir, feed_id, date_str);
-    let path = std::path::Path::new(&csv_path);
+    #[tokio::test]
+    async fn test_analyze_for_date_keeps_unconfirmed_files() {
+        let files = MemoryStore::new();
+        let dest = MemoryStore::new();
+        let csv = sample_csv(&[sample(2)]);
+        for key in [
+            "agency_id=a/date=2024-01-01.csv",
+            "agency_id=b/date=2024-01-01.csv",
+        ] {
+            files.put(key, csv.clone(), None).await.unwrap();
+        }
 
-    if !path.exists() {
-        return Ok(Vec::new());
+        let uploaded = BTreeSet::from(["agency_id=a/date=2024-01-01.csv".to_string()]);
+        let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        analyze_for_date(&files, &dest, date, Some(&uploaded))
+            .await
+            .unwrap();
+
+        assert!(dest.keys().contains(&"aggregates/feeds/b.json".to_string()));
+        assert_eq!(files.keys(), vec!["agency_id=b/date=2024-01-01.csv"]);
     }
 
-    let file = File::open(path)?;
-    let mut rdr = csv::Reader::from_reader(file);
-    let mut rows = Vec::new();
+    #[tokio::test]
+    async fn test_older_day_keeps_latest_aggregates() {
+        let files = MemoryStore::new();
+        let dest = MemoryStore::new();
+        for (date, vehicles) in [("2024-01-02", 4), ("2024-01-01", 2)] {
+            let key = format!("agency_id=a/date={}.csv", date);
+            files
+                .put(&key, sample_csv(&[sample(vehicles)]), None)
+                .await
+                .unwrap();
+            analyze_for_date(&files, &dest, date.parse().unwrap(), None)
+                .await
+                .unwrap();
+        }
 
-    for result in rdr.deserialize() {
-        let record: FeedStats = result?;
-        rows.push(record);
+        let avg_vehicles = async |key: &str| -> serde_json::Value {
+            let aggregate: serde_json::Value =
+                serde_json::from_slice(&dest.get(key).await.unwrap().unwrap()).unwrap();
+            aggregate["entity_stats"]["avg_vehicles"].clone()
+        };
+        assert_eq!(avg_vehicles("aggregates/feeds/a.json").await, 4.0);
+        // The late day still counts toward the rolling windows
+        assert_eq!(
+            avg_vehicles("aggregates/rolling/7d/feeds/a.json").await,
+            3.0
+        );
+        assert_eq!(
+            avg_vehicles("aggregates/daily/date=2024-01-01/feeds/a.json").await,
+            2.0
+        );
     }
 
-    Ok(rows)
-}
+    #[tokio::test]
+    async fn test_late_day_updates_feeds_without_newer_day() {
+        let files = MemoryStore::new();
+        let dest = MemoryStore::new();
+        let error = crate::stats::FeedStats::from_error("fetch_error", "timeout");
+        // Feed b has day 2 already; feed a only starts on the late day 1
+        files
+            .put(
+                "agency_id=b/date=2024-01-02.csv",
+                sample_csv(&[sample(4), error]),
+                None,
+            )
+            .await
+            .unwrap();
+        analyze_for_date(&files, &dest, "2024-01-02".parse().unwrap(), None)
+            .await
+            .unwrap();
+        for feed in ["a", "b"] {
+            let key = format!("agency_id={}/date=2024-01-01.csv", feed);
+            files
+                .put(&key, sample_csv(&[sample(2)]), None)
+                .await
+                .unwrap();
+        }
+        analyze_for_date(&files, &dest, "2024-01-01".parse().unwrap(), None)
+            .await
+            .unwrap();
 
-fn delete_feed_csv_for_date(base_dir: &str, feed_id: &str, date_str: &str) -> Result<()> {
-    let csv_path = format!("{}/agency_id={}/date={}.csv", base_dir, feed_id, date_str);
-    let path = std::path::Path::new(&csv_path);
+        let json = async |key: &str| -> serde_json::Value {
+            serde_json::from_slice(&dest.get(key).await.unwrap().unwrap()).unwrap()
+        };
+        assert_eq!(
+            json("aggregates/feeds/a.json").await["entity_stats"]["avg_vehicles"],
+            2.0
+        );
+        assert_eq!(
+            json("aggregates/feeds/b.json").await["entity_stats"]["uptime_percent"],
+            0.5
+        );
 
-    if path.exists() {
-        fs::remove_file(path)?;
-        debug!(path = %csv_path, "Deleted local CSV after aggregation");
+        // The index agrees with the per-feed files
+        let index = json("aggregates/feeds.json").await;
+        let uptime: HashMap<String, f64> = index["feeds"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| {
+                let id = e["feed_id"].as_str().unwrap().to_string();
+                (id, e["uptime_percent"].as_f64().unwrap())
+            })
+            .collect();
+        assert_eq!(
+            uptime,
+            HashMap::from([("a".into(), 1.0), ("b".into(), 0.5)])
+        );
+        let dated = json("aggregates/daily/date=2024-01-01/feeds.json").await;
+        assert_eq!(dated["feeds"][1]["uptime_percent"], 1.0);
     }
 
-    Ok(())
+    #[tokio::test]
+    async fn test_late_day_with_some_feeds_keeps_other_index_entries() {
+        let files = MemoryStore::new();
+        let dest = MemoryStore::new();
+        for feed in ["a", "b"] {
+            let key = format!("agency_id={}/date=2024-01-02.csv", feed);
+            files
+                .put(&key, sample_csv(&[sample(4)]), None)
+                .await
+                .unwrap();
+        }
+        analyze_for_date(&files, &dest, "2024-01-02".parse().unwrap(), None)
+            .await
+            .unwrap();
+        // Only feed a has rows for the late day
+        files
+            .put(
+                "agency_id=a/date=2024-01-01.csv",
+                sample_csv(&[sample(2)]),
+                None,
+            )
+            .await
+            .unwrap();
+        analyze_for_date(&files, &dest, "2024-01-01".parse().unwrap(), None)
+            .await
+            .unwrap();
+
+        let feed_ids = async |key: &str| -> Vec<String> {
+            let index: serde_json::Value =
+                serde_json::from_slice(&dest.get(key).await.unwrap().unwrap()).unwrap();
+            index["feeds"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|e| e["feed_id"].as_str().unwrap().to_string())
+                .collect()
+        };
+        for key in [
+            "aggregates/feeds.json",
+            "aggregates/rolling/7d/feeds.json",
+            "aggregates/rolling/30d/feeds.json",
+        ] {
+            assert_eq!(feed_ids(key).await, vec!["a", "b"], "{}", key);
+        }
+        assert_eq!(
+            feed_ids("aggregates/daily/date=2024-01-01/feeds.json").await,
+            vec!["a"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_analyze_selected_filters_and_keeps_inputs() {
+        let files = MemoryStore::new();
+        let dest = MemoryStore::new();
+        let day = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let at = |hour| day.and_hms_opt(hour, 0, 0).unwrap().and_utc();
+        let mut rows = vec![sample(2), sample(4)];
+        rows[0].timestamp = at(1);
+        rows[1].timestamp = at(13);
+        for feed in ["a", "b"] {
+            let key = format!("agency_id={}/date=2024-01-01.csv", feed);
+            files.put(&key, sample_csv(&rows), None).await.unwrap();
+        }
+
+        let selection = Selection {
+            feed_id: Some("a".to_string()),
+            range: TimeRange {
+                since: Some(at(12)),
+                until: None,
+            },
+        };
+        analyze_selected(&files, &dest, &selection).await.unwrap();
+
+        assert_eq!(
+            dest.keys(),
+            vec![
+                "partial/aggregates/changes.json",
+                "partial/aggregates/feeds.json",
+                "partial/aggregates/feeds/a.json"
+            ]
+        );
+        let aggregate: serde_json::Value = serde_json::from_slice(
+            &dest
+                .get("partial/aggregates/feeds/a.json")
+                .await
+                .unwrap()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(aggregate["entity_stats"]["avg_vehicles"], 4.0);
+        assert_eq!(files.keys().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_filtered_run_keeps_published_index() {
+        let files = MemoryStore::new();
+        let dest = MemoryStore::new();
+        for feed in ["a", "b"] {
+            let key = format!("agency_id={}/date=2024-01-01.csv", feed);
+            files
+                .put(&key, sample_csv(&[sample(2)]), None)
+                .await
+                .unwrap();
+        }
+        analyze_selected(&files, &dest, &Selection::default())
+            .await
+            .unwrap();
+        let published = dest.get("aggregates/feeds.json").await.unwrap();
+
+        for selection in [
+            Selection {
+                feed_id: Some("a".to_string()),
+                ..Default::default()
+            },
+            Selection {
+                feed_id: None,
+                range: TimeRange {
+                    since: Some(Utc::now()),
+                    until: None,
+                },
+            },
+        ] {
+            assert!(selection.is_partial());
+            analyze_selected(&files, &dest, &selection).await.unwrap();
+            assert_eq!(dest.get("aggregates/feeds.json").await.unwrap(), published);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_feeds_graded_with_assigned_profile() {
+        let files = MemoryStore::new();
+        let dest = MemoryStore::new();
+        let csv = sample_csv(&[sample(2)]);
+        for feed in ["a", "b"] {
+            let key = format!("agency_id={}/date=2024-01-01.csv", feed);
+            files.put(&key, csv.clone(), None).await.unwrap();
+        }
+        let profiles = r#"
+[[profiles]]
+name = "lenient"
+version = 3
+thresholds = { a_plus = 0.1 }
+min_grade_samples = 0
+min_service_hours = 0
+
+[feeds]
+b = "lenient"
+"#;
+        files
+            .put("grading.toml", profiles.as_bytes().to_vec(), None)
+            .await
+            .unwrap();
+
+        analyze_selected(&files, &dest, &Selection::default())
+            .await
+            .unwrap();
+
+        let dest = &dest;
+        let aggregate = |feed: &'static str| async move {
+            let key = format!("aggregates/feeds/{}.json", feed);
+            serde_json::from_slice::<serde_json::Value>(&dest.get(&key).await.unwrap().unwrap())
+                .unwrap()
+        };
+        let a = aggregate("a").await;
+        assert_eq!(a["profile"]["name"], "default");
+        let b = aggregate("b").await;
+        assert_eq!(b["profile"]["name"], "lenient");
+        assert_eq!(b["profile"]["version"], 3);
+        assert_eq!(b["overall"]["grade"], "A+");
+        assert_eq!(a["overall"]["score"], b["overall"]["score"]);
+    }
+
+    #[tokio::test]
+    async fn test_trip_updates_only_indexed_when_present() {
+        let files = MemoryStore::new();
+        let dest = MemoryStore::new();
+
+        analyze(&files, &dest).await.unwrap();
+
+        // An empty vehicle positions index is still written for the agency list
+        assert_eq!(
+            dest.keys(),
+            vec!["aggregates/changes.json", "aggregates/feeds.json"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_changes_since_previous_run() {
+        let files = MemoryStore::new();
+        let dest = MemoryStore::new();
+        let with_route = |route: bool| {
+            let mut row = sample(20);
+            row.fields.set("route_id", if route { 20 } else { 0 });
+            row
+        };
+        let report = async |dest: &MemoryStore| -> serde_json::Value {
+            serde_json::from_slice(&dest.get("aggregates/changes.json").await.unwrap().unwrap())
+                .unwrap()
+        };
+
+        for route in [true, false] {
+            let rows: Vec<_> = (0..20).map(|_| with_route(route)).collect();
+            files
+                .put("agency_id=a/date=2024-01-01.csv", sample_csv(&rows), None)
+                .await
+                .unwrap();
+            analyze(&files, &dest).await.unwrap();
+            if route {
+                assert_eq!(report(&dest).await["feeds_compared"], 0);
+            }
+        }
+
+        let report = report(&dest).await;
+        assert_eq!(report["feeds_compared"], 1);
+        let regressions = report["regressions"].as_array().unwrap();
+        assert!(
+            regressions
+                .iter()
+                .any(|c| c["feed_id"] == "a" && c["component"] == "route_id")
+        );
+        assert!(report["improvements"].as_array().unwrap().is_empty());
+    }
 }
@@ -0,0 +1,264 @@
+//! Hour-of-day and day-of-week quality breakdowns.
+//!
+//! A day's [`FeedAggregate`](crate::analyzers::types::FeedAggregate) averages
+//! away patterns like a field that disappears every evening or a feed that
+//! goes down every night. [`BreakdownTally`] buckets samples by their local hour
+//! and weekday in the agency's timezone and computes the same uptime,
+//! service time, entity count and field support figures per bucket.
+
+use chrono::{Datelike, Timelike, Weekday};
+use chrono_tz::Tz;
+use std::collections::BTreeMap;
+
+use crate::analyzers::aggregate::SampleRow;
+use crate::analyzers::types::{Breakdowns, PeriodStats};
+
+const WEEKDAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+/// Running totals for one period.
+#[derive(Default)]
+struct Totals {
+    samples: usize,
+    successful: usize,
+    in_service: usize,
+    entities: f64,
+    field_support: BTreeMap<&'static str, f64>,
+}
+
+impl Totals {
+    fn add<R: SampleRow>(&mut self, row: &R) {
+        self.samples += 1;
+        if row.error_type().is_none_or(|s| s.is_empty()) {
+            self.successful += 1;
+        }
+        let entities = row.entity_count();
+        if entities == 0 {
+            return;
+        }
+        self.in_service += 1;
+        self.entities += entities as f64;
+        for (name, count) in row.field_counts() {
+            *self.field_support.entry(name).or_default() += count as f64 / entities as f64;
+        }
+    }
+
+    fn stats(&self, period: String) -> PeriodStats {
+        let per_sample = |n: usize| n as f64 / self.samples as f64;
+        let per_service = |total: f64| {
+            if self.in_service == 0 {
+                0.0
+            } else {
+                total / self.in_service as f64
+            }
+        };
+        PeriodStats {
+            period,
+            samples: self.samples,
+            uptime_percent: per_sample(self.successful),
+            service_time_percent: per_sample(self.in_service),
+            avg_vehicles: per_service(self.entities),
+            fields: self
+                .field_support
+                .iter()
+                .map(|(name, total)| (name.to_string(), per_service(*total)))
+                .collect(),
+        }
+    }
+}
+
+/// Running per-period totals that break samples down by local hour and
+/// weekday, fed one sample at a time.
+///
+/// Observer outage rows should already be left out, as for the day's aggregate.
+pub struct BreakdownTally {
+    tz: Tz,
+    hours: BTreeMap<u32, Totals>,
+    weekdays: BTreeMap<u32, Totals>,
+}
+
+impl BreakdownTally {
+    /// An empty tally bucketing samples by local time in `tz`.
+    pub fn new(tz: Tz) -> Self {
+        Self {
+            tz,
+            hours: BTreeMap::new(),
+            weekdays: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a sample to its hour and weekday.
+    pub fn push<R: SampleRow>(&mut self, row: &R) {
+        let local = row.timestamp().with_timezone(&self.tz);
+        self.hours.entry(local.hour()).or_default().add(row);
+        self.weekdays
+            .entry(local.weekday().num_days_from_monday())
+            .or_default()
+            .add(row);
+    }
+
+    /// The breakdowns of the samples added so far.
+    pub fn finish(self) -> Breakdowns {
+        Breakdowns {
+            timezone: self.tz.name().to_string(),
+            hourly: self
+                .hours
+                .iter()
+                .map(|(hour, totals)| totals.stats(format!("{:02}", hour)))
+                .collect(),
+            weekday: self
+                .weekdays
+                .iter()
+                .map(|(day, totals)| totals.stats(WEEKDAYS[*day as usize].to_string()))
+                .collect(),
+        }
+    }
+}
+
+/// Combines several days' breakdowns, weighting each period by its samples.
+///
+/// Field support and entity counts only cover samples in service, so they are
+/// weighted by each day's samples in service (`samples × service_time_percent`).
+pub fn combine(days: &[&Breakdowns]) -> Breakdowns {
+    let merge = |periods: Vec<&PeriodStats>| -> Vec<PeriodStats> {
+        let mut merged: Vec<PeriodStats> = Vec::new();
+        let mut by_period: BTreeMap<&str, Vec<&PeriodStats>> = BTreeMap::new();
+        for stats in periods {
+            by_period.entry(&stats.period).or_default().push(stats);
+        }
+        for (period, stats) in by_period {
+            let samples: usize = stats.iter().map(|s| s.samples).sum();
+            let weighted = |f: &dyn Fn(&PeriodStats) -> f64| {
+                stats.iter().map(|s| f(s) * s.samples as f64).sum::<f64>() / samples.max(1) as f64
+            };
+            let in_service = |s: &PeriodStats| s.samples as f64 * s.service_time_percent;
+            let service_samples: f64 = stats.iter().map(|s| in_service(s)).sum();
+            let per_service = |total: f64| {
+                if service_samples > 0.0 {
+                    total / service_samples
+                } else {
+                    0.0
+                }
+            };
+            let mut fields: BTreeMap<String, f64> = BTreeMap::new();
+            for s in &stats {
+                for (name, support) in &s.fields {
+                    *fields.entry(name.clone()).or_default() += support * in_service(s);
+                }
+            }
+            for support in fields.values_mut() {
+                *support = per_service(*support);
+            }
+            merged.push(PeriodStats {
+                period: period.to_string(),
+                samples,
+                uptime_percent: weighted(&|s| s.uptime_percent),
+                service_time_percent: weighted(&|s| s.service_time_percent),
+                avg_vehicles: per_service(
+                    stats.iter().map(|s| s.avg_vehicles * in_service(s)).sum(),
+                ),
+                fields,
+            });
+        }
+        merged
+    };
+
+    let weekday_order = |p: &PeriodStats| {
+        WEEKDAYS
+            .iter()
+            .position(|d| d.to_string() == p.period)
+            .unwrap_or(WEEKDAYS.len())
+    };
+    let mut weekday = merge(days.iter().flat_map(|d| &d.weekday).collect());
+    weekday.sort_by_key(weekday_order);
+
+    Breakdowns {
+        timezone: days.last().map(|d| d.timezone.clone()).unwrap_or_default(),
+        hourly: merge(days.iter().flat_map(|d| &d.hourly).collect()),
+        weekday,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::FeedStats;
+    use chrono::NaiveDate;
+
+    fn row(hour: u32, vehicles: usize, with_route: usize) -> FeedStats {
+        // 2024-01-01 was a Monday
+        let mut row = FeedStats {
+            timestamp: NaiveDate::from_ymd_opt(2024, 1, 1)
+                .unwrap()
+                .and_hms_opt(hour, 0, 0)
+                .unwrap()
+                .and_utc(),
+            vehicles,
+            total_entities: vehicles,
+            ..Default::default()
+        };
+        row.fields.set("route_id", with_route);
+        row
+    }
+
+    fn tally(rows: &[FeedStats], tz: Tz) -> Breakdowns {
+        let mut tally = BreakdownTally::new(tz);
+        for row in rows {
+            tally.push(row);
+        }
+        tally.finish()
+    }
+
+    #[test]
+    fn test_breakdowns_use_local_time() {
+        // 01:00 and 02:00 UTC are 20:00 and 21:00 on Sunday in New York
+        let rows = vec![row(1, 10, 10), row(2, 10, 0), row(15, 0, 0)];
+        let result = tally(&rows, chrono_tz::America::New_York);
+
+        assert_eq!(result.timezone, "America/New_York");
+        let periods: Vec<&str> = result.hourly.iter().map(|p| p.period.as_str()).collect();
+        assert_eq!(periods, vec!["10", "20", "21"]);
+        assert_eq!(result.hourly[1].fields["route_id"], 1.0);
+        assert_eq!(result.hourly[2].fields["route_id"], 0.0);
+        assert_eq!(result.hourly[0].service_time_percent, 0.0);
+
+        let days: Vec<&str> = result.weekday.iter().map(|p| p.period.as_str()).collect();
+        assert_eq!(days, vec!["Mon", "Sun"]);
+        assert_eq!(result.weekday[1].samples, 2);
+        assert_eq!(result.weekday[1].avg_vehicles, 10.0);
+    }
+
+    #[test]
+    fn test_combine_weights_by_samples() {
+        let a = tally(&[row(1, 10, 10)], Tz::UTC);
+        let b = tally(&[row(1, 10, 0), row(1, 10, 0), row(1, 10, 0)], Tz::UTC);
+        let combined = combine(&[&a, &b]);
+        assert_eq!(combined.hourly.len(), 1);
+        assert_eq!(combined.hourly[0].samples, 4);
+        assert!((combined.hourly[0].fields["route_id"] - 0.25).abs() < 1e-10);
+        assert_eq!(combined.timezone, "UTC");
+    }
+
+    #[test]
+    fn test_combine_weights_fields_by_samples_in_service() {
+        // One sample in service on the first day; three out of service, then one in service
+        let a = tally(&[row(1, 10, 10)], Tz::UTC);
+        let b = tally(
+            &[row(1, 0, 0), row(1, 0, 0), row(1, 0, 0), row(1, 20, 0)],
+            Tz::UTC,
+        );
+        let combined = combine(&[&a, &b]);
+        let hour = &combined.hourly[0];
+        assert_eq!(hour.samples, 5);
+        assert!((hour.service_time_percent - 0.4).abs() < 1e-10);
+        assert!((hour.fields["route_id"] - 0.5).abs() < 1e-10);
+        assert!((hour.avg_vehicles - 15.0).abs() < 1e-10);
+    }
+}
@@ -0,0 +1,288 @@
+//! Regression detection between aggregation periods.
+//!
+//! Before a feed's latest aggregate is overwritten, the new aggregate is
+//! compared with it. Changes to the overall score or to a field's support
+//! that are both large and backed by enough data are listed, for every feed,
+//! in a [`ChangesReport`] at [`CHANGES_KEY`].
+//!
+//! A field change counts when it is at least [`MIN_FIELD_CHANGE`] and the
+//! two periods' 95% confidence intervals don't overlap, so a swing over a
+//! few samples isn't flagged. An overall change counts when it is
+//! at least [`MIN_SCORE_CHANGE`] and both periods had enough evidence to be
+//! graded.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::analyzers::grade::NOT_APPLICABLE;
+use crate::analyzers::types::{ConfidenceInterval, FeedAggregate, GradeStatus};
+use crate::analyzers::utility::wilson_interval;
+use crate::feed_type::FeedType;
+
+/// Key of the changes report, next to the vehicle positions index.
+pub const CHANGES_KEY: &str = "aggregates/changes.json";
+
+/// Smallest change in overall score that is reported.
+pub const MIN_SCORE_CHANGE: f64 = 0.05;
+
+/// Smallest change in a field's average support that is reported.
+pub const MIN_FIELD_CHANGE: f64 = 0.10;
+
+/// Name of the overall score in [`FeedChange::component`].
+pub const OVERALL: &str = "overall";
+
+/// A significant change in one feed between two aggregation periods.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeedChange {
+    pub(crate) feed_id: String,
+    pub(crate) feed_type: FeedType,
+    /// [`OVERALL`], or the name of the field that changed.
+    pub(crate) component: String,
+    pub(crate) previous: f64,
+    pub(crate) current: f64,
+    /// `current - previous`; negative for regressions.
+    pub(crate) delta: f64,
+    pub(crate) previous_grade: String,
+    pub(crate) current_grade: String,
+    /// When the previous aggregate was computed.
+    pub(crate) previous_updated: DateTime<Utc>,
+}
+
+/// Significant changes across all feeds since their previous aggregates,
+/// largest first.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChangesReport {
+    pub(crate) generated_at: DateTime<Utc>,
+    /// Feeds compared with a previous aggregate.
+    pub(crate) feeds_compared: usize,
+    pub(crate) regressions: Vec<FeedChange>,
+    pub(crate) improvements: Vec<FeedChange>,
+}
+
+impl ChangesReport {
+    /// Splits `changes` into regressions and improvements, largest first.
+    pub fn new(feeds_compared: usize, changes: Vec<FeedChange>) -> Self {
+        let (mut regressions, mut improvements): (Vec<_>, Vec<_>) =
+            changes.into_iter().partition(|c| c.delta < 0.0);
+        let largest_first =
+            |a: &FeedChange, b: &FeedChange| b.delta.abs().total_cmp(&a.delta.abs());
+        regressions.sort_by(largest_first);
+        improvements.sort_by(largest_first);
+        Self {
+            generated_at: Utc::now(),
+            feeds_compared,
+            regressions,
+            improvements,
+        }
+    }
+}
+
+/// Significant changes from `previous` to `current`, aggregates of the same feed.
+pub fn compare(previous: &FeedAggregate, current: &FeedAggregate) -> Vec<FeedChange> {
+    let change =
+        |component: &str, from: f64, to: f64, from_grade: &str, to_grade: &str| FeedChange {
+            feed_id: current.feed_id.clone(),
+            feed_type: current.feed_type,
+            component: component.to_string(),
+            previous: from,
+            current: to,
+            delta: to - from,
+            previous_grade: from_grade.to_string(),
+            current_grade: to_grade.to_string(),
+            previous_updated: previous.last_updated,
+        };
+    let mut changes = Vec::new();
+
+    let (before, after) = (&previous.overall, &current.overall);
+    if before.status == GradeStatus::Graded
+        && after.status == GradeStatus::Graded
+        && (after.score - before.score).abs() >= MIN_SCORE_CHANGE
+    {
+        changes.push(change(
+            OVERALL,
+            before.score,
+            after.score,
+            &before.grade,
+            &after.grade,
+        ));
+    }
+
+    let mut names: Vec<&String> = current.fields.keys().collect();
+    names.sort();
+    for name in names {
+        let (Some(before), after) = (previous.fields.get(name), &current.fields[name]) else {
+            continue;
+        };
+        if before.grade == NOT_APPLICABLE || after.grade == NOT_APPLICABLE {
+            continue;
+        }
+        if (after.avg_support - before.avg_support).abs() < MIN_FIELD_CHANGE {
+            continue;
+        }
+        let (from, to) = (
+            field_interval(previous, name),
+            field_interval(current, name),
+        );
+        if from.lower <= to.upper && to.lower <= from.upper {
+            continue;
+        }
+        changes.push(change(
+            name,
+            before.avg_support,
+            after.avg_support,
+            &before.grade,
+            &after.grade,
+        ));
+    }
+
+    changes
+}
+
+/// Confidence interval of field `name`'s support, as recorded in the aggregate.
+///
+/// Aggregates written before intervals were recorded read as an empty one;
+/// theirs is recomputed from the samples the field was counted in, so it
+/// counts as unknown (0.0–1.0) rather than exact when those weren't recorded either.
+fn field_interval(aggregate: &FeedAggregate, name: &str) -> ConfidenceInterval {
+    let field = &aggregate.fields[name];
+    if field.confidence != ConfidenceInterval::default() {
+        return field.confidence;
+    }
+    wilson_interval(field.avg_support, field.distribution.count as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzers::grade::grade;
+    use crate::analyzers::types::{
+        Distribution, EntityStats, Evidence, FieldAggregate, OverallAggregate, RaterCoverage,
+    };
+    use std::collections::HashMap;
+
+    fn aggregate(score: f64, fields: &[(&str, f64)], samples: usize) -> FeedAggregate {
+        FeedAggregate {
+            schema_version: 1,
+            algorithm_version: 5,
+            feed_id: "a".to_string(),
+            feed_type: FeedType::VehiclePositions,
+            last_updated: Utc::now(),
+            window_minutes: 1440,
+            entity_stats: EntityStats {
+                avg_vehicles: 10.0,
+                vehicle_distribution: Distribution::default(),
+                uptime_percent: 1.0,
+                uptime_confidence: Default::default(),
+                service_time_percent: 1.0,
+            },
+            fields: fields
+                .iter()
+                .map(|(name, support)| {
+                    let field = FieldAggregate {
+                        avg_support: *support,
+                        stddev: 0.0,
+                        distribution: Distribution {
+                            count: samples,
+                            ..Default::default()
+                        },
+                        confidence: Default::default(),
+                        grade: grade(*support),
+                    };
+                    (name.to_string(), field)
+                })
+                .collect::<HashMap<_, _>>(),
+            categories: Default::default(),
+            catalog_accuracy: None,
+            rater_coverage: RaterCoverage {
+                observer_outage_samples: 0,
+                coverage_percent: 1.0,
+            },
+            breakdowns: Default::default(),
+            outages: Default::default(),
+            score_breakdown: Default::default(),
+            profile: Default::default(),
+            mode: None,
+            modes: Default::default(),
+            evidence: Evidence {
+                samples,
+                service_hours: 24.0,
+            },
+            overall: OverallAggregate {
+                score,
+                grade: grade(score),
+                status: Default::default(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_compare_flags_large_changes() {
+        let previous = aggregate(0.9, &[("route_id", 0.95), ("bearing", 0.5)], 100);
+        let current = aggregate(0.7, &[("route_id", 0.5), ("bearing", 0.55)], 100);
+        let changes = compare(&previous, &current);
+
+        let components: Vec<&str> = changes.iter().map(|c| c.component.as_str()).collect();
+        assert_eq!(components, vec![OVERALL, "route_id"]);
+        assert!((changes[0].delta + 0.2).abs() < 1e-10);
+        assert_eq!(changes[0].previous_grade, "A");
+        assert_eq!(changes[0].current_grade, "C");
+    }
+
+    #[test]
+    fn test_compare_needs_enough_data() {
+        // One sample of ten vehicles: the intervals overlap despite the swing
+        let previous = aggregate(0.9, &[("route_id", 0.9)], 1);
+        let current = aggregate(0.9, &[("route_id", 0.6)], 1);
+        assert!(compare(&previous, &current).is_empty());
+
+        let mut previous = aggregate(0.9, &[], 100);
+        previous.overall.status = GradeStatus::InsufficientData;
+        let current = aggregate(0.5, &[], 100);
+        assert!(compare(&previous, &current).is_empty());
+    }
+
+    #[test]
+    fn test_compare_uses_recorded_confidence() {
+        let mut previous = aggregate(0.9, &[("multi_carriage", 0.9)], 100);
+        let mut current = aggregate(0.9, &[("multi_carriage", 0.6)], 100);
+        assert_eq!(compare(&previous, &current).len(), 1);
+
+        // Recorded over few samples of the field's mode, the intervals overlap
+        let wide = ConfidenceInterval {
+            lower: 0.3,
+            upper: 0.95,
+        };
+        for aggregate in [&mut previous, &mut current] {
+            aggregate
+                .fields
+                .get_mut("multi_carriage")
+                .unwrap()
+                .confidence = wide;
+        }
+        assert!(compare(&previous, &current).is_empty());
+    }
+
+    #[test]
+    fn test_compare_skips_not_applicable_fields() {
+        let previous = aggregate(0.9, &[("odometer", 0.0)], 100);
+        let mut current = aggregate(0.9, &[("odometer", 1.0)], 100);
+        current.fields.get_mut("odometer").unwrap().grade = NOT_APPLICABLE.to_string();
+        assert!(compare(&previous, &current).is_empty());
+    }
+
+    #[test]
+    fn test_report_splits_and_orders_changes() {
+        let previous = aggregate(0.9, &[("route_id", 0.2), ("trip_id", 0.9)], 100);
+        let current = aggregate(0.8, &[("route_id", 0.9), ("trip_id", 0.5)], 100);
+        let report = ChangesReport::new(1, compare(&previous, &current));
+
+        let components = |changes: &[FeedChange]| {
+            changes
+                .iter()
+                .map(|c| c.component.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(components(&report.regressions), vec!["trip_id", OVERALL]);
+        assert_eq!(components(&report.improvements), vec!["route_id"]);
+    }
+}
@@ -1,4 +1,59 @@
-/// Converts a support proportion (0.0–1.0) into a letter grade.
+use serde::{Deserialize, Serialize};
+
+/// Grade of a field that doesn't apply to a feed, e.g. carriage details for buses.
+pub const NOT_APPLICABLE: &str = "N/A";
+
+/// Grade of a feed with too little data to be graded.
+pub const INSUFFICIENT_DATA: &str = "insufficient data";
+
+/// Letter grades from best to worst.
+pub const GRADES: [&str; 6] = ["A+", "A", "B", "C", "D", "F"];
+
+/// Position of letter grade `grade` in [`GRADES`] (0 is best), or `None` for
+/// anything else, such as [`NOT_APPLICABLE`] or [`INSUFFICIENT_DATA`].
+pub fn grade_rank(grade: &str) -> Option<usize> {
+    GRADES.iter().position(|g| *g == grade)
+}
+
+/// Lowest support proportion earning each letter grade; anything below `d` is an F.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GradeThresholds {
+    pub a_plus: f64,
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+    pub d: f64,
+}
+
+impl Default for GradeThresholds {
+    fn default() -> Self {
+        Self {
+            a_plus: 0.95,
+            a: 0.90,
+            b: 0.80,
+            c: 0.65,
+            d: 0.40,
+        }
+    }
+}
+
+impl GradeThresholds {
+    /// Converts a support proportion (0.0–1.0) into a letter grade.
+    pub fn grade(&self, p: f64) -> String {
+        match p {
+            p if p >= self.a_plus => "A+".into(),
+            p if p >= self.a => "A".into(),
+            p if p >= self.b => "B".into(),
+            p if p >= self.c => "C".into(),
+            p if p >= self.d => "D".into(),
+            _ => "F".into(),
+        }
+    }
+}
+
+/// Converts a support proportion (0.0–1.0) into a letter grade with the
+/// default [`GradeThresholds`].
 ///
 /// | Range       | Grade |
 /// |-------------|-------|
@@ -9,14 +64,7 @@
 /// | >= 0.40     | D     |
 /// | < 0.40      | F     |
 pub fn grade(p: f64) -> String {
-    match p {
-        p if p >= 0.95 => "A+".into(),
-        p if p >= 0.90 => "A".into(),
-        p if p >= 0.80 => "B".into(),
-        p if p >= 0.65 => "C".into(),
-        p if p >= 0.40 => "D".into(),
-        _ => "F".into(),
-    }
+    GradeThresholds::default().grade(p)
 }
 
 #[cfg(test)]
@@ -38,4 +86,21 @@ mod tests {
         assert_eq!(grade(0.39), "F");
         assert_eq!(grade(0.00), "F");
     }
+
+    #[test]
+    fn test_custom_thresholds() {
+        let strict = GradeThresholds {
+            a_plus: 0.99,
+            ..Default::default()
+        };
+        assert_eq!(strict.grade(0.97), "A");
+        assert_eq!(strict.grade(0.99), "A+");
+    }
+
+    #[test]
+    fn test_grade_rank() {
+        assert!(grade_rank("A+") < grade_rank("C"));
+        assert_eq!(grade_rank("F"), Some(5));
+        assert_eq!(grade_rank(INSUFFICIENT_DATA), None);
+    }
 }
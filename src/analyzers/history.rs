@@ -0,0 +1,539 @@
+//! Aggregate history: dated daily aggregates, rolling windows and per-feed
+//! score time series.
+//!
+//! Each feed's latest aggregate under `aggregates/` is that of the newest day
+//! aggregated for it. When a whole day is aggregated, every aggregate is also written under
+//! `aggregates/daily/date={date}/` (see [`daily_key`]). From those dated
+//! copies each feed gets rolling [`WINDOWS`] aggregates under
+//! `aggregates/rolling/{n}d/`, ending on its newest day, and a compact
+//! [`FeedHistory`] of daily scores under `aggregates/history/` for trend charts.
+
+use anyhow::Result;
+use chrono::{NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::analyzers::aggregate::category_support;
+use crate::analyzers::breakdown;
+use crate::analyzers::grade::{GradeThresholds, NOT_APPLICABLE};
+use crate::analyzers::mode::Mode;
+use crate::analyzers::outage;
+use crate::analyzers::score::ScoreBreakdown;
+use crate::analyzers::types::{
+    CatalogAccuracy, Distribution, EntityStats, Evidence, FeedAggregate, FieldAggregate,
+    OutageInterval, OutageStats, OverallAggregate, RaterCoverage,
+};
+use crate::analyzers::utility::{mean, stddev, wilson_interval};
+use crate::feed_type::FeedType;
+use crate::object_store::{ObjectStore, put_json};
+
+/// Lengths in days of the rolling aggregates kept for each feed.
+pub const WINDOWS: [u32; 2] = [7, 30];
+
+/// Key of the dated copy of the aggregate at `key` (which starts with `aggregates/`).
+pub fn daily_key(date: NaiveDate, key: &str) -> String {
+    let rest = key.strip_prefix("aggregates/").unwrap_or(key);
+    format!("aggregates/daily/date={}/{}", date.format("%Y-%m-%d"), rest)
+}
+
+/// Key prefix of the rolling aggregates over the last `days` days.
+pub fn rolling_prefix(days: u32) -> String {
+    format!("aggregates/rolling/{}d", days)
+}
+
+/// Key of a feed's score time series.
+pub fn history_key(feed_type: FeedType, feed_id: &str) -> String {
+    format!(
+        "aggregates/history/{}",
+        feed_type.key(&format!("feeds/{}.json", feed_id))
+    )
+}
+
+/// One day in a [`FeedHistory`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DailyScore {
+    pub date: NaiveDate,
+    pub score: f64,
+    pub grade: String,
+    pub uptime_percent: f64,
+}
+
+/// Daily scores of one feed, oldest first.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeedHistory {
+    pub feed_id: String,
+    pub feed_type: FeedType,
+    pub days: Vec<DailyScore>,
+}
+
+impl FeedHistory {
+    /// Adds or replaces the score for `date`, keeping days in order.
+    pub fn record(&mut self, date: NaiveDate, aggregate: &FeedAggregate) {
+        let day = DailyScore {
+            date,
+            score: aggregate.overall.score,
+            grade: aggregate.overall.grade.clone(),
+            uptime_percent: aggregate.entity_stats.uptime_percent,
+        };
+        match self.days.binary_search_by_key(&date, |d| d.date) {
+            Ok(i) => self.days[i] = day,
+            Err(i) => self.days.insert(i, day),
+        }
+    }
+}
+
+/// Outcome of recording one day of a feed with [`record_feed_day`].
+pub struct RecordedDay {
+    /// Whether the day is the feed's newest, so its aggregate is the latest one.
+    pub latest: bool,
+    /// Rolling aggregates ending on the feed's newest day, one per entry of
+    /// [`WINDOWS`] that has data.
+    pub rolling: Vec<(u32, FeedAggregate)>,
+}
+
+/// Records a feed's aggregate for `date` in its history, and rebuilds its
+/// rolling aggregates from the dated aggregates of the days up to the newest
+/// one in the history.
+///
+/// The day's dated aggregate must already be written. A day aggregated late,
+/// e.g. a pending day retried after later days were aggregated, still lands
+/// in every window that covers it, but is not the feed's latest day.
+pub async fn record_feed_day(
+    dest: &dyn ObjectStore,
+    date: NaiveDate,
+    feed_type: FeedType,
+    feed_id: &str,
+    aggregate: &FeedAggregate,
+    latest_key: &str,
+) -> Result<RecordedDay> {
+    let key = history_key(feed_type, feed_id);
+    let mut history = match dest.get(&key).await? {
+        Some(bytes) => serde_json::from_slice(&bytes)?,
+        None => FeedHistory {
+            feed_id: feed_id.to_string(),
+            feed_type,
+            days: Vec::new(),
+        },
+    };
+    history.record(date, aggregate);
+    put_json(dest, &key, &history).await?;
+    let newest = history.days.last().map_or(date, |d| d.date);
+
+    // Read back each day of the longest window once
+    let longest = WINDOWS.iter().copied().max().unwrap_or(0);
+    let mut daily: Vec<(NaiveDate, FeedAggregate)> = Vec::new();
+    for day in history.days.iter().rev().map(|d| d.date) {
+        if (newest - day).num_days() >= longest as i64 {
+            break;
+        }
+        if let Some(bytes) = dest.get(&daily_key(day, latest_key)).await? {
+            daily.push((day, serde_json::from_slice(&bytes)?));
+        }
+    }
+
+    let mut rolling = Vec::new();
+    for days in WINDOWS {
+        let window: Vec<(NaiveDate, &FeedAggregate)> = daily
+            .iter()
+            .filter(|(day, _)| (newest - *day).num_days() < days as i64)
+            .map(|(day, aggregate)| (*day, aggregate))
+            .collect();
+        if let Some(combined) = combine(&window) {
+            let rest = latest_key.strip_prefix("aggregates/").unwrap_or(latest_key);
+            put_json(
+                dest,
+                &format!("{}/{}", rolling_prefix(days), rest),
+                &combined,
+            )
+            .await?;
+            rolling.push((days, combined));
+        }
+    }
+    Ok(RecordedDay {
+        latest: newest == date,
+        rolling,
+    })
+}
+
+/// Combines daily aggregates of one feed into a single aggregate.
+///
+/// Each day counts equally: field support, uptime, service time and rater
+/// coverage are the mean of the daily values, and a field's `stddev` is its
+/// day-to-day variation. The overall score is the mean daily score. Hourly
+/// and weekday breakdowns are weighted by each period's samples, as are the
+/// percentiles of each [`Distribution`] (min, max and count are exact).
+/// Grades use the thresholds and evidence requirements of the latest day's
+/// grading profile, applied to the days' combined samples and service hours;
+/// a field not applicable on every day stays `N/A`.
+///
+/// Each day is given with its date; the latest day is the one with the newest
+/// date, however recently it was aggregated.
+pub fn combine(dated: &[(NaiveDate, &FeedAggregate)]) -> Option<FeedAggregate> {
+    let (_, latest) = dated.iter().max_by_key(|(date, _)| *date)?;
+    let days: Vec<&FeedAggregate> = dated.iter().map(|(_, aggregate)| *aggregate).collect();
+    let thresholds = &latest.profile.thresholds;
+    let daily = |f: fn(&FeedAggregate) -> f64| days.iter().map(|d| f(d)).collect::<Vec<_>>();
+
+    // Each day's field
+    let mut field_series: HashMap<&str, Vec<&FieldAggregate>> = HashMap::new();
+    for day in &days {
+        for (name, field) in &day.fields {
+            field_series.entry(name).or_default().push(field);
+        }
+    }
+    let fields = field_series
+        .into_iter()
+        .map(|(name, fields)| {
+            let series: Vec<f64> = fields.iter().map(|f| f.avg_support).collect();
+            // Samples the field was counted in, as for a single day
+            let observed: usize = fields.iter().map(|f| f.distribution.count).sum();
+            let avg = mean(&series);
+            let aggregate = FieldAggregate {
+                avg_support: avg,
+                stddev: stddev(&series, avg),
+                distribution: combine_distributions(fields.iter().map(|f| &f.distribution)),
+                confidence: wilson_interval(avg, observed as f64),
+                grade: if fields.iter().all(|f| f.grade == NOT_APPLICABLE) {
+                    NOT_APPLICABLE.to_string()
+                } else {
+                    thresholds.grade(avg)
+                },
+            };
+            (name.to_string(), aggregate)
+        })
+        .collect();
+
+    let accuracies: Vec<&CatalogAccuracy> = days
+        .iter()
+        .filter_map(|d| d.catalog_accuracy.as_ref())
+        .collect();
+    let catalog_accuracy = accuracies.last().map(|last| {
+        let samples = accuracies.iter().map(|a| a.samples).sum::<usize>();
+        let mismatched_samples = accuracies.iter().map(|a| a.mismatched_samples).sum();
+        let mut observed: Vec<FeedType> = accuracies
+            .iter()
+            .flat_map(|a| a.observed_entity_types.iter().copied())
+            .collect();
+        observed.sort();
+        observed.dedup();
+        CatalogAccuracy {
+            declared_entity_types: last.declared_entity_types.clone(),
+            observed_entity_types: observed,
+            samples,
+            mismatched_samples,
+            mismatch_percent: mismatched_samples as f64 / samples.max(1) as f64,
+        }
+    });
+
+    let evidence = Evidence {
+        samples: days.iter().map(|d| d.evidence.samples).sum(),
+        service_hours: days.iter().map(|d| d.evidence.service_hours).sum(),
+    };
+    let status = latest.profile.status(&evidence);
+    let uptime_percent = mean(&daily(|d| d.entity_stats.uptime_percent));
+
+    let score = mean(&daily(|d| d.overall.score));
+    Some(FeedAggregate {
+        schema_version: latest.schema_version,
+        algorithm_version: latest.algorithm_version,
+        feed_id: latest.feed_id.clone(),
+        feed_type: latest.feed_type,
+        last_updated: Utc::now(),
+        window_minutes: days.iter().map(|d| d.window_minutes).sum(),
+        entity_stats: EntityStats {
+            avg_vehicles: mean(&daily(|d| d.entity_stats.avg_vehicles)),
+            vehicle_distribution: combine_distributions(
+                days.iter().map(|d| &d.entity_stats.vehicle_distribution),
+            ),
+            uptime_percent,
+            uptime_confidence: wilson_interval(uptime_percent, evidence.samples as f64),
+            service_time_percent: mean(&daily(|d| d.entity_stats.service_time_percent)),
+        },
+        categories: category_support(latest.feed_type, &fields),
+        fields,
+        catalog_accuracy,
+        rater_coverage: RaterCoverage {
+            observer_outage_samples: days
+                .iter()
+                .map(|d| d.rater_coverage.observer_outage_samples)
+                .sum(),
+            coverage_percent: mean(&daily(|d| d.rater_coverage.coverage_percent)),
+        },
+        outages: combine_outages(&days),
+        breakdowns: breakdown::combine(&days.iter().map(|d| &d.breakdowns).collect::<Vec<_>>()),
+        score_breakdown: combine_score_breakdowns(&days, thresholds),
+        profile: latest.profile.clone(),
+        mode: latest.mode,
+        modes: combine_modes(&days),
+        evidence,
+        overall: OverallAggregate {
+            score,
+            grade: latest.profile.grade(score, status),
+            status,
+        },
+    })
+}
+
+/// Mean daily share of entities of each mode.
+fn combine_modes(days: &[&FeedAggregate]) -> BTreeMap<Mode, f64> {
+    let mut shares: BTreeMap<Mode, f64> = BTreeMap::new();
+    for day in days {
+        for (mode, share) in &day.modes {
+            *shares.entry(*mode).or_default() += share / days.len() as f64;
+        }
+    }
+    shares
+}
+
+/// Merges the outage episodes of several days, recomputing the metrics over
+/// the combined window.
+fn combine_outages(days: &[&FeedAggregate]) -> OutageStats {
+    let mut intervals: Vec<OutageInterval> = days
+        .iter()
+        .flat_map(|d| d.outages.intervals.iter().cloned())
+        .collect();
+    intervals.sort_by_key(|i| i.start);
+    let window = days.iter().map(|d| d.window_minutes * 60).sum();
+    outage::summarize(intervals, window)
+}
+
+/// Rebuilds the score breakdown from each component's mean daily value.
+///
+/// With the same components and weights every day this explains the mean
+/// daily score exactly.
+fn combine_score_breakdowns(
+    days: &[&FeedAggregate],
+    thresholds: &GradeThresholds,
+) -> ScoreBreakdown {
+    let mut components: Vec<(&str, f64, Vec<f64>)> = Vec::new();
+    for day in days {
+        for component in &day.score_breakdown.components {
+            match components
+                .iter_mut()
+                .find(|(name, _, _)| *name == component.name)
+            {
+                Some((_, _, values)) => values.push(component.value),
+                None => components.push((&component.name, component.weight, vec![component.value])),
+            }
+        }
+    }
+    ScoreBreakdown::new(
+        components
+            .into_iter()
+            .map(|(name, weight, values)| (name.to_string(), mean(&values), weight))
+            .collect(),
+        thresholds,
+    )
+}
+
+/// Merges distributions of several days. Percentiles can't be merged
+/// exactly, so they are averaged weighted by count.
+fn combine_distributions<'a>(days: impl Iterator<Item = &'a Distribution>) -> Distribution {
+    let days: Vec<&Distribution> = days.filter(|d| d.count > 0).collect();
+    let count: usize = days.iter().map(|d| d.count).sum();
+    if count == 0 {
+        return Distribution::default();
+    }
+    let weighted = |f: fn(&Distribution) -> f64| {
+        days.iter().map(|d| f(d) * d.count as f64).sum::<f64>() / count as f64
+    };
+    Distribution {
+        count,
+        min: days.iter().map(|d| d.min).fold(f64::INFINITY, f64::min),
+        max: days.iter().map(|d| d.max).fold(f64::NEG_INFINITY, f64::max),
+        p5: weighted(|d| d.p5),
+        p25: weighted(|d| d.p25),
+        median: weighted(|d| d.median),
+        p75: weighted(|d| d.p75),
+        p95: weighted(|d| d.p95),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzers::grade::{INSUFFICIENT_DATA, grade};
+    use crate::analyzers::types::GradeStatus;
+    use crate::object_store::MemoryStore;
+
+    fn aggregate(score: f64, route_support: f64) -> FeedAggregate {
+        FeedAggregate {
+            schema_version: 1,
+            algorithm_version: 4,
+            feed_id: "a".to_string(),
+            feed_type: FeedType::VehiclePositions,
+            last_updated: Utc::now(),
+            window_minutes: 1440,
+            entity_stats: EntityStats {
+                avg_vehicles: 10.0,
+                vehicle_distribution: Distribution::default(),
+                uptime_percent: score,
+                uptime_confidence: Default::default(),
+                service_time_percent: 1.0,
+            },
+            fields: HashMap::from([(
+                "route_id".to_string(),
+                FieldAggregate {
+                    avg_support: route_support,
+                    stddev: 0.0,
+                    distribution: Distribution::default(),
+                    confidence: Default::default(),
+                    grade: grade(route_support),
+                },
+            )]),
+            categories: Default::default(),
+            catalog_accuracy: None,
+            rater_coverage: RaterCoverage {
+                observer_outage_samples: 1,
+                coverage_percent: 1.0,
+            },
+            breakdowns: Default::default(),
+            outages: Default::default(),
+            score_breakdown: Default::default(),
+            profile: Default::default(),
+            mode: None,
+            modes: Default::default(),
+            evidence: Evidence {
+                samples: 1440,
+                service_hours: 24.0,
+            },
+            overall: OverallAggregate {
+                score,
+                grade: grade(score),
+                status: Default::default(),
+            },
+        }
+    }
+
+    fn day(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 1, d).unwrap()
+    }
+
+    #[test]
+    fn test_daily_key() {
+        assert_eq!(
+            daily_key(day(2), "aggregates/trip_updates/feeds/a.json"),
+            "aggregates/daily/date=2024-01-02/trip_updates/feeds/a.json"
+        );
+    }
+
+    #[test]
+    fn test_combine_averages_days() {
+        let (a, b) = (aggregate(1.0, 1.0), aggregate(0.5, 0.0));
+        let combined = combine(&[(day(1), &a), (day(2), &b)]).unwrap();
+        assert!((combined.overall.score - 0.75).abs() < 1e-10);
+        assert_eq!(combined.window_minutes, 2880);
+        assert_eq!(combined.rater_coverage.observer_outage_samples, 2);
+        let route = &combined.fields["route_id"];
+        assert!((route.avg_support - 0.5).abs() < 1e-10);
+        assert!((route.stddev - 0.5).abs() < 1e-10);
+        assert!(combine(&[]).is_none());
+    }
+
+    #[test]
+    fn test_combine_latest_is_newest_date() {
+        // Day 1 re-aggregated after day 2, with another mode
+        let mut newest = aggregate(1.0, 1.0);
+        newest.mode = Some(Mode::Rail);
+        newest.last_updated = Utc::now() - chrono::Duration::hours(1);
+        let mut late = aggregate(1.0, 1.0);
+        late.mode = Some(Mode::Bus);
+
+        let combined = combine(&[(day(1), &late), (day(2), &newest)]).unwrap();
+        assert_eq!(combined.mode, Some(Mode::Rail));
+    }
+
+    #[test]
+    fn test_combined_days_can_reach_minimum_evidence() {
+        let thin = || {
+            let mut day = aggregate(1.0, 1.0);
+            day.evidence = Evidence {
+                samples: 6,
+                service_hours: 0.5,
+            };
+            day.overall.status = GradeStatus::InsufficientData;
+            day
+        };
+        let (a, b) = (thin(), thin());
+        let one = combine(&[(day(1), &a)]).unwrap();
+        assert_eq!(one.overall.status, GradeStatus::InsufficientData);
+        assert_eq!(one.overall.grade, INSUFFICIENT_DATA);
+
+        let two = combine(&[(day(1), &a), (day(2), &b)]).unwrap();
+        assert_eq!(two.evidence.samples, 12);
+        assert_eq!(two.overall.status, GradeStatus::Graded);
+        assert_eq!(two.overall.grade, "A+");
+    }
+
+    #[tokio::test]
+    async fn test_record_feed_day_builds_history_and_windows() {
+        let dest = MemoryStore::new();
+        let latest = "aggregates/feeds/a.json";
+        // Day 1 falls outside the 7-day window ending on day 10
+        for (d, score) in [(1, 0.0), (5, 0.5), (10, 1.0)] {
+            let agg = aggregate(score, score);
+            put_json(&dest, &daily_key(day(d), latest), &agg)
+                .await
+                .unwrap();
+            record_feed_day(&dest, day(d), FeedType::VehiclePositions, "a", &agg, latest)
+                .await
+                .unwrap();
+        }
+
+        let history: FeedHistory = serde_json::from_slice(
+            &dest
+                .get("aggregates/history/feeds/a.json")
+                .await
+                .unwrap()
+                .unwrap(),
+        )
+        .unwrap();
+        let dates: Vec<NaiveDate> = history.days.iter().map(|d| d.date).collect();
+        assert_eq!(dates, vec![day(1), day(5), day(10)]);
+
+        let dest = &dest;
+        let rolling = |days| async move {
+            let key = format!("{}/feeds/a.json", rolling_prefix(days));
+            let bytes = dest.get(&key).await.unwrap().unwrap();
+            serde_json::from_slice::<FeedAggregate>(&bytes).unwrap()
+        };
+        assert!((rolling(7).await.overall.score - 0.75).abs() < 1e-10);
+        assert!((rolling(30).await.overall.score - 0.5).abs() < 1e-10);
+    }
+
+    #[tokio::test]
+    async fn test_older_day_recorded_after_newer_day() {
+        let dest = MemoryStore::new();
+        let latest = "aggregates/feeds/a.json";
+        let rolling = format!("{}/feeds/a.json", rolling_prefix(7));
+        for (d, score) in [(2, 1.0), (1, 0.0)] {
+            let agg = aggregate(score, score);
+            put_json(&dest, &daily_key(day(d), latest), &agg)
+                .await
+                .unwrap();
+            let recorded =
+                record_feed_day(&dest, day(d), FeedType::VehiclePositions, "a", &agg, latest)
+                    .await
+                    .unwrap();
+            assert_eq!(recorded.latest, d == 2);
+            assert_eq!(recorded.rolling.len(), WINDOWS.len());
+        }
+
+        let history: FeedHistory = serde_json::from_slice(
+            &dest
+                .get("aggregates/history/feeds/a.json")
+                .await
+                .unwrap()
+                .unwrap(),
+        )
+        .unwrap();
+        let dates: Vec<NaiveDate> = history.days.iter().map(|d| d.date).collect();
+        assert_eq!(dates, vec![day(1), day(2)]);
+
+        // The rolling window still ends on day 2, and now includes the late day 1
+        let window: FeedAggregate =
+            serde_json::from_slice(&dest.get(&rolling).await.unwrap().unwrap()).unwrap();
+        assert!((window.overall.score - 0.5).abs() < 1e-10);
+        assert_eq!(window.window_minutes, 2880);
+    }
+}
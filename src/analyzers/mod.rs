@@ -2,11 +2,19 @@
 //!
 //! This module collects per-sample CSV data, computes weighted averages
 //! for each optional GTFS-RT field, assigns letter grades, and uploads
-//! the results as JSON to S3.
+//! the results as JSON to an object store. Feeds belonging to the same agency are also
+//! rolled up into a combined agency grade.
 
+pub mod agency;
 pub mod aggregate;
 pub mod analyzer;
+pub mod breakdown;
+pub mod changes;
 pub mod grade;
+pub mod history;
+pub mod mode;
+pub mod outage;
+pub mod profile;
+pub mod score;
 pub mod types;
 pub mod utility;
-pub mod writetos3;
@@ -0,0 +1,285 @@
+//! Transit modes, and which fields only make sense for some of them.
+//!
+//! A few vehicle fields describe things buses don't have, like carriages, so
+//! they are weighted 0 in the registry for everyone. Each vehicle's [`Mode`]
+//! comes from the `route_type` of its route in the agency's static GTFS
+//! ([`RouteModes`]), so a feed running buses and trams grades those fields
+//! over its trams only. Vehicles on routes of no known type take the mode set
+//! per feed or agency in the grading profiles file. Once a mode is known, the
+//! fields are graded with a weight of their own where they apply, and
+//! reported as not applicable elsewhere.
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Cursor, Read};
+use std::str::FromStr;
+
+/// Transit mode of a feed's vehicles, after the GTFS `route_type`.
+///
+/// Deserializes from its name or from a `route_type` number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", try_from = "ModeSetting")]
+pub enum Mode {
+    Bus,
+    Tram,
+    Subway,
+    Rail,
+    Ferry,
+    /// Cable cars, gondolas and funiculars.
+    Cable,
+    Other,
+}
+
+/// A field graded only for some modes.
+struct ModeField {
+    name: &'static str,
+    modes: &'static [Mode],
+    /// Weight where the field applies.
+    weight: f64,
+}
+
+static MODE_FIELDS: &[ModeField] = &[
+    ModeField {
+        name: "multi_carriage",
+        modes: &[Mode::Tram, Mode::Subway, Mode::Rail],
+        weight: 1.0,
+    },
+    ModeField {
+        name: "odometer",
+        modes: &[Mode::Rail],
+        weight: 1.0,
+    },
+    ModeField {
+        name: "wheelchair_accessible",
+        modes: &[Mode::Tram, Mode::Subway, Mode::Rail, Mode::Ferry],
+        weight: 1.0,
+    },
+];
+
+impl Mode {
+    /// The mode of a GTFS `route_type`, basic or extended.
+    pub fn from_route_type(route_type: u16) -> Self {
+        match route_type {
+            0 | 900..=999 => Mode::Tram,
+            1 | 12 | 400..=499 => Mode::Subway,
+            2 | 100..=199 => Mode::Rail,
+            3 | 11 | 200..=299 | 700..=899 => Mode::Bus,
+            4 | 1000..=1299 => Mode::Ferry,
+            5..=7 | 1300..=1499 => Mode::Cable,
+            _ => Mode::Other,
+        }
+    }
+
+    /// Weight of field `name` for this mode given its built-in weight, or
+    /// `None` when the field doesn't apply to the mode.
+    pub fn weight(self, name: &str, built_in: f64) -> Option<f64> {
+        match MODE_FIELDS.iter().find(|f| f.name == name) {
+            Some(field) if field.modes.contains(&self) => Some(field.weight),
+            Some(_) => None,
+            None => Some(built_in),
+        }
+    }
+
+    /// Whether field `name` applies to vehicles of this mode.
+    pub fn applies(self, name: &str) -> bool {
+        self.weight(name, 0.0).is_some()
+    }
+
+    /// The mode's name, as written in settings files.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Mode::Bus => "bus",
+            Mode::Tram => "tram",
+            Mode::Subway => "subway",
+            Mode::Rail => "rail",
+            Mode::Ferry => "ferry",
+            Mode::Cable => "cable",
+            Mode::Other => "other",
+        }
+    }
+}
+
+/// Whether field `name` is graded only for some modes.
+pub fn is_mode_field(name: &str) -> bool {
+    mode_field_name(name).is_some()
+}
+
+/// Weight of mode-specific field `name` where it applies, or `None` for
+/// fields graded for every mode.
+pub fn mode_weight(name: &str) -> Option<f64> {
+    MODE_FIELDS
+        .iter()
+        .find(|f| f.name == name)
+        .map(|f| f.weight)
+}
+
+/// The `'static` name of mode-specific field `name`.
+pub(crate) fn mode_field_name(name: &str) -> Option<&'static str> {
+    MODE_FIELDS.iter().map(|f| f.name).find(|f| *f == name)
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "bus" => Mode::Bus,
+            "tram" => Mode::Tram,
+            "subway" => Mode::Subway,
+            "rail" => Mode::Rail,
+            "ferry" => Mode::Ferry,
+            "cable" => Mode::Cable,
+            "other" => Mode::Other,
+            _ => anyhow::bail!("unknown mode '{}'", s),
+        })
+    }
+}
+
+/// A mode as written in a settings file.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ModeSetting {
+    RouteType(u16),
+    Name(String),
+}
+
+impl TryFrom<ModeSetting> for Mode {
+    type Error = anyhow::Error;
+
+    fn try_from(setting: ModeSetting) -> Result<Self, Self::Error> {
+        match setting {
+            ModeSetting::RouteType(route_type) => Ok(Mode::from_route_type(route_type)),
+            ModeSetting::Name(name) => name.parse(),
+        }
+    }
+}
+
+/// Mode of each route of a static GTFS feed, by `route_id`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RouteModes {
+    routes: HashMap<String, Mode>,
+}
+
+#[derive(Deserialize)]
+struct RouteRecord {
+    route_id: String,
+    route_type: u16,
+}
+
+impl RouteModes {
+    /// Reads the modes from a static GTFS zip's `routes.txt`.
+    pub fn from_gtfs_zip(bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+        // Some producers nest the files in a folder
+        let name = archive
+            .file_names()
+            .find(|name| name.rsplit('/').next() == Some("routes.txt"))
+            .map(str::to_string)
+            .context("no routes.txt in GTFS archive")?;
+        let mut routes = String::new();
+        archive.by_name(&name)?.read_to_string(&mut routes)?;
+        Self::from_routes_txt(routes.as_bytes())
+    }
+
+    /// Reads the modes from the contents of a `routes.txt` file.
+    pub fn from_routes_txt(routes: impl Read) -> anyhow::Result<Self> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(routes);
+        let mut modes = Self::default();
+        for record in reader.deserialize() {
+            let record: RouteRecord = record?;
+            modes
+                .routes
+                .insert(record.route_id, Mode::from_route_type(record.route_type));
+        }
+        Ok(modes)
+    }
+
+    /// Adds the routes of `other`, keeping existing entries on conflicts.
+    pub fn merge(&mut self, other: RouteModes) {
+        for (route_id, mode) in other.routes {
+            self.routes.entry(route_id).or_insert(mode);
+        }
+    }
+
+    /// The mode of route `route_id`, if it is known.
+    pub fn mode_of(&self, route_id: &str) -> Option<Mode> {
+        self.routes.get(route_id).copied()
+    }
+
+    /// Returns true when no route is known.
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_route_type() {
+        assert_eq!(Mode::from_route_type(3), Mode::Bus);
+        assert_eq!(Mode::from_route_type(2), Mode::Rail);
+        assert_eq!(Mode::from_route_type(109), Mode::Rail);
+        assert_eq!(Mode::from_route_type(11), Mode::Bus);
+        assert_eq!(Mode::from_route_type(1500), Mode::Other);
+    }
+
+    #[test]
+    fn test_deserialize_name_or_route_type() {
+        let modes: Vec<Mode> = serde_json::from_str(r#"["rail", 3]"#).unwrap();
+        assert_eq!(modes, vec![Mode::Rail, Mode::Bus]);
+        assert!(serde_json::from_str::<Mode>(r#""hovercraft""#).is_err());
+    }
+
+    #[test]
+    fn test_mode_weights() {
+        assert_eq!(Mode::Bus.weight("multi_carriage", 0.0), None);
+        assert_eq!(Mode::Rail.weight("multi_carriage", 0.0), Some(1.0));
+        assert_eq!(Mode::Tram.weight("odometer", 0.0), None);
+        assert_eq!(Mode::Bus.weight("route_id", 3.0), Some(3.0));
+        assert!(Mode::Bus.applies("route_id"));
+        assert!(!Mode::Bus.applies("wheelchair_accessible"));
+        assert!(is_mode_field("odometer"));
+        assert_eq!(mode_weight("odometer"), Some(1.0));
+        assert_eq!(mode_weight("route_id"), None);
+        assert!(!is_mode_field("route_id"));
+    }
+
+    #[test]
+    fn test_route_modes_from_gtfs_zip() {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("gtfs/routes.txt", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        std::io::Write::write_all(
+            &mut zip,
+            b"route_id,route_short_name,route_type\n10,10,3\nL1, L1 ,0\nS,S,109\n",
+        )
+        .unwrap();
+        let bytes = zip.finish().unwrap().into_inner();
+
+        let modes = RouteModes::from_gtfs_zip(&bytes).unwrap();
+        assert_eq!(modes.mode_of("10"), Some(Mode::Bus));
+        assert_eq!(modes.mode_of("L1"), Some(Mode::Tram));
+        assert_eq!(modes.mode_of("S"), Some(Mode::Rail));
+        assert_eq!(modes.mode_of("11"), None);
+    }
+
+    #[test]
+    fn test_route_modes_merge_keeps_existing() {
+        let mut modes = RouteModes::from_routes_txt(&b"route_id,route_type\n1,3\n"[..]).unwrap();
+        modes.merge(RouteModes::from_routes_txt(&b"route_id,route_type\n1,2\n2,2\n"[..]).unwrap());
+        assert_eq!(modes.mode_of("1"), Some(Mode::Bus));
+        assert_eq!(modes.mode_of("2"), Some(Mode::Rail));
+    }
+}
@@ -0,0 +1,202 @@
+//! Outage episodes derived from sample timestamps.
+//!
+//! `uptime_percent` can't tell one long outage from many short blips, so
+//! [`OutageTracker`] groups consecutive failed or frozen samples into episodes and
+//! reports their count, durations, mean time between failures and mean time
+//! to recovery.
+
+use chrono::{DateTime, TimeDelta, Utc};
+
+use crate::analyzers::aggregate::SampleRow;
+use crate::analyzers::types::{OutageCause, OutageInterval, OutageStats};
+
+/// How long a feed header timestamp may stay unchanged before the feed counts as frozen.
+pub const FROZEN_AFTER: TimeDelta = TimeDelta::minutes(5);
+
+/// Groups samples into outage episodes as they arrive, in time order.
+///
+/// A sample is down when the poll failed, or when it succeeded but the feed
+/// header timestamp has not changed for at least [`FROZEN_AFTER`]. Observer
+/// outage rows should already be left out, as for uptime.
+#[derive(Debug, Default)]
+pub struct OutageTracker {
+    intervals: Vec<OutageInterval>,
+    current: Option<OutageInterval>,
+    // Last header timestamp seen, and the sample time it was first seen at
+    last_header: Option<(u64, DateTime<Utc>)>,
+    first: Option<DateTime<Utc>>,
+    last: Option<DateTime<Utc>>,
+}
+
+impl OutageTracker {
+    /// Adds the next sample.
+    pub fn push<R: SampleRow>(&mut self, row: &R) {
+        let ts = row.timestamp();
+        self.first.get_or_insert(ts);
+        self.last = Some(ts);
+
+        let cause = if row.error_type().is_some_and(|e| !e.is_empty()) {
+            Some(OutageCause::Error)
+        } else {
+            match (row.header_timestamp(), self.last_header) {
+                (Some(header), Some((last, since))) if header == last => {
+                    (ts - since >= FROZEN_AFTER).then_some(OutageCause::Frozen)
+                }
+                (Some(header), _) => {
+                    self.last_header = Some((header, ts));
+                    None
+                }
+                (None, _) => None,
+            }
+        };
+
+        match (cause, self.current.as_mut()) {
+            (Some(cause), Some(episode)) => {
+                episode.end = ts;
+                episode.samples += 1;
+                if episode.cause != cause {
+                    episode.cause = OutageCause::Mixed;
+                }
+            }
+            (Some(cause), None) => {
+                self.current = Some(OutageInterval {
+                    start: ts,
+                    end: ts,
+                    duration_seconds: 0,
+                    samples: 1,
+                    cause,
+                    ongoing: true,
+                });
+            }
+            (None, Some(_)) => {
+                let mut episode = self.current.take().unwrap();
+                episode.end = ts;
+                episode.ongoing = false;
+                self.intervals.push(episode);
+            }
+            (None, None) => {}
+        }
+    }
+
+    /// The episodes found, with metrics over the window from the first to the last sample.
+    pub fn finish(mut self) -> OutageStats {
+        self.intervals.extend(self.current);
+        for episode in &mut self.intervals {
+            episode.duration_seconds = (episode.end - episode.start).num_seconds();
+        }
+
+        let window = match (self.first, self.last) {
+            (Some(first), Some(last)) => (last - first).num_seconds(),
+            _ => 0,
+        };
+        summarize(self.intervals, window)
+    }
+}
+
+/// Reliability metrics for `intervals` within a window of `window_seconds`.
+pub fn summarize(intervals: Vec<OutageInterval>, window_seconds: i64) -> OutageStats {
+    let incidents = intervals.len();
+    let total_outage_seconds: i64 = intervals.iter().map(|i| i.duration_seconds).sum();
+    let per_incident = |seconds: i64| (incidents > 0).then(|| seconds as f64 / incidents as f64);
+
+    OutageStats {
+        incidents,
+        total_outage_seconds,
+        longest_outage_seconds: intervals
+            .iter()
+            .map(|i| i.duration_seconds)
+            .max()
+            .unwrap_or(0),
+        mtbf_seconds: per_incident((window_seconds - total_outage_seconds).max(0)),
+        mttr_seconds: per_incident(total_outage_seconds),
+        intervals,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::FeedStats;
+    use chrono::NaiveDate;
+
+    /// A sample `minute` minutes into the day, failed or with the given header timestamp.
+    fn row(minute: i64, header: Option<u64>) -> FeedStats {
+        let start = NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_utc();
+        FeedStats {
+            timestamp: start + TimeDelta::minutes(minute),
+            header_timestamp: header,
+            error_type: header.is_none().then(|| "fetch_error".to_string()),
+            vehicles: 1,
+            ..Default::default()
+        }
+    }
+
+    fn track(rows: &[FeedStats]) -> OutageStats {
+        let mut tracker = OutageTracker::default();
+        for row in rows {
+            tracker.push(row);
+        }
+        tracker.finish()
+    }
+
+    #[test]
+    fn test_no_outages() {
+        let rows: Vec<FeedStats> = (0..5).map(|m| row(m, Some(m as u64))).collect();
+        let stats = track(&rows);
+        assert_eq!(stats.incidents, 0);
+        assert_eq!(stats.mtbf_seconds, None);
+        assert_eq!(stats.mttr_seconds, None);
+    }
+
+    #[test]
+    fn test_error_episode_until_recovery() {
+        let rows = vec![
+            row(0, Some(0)),
+            row(1, None),
+            row(2, None),
+            row(4, Some(4)),
+            row(10, Some(10)),
+        ];
+        let stats = track(&rows);
+        assert_eq!(stats.incidents, 1);
+        let episode = &stats.intervals[0];
+        assert_eq!(episode.cause, OutageCause::Error);
+        assert_eq!(episode.samples, 2);
+        assert_eq!(episode.duration_seconds, 180);
+        assert!(!episode.ongoing);
+        assert_eq!(stats.longest_outage_seconds, 180);
+        assert_eq!(stats.mttr_seconds, Some(180.0));
+        // 10 minute window, 3 of them down
+        assert_eq!(stats.mtbf_seconds, Some(420.0));
+    }
+
+    #[test]
+    fn test_frozen_header_and_ongoing_outage() {
+        let mut rows: Vec<FeedStats> = (0..8).map(|m| row(m, Some(100))).collect();
+        rows.push(row(8, None));
+        let stats = track(&rows);
+
+        // Frozen from minute 5 on, then failing until the window ends
+        assert_eq!(stats.incidents, 1);
+        let episode = &stats.intervals[0];
+        assert_eq!(episode.cause, OutageCause::Mixed);
+        assert_eq!(episode.samples, 4);
+        assert_eq!(episode.duration_seconds, 180);
+        assert!(episode.ongoing);
+    }
+
+    #[test]
+    fn test_scattered_blips_are_separate_incidents() {
+        let rows: Vec<FeedStats> = (0..10)
+            .map(|m| row(m, (m % 2 == 0).then_some(m as u64)))
+            .collect();
+        let stats = track(&rows);
+        assert_eq!(stats.incidents, 5);
+        assert_eq!(stats.longest_outage_seconds, 60);
+        assert!(stats.intervals.last().unwrap().ongoing);
+    }
+}
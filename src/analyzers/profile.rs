@@ -0,0 +1,326 @@
+//! Grading profiles: configurable weights, grade thresholds and data requirements.
+//!
+//! Field weights are declared in each feed type's
+//! [`FieldRegistry`](crate::stats::fields::FieldRegistry) and grades use the
+//! default [`GradeThresholds`]. A [`GradingProfile`] overrides them, so
+//! partners can put the emphasis elsewhere. Profiles are read from
+//! `grading.toml` or `grading.json` in the output directory, alongside
+//! `agencies.json`, and assigned to single feeds or to every feed of an
+//! agency. Each aggregate records the [`ProfileRef`] it was graded with.
+//! The same file sets the transit [`Mode`] of feeds and agencies.
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::LazyLock;
+
+use crate::analyzers::grade::{GradeThresholds, INSUFFICIENT_DATA};
+use crate::analyzers::mode::Mode;
+use crate::analyzers::types::{Evidence, GradeStatus};
+use crate::object_store::ObjectStore;
+use crate::stats::fields::FieldRegistry;
+use crate::stats::{AlertFields, TripUpdateFields, VehiclePositionFields};
+
+/// Files grading profiles are read from, relative to the output directory, in order of preference.
+pub const PROFILE_FILES: [&str; 2] = ["grading.toml", "grading.json"];
+
+/// Name of the built-in profile, used unless a feed is assigned another.
+pub const DEFAULT_PROFILE: &str = "default";
+
+static BUILT_IN: LazyLock<GradingProfile> = LazyLock::new(GradingProfile::default);
+
+/// How feeds are weighted and graded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GradingProfile {
+    pub name: String,
+    /// Bump when the profile changes, so aggregates tell which revision graded them.
+    pub version: u32,
+    /// Weights by field name, `uptime` or `service_time`. Anything not listed
+    /// keeps its built-in weight.
+    pub weights: BTreeMap<String, f64>,
+    pub thresholds: GradeThresholds,
+    /// Fields left out of the score, e.g. occupancy for fleets without sensors.
+    /// They are still reported.
+    pub not_applicable: Vec<String>,
+    /// Samples with entities a field needs before it counts toward the score.
+    pub min_samples: usize,
+    /// Samples a feed needs before it gets a letter grade.
+    pub min_grade_samples: usize,
+    /// Service hours a feed needs before it gets a letter grade.
+    pub min_service_hours: f64,
+}
+
+impl Default for GradingProfile {
+    fn default() -> Self {
+        Self {
+            name: DEFAULT_PROFILE.to_string(),
+            version: 1,
+            weights: BTreeMap::new(),
+            thresholds: GradeThresholds::default(),
+            not_applicable: Vec::new(),
+            min_samples: 1,
+            min_grade_samples: 10,
+            min_service_hours: 1.0,
+        }
+    }
+}
+
+impl GradingProfile {
+    /// Weight of the score component `name` given its built-in weight, or
+    /// `None` when the profile marks it not applicable.
+    pub fn weight(&self, name: &str, built_in: f64) -> Option<f64> {
+        if self.not_applicable.iter().any(|n| n == name) {
+            return None;
+        }
+        Some(*self.weights.get(name).unwrap_or(&built_in))
+    }
+
+    /// What an aggregate graded with this profile records.
+    pub fn reference(&self) -> ProfileRef {
+        ProfileRef {
+            name: self.name.clone(),
+            version: self.version,
+            thresholds: self.thresholds,
+            min_grade_samples: self.min_grade_samples,
+            min_service_hours: self.min_service_hours,
+        }
+    }
+}
+
+/// The profile an aggregate was graded with. Its thresholds and evidence
+/// requirements are kept so combined aggregates are graded the same way.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProfileRef {
+    pub name: String,
+    pub version: u32,
+    pub thresholds: GradeThresholds,
+    pub min_grade_samples: usize,
+    pub min_service_hours: f64,
+}
+
+impl ProfileRef {
+    /// Whether `evidence` is enough for a letter grade.
+    pub fn status(&self, evidence: &Evidence) -> GradeStatus {
+        if evidence.samples >= self.min_grade_samples
+            && evidence.service_hours >= self.min_service_hours
+        {
+            GradeStatus::Graded
+        } else {
+            GradeStatus::InsufficientData
+        }
+    }
+
+    /// The letter grade of `score`, unless `status` withholds it.
+    pub fn grade(&self, score: f64, status: GradeStatus) -> String {
+        match status {
+            GradeStatus::Graded => self.thresholds.grade(score),
+            GradeStatus::InsufficientData => INSUFFICIENT_DATA.to_string(),
+        }
+    }
+}
+
+impl Default for ProfileRef {
+    fn default() -> Self {
+        BUILT_IN.reference()
+    }
+}
+
+/// The profiles file: the profiles, and which feeds use them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GradingProfiles {
+    /// Profile for feeds without an assignment; the built-in one when unset.
+    pub default: Option<String>,
+    pub profiles: Vec<GradingProfile>,
+    /// Profile name by feed id.
+    pub feeds: BTreeMap<String, String>,
+    /// Profile name by agency id, for all of the agency's feeds.
+    pub agencies: BTreeMap<String, String>,
+    /// Transit mode by feed or agency id.
+    pub modes: BTreeMap<String, Mode>,
+}
+
+impl GradingProfiles {
+    /// Parses a profiles file, as TOML when `file_name` ends in `.toml` and
+    /// as JSON otherwise, and checks every assigned profile exists and every
+    /// weighted or not-applicable name is a score component.
+    pub fn parse(file_name: &str, bytes: &[u8]) -> Result<Self> {
+        let profiles: Self = if file_name.ends_with(".toml") {
+            toml::from_str(std::str::from_utf8(bytes)?)?
+        } else {
+            serde_json::from_slice(bytes)?
+        };
+
+        for (i, profile) in profiles.profiles.iter().enumerate() {
+            if profiles.profiles[..i]
+                .iter()
+                .any(|p| p.name == profile.name)
+            {
+                bail!("grading profile '{}' is defined twice", profile.name);
+            }
+            let names = profile.weights.keys().chain(&profile.not_applicable);
+            if let Some(name) = names.into_iter().find(|n| !is_score_component(n)) {
+                bail!(
+                    "grading profile '{}' refers to unknown field '{}'",
+                    profile.name,
+                    name
+                );
+            }
+        }
+        let assigned = profiles
+            .default
+            .iter()
+            .chain(profiles.feeds.values())
+            .chain(profiles.agencies.values());
+        for name in assigned {
+            if profiles.profile(name).is_none() {
+                bail!("unknown grading profile '{}'", name);
+            }
+        }
+        Ok(profiles)
+    }
+
+    /// Reads the first of [`PROFILE_FILES`] in a store over the output
+    /// directory, or only the built-in profile when there is none.
+    pub async fn fetch(store: &dyn ObjectStore) -> Result<Self> {
+        for file in PROFILE_FILES {
+            if let Some(bytes) = store.get(file).await? {
+                return Self::parse(file, &bytes).with_context(|| format!("reading {}", file));
+            }
+        }
+        Ok(Self::default())
+    }
+
+    /// The profile called `name`. A file may redefine the built-in `default` profile.
+    pub fn profile(&self, name: &str) -> Option<&GradingProfile> {
+        self.profiles
+            .iter()
+            .find(|p| p.name == name)
+            .or_else(|| (name == DEFAULT_PROFILE).then_some(&*BUILT_IN))
+    }
+
+    /// Profile for a feed: its own assignment, then its agency's, then the default.
+    pub fn for_feed(&self, feed_id: &str, agency_id: Option<&str>) -> &GradingProfile {
+        let assigned = self
+            .feeds
+            .get(feed_id)
+            .or_else(|| agency_id.and_then(|id| self.agencies.get(id)));
+        self.resolve(assigned)
+    }
+
+    /// Mode of a feed: its own setting, then its agency's.
+    pub fn mode_of(&self, feed_id: &str, agency_id: Option<&str>) -> Option<Mode> {
+        self.modes
+            .get(feed_id)
+            .or_else(|| agency_id.and_then(|id| self.modes.get(id)))
+            .copied()
+    }
+
+    /// Profile for an agency's rollup.
+    pub fn for_agency(&self, agency_id: &str) -> &GradingProfile {
+        self.resolve(self.agencies.get(agency_id))
+    }
+
+    fn resolve(&self, assigned: Option<&String>) -> &GradingProfile {
+        assigned
+            .or(self.default.as_ref())
+            .and_then(|name| self.profile(name))
+            .unwrap_or(&BUILT_IN)
+    }
+}
+
+/// Whether `name` is a field of any feed type's registry, `uptime` or `service_time`.
+fn is_score_component(name: &str) -> bool {
+    fn has<F: FieldRegistry>(name: &str) -> bool {
+        F::metrics().iter().any(|m| m.name == name)
+    }
+
+    matches!(name, "uptime" | "service_time")
+        || has::<VehiclePositionFields>(name)
+        || has::<TripUpdateFields>(name)
+        || has::<AlertFields>(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOML: &str = r#"
+default = "strict"
+
+[[profiles]]
+name = "strict"
+version = 2
+min_samples = 10
+thresholds = { a_plus = 0.99 }
+
+[[profiles]]
+name = "regional"
+not_applicable = ["occupancy"]
+
+[profiles.weights]
+uptime = 5.0
+
+[agencies]
+mdb-1 = "regional"
+
+[feeds]
+vp-2 = "default"
+
+[modes]
+mdb-1 = "rail"
+vp-2 = 3
+"#;
+
+    #[test]
+    fn test_parse_toml_and_assignments() {
+        let profiles = GradingProfiles::parse("grading.toml", TOML.as_bytes()).unwrap();
+
+        let strict = profiles.for_feed("vp-9", None);
+        assert_eq!((strict.name.as_str(), strict.version), ("strict", 2));
+        assert_eq!(strict.min_samples, 10);
+        assert_eq!(strict.thresholds.a_plus, 0.99);
+        assert_eq!(strict.thresholds.a, 0.90);
+
+        let regional = profiles.for_feed("vp-1", Some("mdb-1"));
+        assert_eq!(regional.name, "regional");
+        assert_eq!(regional.weight("uptime", 3.0), Some(5.0));
+        assert_eq!(regional.weight("route_id", 3.0), Some(3.0));
+        assert_eq!(regional.weight("occupancy", 1.0), None);
+        assert_eq!(profiles.for_agency("mdb-1").name, "regional");
+
+        // A feed's own assignment beats its agency's
+        assert_eq!(profiles.for_feed("vp-2", Some("mdb-1")).name, "default");
+
+        assert_eq!(profiles.mode_of("vp-1", Some("mdb-1")), Some(Mode::Rail));
+        assert_eq!(profiles.mode_of("vp-2", Some("mdb-1")), Some(Mode::Bus));
+        assert_eq!(profiles.mode_of("vp-9", None), None);
+    }
+
+    #[test]
+    fn test_parse_json_and_unknown_profile() {
+        let json = br#"{"profiles": [{"name": "a"}], "feeds": {"vp-1": "a"}}"#;
+        let profiles = GradingProfiles::parse("grading.json", json).unwrap();
+        assert_eq!(profiles.for_feed("vp-1", None).name, "a");
+        assert_eq!(profiles.for_feed("vp-2", None).name, DEFAULT_PROFILE);
+
+        let json = br#"{"feeds": {"vp-1": "missing"}}"#;
+        assert!(GradingProfiles::parse("grading.json", json).is_err());
+    }
+
+    #[test]
+    fn test_unknown_field_names_rejected() {
+        let json = br#"{"profiles": [{"name": "a", "weights": {"bearing": 2.0, "beraing": 1.0}}]}"#;
+        let err = GradingProfiles::parse("grading.json", json).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "grading profile 'a' refers to unknown field 'beraing'"
+        );
+
+        let json = br#"{"profiles": [{"name": "b", "not_applicable": ["occupancy_status"]}]}"#;
+        let err = GradingProfiles::parse("grading.json", json).unwrap_err();
+        assert!(err.to_string().contains("'occupancy_status'"));
+    }
+}
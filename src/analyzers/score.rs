@@ -0,0 +1,161 @@
+//! Explainable overall scores.
+//!
+//! A feed's overall score is the weighted mean of its graded fields' support,
+//! uptime and service time. [`ScoreBreakdown`] keeps each component's share of
+//! that mean, so a grade can be explained as points earned and lost, and
+//! lists the components whose improvement would raise the score the most.
+
+use serde::{Deserialize, Serialize};
+
+use crate::analyzers::grade::GradeThresholds;
+
+/// Number of improvements listed in a [`ScoreBreakdown`].
+pub const TOP_IMPROVEMENTS: usize = 3;
+
+/// One weighted input of the overall score.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScoreComponent {
+    /// Field name, or `uptime` / `service_time`.
+    pub name: String,
+    /// Support or fraction of samples, 0.0–1.0.
+    pub value: f64,
+    pub weight: f64,
+    /// `value × weight` as a share of the total weight: what the component adds to the score.
+    pub points: f64,
+    /// Points lost against a perfect value of 1.0.
+    pub points_lost: f64,
+}
+
+/// Raising one component to 1.0, and what that would do to the score.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Improvement {
+    pub name: String,
+    pub current_value: f64,
+    pub score_gain: f64,
+    pub score_after: f64,
+    pub grade_after: String,
+}
+
+/// Per-component contributions to a feed's overall score.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScoreBreakdown {
+    /// Components in descending order of points lost.
+    pub components: Vec<ScoreComponent>,
+    /// The [`TOP_IMPROVEMENTS`] components losing the most points.
+    pub improvements: Vec<Improvement>,
+}
+
+impl ScoreBreakdown {
+    /// Breaks down the weighted mean of `(name, value, weight)` components,
+    /// grading improvements with `thresholds`.
+    pub fn new(inputs: Vec<(String, f64, f64)>, thresholds: &GradeThresholds) -> Self {
+        let weight_sum: f64 = inputs.iter().map(|(_, _, weight)| weight).sum();
+        let share = |weight: f64| {
+            if weight_sum == 0.0 {
+                0.0
+            } else {
+                weight / weight_sum
+            }
+        };
+
+        let mut components: Vec<ScoreComponent> = inputs
+            .into_iter()
+            .map(|(name, value, weight)| ScoreComponent {
+                points: value * share(weight),
+                points_lost: (1.0 - value).max(0.0) * share(weight),
+                name,
+                value,
+                weight,
+            })
+            .collect();
+        components.sort_by(|a, b| {
+            b.points_lost
+                .total_cmp(&a.points_lost)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+
+        let score: f64 = components.iter().map(|c| c.points).sum();
+        let improvements = components
+            .iter()
+            .filter(|c| c.points_lost > 0.0)
+            .take(TOP_IMPROVEMENTS)
+            .map(|c| Improvement {
+                name: c.name.clone(),
+                current_value: c.value,
+                score_gain: c.points_lost,
+                score_after: score + c.points_lost,
+                grade_after: thresholds.grade(score + c.points_lost),
+            })
+            .collect();
+
+        Self {
+            components,
+            improvements,
+        }
+    }
+
+    /// The overall score: the sum of every component's points.
+    pub fn score(&self) -> f64 {
+        self.components.iter().map(|c| c.points).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(name: &str, value: f64, weight: f64) -> (String, f64, f64) {
+        (name.to_string(), value, weight)
+    }
+
+    #[test]
+    fn test_points_sum_to_weighted_mean() {
+        let breakdown = ScoreBreakdown::new(
+            vec![
+                input("uptime", 1.0, 3.0),
+                input("route_id", 0.5, 3.0),
+                input("bearing", 0.0, 2.0),
+                input("vehicle_id", 0.0, 0.0),
+            ],
+            &GradeThresholds::default(),
+        );
+        assert!((breakdown.score() - 4.5 / 8.0).abs() < 1e-10);
+
+        let lost: Vec<&str> = breakdown
+            .components
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect();
+        assert_eq!(lost, vec!["bearing", "route_id", "uptime", "vehicle_id"]);
+        assert!((breakdown.components[0].points_lost - 0.25).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_improvements_skip_perfect_and_unweighted_components() {
+        let breakdown = ScoreBreakdown::new(
+            vec![
+                input("uptime", 1.0, 3.0),
+                input("route_id", 0.5, 3.0),
+                input("bearing", 0.0, 2.0),
+                input("vehicle_id", 0.0, 0.0),
+            ],
+            &GradeThresholds::default(),
+        );
+        let names: Vec<&str> = breakdown
+            .improvements
+            .iter()
+            .map(|i| i.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["bearing", "route_id"]);
+        let best = &breakdown.improvements[0];
+        assert!((best.score_after - 6.5 / 8.0).abs() < 1e-10);
+        assert_eq!(best.grade_after, "B");
+    }
+
+    #[test]
+    fn test_empty_breakdown() {
+        let breakdown = ScoreBreakdown::new(Vec::new(), &GradeThresholds::default());
+        assert_eq!(breakdown.score(), 0.0);
+        assert!(breakdown.improvements.is_empty());
+    }
+}
@@ -1,85 +1,319 @@
 //! Data types used by the aggregation pipeline.
 
+use crate::analyzers::mode::Mode;
+use crate::analyzers::profile::ProfileRef;
+use crate::analyzers::score::ScoreBreakdown;
+use crate::feed_type::FeedType;
+use crate::stats::fields::Category;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-/// A single row deserialized from a per-feed CSV file.
+/// A single row deserialized from an agency's cross-feed consistency CSV.
 #[derive(Debug, Deserialize)]
-pub struct FeedStats {
+pub struct ConsistencyRow {
     pub(crate) timestamp: DateTime<Utc>,
-    pub(crate) vehicles: usize,
-    pub(crate) error_type: Option<String>,
-
-    pub(crate) with_trip_id: usize,
-    pub(crate) with_route_id: usize,
-    pub(crate) with_direction_id: usize,
-
-    pub(crate) with_vehicle_id: usize,
-    pub(crate) with_vehicle_label: usize,
-    pub(crate) with_license_plate: usize,
-    pub(crate) with_wheelchair_accessible: usize,
-
-    pub(crate) with_bearing: usize,
-    pub(crate) with_speed: usize,
-    pub(crate) with_odometer: usize,
-    pub(crate) with_current_stop_sequence: usize,
-    pub(crate) with_stop_id: usize,
-    pub(crate) with_current_status: usize,
-    pub(crate) with_timestamp: usize,
-    pub(crate) with_congestion_level: usize,
-    pub(crate) with_occupancy: usize,
-    pub(crate) with_occupancy_percentage: usize,
-    pub(crate) with_multi_carriage_details: usize,
+    pub(crate) vp_trip_ids: usize,
+    pub(crate) matched_trip_ids: usize,
+    pub(crate) vp_vehicle_ids: usize,
+    pub(crate) matched_vehicle_ids: usize,
 }
+
+/// Count, range and percentiles of a per-sample series.
+///
+/// Percentiles come from a streaming estimator (see
+/// [`Summary`](crate::analyzers::utility::Summary)) and are approximate for
+/// long series. All zero when the series is empty.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Distribution {
+    pub(crate) count: usize,
+    pub(crate) min: f64,
+    pub(crate) max: f64,
+    pub(crate) p5: f64,
+    pub(crate) p25: f64,
+    pub(crate) median: f64,
+    pub(crate) p75: f64,
+    pub(crate) p95: f64,
+}
+
+/// 95% confidence interval of a proportion.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ConfidenceInterval {
+    pub(crate) lower: f64,
+    pub(crate) upper: f64,
+}
+
+/// Whether there was enough data to issue a letter grade.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GradeStatus {
+    #[default]
+    Graded,
+    /// Too few samples or service hours; the grade is withheld.
+    InsufficientData,
+}
+
+/// How much data a feed's grade rests on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Evidence {
+    /// Samples aggregated, excluding rater-side outages.
+    pub(crate) samples: usize,
+    /// Hours of the window the feed was in service, or the whole window when
+    /// service time isn't scored (e.g. alerts).
+    pub(crate) service_hours: f64,
+}
+
 /// Aggregated statistics for a single optional vehicle field.
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct FieldAggregate {
     pub(crate) avg_support: f64,
     pub(crate) stddev: f64,
+    /// Distribution of per-sample support.
+    #[serde(default)]
+    pub(crate) distribution: Distribution,
+    /// Range of `avg_support`, counting each sample as one observation.
+    #[serde(default)]
+    pub(crate) confidence: ConfidenceInterval,
+    /// Letter grade, or `N/A` when the field doesn't apply to the feed.
     pub(crate) grade: String,
 }
 
 /// High-level entity statistics: average vehicle count, uptime, and service time.
-#[derive(Serialize)]
+///
+/// For TripUpdates and ServiceAlerts feeds `avg_vehicles` holds the average
+/// number of trip updates or alerts per sample.
+#[derive(Serialize, Deserialize)]
 pub struct EntityStats {
     pub(crate) avg_vehicles: f64,
+    /// Distribution of the entity count over samples with any entities.
+    #[serde(default)]
+    pub(crate) vehicle_distribution: Distribution,
+    pub(crate) uptime_percent: f64,
+    /// Range of `uptime_percent` given the number of samples.
+    #[serde(default)]
+    pub(crate) uptime_confidence: ConfidenceInterval,
+    pub(crate) service_time_percent: f64,
+}
+
+/// Whether a feed serves the entity types the catalog declares for it.
+///
+/// Only samples with both declared and observed entity types are counted.
+#[derive(Serialize, Deserialize)]
+pub struct CatalogAccuracy {
+    pub(crate) declared_entity_types: String,
+    pub(crate) observed_entity_types: Vec<FeedType>,
+    pub(crate) samples: usize,
+    pub(crate) mismatched_samples: usize,
+    pub(crate) mismatch_percent: f64,
+}
+
+/// A feed whose declared catalog entity types disagree with what it serves,
+/// as listed by the catalog report.
+#[derive(Debug, Serialize)]
+pub struct CatalogIssue {
+    pub feed_id: String,
+    pub declared_entity_types: String,
+    pub observed_entity_types: Vec<FeedType>,
+    pub samples: usize,
+    pub mismatch_percent: f64,
+}
+
+/// How much of the aggregation window the rater itself was able to observe.
+///
+/// Samples lost to an observer outage (the rater's network dropping, or the
+/// sampler not running) are excluded from uptime and reported here instead.
+#[derive(Serialize, Deserialize)]
+pub struct RaterCoverage {
+    pub(crate) observer_outage_samples: usize,
+    pub(crate) coverage_percent: f64,
+}
+
+/// Feed quality within one hour of the day or one day of the week.
+///
+/// Field support is the mean share of entities populating each graded field,
+/// over samples in the period that had any entities.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeriodStats {
+    /// `"00"`–`"23"` for hours, `"Mon"`–`"Sun"` for weekdays.
+    pub(crate) period: String,
+    pub(crate) samples: usize,
     pub(crate) uptime_percent: f64,
     pub(crate) service_time_percent: f64,
+    pub(crate) avg_vehicles: f64,
+    pub(crate) fields: BTreeMap<String, f64>,
+}
+
+/// Hour-of-day and day-of-week quality breakdowns, in the agency's local time.
+///
+/// Periods without samples are left out.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Breakdowns {
+    /// IANA name of the timezone periods are computed in.
+    pub(crate) timezone: String,
+    pub(crate) hourly: Vec<PeriodStats>,
+    pub(crate) weekday: Vec<PeriodStats>,
+}
+
+/// Why a feed was down during an [`OutageInterval`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutageCause {
+    /// Polls failed.
+    Error,
+    /// Polls succeeded but the feed header timestamp stopped advancing.
+    Frozen,
+    /// Both, one after the other.
+    Mixed,
+}
+
+/// One outage episode: a run of consecutive failed or frozen samples.
+///
+/// `end` is the first sample after the run that was up again, or the last
+/// sample of the window when the feed had not recovered (`ongoing`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutageInterval {
+    pub(crate) start: DateTime<Utc>,
+    pub(crate) end: DateTime<Utc>,
+    pub(crate) duration_seconds: i64,
+    pub(crate) samples: usize,
+    pub(crate) cause: OutageCause,
+    pub(crate) ongoing: bool,
+}
+
+/// Outage episodes within the aggregation window, with reliability metrics.
+///
+/// Mean time between failures is the time the feed was up divided by the
+/// number of incidents; mean time to recovery is the mean episode duration.
+/// Both are `None` without incidents.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OutageStats {
+    pub(crate) incidents: usize,
+    pub(crate) total_outage_seconds: i64,
+    pub(crate) longest_outage_seconds: i64,
+    pub(crate) mtbf_seconds: Option<f64>,
+    pub(crate) mttr_seconds: Option<f64>,
+    pub(crate) intervals: Vec<OutageInterval>,
 }
 
 /// Overall weighted score and letter grade for a feed.
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct OverallAggregate {
     pub(crate) score: f64,
+    /// Letter grade, or `insufficient data` when `status` withholds it.
     pub(crate) grade: String,
+    #[serde(default)]
+    pub(crate) status: GradeStatus,
 }
 
 /// Complete aggregation result for a single feed, uploaded as JSON to S3.
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct FeedAggregate {
     pub(crate) schema_version: u8,
     pub(crate) algorithm_version: u8,
     pub(crate) feed_id: String,
+    pub(crate) feed_type: FeedType,
     pub(crate) last_updated: DateTime<Utc>,
     pub(crate) window_minutes: i64,
     pub(crate) entity_stats: EntityStats,
     pub(crate) fields: HashMap<String, FieldAggregate>,
+    /// Mean `avg_support` of the applicable fields in each registry category.
+    #[serde(default)]
+    pub(crate) categories: BTreeMap<Category, f64>,
+    pub(crate) catalog_accuracy: Option<CatalogAccuracy>,
+    pub(crate) rater_coverage: RaterCoverage,
+    #[serde(default)]
+    pub(crate) breakdowns: Breakdowns,
+    #[serde(default)]
+    pub(crate) outages: OutageStats,
+    /// How each component contributed to `overall`.
+    #[serde(default)]
+    pub(crate) score_breakdown: ScoreBreakdown,
+    /// Grading profile the feed was scored with.
+    #[serde(default)]
+    pub(crate) profile: ProfileRef,
+    /// Transit mode set for the feed, taken for entities on routes of no known mode.
+    #[serde(default)]
+    pub(crate) mode: Option<Mode>,
+    /// Share of entities of each transit mode, from their route or `mode`.
+    #[serde(default)]
+    pub(crate) modes: BTreeMap<Mode, f64>,
+    #[serde(default)]
+    pub(crate) evidence: Evidence,
     pub(crate) overall: OverallAggregate,
 }
 
 /// Summary entry for the feed index listing.
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct FeedIndexEntry {
     pub(crate) feed_id: String,
     pub(crate) overall_grade: String,
     pub(crate) overall_score: f64,
+    #[serde(default)]
+    pub(crate) status: GradeStatus,
     pub(crate) uptime_percent: f64,
 }
 
-/// Top-level index of all aggregated feeds, served as `aggregates/feeds.json`.
-#[derive(Serialize)]
+/// Top-level index of all aggregated feeds of one type, served as
+/// `aggregates/feeds.json` (vehicle positions) or
+/// `aggregates/<feed type>/feeds.json`.
+///
+/// Agency rollups are listed in the vehicle positions index only.
+#[derive(Serialize, Deserialize)]
 pub struct FeedIndex {
     pub(crate) generated_at: DateTime<Utc>,
+    pub(crate) feed_type: FeedType,
     pub(crate) feeds: Vec<FeedIndexEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) agencies: Vec<AgencyIndexEntry>,
+}
+
+/// Score of one member feed within an [`AgencyAggregate`].
+#[derive(Serialize)]
+pub struct AgencyFeedScore {
+    pub(crate) feed_id: String,
+    pub(crate) feed_type: FeedType,
+    pub(crate) score: f64,
+    pub(crate) grade: String,
+}
+
+/// Cross-feed consistency between an agency's VehiclePositions and TripUpdates.
+///
+/// Match rates are the mean fraction of VehiclePositions ids also present in
+/// TripUpdates during the same sampling round.
+#[derive(Serialize)]
+pub struct ConsistencyAggregate {
+    pub(crate) samples: usize,
+    pub(crate) trip_id_match: f64,
+    pub(crate) vehicle_id_match: f64,
+    pub(crate) score: f64,
+    pub(crate) grade: String,
+}
+
+/// Combined grade for all realtime feeds belonging to one agency, uploaded as
+/// `aggregates/agencies/{agency_id}.json`.
+#[derive(Serialize)]
+pub struct AgencyAggregate {
+    pub(crate) schema_version: u8,
+    pub(crate) algorithm_version: u8,
+    pub(crate) agency_id: String,
+    pub(crate) name: String,
+    pub(crate) last_updated: DateTime<Utc>,
+    pub(crate) feeds: Vec<AgencyFeedScore>,
+    pub(crate) feed_types: BTreeMap<FeedType, OverallAggregate>,
+    pub(crate) missing_feed_types: Vec<FeedType>,
+    pub(crate) consistency: Option<ConsistencyAggregate>,
+    pub(crate) profile: ProfileRef,
+    pub(crate) overall: OverallAggregate,
+}
+
+/// Summary entry for an agency in the feed index listing.
+#[derive(Serialize, Deserialize)]
+pub struct AgencyIndexEntry {
+    pub(crate) agency_id: String,
+    pub(crate) name: String,
+    pub(crate) overall_grade: String,
+    pub(crate) overall_score: f64,
+    #[serde(default)]
+    pub(crate) status: GradeStatus,
+    pub(crate) feed_count: usize,
 }
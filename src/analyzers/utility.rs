@@ -1,3 +1,5 @@
+use crate::analyzers::types::{ConfidenceInterval, Distribution};
+
 /// Computes the arithmetic mean of a slice of values. Returns 0.0 for empty input.
 pub fn mean(values: &[f64]) -> f64 {
     if values.is_empty() {
@@ -17,10 +19,230 @@ pub fn stddev(values: &[f64], mean: f64) -> f64 {
     variance.sqrt()
 }
 
+/// z-score of the 95% confidence level.
+const Z_95: f64 = 1.96;
+
+/// 95% Wilson score interval of a proportion `p` observed over `n` trials.
+///
+/// Unlike the normal approximation it stays within 0.0–1.0 and is wide for
+/// small `n` even when `p` is 0 or 1. Without trials it spans 0.0–1.0.
+pub fn wilson_interval(p: f64, n: f64) -> ConfidenceInterval {
+    if n <= 0.0 {
+        return ConfidenceInterval {
+            lower: 0.0,
+            upper: 1.0,
+        };
+    }
+    let z2 = Z_95 * Z_95;
+    let denominator = 1.0 + z2 / n;
+    let center = (p + z2 / (2.0 * n)) / denominator;
+    let margin = Z_95 * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt() / denominator;
+    ConfidenceInterval {
+        lower: (center - margin).max(0.0),
+        upper: (center + margin).min(1.0),
+    }
+}
+
+/// Percentiles tracked by a [`Summary`], as fractions.
+pub const PERCENTILES: [f64; 5] = [0.05, 0.25, 0.5, 0.75, 0.95];
+
+/// Streaming summary of a series: count, mean, population standard deviation,
+/// min/max and the [`PERCENTILES`], in constant memory.
+///
+/// Percentiles use the P² estimator (Jain & Chlamtac, 1985), which keeps five
+/// markers per percentile instead of the whole series. They are exact for up
+/// to five values and approximate beyond.
+#[derive(Debug, Clone)]
+pub struct Summary {
+    count: usize,
+    mean: f64,
+    m2: f64,
+    min: f64,
+    max: f64,
+    quantiles: [P2Quantile; 5],
+}
+
+impl Default for Summary {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            mean: 0.0,
+            m2: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            quantiles: PERCENTILES.map(P2Quantile::new),
+        }
+    }
+}
+
+impl Summary {
+    /// Adds a value to the series.
+    pub fn push(&mut self, value: f64) {
+        // Welford's online mean and variance
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        for q in &mut self.quantiles {
+            q.push(value);
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Arithmetic mean, or 0.0 for an empty series.
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// Population standard deviation, or 0.0 for an empty series.
+    pub fn stddev(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        (self.m2 / self.count as f64).sqrt()
+    }
+
+    /// Count, min/max and percentiles of the series; all zero when empty.
+    pub fn distribution(&self) -> Distribution {
+        if self.count == 0 {
+            return Distribution::default();
+        }
+        let [p5, p25, median, p75, p95] = self.quantiles.each_ref().map(P2Quantile::estimate);
+        Distribution {
+            count: self.count,
+            min: self.min,
+            max: self.max,
+            p5,
+            p25,
+            median,
+            p75,
+            p95,
+        }
+    }
+}
+
+/// P² estimate of a single quantile `p`.
+#[derive(Debug, Clone)]
+struct P2Quantile {
+    p: f64,
+    /// The first five values, until the markers are initialised.
+    initial: Vec<f64>,
+    /// Marker heights.
+    heights: [f64; 5],
+    /// Actual marker positions (1-based).
+    positions: [f64; 5],
+    /// Desired marker positions.
+    desired: [f64; 5],
+    /// Increments of the desired positions per value.
+    increments: [f64; 5],
+}
+
+impl P2Quantile {
+    fn new(p: f64) -> Self {
+        Self {
+            p,
+            initial: Vec::with_capacity(5),
+            heights: [0.0; 5],
+            positions: [1.0, 2.0, 3.0, 4.0, 5.0],
+            desired: [1.0, 1.0 + 2.0 * p, 1.0 + 4.0 * p, 3.0 + 2.0 * p, 5.0],
+            increments: [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0],
+        }
+    }
+
+    fn push(&mut self, value: f64) {
+        if self.initial.len() < 5 {
+            self.initial.push(value);
+            if self.initial.len() == 5 {
+                self.initial.sort_by(f64::total_cmp);
+                self.heights.copy_from_slice(&self.initial);
+            }
+            return;
+        }
+
+        let h = &mut self.heights;
+        // Cell containing the value, extending the extremes if needed
+        let k = if value < h[0] {
+            h[0] = value;
+            0
+        } else if value >= h[4] {
+            h[4] = value;
+            3
+        } else {
+            (1..5).find(|&i| value < h[i]).unwrap() - 1
+        };
+        for position in &mut self.positions[k + 1..] {
+            *position += 1.0;
+        }
+        for (desired, increment) in self.desired.iter_mut().zip(self.increments) {
+            *desired += increment;
+        }
+
+        // Move the middle markers towards their desired positions
+        for i in 1..4 {
+            let n = self.positions;
+            let d = self.desired[i] - n[i];
+            if (d >= 1.0 && n[i + 1] - n[i] > 1.0) || (d <= -1.0 && n[i - 1] - n[i] < -1.0) {
+                let d = d.signum();
+                let q = self.heights;
+                let parabolic = q[i]
+                    + d / (n[i + 1] - n[i - 1])
+                        * ((n[i] - n[i - 1] + d) * (q[i + 1] - q[i]) / (n[i + 1] - n[i])
+                            + (n[i + 1] - n[i] - d) * (q[i] - q[i - 1]) / (n[i] - n[i - 1]));
+                self.heights[i] = if q[i - 1] < parabolic && parabolic < q[i + 1] {
+                    parabolic
+                } else {
+                    let j = if d > 0.0 { i + 1 } else { i - 1 };
+                    q[i] + d * (q[j] - q[i]) / (n[j] - n[i])
+                };
+                self.positions[i] += d;
+            }
+        }
+    }
+
+    fn estimate(&self) -> f64 {
+        if self.initial.len() < 5 {
+            // Exact, interpolating between the closest ranks
+            let mut values = self.initial.clone();
+            values.sort_by(f64::total_cmp);
+            let Some(last) = values.len().checked_sub(1) else {
+                return 0.0;
+            };
+            let rank = self.p * last as f64;
+            let (lo, hi) = (rank.floor() as usize, rank.ceil() as usize);
+            return values[lo] + (values[hi] - values[lo]) * (rank - lo as f64);
+        }
+        self.heights[2]
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_wilson_interval() {
+        // A single success says little
+        let one = wilson_interval(1.0, 1.0);
+        assert!(one.lower < 0.25 && one.upper == 1.0);
+
+        let many = wilson_interval(0.5, 10_000.0);
+        assert!((many.lower - 0.49).abs() < 0.001);
+        assert!((many.upper - 0.51).abs() < 0.001);
+
+        assert_eq!(
+            wilson_interval(0.0, 0.0),
+            ConfidenceInterval {
+                lower: 0.0,
+                upper: 1.0
+            }
+        );
+    }
+
     #[test]
     fn test_mean_empty() {
         assert_eq!(mean(&[]), 0.0);
@@ -59,6 +281,54 @@ mod tests {
         assert_eq!(stddev(&[42.0], 42.0), 0.0);
     }
 
+    #[test]
+    fn test_summary_matches_mean_and_stddev() {
+        let vals = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+        let mut summary = Summary::default();
+        vals.iter().for_each(|v| summary.push(*v));
+        assert_eq!(summary.count(), 8);
+        assert!((summary.mean() - 5.0).abs() < 1e-10);
+        assert!((summary.stddev() - 2.0).abs() < 1e-10);
+        let dist = summary.distribution();
+        assert_eq!((dist.min, dist.max), (2.0, 9.0));
+    }
+
+    #[test]
+    fn test_summary_small_series_is_exact() {
+        let mut summary = Summary::default();
+        [3.0, 1.0, 2.0].iter().for_each(|v| summary.push(*v));
+        let dist = summary.distribution();
+        assert_eq!(dist.median, 2.0);
+        assert!((dist.p25 - 1.5).abs() < 1e-10);
+        assert_eq!(Summary::default().distribution().count, 0);
+    }
+
+    #[test]
+    fn test_summary_estimates_percentiles() {
+        // 1..=1000 in a scrambled order
+        let mut summary = Summary::default();
+        for i in 0..1000u64 {
+            summary.push(((i * 7919) % 1000 + 1) as f64);
+        }
+        let dist = summary.distribution();
+        assert!((dist.median - 500.0).abs() < 15.0, "{}", dist.median);
+        assert!((dist.p5 - 50.0).abs() < 15.0, "{}", dist.p5);
+        assert!((dist.p95 - 950.0).abs() < 15.0, "{}", dist.p95);
+    }
+
+    #[test]
+    fn test_summary_bimodal_series() {
+        // A field fully supported half the time and missing the other half
+        let mut summary = Summary::default();
+        for i in 0..200 {
+            summary.push(if i % 2 == 0 { 1.0 } else { 0.0 });
+        }
+        let dist = summary.distribution();
+        assert!(dist.p5.abs() < 1e-6, "{}", dist.p5);
+        assert!((dist.p95 - 1.0).abs() < 1e-6, "{}", dist.p95);
+        assert!((summary.mean() - 0.5).abs() < 1e-10);
+    }
+
     #[test]
     fn test_mean_negative() {
         assert!((mean(&[-3.0, 1.0]) - (-1.0)).abs() < 1e-10);
@@ -0,0 +1,211 @@
+//! GTFS-RT feed types as listed in the MobilityData catalog.
+//!
+//! A catalog entry declares which entity types it serves (`vp`, `tu`, `sa`).
+//! [`FeedType`] maps each of those codes to the local directory, S3 prefix,
+//! and analyzer used for that stream.
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// One of the three GTFS Realtime feed types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedType {
+    VehiclePositions,
+    TripUpdates,
+    ServiceAlerts,
+}
+
+impl FeedType {
+    /// All feed types, in the order they are processed.
+    pub const ALL: [FeedType; 3] = [
+        FeedType::VehiclePositions,
+        FeedType::TripUpdates,
+        FeedType::ServiceAlerts,
+    ];
+
+    /// The entity type code used by the MobilityData catalog (`vp`, `tu`, `sa`).
+    pub fn catalog_code(&self) -> &'static str {
+        match self {
+            FeedType::VehiclePositions => "vp",
+            FeedType::TripUpdates => "tu",
+            FeedType::ServiceAlerts => "sa",
+        }
+    }
+
+    /// Sub-directory (and S3 key prefix) that holds this feed type's files.
+    ///
+    /// Vehicle positions live at the root so the existing
+    /// `agency_id=*/date=*.csv` layout is unchanged.
+    pub fn subdir(&self) -> &'static str {
+        match self {
+            FeedType::VehiclePositions => "",
+            FeedType::TripUpdates => "trip_updates",
+            FeedType::ServiceAlerts => "service_alerts",
+        }
+    }
+
+    /// Joins `base` with this feed type's sub-directory.
+    pub fn dir(&self, base: &str) -> String {
+        match self.subdir() {
+            "" => base.to_string(),
+            sub => format!("{}/{}", base, sub),
+        }
+    }
+
+    /// Prefixes an S3 key with this feed type's sub-directory.
+    pub fn key(&self, key: &str) -> String {
+        match self.subdir() {
+            "" => key.to_string(),
+            sub => format!("{}/{}", sub, key),
+        }
+    }
+
+    /// Splits a sample file key `[subdir/]agency_id={feed_id}/{file}` of this
+    /// feed type into its feed id and file name.
+    pub fn parse_key<'a>(&self, key: &'a str) -> Option<(&'a str, &'a str)> {
+        let rest = match self.subdir() {
+            "" => key,
+            sub => key.strip_prefix(sub)?.strip_prefix('/')?,
+        };
+        let (dir, file) = rest.split_once('/')?;
+        let feed_id = dir.strip_prefix("agency_id=")?;
+        (!file.contains('/')).then_some((feed_id, file))
+    }
+
+    /// Formats a list of feed types as a comma-separated catalog query value.
+    pub fn catalog_query(types: &[FeedType]) -> String {
+        types
+            .iter()
+            .map(|t| t.catalog_code())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Parses a comma-separated list of catalog codes, skipping unknown ones.
+    pub fn parse_list(s: &str) -> Vec<FeedType> {
+        s.split(',')
+            .filter(|t| !t.trim().is_empty())
+            .filter_map(|t| t.parse().ok())
+            .collect()
+    }
+
+    /// Feed types actually present in a snapshot, given its entity counts.
+    pub fn observed(vehicles: usize, trip_updates: usize, alerts: usize) -> Vec<FeedType> {
+        [
+            (FeedType::VehiclePositions, vehicles),
+            (FeedType::TripUpdates, trip_updates),
+            (FeedType::ServiceAlerts, alerts),
+        ]
+        .into_iter()
+        .filter(|(_, count)| *count > 0)
+        .map(|(t, _)| t)
+        .collect()
+    }
+
+    /// Returns true when the observed entity types contradict the declared ones.
+    ///
+    /// A snapshot conforms when every observed type is declared and, if it has
+    /// any entities at all, at least one declared type is observed. An empty
+    /// snapshot (e.g. no vehicles overnight) is never a mismatch.
+    pub fn is_mismatch(declared: &[FeedType], observed: &[FeedType]) -> bool {
+        if declared.is_empty() || observed.is_empty() {
+            return false;
+        }
+        let unexpected = observed.iter().any(|t| !declared.contains(t));
+        let none_declared_seen = !declared.iter().any(|t| observed.contains(t));
+        unexpected || none_declared_seen
+    }
+}
+
+impl fmt::Display for FeedType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.catalog_code())
+    }
+}
+
+impl FromStr for FeedType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "vp" | "vehicle_positions" => Ok(FeedType::VehiclePositions),
+            "tu" | "trip_updates" => Ok(FeedType::TripUpdates),
+            "sa" | "service_alerts" | "alerts" => Ok(FeedType::ServiceAlerts),
+            other => Err(anyhow!(
+                "unknown feed type '{}' (expected vp, tu or sa)",
+                other
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_str_catalog_codes() {
+        assert_eq!(
+            "vp".parse::<FeedType>().unwrap(),
+            FeedType::VehiclePositions
+        );
+        assert_eq!("tu".parse::<FeedType>().unwrap(), FeedType::TripUpdates);
+        assert_eq!("SA".parse::<FeedType>().unwrap(), FeedType::ServiceAlerts);
+        assert!("xx".parse::<FeedType>().is_err());
+    }
+
+    #[test]
+    fn test_dir_keeps_vehicle_positions_at_root() {
+        assert_eq!(FeedType::VehiclePositions.dir("feeds"), "feeds");
+        assert_eq!(FeedType::TripUpdates.dir("feeds"), "feeds/trip_updates");
+        assert_eq!(
+            FeedType::ServiceAlerts.key("agency_id=1/date=2024-01-01.csv"),
+            "service_alerts/agency_id=1/date=2024-01-01.csv"
+        );
+    }
+
+    #[test]
+    fn test_parse_key() {
+        let key = "trip_updates/agency_id=mdb-1/date=2024-01-01.csv";
+        assert_eq!(
+            FeedType::TripUpdates.parse_key(key),
+            Some(("mdb-1", "date=2024-01-01.csv"))
+        );
+        assert_eq!(FeedType::VehiclePositions.parse_key(key), None);
+        assert_eq!(
+            FeedType::VehiclePositions.parse_key("agency_id=2/date=2024-01-01.parquet"),
+            Some(("2", "date=2024-01-01.parquet"))
+        );
+        assert_eq!(
+            FeedType::VehiclePositions.parse_key("agencies/agency=a/date=2024-01-01.csv"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_is_mismatch() {
+        use FeedType::*;
+        // Declared vp, only trip updates served
+        assert!(FeedType::is_mismatch(&[VehiclePositions], &[TripUpdates]));
+        // Declared vp, feed mixes vp and tu
+        assert!(FeedType::is_mismatch(
+            &[VehiclePositions],
+            &[VehiclePositions, TripUpdates]
+        ));
+        // Combined feed declared as such
+        assert!(!FeedType::is_mismatch(
+            &[VehiclePositions, TripUpdates],
+            &[TripUpdates]
+        ));
+        // Empty snapshot
+        assert!(!FeedType::is_mismatch(&[VehiclePositions], &[]));
+    }
+
+    #[test]
+    fn test_catalog_query() {
+        assert_eq!(FeedType::catalog_query(&FeedType::ALL), "vp,tu,sa");
+    }
+}
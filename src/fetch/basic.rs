@@ -9,8 +9,14 @@ pub struct BasicClient(reqwest::Client);
 impl BasicClient {
     /// Creates a new `BasicClient` with default timeout settings.
     pub fn new() -> Self {
+        Self::with_timeout(Duration::from_secs(30))
+    }
+
+    /// Creates a new `BasicClient` whose requests, body included, may take up
+    /// to `timeout`, e.g. for large downloads.
+    pub fn with_timeout(timeout: Duration) -> Self {
         let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(30))
+            .timeout(timeout)
             .connect_timeout(Duration::from_secs(10))
             .build()
             .expect("Failed to build HTTP client");
@@ -18,6 +24,12 @@ impl BasicClient {
     }
 }
 
+impl Default for BasicClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[async_trait]
 impl HttpClient for BasicClient {
     async fn execute(&self, req: reqwest::Request) -> reqwest::Result<reqwest::Response> {
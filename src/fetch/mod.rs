@@ -11,8 +11,36 @@ pub use basic::BasicClient;
 pub use client::HttpClient;
 
 use anyhow::Result;
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use serde::{Deserialize, Serialize};
 use tracing::{debug, warn};
 
+/// HTTP cache validators remembered from a feed's last response.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheValidators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl CacheValidators {
+    /// Returns true when neither validator is known.
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// Outcome of a conditional GET.
+#[derive(Debug)]
+pub enum ConditionalFetch {
+    /// The server answered `304 Not Modified`.
+    NotModified,
+    /// The server returned a 2xx body, along with its new validators.
+    Modified {
+        bytes: Vec<u8>,
+        validators: CacheValidators,
+    },
+}
+
 /// Fetches raw bytes from the given URL using the provided HTTP client.
 #[tracing::instrument(skip(client), fields(url, bytes_received))]
 pub async fn fetch_bytes<C: HttpClient>(client: &C, url: &str) -> Result<Vec<u8>> {
@@ -32,3 +60,54 @@ pub async fn fetch_bytes<C: HttpClient>(client: &C, url: &str) -> Result<Vec<u8>
 
     Ok(bytes)
 }
+
+/// Fetches a URL with `If-None-Match` / `If-Modified-Since` headers built from
+/// `validators`, so unchanged feeds can answer `304 Not Modified`.
+///
+/// Any other non-2xx status is returned as an error.
+#[tracing::instrument(skip(client, validators), fields(url, bytes_received))]
+pub async fn fetch_conditional<C: HttpClient>(
+    client: &C,
+    url: &str,
+    validators: &CacheValidators,
+) -> Result<ConditionalFetch> {
+    debug!(url, "Sending conditional HTTP GET");
+
+    let mut req = reqwest::Request::new(reqwest::Method::GET, url.parse()?);
+    if let Some(etag) = &validators.etag {
+        req.headers_mut().insert(IF_NONE_MATCH, etag.parse()?);
+    }
+    if let Some(last_modified) = &validators.last_modified {
+        req.headers_mut()
+            .insert(IF_MODIFIED_SINCE, last_modified.parse()?);
+    }
+    let resp = client.execute(req).await?;
+
+    let status = resp.status();
+    if status == reqwest::StatusCode::NOT_MODIFIED {
+        debug!(url, "Feed not modified since last fetch");
+        return Ok(ConditionalFetch::NotModified);
+    }
+    // An error page must not be cached, or a later 304 would replay it as the feed
+    if !status.is_success() {
+        warn!(url, status = %status, "HTTP response non-success");
+        anyhow::bail!("HTTP {status} from {url}");
+    }
+
+    let header = |name| {
+        resp.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string())
+    };
+    let validators = CacheValidators {
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+    };
+
+    let bytes = resp.bytes().await?.to_vec();
+    tracing::Span::current().record("bytes_received", bytes.len());
+    debug!(url, bytes = bytes.len(), "HTTP GET complete");
+
+    Ok(ConditionalFetch::Modified { bytes, validators })
+}
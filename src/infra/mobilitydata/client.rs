@@ -1,10 +1,12 @@
 use anyhow::Result;
 use async_trait::async_trait;
+use gtfs_rt_rater::feed_type::FeedType;
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use tracing::{debug, warn};
 
 use crate::services::catalog_api::{CatalogApi, Feed};
+use crate::services::timezones::timezone_for;
 
 #[derive(Serialize)]
 struct TokenRequest {
@@ -79,10 +81,11 @@ impl MobilityDataClient {
 #[async_trait]
 impl CatalogApi for MobilityDataClient {
     #[tracing::instrument(skip(self), fields(feed_count))]
-    async fn list_feeds(&self) -> Result<Vec<Feed>> {
+    async fn list_feeds(&self, entity_types: &[FeedType]) -> Result<Vec<Feed>> {
         let url = format!(
-            "{}/v1/gtfs_rt_feeds?limit=999&offset=0&entity_types=vp",
-            self.base_url
+            "{}/v1/gtfs_rt_feeds?limit=999&offset=0&entity_types={}",
+            self.base_url,
+            FeedType::catalog_query(entity_types)
         );
 
         let client = reqwest::Client::builder()
@@ -124,6 +127,28 @@ impl CatalogApi for MobilityDataClient {
                     .unwrap_or(0);
                 let requires_auth = auth_type != 0;
                 let status = item["status"].as_str().map(|s| s.to_string());
+                let entity_types = item["entity_types"]
+                    .as_array()
+                    .map(|types| {
+                        types
+                            .iter()
+                            .filter_map(|t| t.as_str()?.parse().ok())
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let feed_references = item["feed_references"]
+                    .as_array()
+                    .map(|refs| {
+                        refs.iter()
+                            .filter_map(|r| r.as_str().map(|s| s.to_string()))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+
+                let location = &item["locations"][0];
+                let timezone = location["country_code"].as_str().and_then(|country| {
+                    timezone_for(country, location["subdivision_name"].as_str())
+                });
 
                 Some(Feed {
                     id,
@@ -131,6 +156,9 @@ impl CatalogApi for MobilityDataClient {
                     url,
                     requires_auth,
                     status,
+                    entity_types,
+                    feed_references,
+                    timezone: timezone.map(str::to_string),
                 })
             })
             .collect();
@@ -144,4 +172,37 @@ impl CatalogApi for MobilityDataClient {
 
         Ok(feeds)
     }
+
+    #[tracing::instrument(skip(self))]
+    async fn latest_dataset_url(&self, static_feed_id: &str) -> Result<Option<String>> {
+        let url = format!("{}/v1/gtfs_feeds/{}", self.base_url, static_feed_id);
+
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .connect_timeout(Duration::from_secs(10))
+            .build()?;
+
+        let response = client
+            .get(&url)
+            .header("Authorization", format!("Bearer {}", self.access_token))
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to send request: {}", e))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            warn!(status = %status, "MobilityData API returned non-success status");
+            let body = response.text().await.unwrap_or_default();
+            return Err(anyhow::anyhow!("API returned status {}: {}", status, body));
+        }
+
+        let json: serde_json::Value = response
+            .json()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to parse response: {}", e))?;
+
+        Ok(json["latest_dataset"]["hosted_url"]
+            .as_str()
+            .map(|s| s.to_string()))
+    }
 }
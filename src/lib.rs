@@ -2,25 +2,46 @@
 //!
 //! A library for fetching, parsing, and analyzing [GTFS Realtime](https://gtfs.org/realtime/) feeds.
 //!
-//! This crate provides tools to evaluate the data quality of GTFS-RT vehicle position feeds
-//! by measuring field completeness (bearing, speed, occupancy, etc.) and computing
+//! This crate provides tools to evaluate the data quality of GTFS-RT vehicle position,
+//! trip update, and service alert feeds by measuring field completeness (bearing, speed, occupancy, etc.) and computing
 //! aggregate scores and letter grades.
 //!
 //! ## Modules
 //!
+//! - [`alerting`] - Alert rules with webhook, email and command notifications
+//! - [`feed_type`] - Vehicle position, trip update, and service alert feed types
 //! - [`fetch`] - HTTP client abstractions for downloading feed data
 //! - [`parser`] - Protobuf deserialization of GTFS-RT `FeedMessage`s
+//! - [`scheduler`] - Adaptive per-feed polling schedule with circuit breaker
+//! - [`state`] - Sampler state persisted across restarts
+//! - [`storage`] - CSV or SQLite storage for sampled rows
 //! - [`stats`] - Per-sample statistics extracted from a single feed snapshot
+//! - [`metrics`] - Prometheus metrics and health endpoint for the sampler
+//! - [`object_store`] - Local, in-memory and S3 object stores for uploads and reads
+//! - [`observer`] - Detection of outages on the rater's side
 //! - [`output`] - CSV and JSON serialization of feed statistics
+//! - [`recompute`] - Re-grading past days from uploaded sample files
+//! - [`upload`] - Verified, retried uploads of daily files with per-day manifests
 //! - [`analyzers`] - Aggregation, grading, and S3 upload of collected data
 
+pub mod alerting;
 pub mod analyzers;
+pub mod feed_type;
 pub mod fetch;
+pub mod metrics;
+pub mod object_store;
+pub mod observer;
 pub mod output;
 pub mod parser;
+pub mod recompute;
+pub mod scheduler;
+pub mod state;
 pub mod stats;
+pub mod storage;
+pub mod upload;
 
 /// Auto-generated protobuf types from the GTFS Realtime specification.
+#[allow(clippy::doc_lazy_continuation, clippy::doc_overindented_list_items)]
 pub mod gtfs_rt {
     include!(concat!(env!("OUT_DIR"), "/transit_realtime.rs"));
 }
@@ -7,23 +7,40 @@ mod infra;
 mod services;
 
 use crate::infra::mobilitydata::client::MobilityDataClient;
-use crate::services::catalog_api::CatalogApi;
-use anyhow::Result;
-use aws_sdk_s3::primitives::ByteStream;
-use chrono::Utc;
+use crate::services::catalog_api::{CatalogApi, Feed};
+use crate::services::static_routes::StaticRoutes;
+use anyhow::{Result, anyhow, bail};
+use chrono::{DateTime, NaiveDate, Utc};
 use clap::{Parser, Subcommand};
-use flate2::Compression;
-use flate2::write::GzEncoder;
-use gtfs_rt_rater::analyzers::analyzer::{analyze, analyze_for_date};
+use gtfs_rt_rater::analyzers::agency::{AgencyDirectory, consistency_dir};
+use gtfs_rt_rater::analyzers::analyzer::{
+    Selection, analyze, analyze_for_date, analyze_selected, analyze_sqlite, catalog_report,
+};
+use gtfs_rt_rater::analyzers::mode::RouteModes;
 use gtfs_rt_rater::{
-    fetch::{BasicClient, fetch_bytes},
-    output::append_record,
+    alerting::Alerter,
+    feed_type::FeedType,
+    fetch::{BasicClient, CacheValidators, ConditionalFetch, fetch_bytes, fetch_conditional},
+    metrics::{self, Metrics},
+    object_store::{LocalStore, MemoryStore, ObjectStore, S3Store},
+    observer::{self, ConnectivityProbe, OBSERVER_OUTAGE, ObserverMonitor, SamplerGap},
+    output::{CsvRecord, UploadFormat, append_record},
     parser::parse_feed,
-    stats::FeedStats,
+    recompute::recompute,
+    scheduler::{BreakerState, FeedSchedule, ScheduleConfig},
+    state::SamplerState,
+    stats::{
+        FeedStats, SampleStats,
+        consistency::{ConsistencyStats, ConsistencyTracker, EntityIds},
+    },
+    storage::{StorageBackend, TimeRange, sqlite::SqliteStore, sqlite_path},
+    upload::{RetryPolicy, upload_day},
 };
 use std::ffi::OsStr;
-use std::io::Write;
+use std::net::SocketAddr;
 use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tracing::Instrument;
 use tracing::{debug, error, info, warn};
 use tracing_subscriber::{
@@ -52,8 +69,26 @@ enum Commands {
         /// CSV file to append results to
         #[arg(short, long, default_value = "data.csv")]
         output: String,
+
+        /// Where to store the sample: the CSV file, or the SQLite database at --db
+        #[arg(long, value_enum, default_value_t = StorageBackend::Csv)]
+        storage: StorageBackend,
+
+        /// SQLite database used with --storage sqlite
+        #[arg(long, default_value = "feeds/samples.sqlite")]
+        db: String,
+
+        /// Catalog feed id to store the sample under with --storage sqlite
+        /// (looked up in the MobilityData catalog by URL when omitted)
+        #[arg(long)]
+        feed_id: Option<String>,
     },
-    /// Aggregate all feed CSVs and upload results to S3
+    /// Aggregate feed samples and write the results to S3, a local directory or stdout
+    #[command(group(
+        clap::ArgGroup::new("destination")
+            .required(true)
+            .args(["s3_bucket", "local_dir", "stdout"])
+    ))]
     Aggregate {
         /// Directory containing CSVs to aggregate
         #[arg(short = 'd', long, default_value = "feeds")]
@@ -61,13 +96,80 @@ enum Commands {
 
         /// S3 bucket name to upload aggregated JSON to (e.g., "my-bucket")
         #[arg(long)]
+        s3_bucket: Option<String>,
+
+        /// Write aggregated JSON under this directory instead of uploading it
+        #[arg(long)]
+        local_dir: Option<String>,
+
+        /// Print aggregated JSON to stdout instead of uploading it
+        #[arg(long, default_value_t = false)]
+        stdout: bool,
+
+        /// Only aggregate this feed (skips agency rollups). Filtered runs write under partial/
+        #[arg(long)]
+        feed_id: Option<String>,
+
+        /// Optional: S3-compatible endpoint to use instead of AWS (e.g., "http://localhost:9000" for MinIO)
+        #[arg(long)]
+        s3_endpoint_url: Option<String>,
+
+        /// Read samples from the per-feed CSVs or from the SQLite database in the output directory
+        #[arg(long, value_enum, default_value_t = StorageBackend::Csv)]
+        storage: StorageBackend,
+
+        /// Only aggregate samples at or after this time (RFC 3339)
+        #[arg(long)]
+        since: Option<DateTime<Utc>>,
+
+        /// Only aggregate samples before this time (RFC 3339)
+        #[arg(long)]
+        until: Option<DateTime<Utc>>,
+    },
+    /// Re-aggregate past days from the sample files uploaded to S3
+    ///
+    /// Results are published under versions/algorithm=v{N}/, leaving the
+    /// aggregates of earlier algorithm versions in place.
+    Recompute {
+        /// S3 bucket the daily sample files were uploaded to
+        #[arg(long)]
         s3_bucket: String,
+
+        /// Optional: S3-compatible endpoint to use instead of AWS (e.g., "http://localhost:9000" for MinIO)
+        #[arg(long)]
+        s3_endpoint_url: Option<String>,
+
+        /// First day to recompute (YYYY-MM-DD)
+        #[arg(long)]
+        since: NaiveDate,
+
+        /// Last day to recompute (YYYY-MM-DD, inclusive; defaults to --since)
+        #[arg(long)]
+        until: Option<NaiveDate>,
+
+        /// Write the versioned aggregates under this directory instead of the bucket
+        #[arg(long)]
+        local_dir: Option<String>,
+
+        /// Output directory holding agencies.json for agency rollups
+        #[arg(short = 'd', long, default_value = "feeds")]
+        output_dir: String,
+    },
+    /// Report feeds whose catalog entity types disagree with what they serve
+    CatalogReport {
+        /// Directory containing collected feed CSVs
+        #[arg(short = 'd', long, default_value = "feeds")]
+        output_dir: String,
+
+        /// Minimum fraction (0.0-1.0) of mismatched samples before a feed is reported
+        #[arg(long, default_value_t = 0.5)]
+        min_mismatch: f64,
     },
     /// List available feeds from MobilityData
     ListFeeds {
-        /// Only show vehicle position feeds
-        #[arg(short, long, default_value_t = true)]
-        vehicle_positions: bool,
+        /// Entity types to list: vp (vehicle positions), tu (trip updates), sa (service alerts)
+        #[arg(short, long, value_delimiter = ',', default_value = "vp")]
+        entity_types: Vec<FeedType>,
     },
     /// Consume all feeds from MobilityData that don't require authentication
     ConsumeAllFeeds {
@@ -79,11 +181,27 @@ enum Commands {
         #[arg(short, long, default_value_t = 5)]
         concurrency: usize,
 
-        /// Sample rate: query each feed every X seconds
+        /// Sample rate: initial interval in seconds between polls of each feed
         #[arg(short = 'r', long, default_value_t = 60)]
         sample_rate: u64,
 
-        /// Number of samples to collect (0 = infinite)
+        /// Shortest interval in seconds a feed's polling may adapt down to
+        #[arg(long, default_value_t = 15)]
+        min_interval: u64,
+
+        /// Longest interval in seconds a feed's polling may adapt up to
+        #[arg(long, default_value_t = 300)]
+        max_interval: u64,
+
+        /// Consecutive failures before a feed is backed off
+        #[arg(long, default_value_t = 3)]
+        failure_threshold: u32,
+
+        /// Maximum backoff in seconds for a failing feed
+        #[arg(long, default_value_t = 1800)]
+        max_backoff: u64,
+
+        /// Number of samples to collect per feed (0 = infinite)
         #[arg(short = 'n', long, default_value_t = 1)]
         num_samples: usize,
 
@@ -91,9 +209,29 @@ enum Commands {
         #[arg(long)]
         s3_bucket: Option<String>,
 
-        /// Optional: Gzip compress CSV files before uploading to S3
+        /// Optional: S3-compatible endpoint to use instead of AWS (e.g., "http://localhost:9000" for MinIO)
+        #[arg(long)]
+        s3_endpoint_url: Option<String>,
+
+        /// Optional: Gzip compress CSV files before uploading to S3 (same as --upload-format csv-gzip)
         #[arg(long, default_value_t = false)]
         gzip: bool,
+
+        /// File format for daily S3 uploads
+        #[arg(long, value_enum, default_value_t = UploadFormat::Csv)]
+        upload_format: UploadFormat,
+
+        /// Where to store samples: per-feed CSVs, or a SQLite database in the output directory
+        #[arg(long, value_enum, default_value_t = StorageBackend::Csv)]
+        storage: StorageBackend,
+
+        /// Entity types to sample: vp (vehicle positions), tu (trip updates), sa (service alerts)
+        #[arg(short, long, value_delimiter = ',', default_value = "vp")]
+        entity_types: Vec<FeedType>,
+
+        /// Optional: address to serve Prometheus /metrics and /healthz on (e.g. "0.0.0.0:9898")
+        #[arg(long)]
+        metrics_addr: Option<SocketAddr>,
     },
 }
 
@@ -136,31 +274,127 @@ async fn main() -> Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Analyze { source, output } => {
+        Commands::Analyze {
+            source,
+            output,
+            storage,
+            db,
+            feed_id,
+        } => {
             let bytes = fetcher(&source).await?;
             let feed = parse_feed(&bytes)?;
             let stats = FeedStats::from_feed(&feed);
 
-            append_record(&output, &stats)?;
+            match storage {
+                StorageBackend::Csv => append_record(&output, &stats)?,
+                StorageBackend::Sqlite => {
+                    let (feed_id, feed_name) = resolve_feed(&source, feed_id).await?;
+                    let stats = stats.with_feed_info(&feed_id, &feed_name);
+                    SqliteStore::open(&db)?.insert_sample(
+                        FeedType::VehiclePositions,
+                        &feed_id,
+                        Some(&source),
+                        &stats,
+                    )?
+                }
+            }
         }
         Commands::Aggregate {
             output_dir,
             s3_bucket,
+            local_dir,
+            stdout,
+            feed_id,
+            s3_endpoint_url,
+            storage,
+            since,
+            until,
         } => {
-            if s3_bucket.is_empty() {
-                info!("S3 bucket not specified, skipping upload");
-            } else {
-                analyze(&s3_bucket, &output_dir).await?;
+            let files = LocalStore::new(&output_dir);
+            let selection = Selection {
+                feed_id,
+                range: TimeRange { since, until },
+            };
+            // Only a full aggregation uploaded to S3 consumes the input CSVs
+            let consume = s3_bucket.is_some()
+                && selection.feed_id.is_none()
+                && selection.range == TimeRange::default();
+
+            let dest: Box<dyn ObjectStore> = match (&s3_bucket, &local_dir) {
+                (Some(bucket), _) => {
+                    Box::new(S3Store::connect(bucket, s3_endpoint_url.as_deref()).await)
+                }
+                (None, Some(dir)) => Box::new(LocalStore::new(dir)),
+                (None, None) => Box::new(MemoryStore::new()),
+            };
+
+            match storage {
+                StorageBackend::Csv if consume => analyze(&files, dest.as_ref()).await?,
+                StorageBackend::Csv => analyze_selected(&files, dest.as_ref(), &selection).await?,
+                StorageBackend::Sqlite => {
+                    let store = SqliteStore::open(&sqlite_path(&output_dir))?;
+                    analyze_sqlite(&files, dest.as_ref(), &store, &selection).await?;
+                }
             }
+
+            if stdout {
+                print_aggregates(dest.as_ref()).await?;
+            } else if let Some(dir) = local_dir {
+                info!(dir = %dir, "Aggregates written");
+            }
+        }
+        Commands::Recompute {
+            s3_bucket,
+            s3_endpoint_url,
+            since,
+            until,
+            local_dir,
+            output_dir,
+        } => {
+            let uploads = S3Store::connect(&s3_bucket, s3_endpoint_url.as_deref()).await;
+            let local = local_dir.as_deref().map(LocalStore::new);
+            let dest: &dyn ObjectStore = match &local {
+                Some(store) => store,
+                None => &uploads,
+            };
+            let until = until.unwrap_or(since);
+            let report =
+                recompute(&uploads, dest, &LocalStore::new(&output_dir), since, until).await?;
+            info!(
+                algorithm_version = report.algorithm_version,
+                days = report.days.len(),
+                "Recomputed aggregates"
+            );
         }
-        Commands::ListFeeds {
-            vehicle_positions: _,
+        Commands::CatalogReport {
+            output_dir,
+            min_mismatch,
         } => {
+            let issues = catalog_report(&LocalStore::new(&output_dir), min_mismatch).await?;
+
+            for issue in &issues {
+                let observed = FeedType::catalog_query(&issue.observed_entity_types);
+                warn!(
+                    feed_id = %issue.feed_id,
+                    declared = %issue.declared_entity_types,
+                    observed = %observed,
+                    samples = issue.samples,
+                    mismatch_percent = issue.mismatch_percent,
+                    "Catalog entity types look wrong"
+                );
+            }
+
+            info!(
+                flagged = issues.len(),
+                min_mismatch, "Catalog report complete"
+            );
+        }
+        Commands::ListFeeds { entity_types } => {
             let refresh_token = std::env::var("MOBILITYDATA_REFRESH_TOKEN")
                 .expect("MOBILITYDATA_REFRESH_TOKEN must be set");
             let client = MobilityDataClient::new(refresh_token).await?;
 
-            let feeds = client.list_feeds().await?;
+            let feeds = client.list_feeds(&entity_types).await?;
 
             info!(total = feeds.len(), "Feed list fetched");
 
@@ -172,10 +406,12 @@ async fn main() -> Result<()> {
                     "open"
                 };
                 let has_url = feed.url.is_some();
+                let types = FeedType::catalog_query(&feed.entity_types);
 
                 info!(
                     feed_id = %feed.id,
                     feed_name = %feed.name,
+                    entity_types = %types,
                     status = status_str,
                     auth = auth_str,
                     has_url,
@@ -210,17 +446,54 @@ async fn main() -> Result<()> {
             output_dir,
             concurrency,
             sample_rate,
+            min_interval,
+            max_interval,
+            failure_threshold,
+            max_backoff,
             num_samples,
             s3_bucket,
+            s3_endpoint_url,
             gzip,
+            upload_format,
+            storage,
+            entity_types,
+            metrics_addr,
         } => {
+            if min_interval > max_interval {
+                anyhow::bail!("--min-interval must not exceed --max-interval");
+            }
+            let schedule = ScheduleConfig {
+                base_interval: Duration::from_secs(sample_rate),
+                min_interval: Duration::from_secs(min_interval),
+                max_interval: Duration::from_secs(max_interval),
+                failure_threshold: failure_threshold.max(1),
+                max_backoff: Duration::from_secs(max_backoff),
+            };
+            // --gzip predates --upload-format and still upgrades plain CSV
+            let upload_format = match upload_format {
+                UploadFormat::Csv if gzip => UploadFormat::CsvGzip,
+                format => format,
+            };
+            let uploads = match s3_bucket {
+                Some(bucket) => {
+                    info!(bucket = %bucket, upload_format = ?upload_format, "S3 upload enabled");
+                    let store = S3Store::connect(&bucket, s3_endpoint_url.as_deref()).await;
+                    Some(Arc::new(store) as Arc<dyn ObjectStore>)
+                }
+                None => None,
+            };
             consume_all_feeds(
                 &output_dir,
                 concurrency,
-                sample_rate,
+                schedule,
                 num_samples,
-                s3_bucket,
-                gzip,
+                StorageOptions {
+                    backend: storage,
+                    upload_format,
+                    uploads,
+                },
+                &entity_types,
+                metrics_addr,
             )
             .await?;
         }
@@ -229,57 +502,114 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// Prints every JSON object in `store` to stdout as one JSON object keyed by object key.
+async fn print_aggregates(store: &dyn ObjectStore) -> Result<()> {
+    let mut objects = serde_json::Map::new();
+    for key in store.list("").await? {
+        if let Some(body) = store.get(&key).await? {
+            objects.insert(key, serde_json::from_slice(&body)?);
+        }
+    }
+    println!("{}", serde_json::to_string_pretty(&objects)?);
+    Ok(())
+}
+
+/// Identifies the catalog feed (id and name) a sample read from `source`
+/// belongs to: `feed_id` when given, otherwise the catalog feed whose URL is
+/// `source`.
+async fn resolve_feed(source: &str, feed_id: Option<String>) -> Result<(String, String)> {
+    if let Some(id) = feed_id {
+        return Ok((id.clone(), id));
+    }
+
+    let Ok(refresh_token) = std::env::var("MOBILITYDATA_REFRESH_TOKEN") else {
+        bail!("--feed-id is required unless MOBILITYDATA_REFRESH_TOKEN is set to look it up");
+    };
+    let client = MobilityDataClient::new(refresh_token).await?;
+    let feeds = client.list_feeds(&[FeedType::VehiclePositions]).await?;
+    feeds
+        .into_iter()
+        .find(|f| f.url.as_deref() == Some(source))
+        .map(|f| (f.id, f.name))
+        .ok_or_else(|| anyhow!("no catalog feed has URL {source}, pass --feed-id"))
+}
+
 /// Loads feed data from a local file path or fetches it over HTTP.
 #[tracing::instrument(fields(source = %url))]
 async fn fetcher(url: &String) -> Result<Vec<u8>> {
     let bytes = if url.starts_with("http") {
         let client = BasicClient::new();
-        fetch_bytes(&client, &url).await?
+        fetch_bytes(&client, url).await?
     } else {
         std::fs::read(url)?
     };
     Ok(bytes)
 }
 
-/// Fetches all public GTFS-RT feeds concurrently, collecting samples at a
-/// configurable interval and optionally uploading previous-day results to S3.
+/// How the sampler stores samples locally and uploads completed days.
+struct StorageOptions {
+    backend: StorageBackend,
+    upload_format: UploadFormat,
+    /// Where completed days are uploaded, if anywhere.
+    uploads: Option<Arc<dyn ObjectStore>>,
+}
+
+/// Fetches all public GTFS-RT feeds concurrently and optionally uploads
+/// previous-day results to the `uploads` store.
+///
+/// Every feed runs on its own [`FeedSchedule`]: start times are staggered across
+/// the base interval, each feed's interval adapts to how often its header
+/// timestamp changes, and failing feeds are backed off by a circuit breaker.
+/// Each sample's stats are written to one CSV stream per requested
+/// [`FeedType`] the feed declares. With `metrics_addr`, [`Metrics`] are
+/// served over HTTP while sampling.
 #[tracing::instrument(
-    skip(s3_bucket, gzip),
-    fields(output_dir, concurrency, sample_rate, num_samples)
+    skip(schedule, storage, entity_types),
+    fields(output_dir, concurrency, num_samples)
 )]
 async fn consume_all_feeds(
     output_dir: &str,
     concurrency: usize,
-    sample_rate: u64,
+    schedule: ScheduleConfig,
     num_samples: usize,
-    s3_bucket: Option<String>,
-    gzip: bool,
+    storage: StorageOptions,
+    entity_types: &[FeedType],
+    metrics_addr: Option<SocketAddr>,
 ) -> Result<()> {
-    let refresh_token = std::env::var("MOBILITYDATA_REFRESH_TOKEN")
-        .expect("MOBILITYDATA_REFRESH_TOKEN must be set");
-    let client = MobilityDataClient::new(refresh_token).await?;
-
-    // Initialize S3 client if bucket is provided
-    let s3_client = if s3_bucket.is_some() {
-        let config = aws_config::load_from_env().await;
-        Some(aws_sdk_s3::Client::new(&config))
-    } else {
-        None
+    let StorageOptions {
+        backend,
+        upload_format,
+        uploads,
+    } = storage;
+
+    let metrics = Arc::new(Metrics::new());
+    let metrics_server = match metrics_addr {
+        Some(addr) => {
+            let listener = tokio::net::TcpListener::bind(addr).await?;
+            info!(addr = %addr, "Serving /metrics and /healthz");
+            Some(tokio::spawn(metrics::serve(listener, metrics.clone())))
+        }
+        None => None,
     };
 
-    if let Some(ref bucket) = s3_bucket {
-        info!(bucket = %bucket, gzip, "S3 upload enabled");
-    }
+    let refresh_token = std::env::var("MOBILITYDATA_REFRESH_TOKEN")
+        .expect("MOBILITYDATA_REFRESH_TOKEN must be set");
+    let client = Arc::new(MobilityDataClient::new(refresh_token).await?);
 
     info!("Fetching feed list from MobilityData");
-    let feeds = client.list_feeds().await?;
+    let feeds = client.list_feeds(entity_types).await?;
 
-    // Filter feeds that don't require authentication, have a URL, and are not deprecated
+    // Filter feeds that don't require authentication, have a URL, are not deprecated,
+    // and serve at least one of the requested entity types
     let public_feeds: Vec<_> = feeds
         .into_iter()
         .filter(|f| {
             !f.requires_auth && f.url.is_some() && f.status.as_deref() != Some("deprecated")
         })
+        .filter_map(|f| {
+            let types = f.sampled_types(entity_types);
+            (!types.is_empty()).then_some((f, types))
+        })
         .collect();
 
     info!(
@@ -287,241 +617,723 @@ async fn consume_all_feeds(
         "Public feeds ready for processing"
     );
 
+    let base_interval = schedule.base_interval.as_secs();
     if num_samples == 0 {
-        info!(sample_rate, "Sampling infinitely. Press Ctrl+C to stop.");
+        info!(base_interval, "Sampling infinitely. Press Ctrl+C to stop.");
     } else {
-        info!(num_samples, sample_rate, "Starting sample collection");
+        info!(num_samples, base_interval, "Starting sample collection");
     }
 
+    // Vehicles are graded for the mode of their route in the static GTFS
+    // Loaded before sampling starts, then kept current in the background
+    let static_routes = Arc::new(StaticRoutes::new(
+        client,
+        public_feeds.iter().map(|(feed, _)| feed),
+    ));
+    static_routes.load_due().await;
+    let static_refresh = tokio::spawn(static_routes.clone().refresh_forever());
+
     // Create output directory if it doesn't exist
     std::fs::create_dir_all(output_dir)?;
 
-    let semaphore = std::sync::Arc::new(tokio::sync::Semaphore::new(concurrency));
+    // Record which sampled feeds belong to the same agency for the agency rollup,
+    // in the timezone of their static GTFS, or failing that of their catalog location
+    let mut agency_directory = AgencyDirectory::default();
+    for (feed, feed_types) in &public_feeds {
+        let timezone = static_routes
+            .timezone_for(feed)
+            .map(|tz| tz.name().to_string())
+            .or_else(|| feed.timezone.clone());
+        for feed_type in feed_types {
+            agency_directory.add(
+                &feed.agency_id(),
+                &feed.name,
+                timezone.as_deref(),
+                &feed.id,
+                *feed_type,
+            );
+        }
+    }
+    agency_directory.save(output_dir)?;
+    info!(
+        agency_count = agency_directory.agencies.len(),
+        "Agency directory written"
+    );
+
+    let store = match backend {
+        StorageBackend::Csv => None,
+        StorageBackend::Sqlite => {
+            let path = sqlite_path(output_dir);
+            info!(path = %path, "Writing samples to SQLite");
+            Some(Arc::new(SqliteStore::open(&path)?))
+        }
+    };
+    let sink = match &store {
+        Some(store) => SampleSink::Sqlite(store.clone()),
+        None => SampleSink::Csv {
+            output_dir: output_dir.to_string(),
+        },
+    };
+
+    // Alert rules and sinks, if configured
+    let alerter = Alerter::load(output_dir)?.map(Arc::new);
 
-    let mut sample_count = 0;
-    let mut last_upload_date: Option<chrono::NaiveDate> = None;
+    // Resume persisted state: upload progress and per-feed cache validators
+    let state = SamplerState::load(output_dir)?;
+    info!(
+        last_uploaded_date = ?state.last_uploaded_date,
+        pending_dates = state.pending_dates.len(),
+        "Sampler state loaded"
+    );
+    // A long silence since the last heartbeat means the sampler was down
+    if let Some(gap) = SamplerGap::detect(state.last_heartbeat, Utc::now()) {
+        warn!(since = %gap.since, "Recording sampler downtime as an observer outage");
+        for (feed, feed_types) in &public_feeds {
+            sink.write_observer_gap(feed, feed_types, &gap);
+        }
+    }
+    let state = Arc::new(Mutex::new(state));
+
+    let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+
+    let worker = FeedWorker {
+        sink: sink.clone(),
+        num_samples,
+        semaphore: Arc::new(tokio::sync::Semaphore::new(concurrency)),
+        // Feeds poll independently, so VP/TU ids are paired if sampled within one max interval
+        tracker: Arc::new(Mutex::new(ConsistencyTracker::new(schedule.max_interval))),
+        // Judge connectivity from feeds polled within roughly one round
+        outages: Arc::new(ObserverMonitor::new(
+            schedule.base_interval,
+            ConnectivityProbe::default(),
+        )),
+        static_routes,
+        state: state.clone(),
+        alerter: alerter.clone(),
+        metrics: metrics.clone(),
+        shutdown: shutdown_rx,
+    };
+
+    let mut feed_tasks = tokio::task::JoinSet::new();
+    let start = tokio::time::Instant::now();
+    let feed_count = public_feeds.len();
+
+    for (index, (feed, feed_types)) in public_feeds.into_iter().enumerate() {
+        let offset = schedule.stagger(index, feed_count);
+        let feed_schedule = FeedSchedule::new(schedule.clone(), (start + offset).into_std());
+        let cache = FeedCache::restore(output_dir, &feed.id, &state.lock().unwrap());
+
+        let feed_span = tracing::info_span!(
+            "feed_schedule",
+            feed_id = %feed.id,
+            feed_name = %feed.name,
+        );
+
+        feed_tasks.spawn(
+            worker
+                .clone()
+                .run(feed, feed_types, feed_schedule, cache)
+                .instrument(feed_span),
+        );
+    }
+
+    let mut last_upload_check: Option<chrono::NaiveDate> = None;
+    let mut upload_task: Option<tokio::task::JoinHandle<()>> = None;
+    let mut upload_check = tokio::time::interval(Duration::from_secs(60));
+    let mut feed_caches = Vec::new();
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
 
     loop {
-        // Check if we've reached the sample limit (0 = infinite)
-        if num_samples > 0 && sample_count >= num_samples {
-            break;
-        }
-
-        sample_count += 1;
-
-        // Check if we need to upload previous day's files
-        let today = Utc::now().date_naive();
-        if let Some(ref bucket) = s3_bucket {
-            if let Some(s3) = &s3_client {
-                // Upload previous day's files if we haven't uploaded today yet
-                if last_upload_date.is_none() || last_upload_date.unwrap() < today {
-                    if let Some(yesterday) = today.pred_opt() {
-                        let s3 = s3.clone();
-                        let bucket = bucket.to_string();
-                        let output_dir = output_dir.to_string();
-                        tokio::spawn(async move {
-                            info!(date = %yesterday, "Uploading previous day's files to S3");
-                            if let Err(e) = upload_previous_day_files(
-                                &s3,
-                                &bucket,
-                                &output_dir,
-                                yesterday,
-                                gzip,
-                            )
-                            .await
-                            {
-                                error!(error = %e, "Failed to upload previous day's files");
-                            } else {
-                                info!(date = %yesterday, "Successfully uploaded previous day's files");
-                            }
-
-                            info!(date = %yesterday, "Aggregating previous day's data");
-                            if let Err(e) =
-                                analyze_for_date(&s3, &bucket, &output_dir, yesterday).await
-                            {
-                                error!(error = %e, "Failed to aggregate previous day's data");
-                            } else {
-                                info!(date = %yesterday, "Successfully aggregated and cleaned up previous day's data");
-                            }
-                        });
-
-                        last_upload_date = Some(today);
+        tokio::select! {
+            _ = upload_check.tick() => {
+                // Persist validators and a heartbeat regularly so an unclean exit loses little
+                {
+                    let mut state = state.lock().unwrap();
+                    state.last_heartbeat = Some(Utc::now());
+                    if let Err(e) = state.save(output_dir) {
+                        error!(error = %e, "Failed to save sampler state");
                     }
+                    metrics.heartbeat(
+                        upload_task.as_ref().is_some_and(|t| !t.is_finished()),
+                        state.last_uploaded_date,
+                        state.pending_dates.len(),
+                    );
+                }
+
+                if let Some(alerter) = &alerter {
+                    alerter.check_down().await;
+                }
+
+                // Catch up on every un-uploaded past day, once at startup and once per new day
+                let today = Utc::now().date_naive();
+                let upload_idle = upload_task.as_ref().is_none_or(|t| t.is_finished());
+                if let Some(uploads) = &uploads
+                    && last_upload_check.is_none_or(|d| d < today)
+                    && upload_idle
+                    && !*shutdown_tx.borrow()
+                {
+                    upload_task = Some(tokio::spawn(catch_up_uploads(
+                        uploads.clone(),
+                        output_dir.to_string(),
+                        upload_format,
+                        store.clone(),
+                        today,
+                        state.clone(),
+                        alerter.clone(),
+                    )));
+                    last_upload_check = Some(today);
+                }
+            }
+            _ = &mut shutdown, if !*shutdown_tx.borrow() => {
+                info!("Shutdown requested, finishing in-flight fetches and uploads");
+                let _ = shutdown_tx.send(true);
+            }
+            finished = feed_tasks.join_next() => {
+                match finished {
+                    Some(Err(e)) => error!(error = %e, "Feed schedule task failed"),
+                    Some(Ok(cache)) => feed_caches.push(cache),
+                    None => break,
                 }
             }
         }
+    }
 
-        info!(
-            sample = sample_count,
-            total = if num_samples == 0 {
-                None
-            } else {
-                Some(num_samples)
-            },
-            "Starting sample round"
-        );
+    if let Some(task) = upload_task {
+        info!("Waiting for upload and aggregation to finish");
+        if let Err(e) = task.await {
+            error!(error = %e, "Upload task failed");
+        }
+    }
 
-        let mut tasks = vec![];
+    if let Some(server) = metrics_server {
+        server.abort();
+    }
+    static_refresh.abort();
 
-        for feed in &public_feeds {
-            let sem = semaphore.clone();
-            let output_dir = output_dir.to_string();
-            let feed = feed.clone();
+    for (feed_id, cache) in &feed_caches {
+        cache.persist(output_dir, feed_id);
+    }
+    {
+        let mut state = state.lock().unwrap();
+        state.last_heartbeat = Some(Utc::now());
+        state.save(output_dir)?;
+    }
 
-            let feed_span = tracing::info_span!(
-                "process_feed",
-                feed_id = %feed.id,
-                feed_name = %feed.name,
-            );
+    info!(output_dir, "Finished processing all feeds");
+    Ok(())
+}
+
+/// Resolves when the process receives SIGINT (Ctrl+C) or SIGTERM.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = sigterm.recv() => {}
+                }
+            }
+            Err(e) => {
+                warn!(error = %e, "Failed to install SIGTERM handler");
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// Uploads and aggregates every past day that still has local files (or, with
+/// SQLite storage, samples newer than the last upload) or was left pending,
+/// oldest first, recording progress in the sampler state. Aggregate alert
+/// rules are evaluated after each aggregated day.
+async fn catch_up_uploads(
+    uploads: Arc<dyn ObjectStore>,
+    output_dir: String,
+    upload_format: UploadFormat,
+    store: Option<Arc<SqliteStore>>,
+    today: chrono::NaiveDate,
+    state: Arc<Mutex<SamplerState>>,
+    alerter: Option<Arc<Alerter>>,
+) {
+    let files = LocalStore::new(&output_dir);
+    let dates = match &store {
+        Some(store) => store.sample_dates().map(|dates| {
+            let state = state.lock().unwrap();
+            let new_dates = dates
+                .into_iter()
+                .filter(|d| state.last_uploaded_date.is_none_or(|last| *d > last));
+            state.catch_up_from(new_dates, today)
+        }),
+        None => state.lock().unwrap().catch_up_dates(&output_dir, today),
+    };
+    let dates = match dates {
+        Ok(dates) => dates,
+        Err(e) => {
+            error!(error = %e, "Failed to list days awaiting upload");
+            return;
+        }
+    };
+
+    for date in dates {
+        {
+            let mut state = state.lock().unwrap();
+            state.mark_pending(date);
+            if let Err(e) = state.save(&output_dir) {
+                error!(error = %e, "Failed to save sampler state");
+            }
+        }
+
+        // SQLite keeps the samples, so there are no daily files to upload
+        let mut uploaded = None;
+        if store.is_none() {
+            info!(date = %date, "Uploading previous day's files");
+            let manifest = match upload_day(
+                &files,
+                uploads.as_ref(),
+                date,
+                upload_format,
+                RetryPolicy::default(),
+            )
+            .await
+            {
+                Ok(manifest) => manifest,
+                Err(e) => {
+                    error!(date = %date, error = %e, "Failed to upload previous day's files");
+                    continue;
+                }
+            };
+            if !manifest.is_complete() {
+                // The day stays pending and is retried on the next catch-up
+                error!(
+                    date = %date,
+                    failed = ?manifest.failed,
+                    "Some of the previous day's files could not be uploaded"
+                );
+                continue;
+            }
+            info!(date = %date, objects = manifest.objects.len(), "Successfully uploaded previous day's files");
+            uploaded = Some(manifest.uploaded_sources());
+        }
+
+        info!(date = %date, "Aggregating previous day's data");
+        let aggregated = match &store {
+            Some(store) => {
+                let selection = Selection {
+                    feed_id: None,
+                    range: TimeRange::day(date),
+                };
+                analyze_sqlite(&files, uploads.as_ref(), store, &selection).await
+            }
+            None => analyze_for_date(&files, uploads.as_ref(), date, uploaded.as_ref()).await,
+        };
+        if let Err(e) = aggregated {
+            error!(date = %date, error = %e, "Failed to aggregate previous day's data");
+            continue;
+        }
+        info!(date = %date, "Successfully aggregated and cleaned up previous day's data");
+
+        if let Some(alerter) = &alerter
+            && let Err(e) = alerter.evaluate_aggregates(uploads.as_ref()).await
+        {
+            error!(date = %date, error = %e, "Failed to evaluate aggregate alert rules");
+        }
+
+        let mut state = state.lock().unwrap();
+        state.mark_uploaded(date);
+        if let Err(e) = state.save(&output_dir) {
+            error!(error = %e, "Failed to save sampler state");
+        }
+    }
+}
 
-            let task = tokio::spawn(
-                async move {
-                    let _permit = sem.acquire().await.unwrap();
+/// Shared context for the per-feed polling tasks.
+#[derive(Clone)]
+struct FeedWorker {
+    sink: SampleSink,
+    num_samples: usize,
+    semaphore: Arc<tokio::sync::Semaphore>,
+    tracker: Arc<Mutex<ConsistencyTracker>>,
+    outages: Arc<ObserverMonitor>,
+    static_routes: Arc<StaticRoutes>,
+    state: Arc<Mutex<SamplerState>>,
+    alerter: Option<Arc<Alerter>>,
+    metrics: Arc<Metrics>,
+    shutdown: tokio::sync::watch::Receiver<bool>,
+}
 
-                    let url = feed.url.as_ref().unwrap();
+impl FeedWorker {
+    /// Polls one feed on its adaptive schedule until `num_samples` samples have
+    /// been taken (0 = forever) or shutdown is requested. An in-flight sample
+    /// always completes. Returns the feed's cache so it can be persisted.
+    async fn run(
+        mut self,
+        feed: Feed,
+        feed_types: Vec<FeedType>,
+        mut schedule: FeedSchedule,
+        mut cache: FeedCache,
+    ) -> (String, FeedCache) {
+        let agency_id = feed.agency_id();
+        let mut sample_count = 0;
+        let mut routes_generation = self.static_routes.generation();
+        let mut route_modes = self.static_routes.modes_for(&feed);
+
+        while self.num_samples == 0 || sample_count < self.num_samples {
+            if *self.shutdown.borrow() {
+                break;
+            }
+            let due = schedule.next_due();
+            tokio::select! {
+                _ = tokio::time::sleep_until(due.into()) => {}
+                _ = self.shutdown.changed() => break,
+            }
+            sample_count += 1;
 
-                    let http_client = BasicClient::new();
+            // Pick up static feeds loaded or refreshed since the last sample
+            let generation = self.static_routes.generation();
+            if generation != routes_generation {
+                routes_generation = generation;
+                route_modes = self.static_routes.modes_for(&feed);
+            }
 
-                    // Create agency directory with date-based CSV files
-                    let now = Utc::now();
-                    let date = now.format("%Y-%m-%d").to_string();
-                    let agency_dir = format!("{}/agency_id={}", output_dir, feed.id);
+            let outcome = {
+                let queued = self.metrics.queued();
+                let _permit = self.semaphore.acquire().await.unwrap();
+                drop(queued);
+                sample_feed(
+                    &feed,
+                    &feed_types,
+                    route_modes.as_ref(),
+                    &self.sink,
+                    &mut cache,
+                    &self.outages,
+                    &self.metrics,
+                )
+                .await
+            };
+            self.metrics.record_round(due.elapsed());
+
+            // Observer outages are the rater's fault, not the feed's
+            if let Some(alerter) = &self.alerter {
+                match &outcome {
+                    SampleOutcome::Sampled(_) => alerter.record_poll(&feed.id, true).await,
+                    SampleOutcome::Failed => alerter.record_poll(&feed.id, false).await,
+                    SampleOutcome::ObserverOutage => {}
+                }
+            }
 
-                    // Create directory structure if it doesn't exist
-                    if let Err(e) = std::fs::create_dir_all(&agency_dir) {
-                        error!(dir = %agency_dir, error = %e, "Failed to create agency directory");
-                        return;
+            let now = std::time::Instant::now();
+            let was_open = schedule.breaker() == BreakerState::Open;
+            match outcome {
+                SampleOutcome::Sampled(sample) => {
+                    schedule.record_success(sample.header_timestamp, now);
+                    if was_open {
+                        info!("Feed recovered, circuit breaker closed");
                     }
 
-                    let output_file = format!("{}/date={}.csv", agency_dir, date);
-
-                    let fetch_start = std::time::Instant::now();
-                    match fetch_bytes(&http_client, url).await {
-                        Ok(bytes) => {
-                            let elapsed = fetch_start.elapsed();
-                            if elapsed.as_secs() > 15 {
-                                warn!(elapsed_secs = elapsed.as_secs(), "Feed fetch was slow");
-                            }
-                            debug!(bytes = bytes.len(), "Feed bytes received, parsing");
-                            match parse_feed(&bytes) {
-                                Ok(parsed_feed) => {
-                                    debug!(
-                                        entity_count = parsed_feed.entity.len(),
-                                        "Feed parsed successfully"
-                                    );
-                                    let stats = FeedStats::from_feed(&parsed_feed)
-                                        .with_feed_info(&feed.id, &feed.name);
-                                    if let Err(e) = append_record(&output_file, &stats) {
-                                        error!(error = %e, "Failed to write stats for feed");
-                                    } else {
-                                        info!("Feed processed successfully");
-                                    }
-                                }
-                                Err(e) => {
-                                    error!(error = %e, "Feed parse failed");
-                                    let error_stats =
-                                        FeedStats::from_error("parse_error", &e.to_string())
-                                            .with_feed_info(&feed.id, &feed.name);
-                                    let _ = append_record(&output_file, &error_stats);
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            error!(error = %e, "Feed HTTP fetch failed");
-                            let error_stats = FeedStats::from_error("fetch_error", &e.to_string())
-                                .with_feed_info(&feed.id, &feed.name);
-                            let _ = append_record(&output_file, &error_stats);
-                        }
+                    self.state
+                        .lock()
+                        .unwrap()
+                        .validators
+                        .insert(feed.id.clone(), cache.validators.clone());
+
+                    let row = self.tracker.lock().unwrap().record(
+                        &agency_id,
+                        &feed.id,
+                        sample.entity_ids,
+                        now,
+                    );
+                    if let Some(row) = row
+                        && let Err(e) = self.sink.write_consistency(&row)
+                    {
+                        error!(agency_id = %row.agency_id, error = %e, "Failed to write consistency stats");
                     }
                 }
-                .instrument(feed_span),
-            );
+                SampleOutcome::ObserverOutage => {
+                    // Not the feed's fault, so don't push it towards the circuit breaker
+                    schedule.record_skipped(now);
+                }
+                SampleOutcome::Failed => {
+                    schedule.record_failure(now);
+                    if !was_open && schedule.breaker() == BreakerState::Open {
+                        warn!(
+                            retry_in_secs =
+                                schedule.next_due().saturating_duration_since(now).as_secs(),
+                            "Feed keeps failing, circuit breaker opened"
+                        );
+                    }
+                }
+            }
 
-            tasks.push(task);
+            debug!(
+                sample = sample_count,
+                interval_secs = schedule.interval().as_secs(),
+                "Next poll scheduled"
+            );
         }
 
-        // Wait for all tasks to complete
-        for task in tasks {
-            let _ = task.await;
+        (feed.id, cache)
+    }
+}
+
+/// A feed's last response body and its HTTP cache validators.
+///
+/// Validators are only sent when the matching body is available, since a
+/// `304 Not Modified` is answered by re-parsing it.
+#[derive(Default)]
+struct FeedCache {
+    validators: CacheValidators,
+    body: Option<Vec<u8>>,
+}
+
+impl FeedCache {
+    /// Path of the persisted body for a feed.
+    fn body_path(output_dir: &str, feed_id: &str) -> std::path::PathBuf {
+        Path::new(output_dir)
+            .join(FEED_CACHE_DIR)
+            .join(format!("{}.pb", feed_id))
+    }
+
+    /// Restores a feed's validators from the sampler state, along with the
+    /// body persisted at the last shutdown.
+    fn restore(output_dir: &str, feed_id: &str, state: &SamplerState) -> Self {
+        let Some(validators) = state.validators.get(feed_id) else {
+            return Self::default();
+        };
+        match std::fs::read(Self::body_path(output_dir, feed_id)) {
+            Ok(body) => Self {
+                validators: validators.clone(),
+                body: Some(body),
+            },
+            Err(_) => Self::default(),
         }
+    }
 
-        // If not the last sample, wait before next iteration
-        if num_samples == 0 || sample_count < num_samples {
-            info!(sample_rate, "Waiting before next sample");
-            tokio::time::sleep(tokio::time::Duration::from_secs(sample_rate)).await;
+    /// Writes the body to disk so the validators remain usable after a restart.
+    fn persist(&self, output_dir: &str, feed_id: &str) {
+        let Some(body) = &self.body else {
+            return;
+        };
+        if self.validators.is_empty() {
+            return;
+        }
+        let path = Self::body_path(output_dir, feed_id);
+        if let Some(dir) = path.parent()
+            && let Err(e) = std::fs::create_dir_all(dir)
+        {
+            error!(error = %e, "Failed to create feed cache directory");
+            return;
+        }
+        if let Err(e) = std::fs::write(&path, body) {
+            error!(feed_id, error = %e, "Failed to persist feed cache");
         }
     }
+}
 
-    info!(output_dir, "Finished processing all feeds");
-    Ok(())
+/// Directory inside the output directory holding feed bodies persisted at shutdown.
+const FEED_CACHE_DIR: &str = "feed_cache";
+
+/// Result of a successful feed sample.
+struct FeedSample {
+    header_timestamp: Option<u64>,
+    /// Trip/vehicle ids seen, for cross-feed consistency
+    entity_ids: Vec<(FeedType, EntityIds)>,
 }
 
-/// Uploads CSV files from the previous day to S3, optionally gzip-compressing them.
-#[tracing::instrument(skip(client), fields(bucket, output_dir, date = %date, gzip))]
-async fn upload_previous_day_files(
-    client: &aws_sdk_s3::Client,
-    bucket: &str,
-    output_dir: &str,
-    date: chrono::NaiveDate,
-    gzip: bool,
-) -> Result<()> {
-    let date_str = date.format("%Y-%m-%d").to_string();
-    let target_filename = format!("date={}.csv", date_str);
+/// Outcome of one poll of a feed.
+enum SampleOutcome {
+    Sampled(FeedSample),
+    /// The fetch or parse failed.
+    Failed,
+    /// The fetch failed because the rater itself lost connectivity.
+    ObserverOutage,
+}
 
-    // Iterate over agency_id=* directories
-    let entries = std::fs::read_dir(output_dir)?;
-    let mut upload_count = 0;
+/// Where sample and consistency rows are written.
+#[derive(Clone)]
+enum SampleSink {
+    /// Daily CSVs under the output directory.
+    Csv { output_dir: String },
+    /// The SQLite sample store.
+    Sqlite(Arc<SqliteStore>),
+}
 
-    for agency_entry in entries {
-        let agency_entry = agency_entry?;
-        let agency_path = agency_entry.path();
+impl SampleSink {
+    /// Writes one sample row of `feed_type` for a feed.
+    fn write_sample<T: CsvRecord>(&self, feed_type: FeedType, feed: &Feed, row: &T) -> Result<()> {
+        self.write_sample_on(Utc::now().date_naive(), feed_type, feed, row)
+    }
 
-        // Only process agency_id= directories
-        let dir_name = agency_entry.file_name();
-        let dir_name_str = dir_name.to_str().unwrap_or("");
-        if !agency_path.is_dir() || !dir_name_str.starts_with("agency_id=") {
-            continue;
+    /// Writes one sample row of `feed_type` for a feed into the day `date`.
+    fn write_sample_on<T: CsvRecord>(
+        &self,
+        date: chrono::NaiveDate,
+        feed_type: FeedType,
+        feed: &Feed,
+        row: &T,
+    ) -> Result<()> {
+        match self {
+            SampleSink::Csv { output_dir } => {
+                // One date-based CSV per feed type, under that type's agency directory
+                let agency_dir = format!("{}/agency_id={}", feed_type.dir(output_dir), feed.id);
+                std::fs::create_dir_all(&agency_dir)?;
+                let date = date.format("%Y-%m-%d");
+                append_record(&format!("{}/date={}.csv", agency_dir, date), row)
+            }
+            SampleSink::Sqlite(store) => {
+                store.insert_sample(feed_type, &feed.id, feed.url.as_deref(), row)
+            }
         }
+    }
 
-        let feed_id = &dir_name_str["agency_id=".len()..];
-        let csv_path = agency_path.join(&target_filename);
+    /// Writes a cross-feed consistency row for an agency.
+    fn write_consistency(&self, stats: &ConsistencyStats) -> Result<()> {
+        match self {
+            SampleSink::Csv { output_dir } => {
+                let dir = consistency_dir(output_dir, &stats.agency_id);
+                std::fs::create_dir_all(&dir)?;
+                let date = Utc::now().format("%Y-%m-%d");
+                append_record(&format!("{}/date={}.csv", dir, date), stats)
+            }
+            SampleSink::Sqlite(store) => store.insert_consistency(stats),
+        }
+    }
 
-        if !csv_path.exists() {
-            continue;
+    /// Writes an error row for each of a feed's types.
+    fn write_errors(&self, feed: &Feed, feed_types: &[FeedType], error_type: &str, message: &str) {
+        for feed_type in feed_types {
+            let error_stats = SampleStats::from_error(*feed_type, error_type, message)
+                .with_feed_info(&feed.id, &feed.name);
+            if let Err(e) = self.write_sample(*feed_type, feed, &error_stats) {
+                error!(feed_type = %feed_type, error = %e, "Failed to write error row for feed");
+            }
         }
+    }
 
-        let path = csv_path;
-        {
-            // Read the file
-            let file_contents = std::fs::read(&path)?;
-
-            // Prepare the data to upload
-            let (body, s3_key) = if gzip {
-                // Gzip compress the file
-                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-                encoder.write_all(&file_contents)?;
-                let compressed = encoder.finish()?;
-
-                let key = format!("agency_id={}/{}.gz", feed_id, target_filename);
-                (compressed, key)
-            } else {
-                let key = format!("agency_id={}/{}", feed_id, target_filename);
-                (file_contents, key)
-            };
+    /// Writes a feed's restart markers for sampler downtime, one into each day
+    /// the gap touches, so each day's aggregate counts its part of the gap.
+    fn write_observer_gap(&self, feed: &Feed, feed_types: &[FeedType], gap: &SamplerGap) {
+        let message = gap.message();
+        for marker in gap.markers() {
+            for feed_type in feed_types {
+                let row = SampleStats::from_error(*feed_type, OBSERVER_OUTAGE, &message)
+                    .with_feed_info(&feed.id, &feed.name)
+                    .with_timestamp(marker);
+                if let Err(e) = self.write_sample_on(marker.date_naive(), *feed_type, feed, &row) {
+                    error!(feed_type = %feed_type, error = %e, "Failed to write observer outage row for feed");
+                }
+            }
+        }
+    }
+}
+
+/// Fetches and parses one feed, writing a stats row (or error row) for each
+/// of its feed types to `sink`. Entities are counted by the mode of their
+/// route when `route_modes` are known.
+async fn sample_feed(
+    feed: &Feed,
+    feed_types: &[FeedType],
+    route_modes: Option<&RouteModes>,
+    sink: &SampleSink,
+    cache: &mut FeedCache,
+    outages: &ObserverMonitor,
+    metrics: &Metrics,
+) -> SampleOutcome {
+    let url = feed.url.as_ref().unwrap();
+
+    let http_client = BasicClient::new();
+
+    // Only ask for a 304 when we still hold the body it would refer to
+    let validators = if cache.body.is_some() {
+        cache.validators.clone()
+    } else {
+        CacheValidators::default()
+    };
+
+    let fetch_start = std::time::Instant::now();
+    let fetched = fetch_conditional(&http_client, url, &validators).await;
+    metrics.record_fetch(&feed.id, fetch_start.elapsed());
 
-            // Upload to S3
-            client
-                .put_object()
-                .bucket(bucket)
-                .key(&s3_key)
-                .body(ByteStream::from(body))
-                .send()
-                .await?;
+    let network_failure = fetched.as_ref().is_err_and(observer::is_network_error);
+    let observer_outage = outages.record(&feed.id, network_failure).await;
 
-            upload_count += 1;
+    let bytes = match fetched {
+        Ok(ConditionalFetch::Modified { bytes, validators }) => {
+            cache.validators = validators;
+            cache.body = Some(bytes.clone());
+            bytes
+        }
+        Ok(ConditionalFetch::NotModified) => cache.body.clone().unwrap_or_default(),
+        Err(e) if observer_outage => {
+            warn!(error = %e, "Feed unreachable while the rater is offline, recording observer outage");
+            sink.write_errors(feed, feed_types, OBSERVER_OUTAGE, &e.to_string());
+            metrics.record_error(&feed.id, OBSERVER_OUTAGE);
+            return SampleOutcome::ObserverOutage;
+        }
+        Err(e) => {
+            error!(error = %e, "Feed HTTP fetch failed");
+            sink.write_errors(feed, feed_types, "fetch_error", &e.to_string());
+            metrics.record_error(&feed.id, "fetch_error");
+            return SampleOutcome::Failed;
         }
+    };
+
+    let elapsed = fetch_start.elapsed();
+    if elapsed.as_secs() > 15 {
+        warn!(elapsed_secs = elapsed.as_secs(), "Feed fetch was slow");
     }
+    debug!(bytes = bytes.len(), "Feed bytes received, parsing");
+
+    let parsed_feed = match parse_feed(&bytes) {
+        Ok(parsed_feed) => parsed_feed,
+        Err(e) => {
+            error!(error = %e, "Feed parse failed");
+            sink.write_errors(feed, feed_types, "parse_error", &e.to_string());
+            metrics.record_error(&feed.id, "parse_error");
+            return SampleOutcome::Failed;
+        }
+    };
 
-    info!(upload_count, date = %date_str, "S3 upload complete");
-    Ok(())
+    debug!(
+        entity_count = parsed_feed.entity.len(),
+        "Feed parsed successfully"
+    );
+
+    let mut entity_ids = Vec::new();
+    for feed_type in feed_types {
+        let mut stats = SampleStats::from_feed(*feed_type, &parsed_feed)
+            .with_declared_types(&feed.entity_types)
+            .with_feed_info(&feed.id, &feed.name);
+        if let Some(routes) = route_modes {
+            stats = stats.with_route_modes(&parsed_feed, routes);
+        }
+        metrics.record_sample(&feed.id, *feed_type, &stats);
+        if let Err(e) = sink.write_sample(*feed_type, feed, &stats) {
+            error!(feed_type = %feed_type, error = %e, "Failed to write stats for feed");
+        } else {
+            info!(feed_type = %feed_type, "Feed processed successfully");
+        }
+
+        match feed_type {
+            FeedType::VehiclePositions => {
+                entity_ids.push((*feed_type, EntityIds::from_vehicle_positions(&parsed_feed)))
+            }
+            FeedType::TripUpdates => {
+                entity_ids.push((*feed_type, EntityIds::from_trip_updates(&parsed_feed)))
+            }
+            FeedType::ServiceAlerts => {}
+        }
+    }
+    metrics.record_success(&feed.id);
+
+    SampleOutcome::Sampled(FeedSample {
+        header_timestamp: parsed_feed.header.timestamp,
+        entity_ids,
+    })
 }
@@ -0,0 +1,638 @@
+//! Prometheus metrics and a health check for the long-running sampler.
+//!
+//! The sampler records into a shared [`Metrics`] as it polls feeds, and
+//! [`serve`] exposes them over plain HTTP: `/metrics` in the Prometheus text
+//! format, and `/healthz`, which fails once the sampler's main loop stops
+//! ticking.
+
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, Utc};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, warn};
+
+use crate::feed_type::FeedType;
+use crate::stats::SampleStats;
+
+/// Upper bounds in seconds of the fetch latency and round duration histogram buckets.
+pub const DURATION_BUCKETS: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// How long the main loop may go without a heartbeat before `/healthz` fails.
+/// The loop ticks every minute.
+pub const HEALTH_TIMEOUT: Duration = Duration::from_secs(180);
+
+/// Largest request head read before answering.
+const MAX_REQUEST: usize = 8192;
+
+/// How long a client may take to send its request head.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Pause after a failed accept, e.g. when out of file descriptors, so the
+/// loop doesn't spin and flood the log. Doubles while accepts keep failing.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Longest pause after failed accepts.
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Histogram over [`DURATION_BUCKETS`], rendered with cumulative buckets.
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    /// Observations per bucket, not yet cumulative; the last counts those
+    /// above every bound.
+    counts: [u64; DURATION_BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        let bucket = DURATION_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(DURATION_BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+/// What the sampler knows about one feed.
+#[derive(Debug, Default)]
+struct FeedMetrics {
+    last_success: Option<DateTime<Utc>>,
+    samples: u64,
+    /// Entities in the last sample of each feed type.
+    entities: BTreeMap<FeedType, usize>,
+    /// Support of each field in the last sample, by feed type and field name.
+    field_support: BTreeMap<(FeedType, &'static str), f64>,
+    fetch_duration: Histogram,
+    errors: BTreeMap<String, u64>,
+}
+
+/// Progress of the daily upload and aggregation.
+#[derive(Debug, Default)]
+struct UploadMetrics {
+    in_progress: bool,
+    last_uploaded_date: Option<NaiveDate>,
+    pending_days: usize,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    feeds: BTreeMap<String, FeedMetrics>,
+    round_duration: Histogram,
+    queue_depth: usize,
+    upload: UploadMetrics,
+    last_heartbeat: Option<DateTime<Utc>>,
+}
+
+/// Metrics shared by the sampler's tasks.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    inner: Mutex<Inner>,
+}
+
+/// Counts a poll waiting for a concurrency slot until dropped.
+pub struct QueueGuard<'a> {
+    metrics: &'a Metrics,
+}
+
+impl Drop for QueueGuard<'_> {
+    fn drop(&mut self) {
+        self.metrics.inner.lock().unwrap().queue_depth -= 1;
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records how long fetching a feed took, successful or not.
+    pub fn record_fetch(&self, feed_id: &str, duration: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        let feed = inner.feeds.entry(feed_id.to_string()).or_default();
+        feed.fetch_duration.observe(duration.as_secs_f64());
+    }
+
+    /// Records one successfully parsed sample of `feed_type`.
+    pub fn record_sample(&self, feed_id: &str, feed_type: FeedType, stats: &SampleStats) {
+        let mut inner = self.inner.lock().unwrap();
+        let feed = inner.feeds.entry(feed_id.to_string()).or_default();
+        feed.entities.insert(feed_type, stats.entity_count());
+        feed.field_support.retain(|(t, _), _| *t != feed_type);
+        for (field, support) in stats.field_support() {
+            feed.field_support.insert((feed_type, field), support);
+        }
+    }
+
+    /// Records a successful poll of a feed.
+    pub fn record_success(&self, feed_id: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        let feed = inner.feeds.entry(feed_id.to_string()).or_default();
+        feed.last_success = Some(Utc::now());
+        feed.samples += 1;
+    }
+
+    /// Records a failed poll of a feed, by error type (e.g. `fetch_error`).
+    pub fn record_error(&self, feed_id: &str, error_type: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        let feed = inner.feeds.entry(feed_id.to_string()).or_default();
+        *feed.errors.entry(error_type.to_string()).or_default() += 1;
+    }
+
+    /// Records a poll's round: from when it was due until its sample was
+    /// written, including the wait for a concurrency slot.
+    pub fn record_round(&self, duration: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.round_duration.observe(duration.as_secs_f64());
+    }
+
+    /// Counts a poll as queued for a concurrency slot until the guard is dropped.
+    pub fn queued(&self) -> QueueGuard<'_> {
+        self.inner.lock().unwrap().queue_depth += 1;
+        QueueGuard { metrics: self }
+    }
+
+    /// Records the upload progress and that the main loop is alive.
+    pub fn heartbeat(
+        &self,
+        upload_in_progress: bool,
+        last_uploaded_date: Option<NaiveDate>,
+        pending_days: usize,
+    ) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.last_heartbeat = Some(Utc::now());
+        inner.upload = UploadMetrics {
+            in_progress: upload_in_progress,
+            last_uploaded_date,
+            pending_days,
+        };
+    }
+
+    /// Whether the main loop has ticked within [`HEALTH_TIMEOUT`] of `now`.
+    pub fn healthy(&self, now: DateTime<Utc>) -> bool {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .last_heartbeat
+            .is_some_and(|last| (now - last).to_std().unwrap_or_default() < HEALTH_TIMEOUT)
+    }
+
+    /// All metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let inner = self.inner.lock().unwrap();
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "gtfs_rt_feed_last_success_timestamp_seconds",
+            "Unix time of the feed's last successful poll.",
+            "gauge",
+        );
+        for (feed_id, feed) in &inner.feeds {
+            if let Some(last) = feed.last_success {
+                let value = last.timestamp_millis() as f64 / 1000.0;
+                line(
+                    &mut out,
+                    "gtfs_rt_feed_last_success_timestamp_seconds",
+                    &[("feed_id", feed_id)],
+                    value,
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "gtfs_rt_feed_samples_total",
+            "Successful polls of the feed.",
+            "counter",
+        );
+        for (feed_id, feed) in &inner.feeds {
+            line(
+                &mut out,
+                "gtfs_rt_feed_samples_total",
+                &[("feed_id", feed_id)],
+                feed.samples as f64,
+            );
+        }
+
+        header(
+            &mut out,
+            "gtfs_rt_feed_vehicles",
+            "Entities in the last sample: vehicles, trip updates or alerts by feed type.",
+            "gauge",
+        );
+        for (feed_id, feed) in &inner.feeds {
+            for (feed_type, count) in &feed.entities {
+                let feed_type = feed_type.to_string();
+                line(
+                    &mut out,
+                    "gtfs_rt_feed_vehicles",
+                    &[("feed_id", feed_id), ("feed_type", &feed_type)],
+                    *count as f64,
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "gtfs_rt_feed_field_support_ratio",
+            "Share of the last sample's entities populating the field.",
+            "gauge",
+        );
+        for (feed_id, feed) in &inner.feeds {
+            for ((feed_type, field), support) in &feed.field_support {
+                let feed_type = feed_type.to_string();
+                line(
+                    &mut out,
+                    "gtfs_rt_feed_field_support_ratio",
+                    &[
+                        ("feed_id", feed_id),
+                        ("feed_type", &feed_type),
+                        ("field", field),
+                    ],
+                    *support,
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "gtfs_rt_feed_fetch_duration_seconds",
+            "Time taken to fetch the feed.",
+            "histogram",
+        );
+        for (feed_id, feed) in &inner.feeds {
+            histogram(
+                &mut out,
+                "gtfs_rt_feed_fetch_duration_seconds",
+                &[("feed_id", feed_id)],
+                &feed.fetch_duration,
+            );
+        }
+
+        header(
+            &mut out,
+            "gtfs_rt_feed_errors_total",
+            "Failed polls of the feed by error type.",
+            "counter",
+        );
+        for (feed_id, feed) in &inner.feeds {
+            for (error_type, count) in &feed.errors {
+                line(
+                    &mut out,
+                    "gtfs_rt_feed_errors_total",
+                    &[("feed_id", feed_id), ("error_type", error_type)],
+                    *count as f64,
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "gtfs_rt_sampler_round_duration_seconds",
+            "Time from a poll being due until its sample is written, including queueing.",
+            "histogram",
+        );
+        histogram(
+            &mut out,
+            "gtfs_rt_sampler_round_duration_seconds",
+            &[],
+            &inner.round_duration,
+        );
+
+        header(
+            &mut out,
+            "gtfs_rt_sampler_queue_depth",
+            "Polls due and waiting for a concurrency slot.",
+            "gauge",
+        );
+        line(
+            &mut out,
+            "gtfs_rt_sampler_queue_depth",
+            &[],
+            inner.queue_depth as f64,
+        );
+
+        header(
+            &mut out,
+            "gtfs_rt_sampler_feeds",
+            "Feeds polled so far.",
+            "gauge",
+        );
+        line(
+            &mut out,
+            "gtfs_rt_sampler_feeds",
+            &[],
+            inner.feeds.len() as f64,
+        );
+
+        header(
+            &mut out,
+            "gtfs_rt_upload_in_progress",
+            "1 while past days are being uploaded and aggregated.",
+            "gauge",
+        );
+        let in_progress = if inner.upload.in_progress { 1.0 } else { 0.0 };
+        line(&mut out, "gtfs_rt_upload_in_progress", &[], in_progress);
+
+        header(
+            &mut out,
+            "gtfs_rt_upload_pending_days",
+            "Days whose upload or aggregation started but hasn't completed.",
+            "gauge",
+        );
+        line(
+            &mut out,
+            "gtfs_rt_upload_pending_days",
+            &[],
+            inner.upload.pending_days as f64,
+        );
+
+        if let Some(date) = inner.upload.last_uploaded_date {
+            header(
+                &mut out,
+                "gtfs_rt_upload_last_date_timestamp_seconds",
+                "Unix time of the start of the last day uploaded and aggregated.",
+                "gauge",
+            );
+            let value = date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp() as f64;
+            line(
+                &mut out,
+                "gtfs_rt_upload_last_date_timestamp_seconds",
+                &[],
+                value,
+            );
+        }
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn line(out: &mut String, name: &str, labels: &[(&str, &str)], value: f64) {
+    out.push_str(name);
+    if !labels.is_empty() {
+        let labels: Vec<String> = labels
+            .iter()
+            .map(|(key, value)| format!("{}=\"{}\"", key, escape(value)))
+            .collect();
+        let _ = write!(out, "{{{}}}", labels.join(","));
+    }
+    let _ = writeln!(out, " {}", value);
+}
+
+fn histogram(out: &mut String, name: &str, labels: &[(&str, &str)], histogram: &Histogram) {
+    let bucket = format!("{}_bucket", name);
+    let mut cumulative = 0;
+    for (bound, count) in DURATION_BUCKETS.iter().zip(&histogram.counts) {
+        cumulative += count;
+        let le = bound.to_string();
+        let labels: Vec<(&str, &str)> = labels
+            .iter()
+            .copied()
+            .chain([("le", le.as_str())])
+            .collect();
+        line(out, &bucket, &labels, cumulative as f64);
+    }
+    let labels_inf: Vec<(&str, &str)> = labels.iter().copied().chain([("le", "+Inf")]).collect();
+    line(out, &bucket, &labels_inf, histogram.count as f64);
+    line(out, &format!("{}_sum", name), labels, histogram.sum);
+    line(
+        out,
+        &format!("{}_count", name),
+        labels,
+        histogram.count as f64,
+    );
+}
+
+/// Escapes a label value for the text format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serves `/metrics` and `/healthz` from `metrics` on `listener` until the
+/// task is dropped.
+pub async fn serve(listener: TcpListener, metrics: Arc<Metrics>) {
+    let mut backoff = ACCEPT_BACKOFF;
+    loop {
+        let (socket, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!(error = %e, retry_in = ?backoff, "Failed to accept metrics connection");
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+                continue;
+            }
+        };
+        backoff = ACCEPT_BACKOFF;
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = respond(socket, &metrics, REQUEST_TIMEOUT).await {
+                debug!(peer = %peer, error = %e, "Metrics request failed");
+            }
+        });
+    }
+}
+
+/// Answers one HTTP request and closes the connection. A client that hasn't
+/// sent its request head within `timeout` is dropped.
+async fn respond(mut socket: TcpStream, metrics: &Metrics, timeout: Duration) -> Result<()> {
+    let mut request = Vec::new();
+    let read = async {
+        let mut buf = [0; 1024];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST {
+            let n = socket.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..n]);
+        }
+        anyhow::Ok(())
+    };
+    tokio::time::timeout(timeout, read)
+        .await
+        .map_err(|_| anyhow::anyhow!("request not received within {:?}", timeout))??;
+    let request = String::from_utf8_lossy(&request);
+    let mut parts = request.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    // Ignore any query string, e.g. from probes adding cache busters
+    let path = path.split('?').next().unwrap_or(path);
+
+    let (status, content_type, body) = match (method, path) {
+        ("GET", "/metrics") => (
+            "200 OK",
+            "text/plain; version=0.0.4; charset=utf-8",
+            metrics.render(),
+        ),
+        ("GET", "/healthz") if metrics.healthy(Utc::now()) => {
+            ("200 OK", "text/plain; charset=utf-8", "ok\n".to_string())
+        }
+        ("GET", "/healthz") => (
+            "503 Service Unavailable",
+            "text/plain; charset=utf-8",
+            "sampler loop stalled\n".to_string(),
+        ),
+        ("GET", _) => (
+            "404 Not Found",
+            "text/plain; charset=utf-8",
+            "not found\n".to_string(),
+        ),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain; charset=utf-8",
+            "method not allowed\n".to_string(),
+        ),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::FeedStats;
+
+    fn sample(vehicles: usize, with_bearing: usize) -> SampleStats {
+        let mut stats = FeedStats {
+            vehicles,
+            ..Default::default()
+        };
+        stats.fields.set("bearing", with_bearing);
+        SampleStats::VehiclePositions(stats)
+    }
+
+    #[test]
+    fn test_render_feed_metrics() {
+        let metrics = Metrics::new();
+        metrics.record_fetch("a", Duration::from_millis(300));
+        metrics.record_fetch("a", Duration::from_secs(120));
+        metrics.record_sample("a", FeedType::VehiclePositions, &sample(4, 3));
+        metrics.record_success("a");
+        metrics.record_error("a", "fetch_error");
+        metrics.record_error("a", "fetch_error");
+
+        let text = metrics.render();
+        assert!(text.contains("gtfs_rt_feed_samples_total{feed_id=\"a\"} 1\n"));
+        assert!(text.contains("gtfs_rt_feed_vehicles{feed_id=\"a\",feed_type=\"vp\"} 4\n"));
+        assert!(text.contains(
+            "gtfs_rt_feed_field_support_ratio{feed_id=\"a\",feed_type=\"vp\",field=\"bearing\"} 0.75\n"
+        ));
+        assert!(
+            text.contains(
+                "gtfs_rt_feed_errors_total{feed_id=\"a\",error_type=\"fetch_error\"} 2\n"
+            )
+        );
+        // Cumulative buckets, with the slow fetch only in +Inf
+        assert!(
+            text.contains(
+                "gtfs_rt_feed_fetch_duration_seconds_bucket{feed_id=\"a\",le=\"0.25\"} 0\n"
+            )
+        );
+        assert!(
+            text.contains(
+                "gtfs_rt_feed_fetch_duration_seconds_bucket{feed_id=\"a\",le=\"0.5\"} 1\n"
+            )
+        );
+        assert!(
+            text.contains(
+                "gtfs_rt_feed_fetch_duration_seconds_bucket{feed_id=\"a\",le=\"60\"} 1\n"
+            )
+        );
+        assert!(
+            text.contains(
+                "gtfs_rt_feed_fetch_duration_seconds_bucket{feed_id=\"a\",le=\"+Inf\"} 2\n"
+            )
+        );
+        assert!(text.contains("gtfs_rt_feed_fetch_duration_seconds_count{feed_id=\"a\"} 2\n"));
+    }
+
+    #[test]
+    fn test_queue_depth_and_health() {
+        let metrics = Metrics::new();
+        {
+            let _queued = metrics.queued();
+            assert!(metrics.render().contains("gtfs_rt_sampler_queue_depth 1\n"));
+        }
+        assert!(metrics.render().contains("gtfs_rt_sampler_queue_depth 0\n"));
+
+        assert!(!metrics.healthy(Utc::now()));
+        metrics.heartbeat(true, NaiveDate::from_ymd_opt(2024, 1, 1), 2);
+        assert!(metrics.healthy(Utc::now()));
+        assert!(!metrics.healthy(Utc::now() + chrono::Duration::minutes(5)));
+
+        let text = metrics.render();
+        assert!(text.contains("gtfs_rt_upload_in_progress 1\n"));
+        assert!(text.contains("gtfs_rt_upload_pending_days 2\n"));
+        assert!(text.contains("gtfs_rt_upload_last_date_timestamp_seconds 1704067200\n"));
+    }
+
+    #[test]
+    fn test_escape_label_values() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+
+    async fn get(addr: std::net::SocketAddr, path: &str) -> String {
+        let mut socket = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        socket.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        socket.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_serve_endpoints() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let metrics = Arc::new(Metrics::new());
+        metrics.record_success("a");
+        let server = tokio::spawn(serve(listener, metrics.clone()));
+
+        let response = get(addr, "/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("gtfs_rt_feed_samples_total{feed_id=\"a\"} 1\n"));
+
+        assert!(get(addr, "/healthz").await.starts_with("HTTP/1.1 503"));
+        metrics.heartbeat(false, None, 0);
+        assert!(
+            get(addr, "/healthz?probe=1")
+                .await
+                .ends_with("\r\n\r\nok\n")
+        );
+        assert!(get(addr, "/other").await.starts_with("HTTP/1.1 404"));
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_silent_client_times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+
+        let result = respond(socket, &Metrics::new(), Duration::from_millis(50)).await;
+        assert!(result.unwrap_err().to_string().contains("not received"));
+    }
+}
@@ -0,0 +1,97 @@
+//! Object storage used for every upload and for reading collected files back.
+//!
+//! [`ObjectStore`] is a minimal key/value interface over `/`-separated keys.
+//! [`LocalStore`] maps keys onto a directory (the sampler's output directory),
+//! [`S3Store`] onto a bucket (optionally at a MinIO/localstack endpoint), and
+//! [`MemoryStore`] keeps everything in memory so the pipeline can be tested
+//! offline. [`PrefixStore`] nests one store's keys under a prefix of another.
+
+pub mod local;
+pub mod memory;
+pub mod prefix;
+pub mod s3;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use md5::{Digest, Md5};
+use serde::Serialize;
+
+pub use local::LocalStore;
+pub use memory::MemoryStore;
+pub use prefix::PrefixStore;
+pub use s3::S3Store;
+
+/// Size and checksum of a stored object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectInfo {
+    /// Size in bytes.
+    pub size: u64,
+    /// Hex MD5 of the object's contents, when the store reports one. S3
+    /// doesn't: it verifies uploads against their `Content-MD5` itself.
+    pub checksum: Option<String>,
+}
+
+impl ObjectInfo {
+    /// Describes `body` with a hex MD5 checksum.
+    pub fn of(body: &[u8]) -> Self {
+        Self {
+            size: body.len() as u64,
+            checksum: Some(md5_hex(body)),
+        }
+    }
+
+    /// Whether the stored object is known to hold exactly `expected`'s bytes.
+    ///
+    /// Checksums are compared when `expected` has one; otherwise the store
+    /// vouches for the contents and only the size is compared.
+    pub fn matches(&self, expected: &ObjectInfo) -> bool {
+        self.size == expected.size
+            && expected
+                .checksum
+                .as_ref()
+                .is_none_or(|checksum| self.checksum.as_ref() == Some(checksum))
+    }
+}
+
+/// Hex-encoded MD5 digest of `bytes`.
+pub fn md5_hex(bytes: &[u8]) -> String {
+    hex::encode(Md5::digest(bytes))
+}
+
+/// A flat store of byte objects addressed by `/`-separated keys.
+#[async_trait]
+pub trait ObjectStore: Send + Sync {
+    /// Writes an object, replacing any existing one.
+    async fn put(&self, key: &str, body: Vec<u8>, content_type: Option<&str>) -> Result<()>;
+
+    /// Reads an object, or `None` if it does not exist.
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Lists all keys starting with `prefix`, sorted.
+    async fn list(&self, prefix: &str) -> Result<Vec<String>>;
+
+    /// Deletes an object. Deleting a missing key is not an error.
+    async fn delete(&self, key: &str) -> Result<()>;
+
+    /// Size and checksum of an object, or `None` if it does not exist.
+    ///
+    /// The default implementation reads the whole object back.
+    async fn head(&self, key: &str) -> Result<Option<ObjectInfo>> {
+        Ok(self.get(key).await?.map(|body| ObjectInfo::of(&body)))
+    }
+
+    /// Size and checksum [`head`](Self::head) reports once `body` is stored.
+    ///
+    /// Stores that check uploads against a checksum themselves may leave the
+    /// checksum out, so only the size is compared.
+    fn expected_info(&self, body: &[u8]) -> ObjectInfo {
+        ObjectInfo::of(body)
+    }
+}
+
+/// Serializes a value to JSON and writes it with `application/json` content type.
+#[tracing::instrument(skip(store, value), fields(key))]
+pub async fn put_json(store: &dyn ObjectStore, key: &str, value: &impl Serialize) -> Result<()> {
+    let body = serde_json::to_vec(value)?;
+    store.put(key, body, Some("application/json")).await
+}
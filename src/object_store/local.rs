@@ -0,0 +1,147 @@
+use super::ObjectStore;
+use anyhow::Result;
+use async_trait::async_trait;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// An [`ObjectStore`] backed by a local directory; keys are relative paths.
+#[derive(Debug, Clone)]
+pub struct LocalStore {
+    root: PathBuf,
+}
+
+impl LocalStore {
+    /// Creates a store rooted at `root`. The directory need not exist yet.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+#[async_trait]
+impl ObjectStore for LocalStore {
+    async fn put(&self, key: &str, body: Vec<u8>, _content_type: Option<&str>) -> Result<()> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(path, body).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match tokio::fs::read(self.path(key)).await {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        // Only walk the deepest directory the prefix names
+        let dir = match prefix.rfind('/') {
+            Some(i) => &prefix[..i],
+            None => "",
+        };
+        let root = self.root.clone();
+        let prefix = prefix.to_string();
+        let dir = dir.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let mut keys = Vec::new();
+            collect_keys(&root, &root.join(&dir), &mut keys)?;
+            keys.retain(|k| k.starts_with(&prefix));
+            keys.sort();
+            Ok(keys)
+        })
+        .await?
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match tokio::fs::remove_file(self.path(key)).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Recursively collects the keys of all files under `dir`, relative to `root`.
+fn collect_keys(root: &Path, dir: &Path, keys: &mut Vec<String>) -> Result<()> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_keys(root, &path, keys)?;
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            let key = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            keys.push(key);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[tokio::test]
+    async fn test_local_store_roundtrip() {
+        let root = env::temp_dir().join("gtfs_rt_rater_test_local_store");
+        let _ = fs::remove_dir_all(&root);
+        let store = LocalStore::new(&root);
+
+        store
+            .put("agency_id=a/date=2024-01-01.csv", b"x".to_vec(), None)
+            .await
+            .unwrap();
+        store
+            .put(
+                "trip_updates/agency_id=b/date=2024-01-01.csv",
+                b"y".to_vec(),
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            store.list("agency_id=").await.unwrap(),
+            vec!["agency_id=a/date=2024-01-01.csv"]
+        );
+        assert_eq!(store.list("").await.unwrap().len(), 2);
+        assert_eq!(
+            store.get("agency_id=a/date=2024-01-01.csv").await.unwrap(),
+            Some(b"x".to_vec())
+        );
+
+        store
+            .delete("agency_id=a/date=2024-01-01.csv")
+            .await
+            .unwrap();
+        assert!(
+            store
+                .get("agency_id=a/date=2024-01-01.csv")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(store.list("missing/").await.unwrap().is_empty());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
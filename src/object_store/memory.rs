@@ -0,0 +1,73 @@
+use super::ObjectStore;
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// An [`ObjectStore`] held entirely in memory, for tests and dry runs.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    objects: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+
+impl MemoryStore {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// All keys currently stored, sorted.
+    pub fn keys(&self) -> Vec<String> {
+        self.objects.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+#[async_trait]
+impl ObjectStore for MemoryStore {
+    async fn put(&self, key: &str, body: Vec<u8>, _content_type: Option<&str>) -> Result<()> {
+        self.objects.lock().unwrap().insert(key.to_string(), body);
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.objects.lock().unwrap().get(key).cloned())
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        Ok(self
+            .objects
+            .lock()
+            .unwrap()
+            .range(prefix.to_string()..)
+            .map(|(k, _)| k)
+            .take_while(|k| k.starts_with(prefix))
+            .cloned()
+            .collect())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.objects.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_put_get_list_delete() {
+        let store = MemoryStore::new();
+        store.put("a/1", b"one".to_vec(), None).await.unwrap();
+        store.put("a/2", b"two".to_vec(), None).await.unwrap();
+        store.put("b/1", b"three".to_vec(), None).await.unwrap();
+
+        assert_eq!(store.get("a/1").await.unwrap().unwrap(), b"one");
+        assert!(store.get("missing").await.unwrap().is_none());
+        assert_eq!(store.list("a/").await.unwrap(), vec!["a/1", "a/2"]);
+
+        store.delete("a/1").await.unwrap();
+        store.delete("a/1").await.unwrap();
+        assert_eq!(store.keys(), vec!["a/2", "b/1"]);
+    }
+}
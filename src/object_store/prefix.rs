@@ -0,0 +1,79 @@
+use super::{ObjectInfo, ObjectStore};
+use anyhow::Result;
+use async_trait::async_trait;
+
+/// An [`ObjectStore`] view of the keys under `prefix` in another store.
+///
+/// Keys passed in are relative to the prefix, e.g. `aggregates/feeds.json`
+/// in a `PrefixStore` with prefix `versions/algorithm=v4/` is stored as
+/// `versions/algorithm=v4/aggregates/feeds.json`.
+pub struct PrefixStore<'a> {
+    inner: &'a dyn ObjectStore,
+    prefix: String,
+}
+
+impl<'a> PrefixStore<'a> {
+    /// Creates a view of `inner` under `prefix`, which should end in `/`.
+    pub fn new(inner: &'a dyn ObjectStore, prefix: impl Into<String>) -> Self {
+        Self {
+            inner,
+            prefix: prefix.into(),
+        }
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+}
+
+#[async_trait]
+impl ObjectStore for PrefixStore<'_> {
+    async fn put(&self, key: &str, body: Vec<u8>, content_type: Option<&str>) -> Result<()> {
+        self.inner.put(&self.key(key), body, content_type).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.inner.get(&self.key(key)).await
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        let keys = self.inner.list(&self.key(prefix)).await?;
+        Ok(keys
+            .into_iter()
+            .filter_map(|key| key.strip_prefix(&self.prefix).map(str::to_string))
+            .collect())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.inner.delete(&self.key(key)).await
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<ObjectInfo>> {
+        self.inner.head(&self.key(key)).await
+    }
+
+    fn expected_info(&self, body: &[u8]) -> ObjectInfo {
+        self.inner.expected_info(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::MemoryStore;
+
+    #[tokio::test]
+    async fn test_prefix_store_nests_keys() {
+        let inner = MemoryStore::new();
+        inner.put("other.json", vec![0], None).await.unwrap();
+        let store = PrefixStore::new(&inner, "v2/");
+
+        store.put("a/b.json", vec![1], None).await.unwrap();
+
+        assert_eq!(inner.keys(), vec!["other.json", "v2/a/b.json"]);
+        assert_eq!(store.list("a/").await.unwrap(), vec!["a/b.json"]);
+        assert_eq!(store.get("a/b.json").await.unwrap(), Some(vec![1]));
+        store.delete("a/b.json").await.unwrap();
+        assert_eq!(inner.keys(), vec!["other.json"]);
+    }
+}
@@ -0,0 +1,255 @@
+use super::{ObjectInfo, ObjectStore};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use aws_sdk_s3::operation::head_object::HeadObjectOutput;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use md5::{Digest, Md5};
+
+/// Objects at least this large are uploaded in parts.
+const MULTIPART_THRESHOLD: usize = 16 * 1024 * 1024;
+
+/// Size of each part of a multipart upload (S3's minimum is 5 MiB).
+const PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// Base64 MD5 for the `Content-MD5` header, which S3 checks on receipt.
+fn content_md5(bytes: &[u8]) -> String {
+    BASE64.encode(Md5::digest(bytes))
+}
+
+/// Size of the object a HEAD request describes.
+///
+/// The ETag is left out: with SSE-KMS or SSE-C encryption it is not an MD5 of
+/// the contents, so it can't be checked against the uploaded bytes. S3
+/// already rejects a corrupted body by its `Content-MD5` header.
+fn head_info(head: &HeadObjectOutput) -> ObjectInfo {
+    ObjectInfo {
+        size: head.content_length().unwrap_or_default().max(0) as u64,
+        checksum: None,
+    }
+}
+
+/// An [`ObjectStore`] backed by an S3 bucket.
+#[derive(Debug, Clone)]
+pub struct S3Store {
+    client: aws_sdk_s3::Client,
+    bucket: String,
+}
+
+impl S3Store {
+    /// Wraps an existing S3 client.
+    pub fn new(client: aws_sdk_s3::Client, bucket: &str) -> Self {
+        Self {
+            client,
+            bucket: bucket.to_string(),
+        }
+    }
+
+    /// Connects using the environment's AWS configuration.
+    ///
+    /// With `endpoint_url` (e.g. `http://localhost:9000` for MinIO), requests
+    /// go to that endpoint using path-style addressing.
+    pub async fn connect(bucket: &str, endpoint_url: Option<&str>) -> Self {
+        let config = aws_config::load_from_env().await;
+        let mut builder = aws_sdk_s3::config::Builder::from(&config);
+        if let Some(endpoint_url) = endpoint_url {
+            builder = builder.endpoint_url(endpoint_url).force_path_style(true);
+        }
+        Self::new(aws_sdk_s3::Client::from_conf(builder.build()), bucket)
+    }
+
+    /// Name of the bucket this store writes to.
+    pub fn bucket(&self) -> &str {
+        &self.bucket
+    }
+
+    /// Uploads `body` in [`PART_SIZE`] parts, aborting the upload if any part fails.
+    async fn put_multipart(
+        &self,
+        key: &str,
+        body: &[u8],
+        content_type: Option<&str>,
+    ) -> Result<()> {
+        let upload = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .set_content_type(content_type.map(str::to_string))
+            .send()
+            .await?;
+        let upload_id = upload
+            .upload_id()
+            .context("S3 returned no multipart upload id")?;
+
+        let result = self.upload_parts(key, upload_id, body).await;
+        if result.is_err() {
+            // Don't leave orphaned parts accruing storage charges
+            let _ = self
+                .client
+                .abort_multipart_upload()
+                .bucket(&self.bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .send()
+                .await;
+        }
+        result
+    }
+
+    async fn upload_parts(&self, key: &str, upload_id: &str, body: &[u8]) -> Result<()> {
+        let mut parts = Vec::new();
+        for (i, chunk) in body.chunks(PART_SIZE).enumerate() {
+            let part_number = i as i32 + 1;
+            let part = self
+                .client
+                .upload_part()
+                .bucket(&self.bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .part_number(part_number)
+                .content_md5(content_md5(chunk))
+                .body(ByteStream::from(chunk.to_vec()))
+                .send()
+                .await?;
+            parts.push(
+                CompletedPart::builder()
+                    .part_number(part_number)
+                    .set_e_tag(part.e_tag().map(str::to_string))
+                    .build(),
+            );
+        }
+
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ObjectStore for S3Store {
+    #[tracing::instrument(skip(self, body), fields(bucket = %self.bucket, bytes = body.len()))]
+    async fn put(&self, key: &str, body: Vec<u8>, content_type: Option<&str>) -> Result<()> {
+        if body.len() >= MULTIPART_THRESHOLD {
+            return self.put_multipart(key, &body, content_type).await;
+        }
+
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_md5(content_md5(&body))
+            .body(ByteStream::from(body))
+            .set_content_type(content_type.map(str::to_string))
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let resp = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await;
+        match resp {
+            Ok(resp) => Ok(Some(resp.body.collect().await?.to_vec())),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(prefix)
+            .into_paginator()
+            .send();
+        while let Some(page) = pages.next().await {
+            keys.extend(
+                page?
+                    .contents()
+                    .iter()
+                    .filter_map(|o| o.key().map(str::to_string)),
+            );
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<ObjectInfo>> {
+        let resp = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await;
+        let resp = match resp {
+            Ok(resp) => resp,
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Some(head_info(&resp)))
+    }
+
+    fn expected_info(&self, body: &[u8]) -> ObjectInfo {
+        ObjectInfo {
+            size: body.len() as u64,
+            checksum: None,
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> S3Store {
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(aws_sdk_s3::config::BehaviorVersion::latest())
+            .region(aws_sdk_s3::config::Region::new("us-east-1"))
+            .build();
+        S3Store::new(aws_sdk_s3::Client::from_conf(config), "bucket")
+    }
+
+    #[test]
+    fn test_head_with_non_md5_etag_matches_by_size() {
+        // SSE-KMS objects get an ETag unrelated to the MD5 of their contents
+        let head = HeadObjectOutput::builder()
+            .e_tag("\"7e3a0c5bd2e5bf3f8e2e4c81f4bd1a0e-kms\"")
+            .content_length(3)
+            .build();
+        let info = head_info(&head);
+        assert!(info.matches(&store().expected_info(b"abc")));
+        assert!(!info.matches(&store().expected_info(b"abcd")));
+    }
+}
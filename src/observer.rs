@@ -0,0 +1,307 @@
+//! Detection of outages on the rater's side rather than the feed's.
+//!
+//! When the sampler's own network drops, every feed fails at once. Feeds are
+//! polled staggered, so the first ones to fail can't wait for the rest: a
+//! [`ConnectivityProbe`] checks whether well-known hosts are reachable as soon
+//! as a feed fails to connect, and the failure is the observer's when they
+//! aren't. The [`OutageDetector`] also watches the latest outcome of each feed
+//! and flags a failure as an observer outage when nearly all recently polled
+//! feeds failed to connect. Periods when the sampler was not running at all
+//! are recorded as marker rows on restart. All are written with the
+//! [`OBSERVER_OUTAGE`] error type so aggregation leaves them out of the feed's
+//! uptime.
+
+use chrono::{DateTime, NaiveTime, Utc};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Error type recorded for samples lost to an outage on the rater's side.
+pub const OBSERVER_OUTAGE: &str = "observer_outage";
+
+/// Shortest sampler downtime that is recorded as an observer outage on restart.
+pub const MIN_OBSERVER_GAP: Duration = Duration::from_secs(300);
+
+/// Fewest recently polled feeds needed before failures are blamed on the observer.
+const MIN_FEEDS: usize = 3;
+
+/// Share of recently polled feeds that must have failed to connect.
+const FAILURE_THRESHOLD: f64 = 0.9;
+
+/// Hosts the rater should always be able to reach, looked up by name so a
+/// DNS failure counts too.
+const PROBE_HOSTS: &[&str] = &["one.one.one.one:443", "dns.google:443"];
+
+/// How long a probe result is reused, so a wave of failing feeds probes once.
+const PROBE_TTL: Duration = Duration::from_secs(10);
+
+/// How long to wait for a probe connection.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Checks the rater's own connectivity by connecting to well-known hosts.
+#[derive(Debug)]
+pub struct ConnectivityProbe {
+    hosts: Vec<String>,
+    last: Mutex<Option<(Instant, bool)>>,
+}
+
+impl Default for ConnectivityProbe {
+    fn default() -> Self {
+        Self::new(PROBE_HOSTS.iter().map(|h| h.to_string()).collect())
+    }
+}
+
+impl ConnectivityProbe {
+    /// A probe of `hosts` (`host:port`).
+    pub fn new(hosts: Vec<String>) -> Self {
+        Self {
+            hosts,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Returns true when any probe host accepts a connection. Results are
+    /// reused for [`PROBE_TTL`].
+    pub async fn is_online(&self) -> bool {
+        if let Some((at, online)) = *self.last.lock().unwrap()
+            && at.elapsed() < PROBE_TTL
+        {
+            return online;
+        }
+
+        let mut online = false;
+        for host in &self.hosts {
+            let connect = tokio::net::TcpStream::connect(host.as_str());
+            if let Ok(Ok(_)) = tokio::time::timeout(PROBE_TIMEOUT, connect).await {
+                online = true;
+                break;
+            }
+        }
+        *self.last.lock().unwrap() = Some((Instant::now(), online));
+        online
+    }
+}
+
+/// Returns true when `error` is a connection or DNS failure rather than an
+/// HTTP or parse error from a reachable server.
+pub fn is_network_error(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .filter_map(|e| e.downcast_ref::<reqwest::Error>())
+        .any(|e| e.is_connect())
+}
+
+/// Message prefix of restart marker rows, followed by the gap's start (RFC 3339).
+const GAP_PREFIX: &str = "sampler not running since ";
+
+/// A period the sampler was not running, from its last heartbeat to a restart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SamplerGap {
+    pub since: DateTime<Utc>,
+    pub until: DateTime<Utc>,
+}
+
+impl SamplerGap {
+    /// The gap since the sampler's last heartbeat, if it was long enough to
+    /// count as an observer outage.
+    pub fn detect(last_heartbeat: Option<DateTime<Utc>>, now: DateTime<Utc>) -> Option<Self> {
+        let since = last_heartbeat?;
+        let gap = (now - since).to_std().ok()?;
+        (gap >= MIN_OBSERVER_GAP).then_some(Self { since, until: now })
+    }
+
+    /// Message of the gap's marker rows, recording when it started.
+    pub fn message(&self) -> String {
+        format!("{}{}", GAP_PREFIX, self.since.to_rfc3339())
+    }
+
+    /// Timestamps of the gap's marker rows: the last millisecond of every UTC
+    /// day it started or ran through, then its end. Each day's aggregate
+    /// counts the part of the gap falling on that day from its own marker.
+    pub fn markers(&self) -> Vec<DateTime<Utc>> {
+        let mut markers = Vec::new();
+        let mut day = self.since.date_naive();
+        while day < self.until.date_naive() {
+            let next = day.succ_opt().expect("date in range");
+            markers
+                .push(next.and_time(NaiveTime::MIN).and_utc() - chrono::Duration::milliseconds(1));
+            day = next;
+        }
+        markers.push(self.until);
+        markers
+    }
+}
+
+/// Start of the sampler gap a marker row's `message` records, if it is one.
+pub fn gap_start(message: &str) -> Option<DateTime<Utc>> {
+    let since = message.strip_prefix(GAP_PREFIX)?;
+    DateTime::parse_from_rfc3339(since)
+        .ok()
+        .map(|since| since.with_timezone(&Utc))
+}
+
+/// Tracks each feed's latest fetch outcome to tell feed failures apart from
+/// failures of the rater's own connectivity.
+#[derive(Debug)]
+pub struct OutageDetector {
+    window: Duration,
+    latest: HashMap<String, (Instant, bool)>,
+}
+
+impl OutageDetector {
+    /// Creates a detector that considers feeds polled within `window`.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            latest: HashMap::new(),
+        }
+    }
+
+    /// Records a fetch outcome and returns true when it is part of an observer outage.
+    ///
+    /// A network failure is an observer outage when at least [`MIN_FEEDS`]
+    /// feeds were polled within the window and nearly all of them last failed
+    /// to connect.
+    pub fn record(&mut self, feed_id: &str, network_failure: bool, now: Instant) -> bool {
+        self.latest
+            .insert(feed_id.to_string(), (now, network_failure));
+        self.latest
+            .retain(|_, (at, _)| now.saturating_duration_since(*at) <= self.window);
+
+        if !network_failure || self.latest.len() < MIN_FEEDS {
+            return false;
+        }
+
+        let failed = self.latest.values().filter(|(_, failed)| *failed).count();
+        failed as f64 / self.latest.len() as f64 >= FAILURE_THRESHOLD
+    }
+}
+
+/// Judges each fetch failure with both the [`ConnectivityProbe`] and the
+/// [`OutageDetector`].
+#[derive(Debug)]
+pub struct ObserverMonitor {
+    detector: Mutex<OutageDetector>,
+    probe: ConnectivityProbe,
+}
+
+impl ObserverMonitor {
+    /// A monitor considering feeds polled within `window` and probing with `probe`.
+    pub fn new(window: Duration, probe: ConnectivityProbe) -> Self {
+        Self {
+            detector: Mutex::new(OutageDetector::new(window)),
+            probe,
+        }
+    }
+
+    /// Records a fetch outcome and returns true when it is part of an
+    /// observer outage: most feeds are failing, or this one failed to connect
+    /// while the probe hosts are unreachable too.
+    pub async fn record(&self, feed_id: &str, network_failure: bool) -> bool {
+        let most_feeds_down =
+            self.detector
+                .lock()
+                .unwrap()
+                .record(feed_id, network_failure, Instant::now());
+        // Don't wait for the other feeds to fail before blaming our own network
+        most_feeds_down || (network_failure && !self.probe.is_online().await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_all_feeds_failing_is_observer_outage() {
+        let now = Instant::now();
+        let mut detector = OutageDetector::new(Duration::from_secs(60));
+        assert!(!detector.record("a", true, now));
+        assert!(!detector.record("b", true, now));
+        assert!(detector.record("c", true, now));
+    }
+
+    #[test]
+    fn test_single_failing_feed_is_not_observer_outage() {
+        let now = Instant::now();
+        let mut detector = OutageDetector::new(Duration::from_secs(60));
+        detector.record("a", false, now);
+        detector.record("b", false, now);
+        detector.record("c", false, now);
+        assert!(!detector.record("d", true, now));
+    }
+
+    #[test]
+    fn test_stale_outcomes_are_ignored() {
+        let start = Instant::now();
+        let mut detector = OutageDetector::new(Duration::from_secs(60));
+        detector.record("a", false, start);
+        detector.record("b", false, start);
+
+        let later = start + Duration::from_secs(120);
+        detector.record("c", true, later);
+        detector.record("d", true, later);
+        assert!(detector.record("e", true, later));
+    }
+
+    #[tokio::test]
+    async fn test_probe_online_when_any_host_connects() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let reachable = listener.local_addr().unwrap().to_string();
+        // Bound and dropped, so nothing listens there
+        let unreachable = {
+            let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            closed.local_addr().unwrap().to_string()
+        };
+
+        let probe = ConnectivityProbe::new(vec![unreachable.clone(), reachable]);
+        assert!(probe.is_online().await);
+        assert!(!ConnectivityProbe::new(vec![unreachable]).is_online().await);
+    }
+
+    #[tokio::test]
+    async fn test_monitor_blames_observer_when_probe_fails() {
+        let unreachable = {
+            let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            closed.local_addr().unwrap().to_string()
+        };
+        let monitor = ObserverMonitor::new(
+            Duration::from_secs(60),
+            ConnectivityProbe::new(vec![unreachable]),
+        );
+        monitor.record("a", false).await;
+        monitor.record("b", false).await;
+        // The first feed to fail is already the observer's
+        assert!(monitor.record("c", true).await);
+        assert!(!monitor.record("d", false).await);
+    }
+
+    #[test]
+    fn test_sampler_gap() {
+        let now = Utc::now();
+        assert!(SamplerGap::detect(None, now).is_none());
+        assert!(SamplerGap::detect(Some(now - chrono::Duration::seconds(60)), now).is_none());
+        let gap = SamplerGap::detect(Some(now - chrono::Duration::hours(2)), now).unwrap();
+        assert_eq!(gap_start(&gap.message()), Some(gap.since));
+        assert_eq!(gap_start("connection refused"), None);
+    }
+
+    #[test]
+    fn test_gap_markers_cover_each_day() {
+        let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+        let gap = SamplerGap {
+            since: at("2024-01-01T22:00:00Z"),
+            until: at("2024-01-02T02:00:00Z"),
+        };
+        assert_eq!(
+            gap.markers(),
+            vec![at("2024-01-01T23:59:59.999Z"), at("2024-01-02T02:00:00Z")]
+        );
+
+        let same_day = SamplerGap {
+            since: at("2024-01-02T01:00:00Z"),
+            until: at("2024-01-02T02:00:00Z"),
+        };
+        assert_eq!(same_day.markers(), vec![at("2024-01-02T02:00:00Z")]);
+    }
+}
@@ -1,14 +1,52 @@
 //! Output formatting and persistence for feed statistics.
 //!
-//! Supports pretty-printing, JSON serialization, and CSV append.
+//! Supports pretty-printing, JSON serialization, and CSV append. Completed
+//! daily CSVs can be converted to Parquet (see [`parquet`]) before upload.
+//!
+//! Every CSV starts with a `# schema_version=N` line (see [`CsvRecord`]). When
+//! a record's columns no longer match an existing file's header, the file is
+//! rotated to `date={date}.{n}.csv` and a new one is started, so a day may
+//! consist of several files with different schemas.
+
+pub mod parquet;
 
 use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use tracing::{debug, info};
 
-use crate::stats::FeedStats;
-use csv::WriterBuilder;
-use std::fs::OpenOptions;
-use std::path::Path;
+use crate::stats::consistency::ConsistencyStats;
+use crate::stats::fields::FieldRegistry;
+use crate::stats::{FeedStats, Stats};
+use csv::{ReaderBuilder, WriterBuilder};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// Prefix of the first line of every CSV written by [`append_record`].
+pub const SCHEMA_VERSION_PREFIX: &str = "# schema_version=";
+
+/// A record type appended to daily CSVs.
+///
+/// The schema version must be bumped whenever a column is added, removed or
+/// renamed. Readers match columns by name, so fields added in later versions
+/// need `#[serde(default)]` on the reading side to keep older files readable.
+pub trait CsvRecord: Serialize {
+    /// Version of the CSV columns this record serializes to.
+    fn schema_version(&self) -> u32;
+}
+
+/// File format used when uploading completed daily files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UploadFormat {
+    /// Plain CSV, as written by the sampler.
+    Csv,
+    /// Gzip-compressed CSV.
+    CsvGzip,
+    /// Snappy-compressed Parquet, for every feed type and the agency consistency files.
+    Parquet,
+}
 
 /// Logs feed statistics using Rust's debug pretty-print format.
 pub fn print_pretty(stats: &FeedStats) {
@@ -21,29 +59,164 @@ pub fn print_json(stats: &FeedStats) -> Result<()> {
     Ok(())
 }
 
-/// Appends a [`FeedStats`] record as a row to a CSV file.
+/// Appends a statistics record (e.g. [`FeedStats`]) as a row to a CSV file.
 ///
-/// Creates the file with headers if it does not already exist.
-pub fn append_record(path: &str, feed_stats: &FeedStats) -> Result<()> {
-    let file_exists = Path::new(path).exists();
+/// Creates the file with a schema version line and headers if it does not
+/// already exist. If the existing file was written with a different header or
+/// schema version, it is first rotated out of the way (see [`rotated_path`]).
+pub fn append_record<T: CsvRecord>(path: &str, record: &T) -> Result<()> {
+    let version = record.schema_version();
+    let header = csv_header(record)?;
+
+    let mut file_exists = fs::metadata(path).is_ok_and(|m| m.len() > 0);
+    if file_exists {
+        let (file_version, file_header) = read_preamble(Path::new(path))?;
+        // Files from before versioning have no version line; compare their header only
+        let version_changed = file_version.is_some_and(|v| v != version);
+        if version_changed || file_header.as_deref() != Some(header.as_str()) {
+            let rotated = rotated_path(Path::new(path));
+            info!(
+                path,
+                rotated = %rotated.display(),
+                ?file_version,
+                version,
+                "CSV schema changed, rotating file"
+            );
+            fs::rename(path, &rotated)?;
+            file_exists = false;
+        }
+    }
     debug!(path, file_exists, "Appending CSV record");
 
-    let file = OpenOptions::new().append(true).create(true).open(path)?;
+    let mut file = OpenOptions::new().append(true).create(true).open(path)?;
+    if !file_exists {
+        writeln!(file, "{}{}", SCHEMA_VERSION_PREFIX, version)?;
+    }
 
     let mut writer = WriterBuilder::new()
         .has_headers(!file_exists) // IMPORTANT when appending
         .from_writer(file);
 
-    writer.serialize(feed_stats)?;
+    writer.serialize(record)?;
     writer.flush()?;
 
     Ok(())
 }
 
+/// Reads CSV rows written by any schema version into `R`.
+///
+/// The version line is skipped and columns are matched to fields by name, so
+/// columns unknown to `R` are ignored.
+pub fn read_csv_records<R: DeserializeOwned>(bytes: &[u8]) -> Result<Vec<R>> {
+    let mut rdr = ReaderBuilder::new().comment(Some(b'#')).from_reader(bytes);
+    let rows = rdr
+        .deserialize()
+        .collect::<std::result::Result<Vec<R>, _>>()?;
+    Ok(rows)
+}
+
+/// Counts the data rows of a CSV file, skipping its version line and header.
+pub fn count_csv_rows(bytes: &[u8]) -> Result<usize> {
+    let mut rdr = ReaderBuilder::new().comment(Some(b'#')).from_reader(bytes);
+    let mut count = 0;
+    for record in rdr.records() {
+        record?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Extracts the date from a daily file name: `date={date}.csv`, a rotated
+/// `date={date}.{n}.csv`, or the `.parquet` equivalents.
+pub fn daily_file_date(file_name: &str) -> Option<&str> {
+    daily_file_part(file_name).map(|(date, _)| date)
+}
+
+/// Extracts the date and rotation index `n` from a daily file name (see
+/// [`daily_file_date`]); the index is `None` for the day's current file.
+///
+/// Rotated files are numbered from 1 in the order they were set aside, and
+/// all hold rows older than the current file's.
+pub fn daily_file_part(file_name: &str) -> Option<(&str, Option<u32>)> {
+    let stem = file_name.strip_prefix("date=")?;
+    let stem = stem
+        .strip_suffix(".csv")
+        .or_else(|| stem.strip_suffix(".parquet"))?;
+    let (date, rotation) = match stem.split_once('.') {
+        Some((date, part)) if !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()) => {
+            (date, Some(part.parse().ok()?))
+        }
+        Some(_) => return None,
+        None => (stem, None),
+    };
+    (!date.contains('/')).then_some((date, rotation))
+}
+
+/// The first free `{stem}.{n}.csv` next to a CSV at `path`.
+pub fn rotated_path(path: &Path) -> PathBuf {
+    let stem = path.with_extension("");
+    (1..)
+        .map(|n| PathBuf::from(format!("{}.{}.csv", stem.display(), n)))
+        .find(|p| !p.exists())
+        .unwrap()
+}
+
+/// The header line `record` serializes to.
+fn csv_header<T: Serialize>(record: &T) -> Result<String> {
+    let mut writer = WriterBuilder::new()
+        .has_headers(true)
+        .from_writer(Vec::new());
+    writer.serialize(record)?;
+    let bytes = writer.into_inner().map_err(|e| e.into_error())?;
+    let text = String::from_utf8(bytes)?;
+    Ok(text.lines().next().unwrap_or_default().to_string())
+}
+
+/// Reads a CSV's schema version (if it has a version line) and header line.
+fn read_preamble(path: &Path) -> Result<(Option<u32>, Option<String>)> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let Some(first) = lines.next().transpose()? else {
+        return Ok((None, None));
+    };
+
+    match first.strip_prefix(SCHEMA_VERSION_PREFIX) {
+        Some(version) => {
+            let header = lines.next().transpose()?;
+            Ok((version.trim().parse().ok(), header))
+        }
+        None => Ok((None, Some(first))),
+    }
+}
+
+/// Converts the contents of a daily sample CSV of registry `F` into a Parquet
+/// file with the matching [`stats_schema`](parquet::stats_schema).
+pub fn stats_csv_to_parquet<F: FieldRegistry>(csv: &[u8]) -> Result<Vec<u8>> {
+    let rows: Vec<Stats<F>> = read_csv_records(csv)?;
+    csv_rows_to_parquet(&rows, &parquet::stats_schema::<F>())
+}
+
+/// Converts the contents of a daily agency consistency CSV into a Parquet file
+/// with the [`consistency_schema`](parquet::consistency_schema).
+pub fn consistency_csv_to_parquet(csv: &[u8]) -> Result<Vec<u8>> {
+    let rows: Vec<ConsistencyStats> = read_csv_records(csv)?;
+    csv_rows_to_parquet(&rows, parquet::consistency_schema())
+}
+
+fn csv_rows_to_parquet<T: Serialize>(rows: &[T], schema: &str) -> Result<Vec<u8>> {
+    let mut parquet = Vec::new();
+    parquet::write_rows(&mut parquet, schema, rows)?;
+    debug!(
+        rows = rows.len(),
+        bytes = parquet.len(),
+        "Converted CSV to Parquet"
+    );
+    Ok(parquet)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::stats::FeedStats;
+    use crate::stats::{FeedStats, VehiclePositionFields};
     use std::env;
     use std::fs;
 
@@ -95,6 +268,122 @@ mod tests {
         fs::remove_file(&path).unwrap();
     }
 
+    #[test]
+    fn test_stats_csv_to_parquet() {
+        let csv_path = temp_path("gtfs_rt_rater_test_convert.csv");
+        let _ = fs::remove_file(&csv_path);
+
+        let stats = FeedStats {
+            vehicles: 3,
+            ..Default::default()
+        };
+        append_record(&csv_path, &stats).unwrap();
+        append_record(&csv_path, &FeedStats::default()).unwrap();
+
+        let csv = fs::read(&csv_path).unwrap();
+        let parquet = stats_csv_to_parquet::<VehiclePositionFields>(&csv).unwrap();
+
+        let rows: Vec<FeedStats> = parquet::read_rows(parquet).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].vehicles, 3);
+
+        fs::remove_file(&csv_path).unwrap();
+    }
+
+    #[derive(Serialize)]
+    struct Narrow {
+        timestamp: &'static str,
+        vehicles: usize,
+    }
+
+    impl CsvRecord for Narrow {
+        fn schema_version(&self) -> u32 {
+            1
+        }
+    }
+
+    #[test]
+    fn test_append_record_writes_schema_version() {
+        let path = temp_path("gtfs_rt_rater_test_version.csv");
+        let _ = fs::remove_file(&path);
+
+        let stats = FeedStats::default();
+        append_record(&path, &stats).unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        let first = content.lines().next().unwrap();
+        assert_eq!(
+            first,
+            format!("{}{}", SCHEMA_VERSION_PREFIX, stats.schema_version())
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_append_record_rotates_on_schema_change() {
+        let path = temp_path("date=2000-01-01.csv");
+        let rotated = temp_path("date=2000-01-01.1.csv");
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&rotated);
+
+        let old = Narrow {
+            timestamp: "2000-01-01T00:00:00Z",
+            vehicles: 2,
+        };
+        append_record(&path, &old).unwrap();
+        append_record(&path, &old).unwrap();
+        append_record(&path, &FeedStats::default()).unwrap();
+
+        // The old schema's rows moved to the rotated file, intact
+        let old_content = fs::read_to_string(&rotated).unwrap();
+        assert!(old_content.starts_with("# schema_version=1\ntimestamp,vehicles\n"));
+        assert_eq!(old_content.lines().count(), 4);
+
+        let rows: Vec<FeedStats> = read_csv_records(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(rows.len(), 1);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&rotated).unwrap();
+    }
+
+    #[test]
+    fn test_read_csv_records_accepts_unversioned_files() {
+        // Written before versioning and before the catalog conformance columns
+        let csv = "timestamp,vehicles,extra\n2024-01-01T00:00:00Z,3,x\n";
+
+        #[derive(serde::Deserialize)]
+        struct Row {
+            vehicles: usize,
+            #[serde(default)]
+            entity_type_mismatch: bool,
+        }
+
+        let rows: Vec<Row> = read_csv_records(csv.as_bytes()).unwrap();
+        assert_eq!(rows[0].vehicles, 3);
+        assert!(!rows[0].entity_type_mismatch);
+    }
+
+    #[test]
+    fn test_daily_file_date() {
+        assert_eq!(daily_file_date("date=2024-01-01.csv"), Some("2024-01-01"));
+        assert_eq!(daily_file_date("date=2024-01-01.2.csv"), Some("2024-01-01"));
+        assert_eq!(
+            daily_file_date("date=2024-01-01.1.parquet"),
+            Some("2024-01-01")
+        );
+        assert_eq!(daily_file_date("date=2024-01-01.csv.gz"), None);
+        assert_eq!(daily_file_date("date=2024-01-01.x.csv"), None);
+        assert_eq!(
+            daily_file_part("date=2024-01-01.10.csv"),
+            Some(("2024-01-01", Some(10)))
+        );
+        assert_eq!(
+            daily_file_part("date=2024-01-01.csv"),
+            Some(("2024-01-01", None))
+        );
+    }
+
     #[test]
     fn test_append_record_two_rows() {
         let path = temp_path("gtfs_rt_rater_test_rows.csv");
@@ -105,9 +394,9 @@ mod tests {
         append_record(&path, &stats).unwrap();
 
         let content = fs::read_to_string(&path).unwrap();
-        // 1 header + 2 data rows = 3 lines (last may be empty due to trailing newline)
+        // version line + header + 2 data rows (last may be empty due to trailing newline)
         let lines: Vec<_> = content.lines().collect();
-        assert_eq!(lines.len(), 3);
+        assert_eq!(lines.len(), 4);
 
         fs::remove_file(&path).unwrap();
     }
@@ -0,0 +1,270 @@
+//! Parquet encoding of per-sample statistics.
+//!
+//! Each record type has an explicit Parquet schema (see [`stats_schema`] and
+//! [`consistency_schema`]).
+//! [`write_rows`] maps a record's serde fields onto the schema's columns by
+//! name, and [`read_rows`] maps them back, so the same `Deserialize` structs
+//! the analyzers use for CSV can read Parquet files too.
+
+use anyhow::{Result, anyhow, bail};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use parquet::basic::{Compression, LogicalType};
+use parquet::column::writer::ColumnWriter;
+use parquet::data_type::ByteArray;
+use parquet::file::properties::WriterProperties;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::file::writer::SerializedFileWriter;
+use parquet::record::Field;
+use parquet::schema::parser::parse_message_type;
+use parquet::schema::types::SchemaDescriptor;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::io::Write;
+use std::sync::Arc;
+
+use crate::stats::fields::FieldRegistry;
+
+/// Parquet schema for [`Stats`](crate::stats::Stats) rows of registry `F`, in CSV column order.
+///
+/// The metric columns are generated from the registry, so they always match
+/// the CSV columns.
+pub fn stats_schema<F: FieldRegistry>() -> String {
+    let metrics: String = F::metrics()
+        .iter()
+        .map(|m| format!("    required int64 {};\n", m.column))
+        .collect();
+    format!(
+        "
+message {}_stats {{
+    required int64 timestamp (TIMESTAMP(MILLIS, true));
+    optional binary feed_id (STRING);
+    optional binary feed_name (STRING);
+    required int64 total_entities;
+    required int64 vehicles;
+    required int64 trip_updates;
+    required int64 alerts;
+    required int64 shapes;
+    required int64 stops;
+    required int64 trip_modifications;
+    optional int64 header_timestamp;
+{}    required binary mode_counts (STRING);
+    required binary declared_entity_types (STRING);
+    required binary observed_entity_types (STRING);
+    required boolean entity_type_mismatch;
+    optional binary error_type (STRING);
+    optional binary error_message (STRING);
+}}
+",
+        F::FEED_TYPE.catalog_code(),
+        metrics
+    )
+}
+
+/// Parquet schema for [`ConsistencyStats`](crate::stats::ConsistencyStats) rows, in CSV column order.
+pub fn consistency_schema() -> &'static str {
+    "
+message consistency_stats {
+    required int64 timestamp (TIMESTAMP(MILLIS, true));
+    required binary agency_id (STRING);
+    required int64 vp_trip_ids;
+    required int64 tu_trip_ids;
+    required int64 matched_trip_ids;
+    required int64 vp_vehicle_ids;
+    required int64 tu_vehicle_ids;
+    required int64 matched_vehicle_ids;
+}
+"
+}
+
+/// Writes `rows` as a Snappy-compressed Parquet file with the given schema.
+///
+/// Every schema column is looked up by name in each row's serialized fields.
+/// A missing or null value is written as null in optional columns and is an
+/// error in required ones.
+pub fn write_rows<T: Serialize, W: Write + Send>(
+    writer: W,
+    schema: &str,
+    rows: &[T],
+) -> Result<()> {
+    let schema = Arc::new(parse_message_type(schema)?);
+    let descriptor = SchemaDescriptor::new(schema.clone());
+
+    let records = rows
+        .iter()
+        .map(|row| match serde_json::to_value(row)? {
+            Value::Object(fields) => Ok(fields),
+            _ => bail!("Parquet rows must serialize as structs"),
+        })
+        .collect::<Result<Vec<Map<String, Value>>>>()?;
+
+    let props = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut writer = SerializedFileWriter::new(writer, schema, Arc::new(props))?;
+    let mut row_group = writer.next_row_group()?;
+
+    let mut index = 0;
+    while let Some(mut column) = row_group.next_column()? {
+        let column_descr = descriptor.column(index);
+        index += 1;
+
+        let name = column_descr.name();
+        let optional = column_descr.max_def_level() > 0;
+        let is_timestamp = matches!(
+            column_descr.logical_type(),
+            Some(LogicalType::Timestamp { .. })
+        );
+
+        let values: Vec<Option<&Value>> = records
+            .iter()
+            .map(|r| r.get(name).filter(|v| !v.is_null()))
+            .collect();
+        if !optional && values.iter().any(|v| v.is_none()) {
+            bail!("missing value for required Parquet column '{}'", name);
+        }
+        let def_levels: Option<Vec<i16>> =
+            optional.then(|| values.iter().map(|v| v.is_some() as i16).collect());
+        let present = values.iter().flatten();
+
+        match column.untyped() {
+            ColumnWriter::Int64ColumnWriter(w) => {
+                let data = present
+                    .map(|v| {
+                        if is_timestamp {
+                            timestamp_millis(v)
+                        } else {
+                            v.as_i64()
+                        }
+                        .ok_or_else(|| anyhow!("invalid value for column '{}': {}", name, v))
+                    })
+                    .collect::<Result<Vec<i64>>>()?;
+                w.write_batch(&data, def_levels.as_deref(), None)?;
+            }
+            ColumnWriter::BoolColumnWriter(w) => {
+                let data = present
+                    .map(|v| {
+                        v.as_bool()
+                            .ok_or_else(|| anyhow!("invalid value for column '{}': {}", name, v))
+                    })
+                    .collect::<Result<Vec<bool>>>()?;
+                w.write_batch(&data, def_levels.as_deref(), None)?;
+            }
+            ColumnWriter::ByteArrayColumnWriter(w) => {
+                let data: Vec<ByteArray> = present
+                    .map(|v| match v {
+                        Value::String(s) => ByteArray::from(s.as_str()),
+                        other => ByteArray::from(other.to_string().as_str()),
+                    })
+                    .collect();
+                w.write_batch(&data, def_levels.as_deref(), None)?;
+            }
+            _ => bail!("unsupported Parquet column type for '{}'", name),
+        }
+        column.close()?;
+    }
+
+    row_group.close()?;
+    writer.close()?;
+    Ok(())
+}
+
+/// Reads every row of an in-memory Parquet file into `R`, matching columns to fields by name.
+pub fn read_rows<R: DeserializeOwned>(bytes: Vec<u8>) -> Result<Vec<R>> {
+    let reader = SerializedFileReader::new(Bytes::from(bytes))?;
+    let mut rows = Vec::new();
+
+    for row in reader.get_row_iter(None)? {
+        let row = row?;
+        let fields = row
+            .get_column_iter()
+            .map(|(name, field)| Ok((name.clone(), field_to_json(field)?)))
+            .collect::<Result<Map<String, Value>>>()?;
+        rows.push(serde_json::from_value(Value::Object(fields))?);
+    }
+
+    Ok(rows)
+}
+
+/// Number of rows in an in-memory Parquet file, read from its footer.
+pub fn row_count(bytes: Vec<u8>) -> Result<usize> {
+    let reader = SerializedFileReader::new(Bytes::from(bytes))?;
+    Ok(reader.metadata().file_metadata().num_rows() as usize)
+}
+
+/// Parses an RFC 3339 timestamp into milliseconds since the epoch.
+fn timestamp_millis(value: &Value) -> Option<i64> {
+    let ts: DateTime<Utc> = value.as_str()?.parse().ok()?;
+    Some(ts.timestamp_millis())
+}
+
+/// Converts a Parquet field into the JSON value serde expects for it.
+fn field_to_json(field: &Field) -> Result<Value> {
+    Ok(match field {
+        Field::Null => Value::Null,
+        Field::Bool(b) => Value::Bool(*b),
+        Field::Int(i) => Value::from(*i),
+        Field::Long(l) => Value::from(*l),
+        Field::Double(d) => Value::from(*d),
+        Field::Str(s) => Value::String(s.clone()),
+        Field::TimestampMillis(ms) => {
+            let ts = DateTime::<Utc>::from_timestamp_millis(*ms)
+                .ok_or_else(|| anyhow!("timestamp out of range: {}", ms))?;
+            Value::String(ts.to_rfc3339())
+        }
+        other => bail!("unsupported Parquet field: {}", other),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::{FeedStats, VehiclePositionFields};
+
+    #[test]
+    fn test_feed_stats_roundtrip() {
+        let mut ok = FeedStats {
+            feed_id: Some("mdb-1".to_string()),
+            vehicles: 12,
+            declared_entity_types: "vp".to_string(),
+            observed_entity_types: "vp".to_string(),
+            ..Default::default()
+        };
+        ok.fields.set("bearing", 7);
+        let failed = FeedStats {
+            error_type: Some("fetch_error".to_string()),
+            error_message: Some("timeout".to_string()),
+            ..Default::default()
+        };
+        let mut bytes = Vec::new();
+        write_rows(
+            &mut bytes,
+            &stats_schema::<VehiclePositionFields>(),
+            &[ok, failed],
+        )
+        .unwrap();
+
+        let rows: Vec<FeedStats> = read_rows(bytes).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].feed_id.as_deref(), Some("mdb-1"));
+        assert_eq!(rows[0].vehicles, 12);
+        assert_eq!(rows[0].fields.get("bearing"), 7);
+        assert!(rows[0].error_type.is_none());
+        assert_eq!(rows[1].error_type.as_deref(), Some("fetch_error"));
+        assert!(rows[1].feed_id.is_none());
+    }
+
+    #[test]
+    fn test_missing_required_column_is_error() {
+        #[derive(Serialize)]
+        struct Partial {
+            timestamp: DateTime<Utc>,
+        }
+
+        let rows = [Partial {
+            timestamp: Utc::now(),
+        }];
+        assert!(write_rows(Vec::new(), &stats_schema::<VehiclePositionFields>(), &rows).is_err());
+    }
+}
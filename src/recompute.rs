@@ -0,0 +1,235 @@
+//! Re-grading past days from the sample files already uploaded to S3.
+//!
+//! Local CSVs are deleted once a day is aggregated, so after a change to the
+//! scoring algorithm (see [`ALGORITHM_VERSION`]) the only copy of past samples
+//! is the upload bucket. [`recompute`] downloads each day's uploaded
+//! `agency_id=*/date=*.csv[.gz]` (and Parquet) files, decompresses them and
+//! aggregates them again. Results are published under
+//! `versions/algorithm=v{N}/aggregates/...`, so the aggregates produced by
+//! earlier algorithm versions stay in place for comparison.
+
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, Utc};
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Read;
+use tracing::{debug, info};
+
+use crate::analyzers::agency::{AGENCY_DIRECTORY_FILE, AGENCY_SUBDIR};
+use crate::analyzers::aggregate::ALGORITHM_VERSION;
+use crate::analyzers::analyzer::analyze_for_date;
+use crate::analyzers::profile::PROFILE_FILES;
+use crate::feed_type::FeedType;
+use crate::object_store::{MemoryStore, ObjectStore, PrefixStore, put_json};
+use crate::output::daily_file_date;
+
+/// Key prefix of the outputs of an algorithm version.
+pub fn version_prefix(version: u8) -> String {
+    format!("versions/algorithm=v{}/", version)
+}
+
+/// Key of the report of the last recompute, relative to [`version_prefix`].
+pub const REPORT_FILE: &str = "recompute.json";
+
+/// One re-aggregated day.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecomputedDay {
+    pub date: NaiveDate,
+    /// Uploaded objects the day was aggregated from.
+    pub files: Vec<String>,
+}
+
+/// Summary of a [`recompute`] run, written next to its outputs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecomputeReport {
+    pub algorithm_version: u8,
+    pub generated_at: DateTime<Utc>,
+    pub since: NaiveDate,
+    pub until: NaiveDate,
+    pub days: Vec<RecomputedDay>,
+}
+
+/// Re-aggregates every day from `since` to `until` (inclusive) from the
+/// sample files in `uploads`, publishing the results to `dest` under
+/// [`version_prefix`] for the current [`ALGORITHM_VERSION`].
+///
+/// `files` is a store over the sampler's output directory; its
+/// `agencies.json` is used for agency rollups, and its grading profiles for
+/// grading. Days are processed oldest
+/// first, so history and rolling aggregates build up as in the daily run.
+/// Days without uploaded files are skipped.
+#[tracing::instrument(skip(uploads, dest, files))]
+pub async fn recompute(
+    uploads: &dyn ObjectStore,
+    dest: &dyn ObjectStore,
+    files: &dyn ObjectStore,
+    since: NaiveDate,
+    until: NaiveDate,
+) -> Result<RecomputeReport> {
+    let prefix = version_prefix(ALGORITHM_VERSION);
+    let versioned = PrefixStore::new(dest, prefix.as_str());
+    // Files the aggregation reads from the output directory besides samples
+    let mut settings = Vec::new();
+    for file in [AGENCY_DIRECTORY_FILE].into_iter().chain(PROFILE_FILES) {
+        if let Some(body) = files.get(file).await? {
+            settings.push((file, body));
+        }
+    }
+
+    let mut report = RecomputeReport {
+        algorithm_version: ALGORITHM_VERSION,
+        generated_at: Utc::now(),
+        since,
+        until,
+        days: Vec::new(),
+    };
+
+    for (date, keys) in uploaded_files(uploads, since, until).await? {
+        info!(date = %date, files = keys.len(), "Recomputing day from uploaded files");
+
+        // Stage the day's files as the sampler wrote them, then aggregate as usual
+        let staging = MemoryStore::new();
+        for (file, body) in &settings {
+            staging.put(file, body.clone(), None).await?;
+        }
+        for key in &keys {
+            let Some(body) = uploads.get(key).await? else {
+                continue;
+            };
+            let (key, body) = match key.strip_suffix(".gz") {
+                Some(csv_key) => (csv_key, gunzip(&body)?),
+                None => (key.as_str(), body),
+            };
+            staging.put(key, body, None).await?;
+        }
+
+        analyze_for_date(&staging, &versioned, date, None).await?;
+        report.days.push(RecomputedDay { date, files: keys });
+    }
+
+    put_json(&versioned, REPORT_FILE, &report).await?;
+    info!(prefix = %prefix, days = report.days.len(), "Recompute complete");
+    Ok(report)
+}
+
+/// Uploaded sample and consistency files of each day from `since` to `until`.
+async fn uploaded_files(
+    uploads: &dyn ObjectStore,
+    since: NaiveDate,
+    until: NaiveDate,
+) -> Result<BTreeMap<NaiveDate, Vec<String>>> {
+    let mut keys = Vec::new();
+    for feed_type in FeedType::ALL {
+        keys.extend(
+            uploads
+                .list(&feed_type.key("agency_id="))
+                .await?
+                .into_iter()
+                .filter(|key| feed_type.parse_key(key).is_some()),
+        );
+    }
+    keys.extend(uploads.list(&format!("{}/agency=", AGENCY_SUBDIR)).await?);
+
+    let mut days: BTreeMap<NaiveDate, Vec<String>> = BTreeMap::new();
+    for key in keys {
+        let file = key.rsplit('/').next().unwrap_or(&key);
+        let Some(date) =
+            daily_file_date(file.trim_end_matches(".gz")).and_then(|d| d.parse::<NaiveDate>().ok())
+        else {
+            debug!(key = %key, "Skipping object that is not a daily sample file");
+            continue;
+        };
+        if (since..=until).contains(&date) {
+            days.entry(date).or_default().push(key);
+        }
+    }
+    Ok(days)
+}
+
+fn gunzip(body: &[u8]) -> Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    GzDecoder::new(body).read_to_end(&mut decoded)?;
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::FeedStats;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    fn gzipped_csv(vehicles: usize) -> Vec<u8> {
+        let mut row = FeedStats {
+            timestamp: Utc::now(),
+            vehicles,
+            total_entities: vehicles,
+            ..Default::default()
+        };
+        row.fields.set("position", vehicles);
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.serialize(&row).unwrap();
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&writer.into_inner().unwrap()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_recompute_publishes_versioned_outputs() {
+        let uploads = MemoryStore::new();
+        for key in [
+            "agency_id=a/date=2024-01-01.csv.gz",
+            "agency_id=a/date=2024-01-02.csv.gz",
+            "agency_id=a/date=2024-01-05.csv.gz",
+        ] {
+            uploads.put(key, gzipped_csv(3), None).await.unwrap();
+        }
+        uploads
+            .put("manifests/date=2024-01-01.json", b"{}".to_vec(), None)
+            .await
+            .unwrap();
+        // Published by an earlier algorithm version
+        uploads
+            .put("aggregates/feeds/a.json", b"old".to_vec(), None)
+            .await
+            .unwrap();
+
+        let since = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let until = NaiveDate::from_ymd_opt(2024, 1, 2).unwrap();
+        let report = recompute(&uploads, &uploads, &MemoryStore::new(), since, until)
+            .await
+            .unwrap();
+
+        let dates: Vec<NaiveDate> = report.days.iter().map(|d| d.date).collect();
+        assert_eq!(dates, vec![since, until]);
+
+        let prefix = version_prefix(ALGORITHM_VERSION);
+        let keys = uploads.keys();
+        for day in ["2024-01-01", "2024-01-02"] {
+            let key = format!("{}aggregates/daily/date={}/feeds/a.json", prefix, day);
+            assert!(keys.contains(&key), "missing {}", key);
+        }
+        assert!(!keys.iter().any(|k| k.contains("date=2024-01-05/")));
+        assert!(keys.contains(&format!("{}{}", prefix, REPORT_FILE)));
+
+        // The old results and the uploaded samples are untouched
+        assert_eq!(
+            uploads.get("aggregates/feeds/a.json").await.unwrap(),
+            Some(b"old".to_vec())
+        );
+        assert!(keys.contains(&"agency_id=a/date=2024-01-01.csv.gz".to_string()));
+
+        let history: serde_json::Value = serde_json::from_slice(
+            &uploads
+                .get(&format!("{}aggregates/history/feeds/a.json", prefix))
+                .await
+                .unwrap()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(history["days"].as_array().unwrap().len(), 2);
+    }
+}
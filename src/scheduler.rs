@@ -0,0 +1,227 @@
+//! Per-feed polling schedule for the long-running sampler.
+//!
+//! Each feed gets its own [`FeedSchedule`]: start times are staggered across
+//! the base interval, the interval adapts to how often the producer updates
+//! the feed header timestamp (within [`ScheduleConfig`] bounds), and feeds
+//! that keep failing are backed off by a circuit breaker.
+
+use std::time::{Duration, Instant};
+
+/// Bounds and thresholds shared by every feed's schedule.
+#[derive(Debug, Clone)]
+pub struct ScheduleConfig {
+    /// Interval every feed starts with.
+    pub base_interval: Duration,
+    /// Shortest interval a feed may adapt down to.
+    pub min_interval: Duration,
+    /// Longest interval a feed may adapt up to.
+    pub max_interval: Duration,
+    /// Consecutive failures before the circuit breaker opens.
+    pub failure_threshold: u32,
+    /// Upper bound on the circuit breaker's backoff.
+    pub max_backoff: Duration,
+}
+
+impl ScheduleConfig {
+    /// Start offset for the `index`-th of `count` feeds, spreading them evenly
+    /// across one base interval.
+    pub fn stagger(&self, index: usize, count: usize) -> Duration {
+        if count == 0 {
+            return Duration::ZERO;
+        }
+        self.base_interval.mul_f64(index as f64 / count as f64)
+    }
+}
+
+/// Circuit breaker state for a feed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Polling normally.
+    Closed,
+    /// Too many consecutive failures; polling is paused until the backoff expires.
+    Open,
+}
+
+/// Adaptive polling state for one feed.
+#[derive(Debug, Clone)]
+pub struct FeedSchedule {
+    config: ScheduleConfig,
+    interval: Duration,
+    next_due: Instant,
+    last_header_timestamp: Option<u64>,
+    consecutive_failures: u32,
+    breaker: BreakerState,
+}
+
+impl FeedSchedule {
+    /// Creates a schedule whose first poll is due at `start`.
+    pub fn new(config: ScheduleConfig, start: Instant) -> Self {
+        let interval = config
+            .base_interval
+            .clamp(config.min_interval, config.max_interval);
+        Self {
+            config,
+            interval,
+            next_due: start,
+            last_header_timestamp: None,
+            consecutive_failures: 0,
+            breaker: BreakerState::Closed,
+        }
+    }
+
+    /// When the next poll should start.
+    pub fn next_due(&self) -> Instant {
+        self.next_due
+    }
+
+    /// The current adaptive polling interval.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// The circuit breaker state.
+    pub fn breaker(&self) -> BreakerState {
+        self.breaker
+    }
+
+    /// Records a successful poll and schedules the next one.
+    ///
+    /// If the header timestamp did not change since the previous poll, the
+    /// feed is polled less often. If it did, the interval moves toward the
+    /// observed update period, probing slightly below it so faster producers
+    /// are eventually detected.
+    pub fn record_success(&mut self, header_timestamp: Option<u64>, now: Instant) {
+        self.consecutive_failures = 0;
+        self.breaker = BreakerState::Closed;
+
+        if let Some(ts) = header_timestamp {
+            match self.last_header_timestamp {
+                Some(prev) if ts == prev => {
+                    self.interval = self.interval.mul_f64(1.25);
+                }
+                Some(prev) if ts > prev => {
+                    let observed = Duration::from_secs(ts - prev).mul_f64(0.9);
+                    self.interval = (self.interval + observed) / 2;
+                }
+                _ => {}
+            }
+            self.last_header_timestamp = Some(ts);
+        }
+
+        self.interval = self
+            .interval
+            .clamp(self.config.min_interval, self.config.max_interval);
+        self.next_due = now + self.interval;
+    }
+
+    /// Schedules the next poll at the current interval without counting the
+    /// poll towards the circuit breaker, e.g. when the rater itself was offline.
+    pub fn record_skipped(&mut self, now: Instant) {
+        self.next_due = now + self.interval;
+    }
+
+    /// Records a failed poll and schedules the next one.
+    ///
+    /// Below the failure threshold the feed is retried at its normal interval.
+    /// From then on the breaker opens and the retry delay doubles with each
+    /// further failure, up to `max_backoff`.
+    pub fn record_failure(&mut self, now: Instant) {
+        self.consecutive_failures += 1;
+
+        if self.consecutive_failures < self.config.failure_threshold {
+            self.next_due = now + self.interval;
+            return;
+        }
+
+        self.breaker = BreakerState::Open;
+        let exponent = (self.consecutive_failures - self.config.failure_threshold).min(16);
+        let backoff = self
+            .config
+            .base_interval
+            .saturating_mul(2u32.saturating_pow(exponent + 1))
+            .min(self.config.max_backoff);
+        self.next_due = now + backoff.max(self.interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ScheduleConfig {
+        ScheduleConfig {
+            base_interval: Duration::from_secs(60),
+            min_interval: Duration::from_secs(15),
+            max_interval: Duration::from_secs(300),
+            failure_threshold: 3,
+            max_backoff: Duration::from_secs(1800),
+        }
+    }
+
+    #[test]
+    fn test_stagger_spreads_start_times() {
+        let cfg = config();
+        assert_eq!(cfg.stagger(0, 4), Duration::ZERO);
+        assert_eq!(cfg.stagger(2, 4), Duration::from_secs(30));
+        assert_eq!(cfg.stagger(0, 0), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_unchanged_header_slows_polling() {
+        let now = Instant::now();
+        let mut s = FeedSchedule::new(config(), now);
+        s.record_success(Some(1000), now);
+        s.record_success(Some(1000), now);
+        assert_eq!(s.interval(), Duration::from_secs(75));
+        assert_eq!(s.next_due(), now + Duration::from_secs(75));
+    }
+
+    #[test]
+    fn test_fast_updates_shrink_interval_to_min() {
+        let now = Instant::now();
+        let mut s = FeedSchedule::new(config(), now);
+        let mut ts = 1000;
+        for _ in 0..50 {
+            s.record_success(Some(ts), now);
+            ts += 10;
+        }
+        assert_eq!(s.interval(), Duration::from_secs(15));
+    }
+
+    #[test]
+    fn test_interval_capped_at_max() {
+        let now = Instant::now();
+        let mut s = FeedSchedule::new(config(), now);
+        for _ in 0..50 {
+            s.record_success(Some(1000), now);
+        }
+        assert_eq!(s.interval(), Duration::from_secs(300));
+    }
+
+    #[test]
+    fn test_circuit_breaker_backs_off_and_resets() {
+        let now = Instant::now();
+        let mut s = FeedSchedule::new(config(), now);
+
+        s.record_failure(now);
+        s.record_failure(now);
+        assert_eq!(s.breaker(), BreakerState::Closed);
+        assert_eq!(s.next_due(), now + Duration::from_secs(60));
+
+        s.record_failure(now);
+        assert_eq!(s.breaker(), BreakerState::Open);
+        assert_eq!(s.next_due(), now + Duration::from_secs(120));
+
+        s.record_failure(now);
+        assert_eq!(s.next_due(), now + Duration::from_secs(240));
+
+        for _ in 0..20 {
+            s.record_failure(now);
+        }
+        assert_eq!(s.next_due(), now + Duration::from_secs(1800));
+
+        s.record_success(None, now);
+        assert_eq!(s.breaker(), BreakerState::Closed);
+        assert_eq!(s.next_due(), now + Duration::from_secs(60));
+    }
+}
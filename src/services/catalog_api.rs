@@ -1,6 +1,8 @@
 //! Trait and types for interacting with a GTFS-RT feed catalog.
 
 use anyhow::Result;
+use gtfs_rt_rater::analyzers::agency::agency_id_for;
+use gtfs_rt_rater::feed_type::FeedType;
 
 /// Metadata for a single GTFS-RT feed from the catalog.
 #[derive(Debug, Clone)]
@@ -10,11 +12,48 @@ pub struct Feed {
     pub url: Option<String>,
     pub requires_auth: bool,
     pub status: Option<String>,
+    /// Entity types the catalog declares for this feed.
+    pub entity_types: Vec<FeedType>,
+    /// Ids of the static GTFS feeds this realtime feed references.
+    pub feed_references: Vec<String>,
+    /// IANA timezone of the feed's location, when known (see [`timezone_for`](crate::services::timezones::timezone_for)).
+    /// Only a fallback for the `agency_timezone` of the feed's static GTFS.
+    pub timezone: Option<String>,
+}
+
+impl Feed {
+    /// Returns the requested entity types this feed declares.
+    ///
+    /// Feeds whose catalog entry lists no entity types are assumed to serve
+    /// vehicle positions, matching the catalog query used before entity types
+    /// were configurable.
+    pub fn sampled_types(&self, requested: &[FeedType]) -> Vec<FeedType> {
+        let declared: &[FeedType] = if self.entity_types.is_empty() {
+            &[FeedType::VehiclePositions]
+        } else {
+            &self.entity_types
+        };
+
+        requested
+            .iter()
+            .copied()
+            .filter(|t| declared.contains(t))
+            .collect()
+    }
+
+    /// Returns the id of the agency this feed belongs to (see [`agency_id_for`]).
+    pub fn agency_id(&self) -> String {
+        agency_id_for(&self.feed_references, &self.name)
+    }
 }
 
 /// Abstraction over a feed catalog provider (e.g., MobilityData).
 #[async_trait::async_trait]
 pub trait CatalogApi {
-    /// Returns all available GTFS-RT vehicle position feeds.
-    async fn list_feeds(&self) -> Result<Vec<Feed>>;
+    /// Returns all available GTFS-RT feeds serving any of the given entity types.
+    async fn list_feeds(&self, entity_types: &[FeedType]) -> Result<Vec<Feed>>;
+
+    /// Returns the download URL of the latest dataset of static GTFS feed
+    /// `static_feed_id`, if the catalog hosts one.
+    async fn latest_dataset_url(&self, static_feed_id: &str) -> Result<Option<String>>;
 }
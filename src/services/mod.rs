@@ -1 +1,3 @@
 pub mod catalog_api;
+pub mod static_routes;
+pub mod timezones;
@@ -0,0 +1,249 @@
+//! Route modes and agency timezones of the static GTFS feeds the sampled
+//! realtime feeds reference.
+//!
+//! Each vehicle's transit mode comes from the `route_type` of its route (see
+//! [`RouteModes`]), and an agency's local time from the `agency_timezone` of
+//! its `agency.txt`. [`StaticRoutes`] downloads each referenced static feed's
+//! latest dataset from the catalog before sampling starts, then keeps them
+//! current in the background: failed loads are retried every
+//! [`RETRY_INTERVAL`] and loaded feeds are refreshed once they are
+//! [`REFRESH_TTL`] old. Feeds whose static GTFS isn't loaded sample without
+//! route modes, and aggregation falls back to the configured mode and to the
+//! timezone of the catalog location.
+
+use anyhow::{Result, anyhow};
+use chrono_tz::Tz;
+use gtfs_rt_rater::analyzers::mode::RouteModes;
+use gtfs_rt_rater::fetch::{BasicClient, fetch_bytes};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tracing::{info, warn};
+
+use crate::services::catalog_api::{CatalogApi, Feed};
+
+/// How long a static dataset download may take; some are hundreds of MB.
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Static feeds downloaded at once.
+const CONCURRENT_LOADS: usize = 4;
+
+/// How often static feeds that failed to load are retried.
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+/// Age after which a loaded static feed is downloaded again.
+pub const REFRESH_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// What was read from one static feed, and when.
+struct Loaded {
+    modes: Arc<RouteModes>,
+    timezone: Option<Tz>,
+    at: Instant,
+}
+
+/// One row of `agency.txt`, keeping only the column read.
+#[derive(Deserialize)]
+struct AgencyRecord {
+    agency_timezone: String,
+}
+
+/// Route modes and agency timezones of static GTFS feeds, loaded up front
+/// and refreshed in the background.
+pub struct StaticRoutes {
+    catalog: Arc<dyn CatalogApi + Send + Sync>,
+    client: BasicClient,
+    static_ids: Vec<String>,
+    loaded: RwLock<HashMap<String, Loaded>>,
+    /// Bumped whenever any feed's modes change, so callers can tell when to re-read.
+    generation: AtomicU64,
+}
+
+impl StaticRoutes {
+    /// Prepares to load the static feeds referenced by `feeds`.
+    pub fn new<'a>(
+        catalog: Arc<dyn CatalogApi + Send + Sync>,
+        feeds: impl IntoIterator<Item = &'a Feed>,
+    ) -> Self {
+        let mut static_ids: Vec<String> = feeds
+            .into_iter()
+            .flat_map(|f| f.feed_references.iter().cloned())
+            .collect();
+        static_ids.sort();
+        static_ids.dedup();
+        Self {
+            catalog,
+            client: BasicClient::with_timeout(DOWNLOAD_TIMEOUT),
+            static_ids,
+            loaded: RwLock::new(HashMap::new()),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    /// Changes each time a static feed's modes are (re)loaded.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// The modes of the routes of `feed`'s static GTFS feeds, or `None` when
+    /// none of them is loaded.
+    pub fn modes_for(&self, feed: &Feed) -> Option<RouteModes> {
+        let loaded = self.loaded.read().unwrap();
+        let mut modes = RouteModes::default();
+        for static_id in &feed.feed_references {
+            if let Some(loaded) = loaded.get(static_id) {
+                modes.merge(RouteModes::clone(&loaded.modes));
+            }
+        }
+        (!modes.is_empty()).then_some(modes)
+    }
+
+    /// The agency timezone of the first of `feed`'s static GTFS feeds that
+    /// is loaded and names a valid one.
+    pub fn timezone_for(&self, feed: &Feed) -> Option<Tz> {
+        let loaded = self.loaded.read().unwrap();
+        feed.feed_references
+            .iter()
+            .find_map(|static_id| loaded.get(static_id)?.timezone)
+    }
+
+    /// Loads every static feed that isn't loaded yet or is older than
+    /// [`REFRESH_TTL`]. A failed refresh keeps the previous modes.
+    pub async fn load_due(self: &Arc<Self>) {
+        let due: Vec<String> = {
+            let loaded = self.loaded.read().unwrap();
+            self.static_ids
+                .iter()
+                .filter(|id| {
+                    loaded
+                        .get(*id)
+                        .is_none_or(|l| l.at.elapsed() >= REFRESH_TTL)
+                })
+                .cloned()
+                .collect()
+        };
+        if due.is_empty() {
+            return;
+        }
+
+        let permits = Arc::new(Semaphore::new(CONCURRENT_LOADS));
+        let mut loads = tokio::task::JoinSet::new();
+        for static_id in due {
+            let routes = self.clone();
+            let permits = permits.clone();
+            loads.spawn(async move {
+                let _permit = permits.acquire().await.unwrap();
+                routes.load(&static_id).await;
+            });
+        }
+        while let Some(done) = loads.join_next().await {
+            if let Err(e) = done {
+                warn!(error = %e, "Static GTFS load task failed");
+            }
+        }
+    }
+
+    /// Retries failed loads every [`RETRY_INTERVAL`], refreshing feeds as they
+    /// reach [`REFRESH_TTL`]. Runs until the task is aborted.
+    pub async fn refresh_forever(self: Arc<Self>) {
+        let mut ticks = tokio::time::interval(RETRY_INTERVAL);
+        // The first tick completes immediately; loading up front is the caller's job
+        ticks.tick().await;
+        loop {
+            ticks.tick().await;
+            self.load_due().await;
+        }
+    }
+
+    async fn load(&self, static_id: &str) {
+        let loaded = async {
+            let url = self
+                .catalog
+                .latest_dataset_url(static_id)
+                .await?
+                .ok_or_else(|| anyhow!("catalog hosts no dataset"))?;
+            let bytes = fetch_bytes(&self.client, &url).await?;
+            let modes = RouteModes::from_gtfs_zip(&bytes)?;
+            let timezone = agency_timezone(&bytes).unwrap_or_else(|e| {
+                warn!(static_feed_id = %static_id, error = %e, "Could not read agency timezone");
+                None
+            });
+            anyhow::Ok((modes, timezone))
+        }
+        .await;
+
+        match loaded {
+            Ok((modes, timezone)) => {
+                info!(static_feed_id = %static_id, timezone = ?timezone, "Loaded route modes from static GTFS");
+                self.loaded.write().unwrap().insert(
+                    static_id.to_string(),
+                    Loaded {
+                        modes: Arc::new(modes),
+                        timezone,
+                        at: Instant::now(),
+                    },
+                );
+                self.generation.fetch_add(1, Ordering::Release);
+            }
+            Err(e) => {
+                warn!(static_feed_id = %static_id, error = %e, "Could not load static GTFS routes");
+            }
+        }
+    }
+}
+
+/// The `agency_timezone` of a static GTFS zip's `agency.txt`. All agencies of
+/// a feed must share one timezone, so the first row's is taken; `None` when it
+/// isn't a known IANA zone.
+fn agency_timezone(bytes: &[u8]) -> Result<Option<Tz>> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+    // Some producers nest the files in a folder
+    let Some(name) = archive
+        .file_names()
+        .find(|name| name.rsplit('/').next() == Some("agency.txt"))
+        .map(str::to_string)
+    else {
+        return Ok(None);
+    };
+    let mut agencies = String::new();
+    archive.by_name(&name)?.read_to_string(&mut agencies)?;
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(agencies.as_bytes());
+    let Some(first) = reader.deserialize::<AgencyRecord>().next() else {
+        return Ok(None);
+    };
+    Ok(first?.agency_timezone.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gtfs_zip(agency_txt: &[u8]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("gtfs/agency.txt", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        std::io::Write::write_all(&mut zip, agency_txt).unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_agency_timezone() {
+        let bytes = gtfs_zip(
+            b"agency_id,agency_name,agency_url,agency_timezone\n\
+              1,Metro,https://example.com, America/Phoenix \n\
+              2,Rail,https://example.com,America/Phoenix\n",
+        );
+        assert_eq!(
+            agency_timezone(&bytes).unwrap(),
+            Some(chrono_tz::America::Phoenix)
+        );
+
+        let unknown = gtfs_zip(b"agency_name,agency_timezone\nMetro,Mars/Olympus_Mons\n");
+        assert_eq!(agency_timezone(&unknown).unwrap(), None);
+    }
+}
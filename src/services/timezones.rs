@@ -0,0 +1,170 @@
+//! Best-effort IANA timezone lookup for catalog feed locations.
+//!
+//! An agency's timezone comes from the `agency_timezone` of its static GTFS
+//! (see [`static_routes`](crate::services::static_routes)); this table is
+//! only the fallback when that can't be loaded. The catalog gives each
+//! feed's country and subdivision but no timezone.
+//! Countries with a single timezone map directly; the United States, Canada
+//! and Australia are resolved by state or province, using the zone most of
+//! it observes. Anything else is left unknown, and aggregation falls back
+//! to UTC.
+
+/// Countries observing a single timezone, by ISO 3166-1 alpha-2 code.
+static COUNTRIES: &[(&str, &str)] = &[
+    ("AR", "America/Argentina/Buenos_Aires"),
+    ("AT", "Europe/Vienna"),
+    ("BE", "Europe/Brussels"),
+    ("BG", "Europe/Sofia"),
+    ("CH", "Europe/Zurich"),
+    ("CL", "America/Santiago"),
+    ("CO", "America/Bogota"),
+    ("CR", "America/Costa_Rica"),
+    ("CZ", "Europe/Prague"),
+    ("DE", "Europe/Berlin"),
+    ("DK", "Europe/Copenhagen"),
+    ("EE", "Europe/Tallinn"),
+    ("FI", "Europe/Helsinki"),
+    ("FR", "Europe/Paris"),
+    ("GB", "Europe/London"),
+    ("GR", "Europe/Athens"),
+    ("HR", "Europe/Zagreb"),
+    ("HU", "Europe/Budapest"),
+    ("IE", "Europe/Dublin"),
+    ("IL", "Asia/Jerusalem"),
+    ("IN", "Asia/Kolkata"),
+    ("IT", "Europe/Rome"),
+    ("JP", "Asia/Tokyo"),
+    ("KR", "Asia/Seoul"),
+    ("LT", "Europe/Vilnius"),
+    ("LU", "Europe/Luxembourg"),
+    ("LV", "Europe/Riga"),
+    ("NL", "Europe/Amsterdam"),
+    ("NO", "Europe/Oslo"),
+    ("NZ", "Pacific/Auckland"),
+    ("PE", "America/Lima"),
+    ("PL", "Europe/Warsaw"),
+    ("PR", "America/Puerto_Rico"),
+    ("PT", "Europe/Lisbon"),
+    ("RO", "Europe/Bucharest"),
+    ("SE", "Europe/Stockholm"),
+    ("SG", "Asia/Singapore"),
+    ("SI", "Europe/Ljubljana"),
+    ("SK", "Europe/Bratislava"),
+    ("TW", "Asia/Taipei"),
+    ("UY", "America/Montevideo"),
+];
+
+/// States, provinces and territories of multi-timezone countries, by
+/// `(country code, subdivision name)`.
+static SUBDIVISIONS: &[(&str, &str, &str)] = &[
+    ("US", "Alabama", "America/Chicago"),
+    ("US", "Alaska", "America/Anchorage"),
+    ("US", "Arizona", "America/Phoenix"),
+    ("US", "Arkansas", "America/Chicago"),
+    ("US", "California", "America/Los_Angeles"),
+    ("US", "Colorado", "America/Denver"),
+    ("US", "Connecticut", "America/New_York"),
+    ("US", "Delaware", "America/New_York"),
+    ("US", "District of Columbia", "America/New_York"),
+    ("US", "Florida", "America/New_York"),
+    ("US", "Georgia", "America/New_York"),
+    ("US", "Hawaii", "Pacific/Honolulu"),
+    ("US", "Idaho", "America/Boise"),
+    ("US", "Illinois", "America/Chicago"),
+    ("US", "Indiana", "America/Indiana/Indianapolis"),
+    ("US", "Iowa", "America/Chicago"),
+    ("US", "Kansas", "America/Chicago"),
+    ("US", "Kentucky", "America/New_York"),
+    ("US", "Louisiana", "America/Chicago"),
+    ("US", "Maine", "America/New_York"),
+    ("US", "Maryland", "America/New_York"),
+    ("US", "Massachusetts", "America/New_York"),
+    ("US", "Michigan", "America/Detroit"),
+    ("US", "Minnesota", "America/Chicago"),
+    ("US", "Mississippi", "America/Chicago"),
+    ("US", "Missouri", "America/Chicago"),
+    ("US", "Montana", "America/Denver"),
+    ("US", "Nebraska", "America/Chicago"),
+    ("US", "Nevada", "America/Los_Angeles"),
+    ("US", "New Hampshire", "America/New_York"),
+    ("US", "New Jersey", "America/New_York"),
+    ("US", "New Mexico", "America/Denver"),
+    ("US", "New York", "America/New_York"),
+    ("US", "North Carolina", "America/New_York"),
+    ("US", "North Dakota", "America/Chicago"),
+    ("US", "Ohio", "America/New_York"),
+    ("US", "Oklahoma", "America/Chicago"),
+    ("US", "Oregon", "America/Los_Angeles"),
+    ("US", "Pennsylvania", "America/New_York"),
+    ("US", "Rhode Island", "America/New_York"),
+    ("US", "South Carolina", "America/New_York"),
+    ("US", "South Dakota", "America/Chicago"),
+    ("US", "Tennessee", "America/Chicago"),
+    ("US", "Texas", "America/Chicago"),
+    ("US", "Utah", "America/Denver"),
+    ("US", "Vermont", "America/New_York"),
+    ("US", "Virginia", "America/New_York"),
+    ("US", "Washington", "America/Los_Angeles"),
+    ("US", "West Virginia", "America/New_York"),
+    ("US", "Wisconsin", "America/Chicago"),
+    ("US", "Wyoming", "America/Denver"),
+    ("CA", "Alberta", "America/Edmonton"),
+    ("CA", "British Columbia", "America/Vancouver"),
+    ("CA", "Manitoba", "America/Winnipeg"),
+    ("CA", "New Brunswick", "America/Moncton"),
+    ("CA", "Newfoundland and Labrador", "America/St_Johns"),
+    ("CA", "Nova Scotia", "America/Halifax"),
+    ("CA", "Ontario", "America/Toronto"),
+    ("CA", "Prince Edward Island", "America/Halifax"),
+    ("CA", "Quebec", "America/Toronto"),
+    ("CA", "Québec", "America/Toronto"),
+    ("CA", "Saskatchewan", "America/Regina"),
+    ("CA", "Yukon", "America/Whitehorse"),
+    ("AU", "Australian Capital Territory", "Australia/Sydney"),
+    ("AU", "New South Wales", "Australia/Sydney"),
+    ("AU", "Northern Territory", "Australia/Darwin"),
+    ("AU", "Queensland", "Australia/Brisbane"),
+    ("AU", "South Australia", "Australia/Adelaide"),
+    ("AU", "Tasmania", "Australia/Hobart"),
+    ("AU", "Victoria", "Australia/Melbourne"),
+    ("AU", "Western Australia", "Australia/Perth"),
+];
+
+/// IANA timezone for a catalog location, if it can be determined.
+pub fn timezone_for(country_code: &str, subdivision: Option<&str>) -> Option<&'static str> {
+    if let Some((_, tz)) = COUNTRIES.iter().find(|(code, _)| *code == country_code) {
+        return Some(tz);
+    }
+    let subdivision = subdivision?;
+    SUBDIVISIONS
+        .iter()
+        .find(|(code, name, _)| *code == country_code && name.eq_ignore_ascii_case(subdivision))
+        .map(|(_, _, tz)| *tz)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timezone_for() {
+        assert_eq!(timezone_for("DE", None), Some("Europe/Berlin"));
+        assert_eq!(
+            timezone_for("US", Some("california")),
+            Some("America/Los_Angeles")
+        );
+        assert_eq!(timezone_for("US", None), None);
+        assert_eq!(timezone_for("XX", Some("Nowhere")), None);
+    }
+
+    #[test]
+    fn test_table_zones_are_valid() {
+        let zones = COUNTRIES
+            .iter()
+            .map(|(_, tz)| tz)
+            .chain(SUBDIVISIONS.iter().map(|(_, _, tz)| tz));
+        for tz in zones {
+            assert!(tz.parse::<chrono_tz::Tz>().is_ok(), "{}", tz);
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! Sampler state persisted across restarts.
+//!
+//! [`SamplerState`] is stored as `sampler_state.json` in the output directory.
+//! It remembers the last day uploaded to S3, days whose upload or aggregation
+//! is still pending, each feed's HTTP cache validators and a heartbeat, so a restarted
+//! service neither re-uploads finished days nor skips unfinished ones.
+
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use crate::feed_type::FeedType;
+use crate::fetch::CacheValidators;
+use crate::output::daily_file_date;
+
+/// File name of the persisted state inside the output directory.
+pub const STATE_FILE: &str = "sampler_state.json";
+
+/// State the sampler needs to resume after a restart.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SamplerState {
+    /// Most recent day whose files were uploaded and aggregated.
+    pub last_uploaded_date: Option<NaiveDate>,
+    /// Days whose upload or aggregation started but has not completed.
+    #[serde(default)]
+    pub pending_dates: BTreeSet<NaiveDate>,
+    /// HTTP cache validators per feed id.
+    #[serde(default)]
+    pub validators: BTreeMap<String, CacheValidators>,
+    /// Last time the running sampler saved its state, used to detect downtime.
+    #[serde(default)]
+    pub last_heartbeat: Option<DateTime<Utc>>,
+}
+
+impl SamplerState {
+    /// Loads the state from `output_dir`, or returns an empty state if none was saved.
+    pub fn load(output_dir: &str) -> Result<Self> {
+        let path = Path::new(output_dir).join(STATE_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    /// Saves the state to `output_dir`.
+    ///
+    /// Writes to a temporary file first and renames it over the old state, so
+    /// a crash mid-write never leaves a truncated file behind.
+    pub fn save(&self, output_dir: &str) -> Result<()> {
+        let path = Path::new(output_dir).join(STATE_FILE);
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// Days before `today` that still need uploading: pending days plus any
+    /// day that has local CSVs left over, oldest first.
+    pub fn catch_up_dates(&self, output_dir: &str, today: NaiveDate) -> Result<Vec<NaiveDate>> {
+        Ok(self.catch_up_from(local_dates(output_dir)?, today))
+    }
+
+    /// Days before `today` that still need uploading: pending days plus
+    /// `dates`, oldest first and without duplicates.
+    pub fn catch_up_from(
+        &self,
+        dates: impl IntoIterator<Item = NaiveDate>,
+        today: NaiveDate,
+    ) -> Vec<NaiveDate> {
+        let mut dates: BTreeSet<NaiveDate> = dates.into_iter().collect();
+        dates.extend(self.pending_dates.iter().copied());
+        dates.into_iter().filter(|d| *d < today).collect()
+    }
+
+    /// Marks `date` as in progress.
+    pub fn mark_pending(&mut self, date: NaiveDate) {
+        self.pending_dates.insert(date);
+    }
+
+    /// Marks `date` as uploaded and aggregated.
+    pub fn mark_uploaded(&mut self, date: NaiveDate) {
+        self.pending_dates.remove(&date);
+        if self.last_uploaded_date.is_none_or(|d| d < date) {
+            self.last_uploaded_date = Some(date);
+        }
+    }
+}
+
+/// Collects the dates of all `agency_id=*/date=YYYY-MM-DD.{csv,parquet}` files under
+/// every feed type's directory.
+pub fn local_dates(output_dir: &str) -> Result<BTreeSet<NaiveDate>> {
+    let mut dates = BTreeSet::new();
+
+    for feed_type in FeedType::ALL {
+        let type_dir = feed_type.dir(output_dir);
+        if !Path::new(&type_dir).is_dir() {
+            continue;
+        }
+
+        for agency_entry in fs::read_dir(&type_dir)? {
+            let agency_entry = agency_entry?;
+            let is_agency = agency_entry
+                .file_name()
+                .to_str()
+                .is_some_and(|n| n.starts_with("agency_id="));
+            if !is_agency || !agency_entry.path().is_dir() {
+                continue;
+            }
+
+            for file in fs::read_dir(agency_entry.path())? {
+                let name = file?.file_name();
+                let Some(date) = name
+                    .to_str()
+                    .and_then(daily_file_date)
+                    .and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+                else {
+                    continue;
+                };
+                dates.insert(date);
+            }
+        }
+    }
+
+    Ok(dates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn temp_dir(name: &str) -> String {
+        let dir = format!("{}/{}", env::temp_dir().display(), name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn test_load_missing_state_is_default() {
+        let dir = temp_dir("gtfs_rt_rater_test_state_missing");
+        let state = SamplerState::load(&dir).unwrap();
+        assert!(state.last_uploaded_date.is_none());
+        assert!(state.pending_dates.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_save_and_load_roundtrip() {
+        let dir = temp_dir("gtfs_rt_rater_test_state_roundtrip");
+        let mut state = SamplerState::default();
+        state.mark_pending(date("2024-01-02"));
+        state.mark_uploaded(date("2024-01-01"));
+        state.validators.insert(
+            "mdb-1".to_string(),
+            CacheValidators {
+                etag: Some("\"abc\"".to_string()),
+                last_modified: None,
+            },
+        );
+        state.save(&dir).unwrap();
+
+        let loaded = SamplerState::load(&dir).unwrap();
+        assert_eq!(loaded.last_uploaded_date, Some(date("2024-01-01")));
+        assert!(loaded.pending_dates.contains(&date("2024-01-02")));
+        assert_eq!(loaded.validators["mdb-1"].etag.as_deref(), Some("\"abc\""));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_catch_up_dates_includes_every_past_day() {
+        let dir = temp_dir("gtfs_rt_rater_test_state_catch_up");
+        for (sub, file) in [
+            ("agency_id=a", "date=2024-01-01.csv"),
+            ("agency_id=a", "date=2024-01-03.csv"),
+            ("trip_updates/agency_id=b", "date=2024-01-02.csv"),
+            ("agency_id=a", "notes.txt"),
+        ] {
+            fs::create_dir_all(format!("{}/{}", dir, sub)).unwrap();
+            fs::write(format!("{}/{}/{}", dir, sub, file), "").unwrap();
+        }
+
+        let mut state = SamplerState::default();
+        state.mark_pending(date("2023-12-31"));
+
+        let dates = state.catch_up_dates(&dir, date("2024-01-03")).unwrap();
+        assert_eq!(
+            dates,
+            vec![date("2023-12-31"), date("2024-01-01"), date("2024-01-02")]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,21 +1,60 @@
 //! Per-sample statistics extracted from a single GTFS-RT feed snapshot.
 //!
-//! [`FeedStats`] counts how many vehicle entities include each optional field
-//! (bearing, speed, occupancy, etc.), providing a completeness profile for a
-//! single point-in-time observation.
+//! [`Stats`] counts how many entities of a feed type include each optional
+//! field (bearing, speed, occupancy, etc.), providing a completeness profile
+//! for a single point-in-time observation. The fields counted for each feed
+//! type are declared once in a [`FieldRegistry`](fields::FieldRegistry):
+//! [`vehicle_positions`], [`trip_updates`] and [`alerts`]. [`FeedStats`],
+//! [`TripUpdateStats`] and [`AlertStats`] are the per-type instances, and
+//! [`SampleStats`] dispatches to the right one for a [`FeedType`].
+//! Cross-feed id matching between an agency's feeds lives in [`consistency`].
+
+pub mod alerts;
+pub mod consistency;
+pub mod fields;
+pub mod modes;
+pub mod trip_updates;
+pub mod vehicle_positions;
 
 use chrono::{DateTime, Utc};
-use serde::Serialize;
-
+use serde::de::{self, IgnoredAny, MapAccess, Visitor};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::marker::PhantomData;
+
+use crate::analyzers::mode::RouteModes;
+use crate::feed_type::FeedType;
 use crate::gtfs_rt::FeedMessage;
+use crate::output::CsvRecord;
+pub use alerts::AlertFields;
+use fields::{FieldCounts, FieldRegistry};
+use modes::ModeCounts;
+pub use trip_updates::TripUpdateFields;
+pub use vehicle_positions::VehiclePositionFields;
+
+/// Statistics captured from a single vehicle positions snapshot.
+pub type FeedStats = Stats<VehiclePositionFields>;
+
+/// Statistics captured from a single TripUpdates feed snapshot.
+pub type TripUpdateStats = Stats<TripUpdateFields>;
 
-/// Statistics captured from a single GTFS-RT feed snapshot.
+/// Statistics captured from a single ServiceAlerts feed snapshot.
+pub type AlertStats = Stats<AlertFields>;
+
+/// Statistics captured from a single GTFS-RT feed snapshot, for the feed
+/// type described by registry `F`.
 ///
-/// Each `with_*` field counts how many vehicle entities in the feed
-/// populated that optional field. These counts are later used by the
+/// `fields` counts how many entities of that type populated each of the
+/// registry's metrics. These counts are later used by the
 /// [`analyzers`](crate::analyzers) module to compute support percentages and grades.
-#[derive(Debug, Default, Serialize)]
-pub struct FeedStats {
+///
+/// Serializes to one column per envelope field and per metric (the metric's
+/// `with_*` column), in registry order, followed by the `mode_counts` column. Deserialization matches columns by
+/// name: unknown columns are ignored and missing ones default to zero, so
+/// files from every schema version can be read.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Stats<F: FieldRegistry> {
     pub timestamp: DateTime<Utc>,
     pub feed_id: Option<String>,
     pub feed_name: Option<String>,
@@ -29,164 +68,37 @@ pub struct FeedStats {
     pub stops: usize,
     pub trip_modifications: usize,
 
-    // vehicle fields
-    pub with_trip: usize,
-    pub with_trip_id: usize,
-    pub with_route_id: usize,
-    pub with_direction_id: usize,
-    pub with_vehicle_descriptor: usize,
-    pub with_vehicle_id: usize,
-    pub with_vehicle_label: usize,
-    pub with_license_plate: usize,
-    pub with_wheelchair_accessible: usize,
-    pub with_position: usize,
-    pub with_bearing: usize,
-    pub with_speed: usize,
-    pub with_odometer: usize,
-    pub with_current_stop_sequence: usize,
-    pub with_stop_id: usize,
-    pub with_current_status: usize,
-    pub with_timestamp: usize,
-    pub with_congestion_level: usize,
-    pub with_occupancy: usize,
-    pub with_occupancy_percentage: usize,
-    pub with_multi_carriage_details: usize,
+    /// The feed header's timestamp (POSIX seconds), used to spot frozen feeds.
+    pub header_timestamp: Option<u64>,
+
+    pub fields: FieldCounts<F>,
+
+    /// Entities by the transit mode of their route, when the routes are known.
+    pub modes: ModeCounts,
+
+    // catalog conformance
+    pub declared_entity_types: String,
+    pub observed_entity_types: String,
+    pub entity_type_mismatch: bool,
 
     // error tracking
     pub error_type: Option<String>,
     pub error_message: Option<String>,
 }
 
-impl FeedStats {
+impl<F: FieldRegistry> Stats<F> {
     /// Extracts field-completeness statistics from a parsed [`FeedMessage`].
     pub fn from_feed(feed: &FeedMessage) -> Self {
-        let mut s = FeedStats {
+        let mut s = Stats {
             timestamp: Utc::now(),
-            feed_id: None,
-            feed_name: None,
-            total_entities: 0,
-            vehicles: 0,
-            trip_updates: 0,
-            alerts: 0,
-            shapes: 0,
-            stops: 0,
-            trip_modifications: 0,
-            with_trip: 0,
-            with_trip_id: 0,
-            with_route_id: 0,
-            with_direction_id: 0,
-            with_vehicle_descriptor: 0,
-            with_vehicle_id: 0,
-            with_vehicle_label: 0,
-            with_license_plate: 0,
-            with_wheelchair_accessible: 0,
-            with_position: 0,
-            with_bearing: 0,
-            with_speed: 0,
-            with_odometer: 0,
-            with_current_stop_sequence: 0,
-            with_stop_id: 0,
-            with_current_status: 0,
-            with_timestamp: 0,
-            with_congestion_level: 0,
-            with_occupancy: 0,
-            with_occupancy_percentage: 0,
-            with_multi_carriage_details: 0,
-            error_type: None,
-            error_message: None,
+            total_entities: feed.entity.len(),
+            header_timestamp: feed.header.timestamp,
+            ..Default::default()
         };
 
-        s.total_entities = feed.entity.len();
-
         for e in &feed.entity {
-            if let Some(v) = &e.vehicle {
+            if e.vehicle.is_some() {
                 s.vehicles += 1;
-
-                if let Some(trip) = &v.trip {
-                    s.with_trip += 1;
-
-                    if trip.trip_id.is_some() {
-                        s.with_trip_id += 1;
-                    }
-
-                    if trip.route_id.is_some() {
-                        s.with_route_id += 1;
-                    }
-
-                    if trip.direction_id.is_some() {
-                        s.with_direction_id += 1;
-                    }
-                }
-
-                if let Some(vd) = &v.vehicle {
-                    s.with_vehicle_descriptor += 1;
-
-                    if vd.id.is_some() {
-                        s.with_vehicle_id += 1;
-                    }
-
-                    if vd.label.is_some() {
-                        s.with_vehicle_label += 1;
-                    }
-
-                    if vd.license_plate.is_some() {
-                        s.with_license_plate += 1;
-                    }
-
-                    // wheelchair_accessible = 0 means NO_VALUE (default); only
-                    // count when the producer explicitly set a non-default value.
-                    if vd.wheelchair_accessible.map_or(false, |v| v != 0) {
-                        s.with_wheelchair_accessible += 1;
-                    }
-                }
-
-                if let Some(pos) = &v.position {
-                    s.with_position += 1;
-
-                    if pos.bearing.is_some() {
-                        s.with_bearing += 1;
-                    }
-
-                    if pos.speed.is_some() {
-                        s.with_speed += 1;
-                    }
-
-                    if pos.odometer.is_some() {
-                        s.with_odometer += 1;
-                    }
-                }
-
-                if v.current_stop_sequence.is_some() {
-                    s.with_current_stop_sequence += 1;
-                }
-
-                if v.stop_id.is_some() {
-                    s.with_stop_id += 1;
-                }
-
-                if v.current_status.is_some() {
-                    s.with_current_status += 1;
-                }
-
-                if v.timestamp.is_some() {
-                    s.with_timestamp += 1;
-                }
-
-                if v.congestion_level.is_some() {
-                    s.with_congestion_level += 1;
-                }
-
-                if v.occupancy_status.is_some() {
-                    s.with_occupancy += 1;
-                }
-
-                if v.occupancy_percentage.is_some() {
-                    s.with_occupancy_percentage += 1;
-                }
-
-                if !v.multi_carriage_details.is_empty() {
-                    s.with_multi_carriage_details += 1;
-                }
             }
 
             if e.trip_update.is_some() {
@@ -208,28 +120,43 @@ impl FeedStats {
             if e.trip_modifications.is_some() {
                 s.trip_modifications += 1;
             }
+
+            if let Some(entity) = F::entity(e) {
+                s.fields.record(entity);
+            }
         }
 
+        s.observed_entity_types =
+            FeedType::catalog_query(&FeedType::observed(s.vehicles, s.trip_updates, s.alerts));
+
         s
     }
 
-    /// Computes a percentage from a part and total, returning 0.0 when total is zero.
-    pub fn pct(part: usize, total: usize) -> f64 {
-        if total == 0 {
-            0.0
-        } else {
-            (part as f64 / total as f64) * 100.0
+    /// Number of entities of this registry's feed type in the sample.
+    pub fn entity_count(&self) -> usize {
+        match F::FEED_TYPE {
+            FeedType::VehiclePositions => self.vehicles,
+            FeedType::TripUpdates => self.trip_updates,
+            FeedType::ServiceAlerts => self.alerts,
         }
     }
 
-    /// Returns the percentage of vehicles that include bearing data.
-    pub fn bearing_pct(&self) -> f64 {
-        Self::pct(self.with_bearing, self.vehicles)
+    /// Share (0.0–1.0) of the sample's entities populating each metric, in
+    /// registry order. Empty when the sample has no entities.
+    pub fn field_support(&self) -> Vec<(&'static str, f64)> {
+        let entities = self.entity_count();
+        if entities == 0 {
+            return Vec::new();
+        }
+        self.fields
+            .iter()
+            .map(|(metric, count)| (metric.name, count as f64 / entities as f64))
+            .collect()
     }
 
     /// Create an error record with timestamp and error information
     pub fn from_error(error_type: &str, error_message: &str) -> Self {
-        FeedStats {
+        Stats {
             timestamp: Utc::now(),
             error_type: Some(error_type.to_string()),
             error_message: Some(error_message.to_string()),
@@ -243,12 +170,314 @@ impl FeedStats {
         self.feed_name = Some(feed_name.to_string());
         self
     }
+
+    /// Set when the record was taken
+    pub fn with_timestamp(mut self, timestamp: DateTime<Utc>) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Counts the sample's entities by the mode of their route in `routes`.
+    pub fn with_route_modes(mut self, feed: &FeedMessage, routes: &RouteModes) -> Self {
+        for entity in feed.entity.iter().filter_map(F::entity) {
+            if let Some(mode) = F::route_id(entity).and_then(|id| routes.mode_of(id)) {
+                self.modes.record::<F>(mode, entity);
+            }
+        }
+        self
+    }
+
+    /// Records the entity types the catalog declares for this feed and flags
+    /// whether this snapshot contradicts them.
+    pub fn with_declared_types(mut self, declared: &[FeedType]) -> Self {
+        self.declared_entity_types = FeedType::catalog_query(declared);
+        let observed = FeedType::observed(self.vehicles, self.trip_updates, self.alerts);
+        self.entity_type_mismatch = FeedType::is_mismatch(declared, &observed);
+        self
+    }
+}
+
+impl FeedStats {
+    /// Computes a percentage from a part and total, returning 0.0 when total is zero.
+    pub fn pct(part: usize, total: usize) -> f64 {
+        if total == 0 {
+            0.0
+        } else {
+            (part as f64 / total as f64) * 100.0
+        }
+    }
+
+    /// Returns the percentage of vehicles that include bearing data.
+    pub fn bearing_pct(&self) -> f64 {
+        Self::pct(self.fields.get("bearing"), self.vehicles)
+    }
+}
+
+impl<F: FieldRegistry> CsvRecord for Stats<F> {
+    fn schema_version(&self) -> u32 {
+        F::SCHEMA_VERSION
+    }
+}
+
+/// Envelope columns written before the metric columns, in order.
+const LEADING_COLUMNS: [&str; 11] = [
+    "timestamp",
+    "feed_id",
+    "feed_name",
+    "total_entities",
+    "vehicles",
+    "trip_updates",
+    "alerts",
+    "shapes",
+    "stops",
+    "trip_modifications",
+    "header_timestamp",
+];
+
+/// Envelope columns written after the metric columns, in order.
+const TRAILING_COLUMNS: [&str; 6] = [
+    "mode_counts",
+    "declared_entity_types",
+    "observed_entity_types",
+    "entity_type_mismatch",
+    "error_type",
+    "error_message",
+];
+
+impl<F: FieldRegistry> Serialize for Stats<F> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let len = LEADING_COLUMNS.len() + F::metrics().len() + TRAILING_COLUMNS.len();
+        let mut s = serializer.serialize_struct("Stats", len)?;
+        let [
+            timestamp,
+            feed_id,
+            feed_name,
+            total_entities,
+            vehicles,
+            trip_updates,
+            alerts,
+            shapes,
+            stops,
+            trip_modifications,
+            header_timestamp,
+        ] = LEADING_COLUMNS;
+        s.serialize_field(timestamp, &self.timestamp)?;
+        s.serialize_field(feed_id, &self.feed_id)?;
+        s.serialize_field(feed_name, &self.feed_name)?;
+        s.serialize_field(total_entities, &self.total_entities)?;
+        s.serialize_field(vehicles, &self.vehicles)?;
+        s.serialize_field(trip_updates, &self.trip_updates)?;
+        s.serialize_field(alerts, &self.alerts)?;
+        s.serialize_field(shapes, &self.shapes)?;
+        s.serialize_field(stops, &self.stops)?;
+        s.serialize_field(trip_modifications, &self.trip_modifications)?;
+        s.serialize_field(header_timestamp, &self.header_timestamp)?;
+        for (metric, count) in self.fields.iter() {
+            s.serialize_field(metric.column, &count)?;
+        }
+        let [
+            mode_counts,
+            declared_entity_types,
+            observed_entity_types,
+            entity_type_mismatch,
+            error_type,
+            error_message,
+        ] = TRAILING_COLUMNS;
+        s.serialize_field(mode_counts, &self.modes.to_string())?;
+        s.serialize_field(declared_entity_types, &self.declared_entity_types)?;
+        s.serialize_field(observed_entity_types, &self.observed_entity_types)?;
+        s.serialize_field(entity_type_mismatch, &self.entity_type_mismatch)?;
+        s.serialize_field(error_type, &self.error_type)?;
+        s.serialize_field(error_message, &self.error_message)?;
+        s.end()
+    }
+}
+
+impl<'de, F: FieldRegistry> Deserialize<'de> for Stats<F> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(StatsVisitor(PhantomData))
+    }
+}
+
+struct StatsVisitor<F>(PhantomData<F>);
+
+impl<'de, F: FieldRegistry> Visitor<'de> for StatsVisitor<F> {
+    type Value = Stats<F>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a {} sample record", F::FEED_TYPE)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Stats<F>, A::Error> {
+        let mut s = Stats::<F>::default();
+        let mut timestamp = None;
+
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "timestamp" => timestamp = Some(map.next_value()?),
+                "feed_id" => s.feed_id = map.next_value()?,
+                "feed_name" => s.feed_name = map.next_value()?,
+                "total_entities" => s.total_entities = map.next_value()?,
+                "vehicles" => s.vehicles = map.next_value()?,
+                "trip_updates" => s.trip_updates = map.next_value()?,
+                "alerts" => s.alerts = map.next_value()?,
+                "shapes" => s.shapes = map.next_value()?,
+                "stops" => s.stops = map.next_value()?,
+                "trip_modifications" => s.trip_modifications = map.next_value()?,
+                "header_timestamp" => s.header_timestamp = map.next_value()?,
+                "mode_counts" => {
+                    let counts: String = map.next_value()?;
+                    s.modes = counts.parse().map_err(de::Error::custom)?;
+                }
+                "declared_entity_types" => s.declared_entity_types = map.next_value()?,
+                "observed_entity_types" => s.observed_entity_types = map.next_value()?,
+                "entity_type_mismatch" => s.entity_type_mismatch = map.next_value()?,
+                "error_type" => s.error_type = map.next_value()?,
+                "error_message" => s.error_message = map.next_value()?,
+                column if column.starts_with("with_") => {
+                    let count = map.next_value()?;
+                    // Columns of metrics since removed from the registry are dropped
+                    s.fields.set_column(column, count);
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        s.timestamp = timestamp.ok_or_else(|| de::Error::missing_field("timestamp"))?;
+        Ok(s)
+    }
+}
+
+/// Per-sample statistics for any supported [`FeedType`].
+///
+/// Serializes as the inner stats struct, so each feed type gets its own CSV columns.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum SampleStats {
+    VehiclePositions(FeedStats),
+    TripUpdates(TripUpdateStats),
+    ServiceAlerts(AlertStats),
+}
+
+impl CsvRecord for SampleStats {
+    fn schema_version(&self) -> u32 {
+        match self {
+            SampleStats::VehiclePositions(s) => s.schema_version(),
+            SampleStats::TripUpdates(s) => s.schema_version(),
+            SampleStats::ServiceAlerts(s) => s.schema_version(),
+        }
+    }
+}
+
+impl SampleStats {
+    /// Extracts statistics for `feed_type` from a parsed [`FeedMessage`].
+    pub fn from_feed(feed_type: FeedType, feed: &FeedMessage) -> Self {
+        match feed_type {
+            FeedType::VehiclePositions => SampleStats::VehiclePositions(FeedStats::from_feed(feed)),
+            FeedType::TripUpdates => SampleStats::TripUpdates(TripUpdateStats::from_feed(feed)),
+            FeedType::ServiceAlerts => SampleStats::ServiceAlerts(AlertStats::from_feed(feed)),
+        }
+    }
+
+    /// Create an error record for `feed_type` with timestamp and error information
+    pub fn from_error(feed_type: FeedType, error_type: &str, error_message: &str) -> Self {
+        match feed_type {
+            FeedType::VehiclePositions => {
+                SampleStats::VehiclePositions(FeedStats::from_error(error_type, error_message))
+            }
+            FeedType::TripUpdates => {
+                SampleStats::TripUpdates(TripUpdateStats::from_error(error_type, error_message))
+            }
+            FeedType::ServiceAlerts => {
+                SampleStats::ServiceAlerts(AlertStats::from_error(error_type, error_message))
+            }
+        }
+    }
+
+    /// Number of entities of the sample's feed type.
+    pub fn entity_count(&self) -> usize {
+        match self {
+            SampleStats::VehiclePositions(s) => s.entity_count(),
+            SampleStats::TripUpdates(s) => s.entity_count(),
+            SampleStats::ServiceAlerts(s) => s.entity_count(),
+        }
+    }
+
+    /// Field support of the sample (see [`Stats::field_support`]).
+    pub fn field_support(&self) -> Vec<(&'static str, f64)> {
+        match self {
+            SampleStats::VehiclePositions(s) => s.field_support(),
+            SampleStats::TripUpdates(s) => s.field_support(),
+            SampleStats::ServiceAlerts(s) => s.field_support(),
+        }
+    }
+
+    /// Records the catalog's declared entity types (see [`FeedStats::with_declared_types`]).
+    pub fn with_declared_types(self, declared: &[FeedType]) -> Self {
+        match self {
+            SampleStats::VehiclePositions(s) => {
+                SampleStats::VehiclePositions(s.with_declared_types(declared))
+            }
+            SampleStats::TripUpdates(s) => {
+                SampleStats::TripUpdates(s.with_declared_types(declared))
+            }
+            SampleStats::ServiceAlerts(s) => {
+                SampleStats::ServiceAlerts(s.with_declared_types(declared))
+            }
+        }
+    }
+
+    /// Counts the sample's entities by route mode (see [`FeedStats::with_route_modes`]).
+    pub fn with_route_modes(self, feed: &FeedMessage, routes: &RouteModes) -> Self {
+        match self {
+            SampleStats::VehiclePositions(s) => {
+                SampleStats::VehiclePositions(s.with_route_modes(feed, routes))
+            }
+            SampleStats::TripUpdates(s) => {
+                SampleStats::TripUpdates(s.with_route_modes(feed, routes))
+            }
+            SampleStats::ServiceAlerts(s) => {
+                SampleStats::ServiceAlerts(s.with_route_modes(feed, routes))
+            }
+        }
+    }
+
+    /// Set feed metadata (id and name)
+    pub fn with_feed_info(self, feed_id: &str, feed_name: &str) -> Self {
+        match self {
+            SampleStats::VehiclePositions(s) => {
+                SampleStats::VehiclePositions(s.with_feed_info(feed_id, feed_name))
+            }
+            SampleStats::TripUpdates(s) => {
+                SampleStats::TripUpdates(s.with_feed_info(feed_id, feed_name))
+            }
+            SampleStats::ServiceAlerts(s) => {
+                SampleStats::ServiceAlerts(s.with_feed_info(feed_id, feed_name))
+            }
+        }
+    }
+
+    /// Set when the record was taken
+    pub fn with_timestamp(self, timestamp: DateTime<Utc>) -> Self {
+        match self {
+            SampleStats::VehiclePositions(s) => {
+                SampleStats::VehiclePositions(s.with_timestamp(timestamp))
+            }
+            SampleStats::TripUpdates(s) => SampleStats::TripUpdates(s.with_timestamp(timestamp)),
+            SampleStats::ServiceAlerts(s) => {
+                SampleStats::ServiceAlerts(s.with_timestamp(timestamp))
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::gtfs_rt::{FeedEntity, FeedMessage, Position, VehiclePosition};
+    use std::collections::HashMap;
 
     #[test]
     fn test_pct_with_zero_total() {
@@ -295,22 +524,38 @@ mod tests {
 
         assert_eq!(stats.total_entities, 1);
         assert_eq!(stats.vehicles, 1);
-        assert_eq!(stats.with_position, 1);
-        assert_eq!(stats.with_bearing, 1);
-        assert_eq!(stats.with_speed, 1);
-        assert_eq!(stats.with_odometer, 0);
-        assert_eq!(stats.with_timestamp, 1);
+        assert_eq!(stats.fields.get("position"), 1);
+        assert_eq!(stats.fields.get("bearing"), 1);
+        assert_eq!(stats.fields.get("speed"), 1);
+        assert_eq!(stats.fields.get("odometer"), 0);
+        assert_eq!(stats.fields.get("timestamp"), 1);
     }
 
     #[test]
     fn test_bearing_pct() {
-        let mut stats = FeedStats::default();
-        stats.vehicles = 100;
-        stats.with_bearing = 75;
+        let mut stats = FeedStats {
+            vehicles: 100,
+            ..Default::default()
+        };
+        stats.fields.set("bearing", 75);
 
         assert_eq!(stats.bearing_pct(), 75.0);
     }
 
+    #[test]
+    fn test_field_support() {
+        let mut stats = FeedStats {
+            vehicles: 4,
+            ..Default::default()
+        };
+        stats.fields.set("bearing", 3);
+
+        let support: HashMap<_, _> = stats.field_support().into_iter().collect();
+        assert_eq!(support["bearing"], 0.75);
+        assert_eq!(support["speed"], 0.0);
+        assert!(FeedStats::default().field_support().is_empty());
+    }
+
     #[test]
     fn test_from_error() {
         let stats = FeedStats::from_error("fetch_error", "timeout after 10s");
@@ -347,10 +592,10 @@ mod tests {
             }],
         };
         let stats = FeedStats::from_feed(&feed);
-        assert_eq!(stats.with_trip, 1);
-        assert_eq!(stats.with_trip_id, 1);
-        assert_eq!(stats.with_route_id, 1);
-        assert_eq!(stats.with_direction_id, 1);
+        assert_eq!(stats.fields.get("trip"), 1);
+        assert_eq!(stats.fields.get("trip_id"), 1);
+        assert_eq!(stats.fields.get("route_id"), 1);
+        assert_eq!(stats.fields.get("direction_id"), 1);
     }
 
     #[test]
@@ -373,11 +618,11 @@ mod tests {
             }],
         };
         let stats = FeedStats::from_feed(&feed);
-        assert_eq!(stats.with_vehicle_descriptor, 1);
-        assert_eq!(stats.with_vehicle_id, 1);
-        assert_eq!(stats.with_vehicle_label, 1);
-        assert_eq!(stats.with_license_plate, 1);
-        assert_eq!(stats.with_wheelchair_accessible, 1);
+        assert_eq!(stats.fields.get("vehicle_descriptor"), 1);
+        assert_eq!(stats.fields.get("vehicle_id"), 1);
+        assert_eq!(stats.fields.get("vehicle_label"), 1);
+        assert_eq!(stats.fields.get("license_plate"), 1);
+        assert_eq!(stats.fields.get("wheelchair_accessible"), 1);
     }
 
     #[test]
@@ -398,7 +643,7 @@ mod tests {
             }],
         };
         let stats = FeedStats::from_feed(&feed);
-        assert_eq!(stats.with_wheelchair_accessible, 0);
+        assert_eq!(stats.fields.get("wheelchair_accessible"), 0);
     }
 
     #[test]
@@ -422,13 +667,13 @@ mod tests {
             }],
         };
         let stats = FeedStats::from_feed(&feed);
-        assert_eq!(stats.with_current_stop_sequence, 1);
-        assert_eq!(stats.with_stop_id, 1);
-        assert_eq!(stats.with_current_status, 1);
-        assert_eq!(stats.with_congestion_level, 1);
-        assert_eq!(stats.with_occupancy, 1);
-        assert_eq!(stats.with_occupancy_percentage, 1);
-        assert_eq!(stats.with_multi_carriage_details, 1);
+        assert_eq!(stats.fields.get("stop_sequence"), 1);
+        assert_eq!(stats.fields.get("stop_id"), 1);
+        assert_eq!(stats.fields.get("current_status"), 1);
+        assert_eq!(stats.fields.get("congestion_level"), 1);
+        assert_eq!(stats.fields.get("occupancy"), 1);
+        assert_eq!(stats.fields.get("occupancy_percentage"), 1);
+        assert_eq!(stats.fields.get("multi_carriage"), 1);
     }
 
     #[test]
@@ -465,6 +710,111 @@ mod tests {
         assert_eq!(stats.vehicles, 0);
     }
 
+    #[test]
+    fn test_with_declared_types_flags_mismatch() {
+        use crate::gtfs_rt::TripUpdate;
+        let feed = FeedMessage {
+            header: create_header(),
+            entity: vec![FeedEntity {
+                id: "tu1".to_string(),
+                trip_update: Some(TripUpdate::default()),
+                ..Default::default()
+            }],
+        };
+        let stats = FeedStats::from_feed(&feed).with_declared_types(&[FeedType::VehiclePositions]);
+        assert_eq!(stats.declared_entity_types, "vp");
+        assert_eq!(stats.observed_entity_types, "tu");
+        assert!(stats.entity_type_mismatch);
+    }
+
+    #[test]
+    fn test_sample_stats_serializes_inner_columns() {
+        let feed = create_empty_feed();
+        let stats = SampleStats::from_feed(FeedType::TripUpdates, &feed);
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(&stats).unwrap();
+        let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        let header = csv.lines().next().unwrap();
+        assert!(header.contains("with_stop_time_updates"));
+        assert!(!header.contains("with_bearing"));
+
+        let metrics = TripUpdateFields::metrics().iter().map(|m| m.column);
+        let expected: Vec<&str> = LEADING_COLUMNS
+            .into_iter()
+            .chain(metrics)
+            .chain(TRAILING_COLUMNS)
+            .collect();
+        assert_eq!(header, expected.join(","));
+    }
+
+    #[test]
+    fn test_round_trip_ignores_unknown_and_missing_columns() {
+        let mut stats = FeedStats::from_feed(&create_empty_feed()).with_feed_info("1", "Feed");
+        stats.vehicles = 4;
+        stats.fields.set("stop_sequence", 3);
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(&stats).unwrap();
+        let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert!(
+            csv.lines()
+                .next()
+                .unwrap()
+                .contains("with_current_stop_sequence")
+        );
+
+        let mut reader = csv::Reader::from_reader(csv.as_bytes());
+        let back: FeedStats = reader.deserialize().next().unwrap().unwrap();
+        assert_eq!(back, stats);
+
+        let legacy = "timestamp,vehicles,with_bearing,with_retired_field,extra\n\
+                      2024-01-01T00:00:00Z,2,1,5,x\n";
+        let mut reader = csv::Reader::from_reader(legacy.as_bytes());
+        let row: FeedStats = reader.deserialize().next().unwrap().unwrap();
+        assert_eq!(row.vehicles, 2);
+        assert_eq!(row.fields.get("bearing"), 1);
+        assert_eq!(row.fields.get("speed"), 0);
+    }
+
+    #[test]
+    fn test_with_route_modes_counts_per_mode() {
+        use crate::gtfs_rt::TripDescriptor;
+        use crate::gtfs_rt::vehicle_position::CarriageDetails;
+        let vehicle = |route: &str, carriages: usize| FeedEntity {
+            id: route.to_string(),
+            vehicle: Some(VehiclePosition {
+                trip: Some(TripDescriptor {
+                    route_id: Some(route.to_string()),
+                    ..Default::default()
+                }),
+                multi_carriage_details: vec![CarriageDetails::default(); carriages],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let feed = FeedMessage {
+            header: create_header(),
+            entity: vec![
+                vehicle("10", 0),
+                vehicle("L1", 2),
+                vehicle("L1", 0),
+                vehicle("99", 1),
+            ],
+        };
+        let routes =
+            RouteModes::from_routes_txt(&b"route_id,route_type\n10,3\nL1,0\n"[..]).unwrap();
+
+        let stats = FeedStats::from_feed(&feed).with_route_modes(&feed, &routes);
+        assert_eq!(stats.modes.to_string(), "bus=1;tram=2,multi_carriage=1");
+        assert_eq!(stats.fields.get("multi_carriage"), 2);
+
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(&stats).unwrap();
+        let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        let mut reader = csv::Reader::from_reader(csv.as_bytes());
+        let back: FeedStats = reader.deserialize().next().unwrap().unwrap();
+        assert_eq!(back.modes, stats.modes);
+    }
+
     // Helper functions for tests
     fn create_empty_feed() -> FeedMessage {
         FeedMessage {
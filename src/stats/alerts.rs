@@ -0,0 +1,154 @@
+//! Field metrics for GTFS-RT ServiceAlerts feeds.
+//!
+//! [`AlertFields`] lists every optional `Alert` field the rater counts
+//! (active period, informed entity, cause, effect, text, etc.).
+
+use super::fields::{Category, FieldRegistry, Metric};
+use crate::feed_type::FeedType;
+use crate::gtfs_rt::{Alert, FeedEntity, TranslatedString};
+
+/// Field registry for service alerts.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AlertFields;
+
+/// Returns true when a translated string carries at least one non-empty translation.
+fn has_text(s: &Option<TranslatedString>) -> bool {
+    s.as_ref()
+        .is_some_and(|t| t.translation.iter().any(|tr| !tr.text.is_empty()))
+}
+
+static METRICS: &[Metric<Alert>] = &[
+    Metric {
+        name: "active_period",
+        column: "with_active_period",
+        category: Category::Scope,
+        weight: Some(2.0),
+        present: |a| !a.active_period.is_empty(),
+    },
+    Metric {
+        name: "informed_entity",
+        column: "with_informed_entity",
+        category: Category::Scope,
+        weight: Some(3.0),
+        present: |a| !a.informed_entity.is_empty(),
+    },
+    Metric {
+        name: "cause",
+        column: "with_cause",
+        category: Category::Classification,
+        weight: Some(1.0),
+        present: |a| a.cause.is_some(),
+    },
+    Metric {
+        name: "effect",
+        column: "with_effect",
+        category: Category::Classification,
+        weight: Some(2.0),
+        present: |a| a.effect.is_some(),
+    },
+    Metric {
+        name: "url",
+        column: "with_url",
+        category: Category::Text,
+        weight: Some(0.0),
+        present: |a| has_text(&a.url),
+    },
+    Metric {
+        name: "header_text",
+        column: "with_header_text",
+        category: Category::Text,
+        weight: Some(3.0),
+        present: |a| has_text(&a.header_text),
+    },
+    Metric {
+        name: "description_text",
+        column: "with_description_text",
+        category: Category::Text,
+        weight: Some(1.0),
+        present: |a| has_text(&a.description_text),
+    },
+    Metric {
+        name: "severity_level",
+        column: "with_severity_level",
+        category: Category::Classification,
+        weight: Some(0.0),
+        present: |a| a.severity_level.is_some(),
+    },
+];
+
+impl FieldRegistry for AlertFields {
+    type Entity = Alert;
+
+    const FEED_TYPE: FeedType = FeedType::ServiceAlerts;
+
+    /// Version 2 added the shapes, stops and trip_modifications entity counts.
+    /// Version 3 added the feed header timestamp.
+    /// Version 4 added the per-mode entity counts, always empty for alerts.
+    const SCHEMA_VERSION: u32 = 4;
+
+    const UPTIME_WEIGHT: f64 = 3.0;
+
+    /// An empty alerts feed just means there are no disruptions.
+    const SERVICE_TIME_WEIGHT: f64 = 0.0;
+
+    fn metrics() -> &'static [Metric<Alert>] {
+        METRICS
+    }
+
+    fn entity(entity: &FeedEntity) -> Option<&Alert> {
+        entity.alert.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs_rt::translated_string::Translation;
+    use crate::gtfs_rt::{EntitySelector, FeedHeader, FeedMessage};
+    use crate::stats::AlertStats;
+
+    fn text(s: &str) -> Option<TranslatedString> {
+        Some(TranslatedString {
+            translation: vec![Translation {
+                text: s.to_string(),
+                language: None,
+            }],
+        })
+    }
+
+    #[test]
+    fn test_from_feed_counts_alert_fields() {
+        let feed = FeedMessage {
+            header: FeedHeader {
+                gtfs_realtime_version: "2.0".to_string(),
+                timestamp: Some(1234567890),
+                incrementality: None,
+                feed_version: None,
+            },
+            entity: vec![FeedEntity {
+                id: "a1".to_string(),
+                alert: Some(Alert {
+                    informed_entity: vec![EntitySelector {
+                        route_id: Some("Red".to_string()),
+                        ..Default::default()
+                    }],
+                    effect: Some(1),
+                    header_text: text("Shuttle buses"),
+                    description_text: text(""),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+        };
+
+        let stats = AlertStats::from_feed(&feed);
+        assert_eq!(stats.alerts, 1);
+        assert_eq!(stats.fields.get("informed_entity"), 1);
+        assert_eq!(stats.fields.get("active_period"), 0);
+        assert_eq!(stats.fields.get("effect"), 1);
+        assert_eq!(stats.fields.get("cause"), 0);
+        assert_eq!(stats.fields.get("header_text"), 1);
+        // Empty translations do not count as populated text.
+        assert_eq!(stats.fields.get("description_text"), 0);
+    }
+}
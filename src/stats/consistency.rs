@@ -0,0 +1,239 @@
+//! Cross-feed consistency between an agency's VehiclePositions and TripUpdates.
+//!
+//! MobilityData lists an agency's realtime feed types as separate feeds, each
+//! polled on its own schedule. [`ConsistencyTracker`] keeps the latest ids
+//! from each feed and, whenever VehiclePositions are sampled while recent
+//! TripUpdates are available, produces a [`ConsistencyStats`] row recording
+//! how many trip and vehicle ids also appear in TripUpdates.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::feed_type::FeedType;
+use crate::gtfs_rt::FeedMessage;
+use crate::output::CsvRecord;
+
+/// Trip and vehicle ids referenced by one feed snapshot.
+#[derive(Debug, Default, Clone)]
+pub struct EntityIds {
+    pub trip_ids: HashSet<String>,
+    pub vehicle_ids: HashSet<String>,
+}
+
+impl EntityIds {
+    /// Collects ids referenced by the `VehiclePosition` entities of a feed.
+    pub fn from_vehicle_positions(feed: &FeedMessage) -> Self {
+        let mut ids = EntityIds::default();
+        for v in feed.entity.iter().filter_map(|e| e.vehicle.as_ref()) {
+            if let Some(trip_id) = v.trip.as_ref().and_then(|t| t.trip_id.clone()) {
+                ids.trip_ids.insert(trip_id);
+            }
+            if let Some(vehicle_id) = v.vehicle.as_ref().and_then(|vd| vd.id.clone()) {
+                ids.vehicle_ids.insert(vehicle_id);
+            }
+        }
+        ids
+    }
+
+    /// Collects ids referenced by the `TripUpdate` entities of a feed.
+    pub fn from_trip_updates(feed: &FeedMessage) -> Self {
+        let mut ids = EntityIds::default();
+        for tu in feed.entity.iter().filter_map(|e| e.trip_update.as_ref()) {
+            if let Some(trip_id) = tu.trip.trip_id.clone() {
+                ids.trip_ids.insert(trip_id);
+            }
+            if let Some(vehicle_id) = tu.vehicle.as_ref().and_then(|vd| vd.id.clone()) {
+                ids.vehicle_ids.insert(vehicle_id);
+            }
+        }
+        ids
+    }
+}
+
+/// One round's comparison of an agency's VehiclePositions and TripUpdates ids.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ConsistencyStats {
+    pub timestamp: DateTime<Utc>,
+    pub agency_id: String,
+    pub vp_trip_ids: usize,
+    pub tu_trip_ids: usize,
+    pub matched_trip_ids: usize,
+    pub vp_vehicle_ids: usize,
+    pub tu_vehicle_ids: usize,
+    pub matched_vehicle_ids: usize,
+}
+
+impl CsvRecord for ConsistencyStats {
+    fn schema_version(&self) -> u32 {
+        1
+    }
+}
+
+impl ConsistencyStats {
+    /// Compares the ids seen in VehiclePositions against those in TripUpdates.
+    pub fn compare(agency_id: &str, vp: &EntityIds, tu: &EntityIds) -> Self {
+        ConsistencyStats {
+            timestamp: Utc::now(),
+            agency_id: agency_id.to_string(),
+            vp_trip_ids: vp.trip_ids.len(),
+            tu_trip_ids: tu.trip_ids.len(),
+            matched_trip_ids: vp.trip_ids.intersection(&tu.trip_ids).count(),
+            vp_vehicle_ids: vp.vehicle_ids.len(),
+            tu_vehicle_ids: tu.vehicle_ids.len(),
+            matched_vehicle_ids: vp.vehicle_ids.intersection(&tu.vehicle_ids).count(),
+        }
+    }
+}
+
+/// Latest ids per feed for one agency.
+#[derive(Debug, Default)]
+struct AgencyIds {
+    vehicle_positions: HashMap<String, (Instant, EntityIds)>,
+    trip_updates: HashMap<String, (Instant, EntityIds)>,
+}
+
+/// Pairs up VehiclePositions and TripUpdates ids sampled at different times.
+#[derive(Debug)]
+pub struct ConsistencyTracker {
+    max_age: Duration,
+    agencies: HashMap<String, AgencyIds>,
+}
+
+impl ConsistencyTracker {
+    /// Creates a tracker that only compares against ids younger than `max_age`.
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            max_age,
+            agencies: HashMap::new(),
+        }
+    }
+
+    /// Records the ids from one feed sample.
+    ///
+    /// Returns a comparison when the sample included VehiclePositions and the
+    /// agency has TripUpdates ids no older than `max_age`. Only
+    /// VehiclePositions samples produce rows, so each is counted once.
+    pub fn record(
+        &mut self,
+        agency_id: &str,
+        feed_id: &str,
+        ids: Vec<(FeedType, EntityIds)>,
+        now: Instant,
+    ) -> Option<ConsistencyStats> {
+        let agency = self.agencies.entry(agency_id.to_string()).or_default();
+        let mut sampled_vp = false;
+
+        for (feed_type, ids) in ids {
+            match feed_type {
+                FeedType::VehiclePositions => {
+                    agency
+                        .vehicle_positions
+                        .insert(feed_id.to_string(), (now, ids));
+                    sampled_vp = true;
+                }
+                FeedType::TripUpdates => {
+                    agency.trip_updates.insert(feed_id.to_string(), (now, ids));
+                }
+                FeedType::ServiceAlerts => {}
+            }
+        }
+
+        if !sampled_vp {
+            return None;
+        }
+
+        let max_age = self.max_age;
+        let fresh = |all: &HashMap<String, (Instant, EntityIds)>| {
+            let mut merged = EntityIds::default();
+            let mut any = false;
+            for (at, ids) in all.values() {
+                if now.saturating_duration_since(*at) <= max_age {
+                    merged.trip_ids.extend(ids.trip_ids.iter().cloned());
+                    merged.vehicle_ids.extend(ids.vehicle_ids.iter().cloned());
+                    any = true;
+                }
+            }
+            any.then_some(merged)
+        };
+
+        let tu = fresh(&agency.trip_updates)?;
+        let vp = fresh(&agency.vehicle_positions)?;
+        Some(ConsistencyStats::compare(agency_id, &vp, &tu))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(trips: &[&str], vehicles: &[&str]) -> EntityIds {
+        EntityIds {
+            trip_ids: trips.iter().map(|s| s.to_string()).collect(),
+            vehicle_ids: vehicles.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_compare_counts_matches() {
+        let vp = ids(&["t1", "t2", "t3"], &["v1", "v2"]);
+        let tu = ids(&["t2", "t3", "t4"], &["v2"]);
+        let stats = ConsistencyStats::compare("agency", &vp, &tu);
+        assert_eq!(stats.vp_trip_ids, 3);
+        assert_eq!(stats.tu_trip_ids, 3);
+        assert_eq!(stats.matched_trip_ids, 2);
+        assert_eq!(stats.vp_vehicle_ids, 2);
+        assert_eq!(stats.matched_vehicle_ids, 1);
+    }
+
+    #[test]
+    fn test_from_vehicle_positions_collects_ids() {
+        use crate::gtfs_rt::{
+            FeedEntity, FeedHeader, TripDescriptor, VehicleDescriptor, VehiclePosition,
+        };
+        let feed = FeedMessage {
+            header: FeedHeader::default(),
+            entity: vec![FeedEntity {
+                id: "e1".to_string(),
+                vehicle: Some(VehiclePosition {
+                    trip: Some(TripDescriptor {
+                        trip_id: Some("t1".to_string()),
+                        ..Default::default()
+                    }),
+                    vehicle: Some(VehicleDescriptor {
+                        id: Some("v1".to_string()),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+        };
+        let ids = EntityIds::from_vehicle_positions(&feed);
+        assert!(ids.trip_ids.contains("t1"));
+        assert!(ids.vehicle_ids.contains("v1"));
+        assert!(EntityIds::from_trip_updates(&feed).trip_ids.is_empty());
+    }
+
+    #[test]
+    fn test_tracker_pairs_fresh_samples_only() {
+        let now = Instant::now();
+        let mut tracker = ConsistencyTracker::new(Duration::from_secs(120));
+
+        // VP alone: nothing to compare against yet
+        let vp = vec![(FeedType::VehiclePositions, ids(&["t1"], &[]))];
+        assert!(tracker.record("a", "vp-1", vp.clone(), now).is_none());
+
+        // TU samples never emit rows themselves
+        let tu = vec![(FeedType::TripUpdates, ids(&["t1"], &[]))];
+        assert!(tracker.record("a", "tu-1", tu, now).is_none());
+
+        let row = tracker.record("a", "vp-1", vp.clone(), now).unwrap();
+        assert_eq!(row.matched_trip_ids, 1);
+
+        // TU ids too old to compare
+        let later = now + Duration::from_secs(300);
+        assert!(tracker.record("a", "vp-1", vp, later).is_none());
+    }
+}
@@ -0,0 +1,235 @@
+//! Declarative registry of field-completeness metrics.
+//!
+//! Every metric is declared once, as a [`Metric`] in its feed type's
+//! [`FieldRegistry`]: its aggregate name, CSV column, category, weight in the
+//! overall score and a predicate telling whether an entity populates it. The
+//! registry drives stats extraction ([`Stats::from_feed`](super::Stats::from_feed)),
+//! the CSV and Parquet columns, aggregation, per-category support and grading,
+//! so adding a metric means adding one entry.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::marker::PhantomData;
+
+use crate::feed_type::FeedType;
+use crate::gtfs_rt::FeedEntity;
+use crate::stats::{AlertFields, TripUpdateFields, VehiclePositionFields};
+
+/// The part of an entity a metric describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Category {
+    /// Trip descriptor fields.
+    Trip,
+    /// Vehicle descriptor fields.
+    Vehicle,
+    /// Geographic position fields.
+    Position,
+    /// Stop progress and stop-time predictions.
+    Stop,
+    /// Crowding and carriage details.
+    Occupancy,
+    /// Timestamps and delays.
+    Timing,
+    /// Which services and periods an alert applies to.
+    Scope,
+    /// Alert cause, effect and severity.
+    Classification,
+    /// Alert text and links.
+    Text,
+}
+
+/// One field-completeness metric, counted once per entity.
+pub struct Metric<E: 'static> {
+    /// Name used in aggregates and score breakdowns, e.g. `"stop_sequence"`.
+    pub name: &'static str,
+    /// CSV column holding the per-sample count, e.g. `"with_current_stop_sequence"`.
+    pub column: &'static str,
+    pub category: Category,
+    /// Weight in the overall score, or `None` for counts that are recorded but not graded.
+    pub weight: Option<f64>,
+    /// Whether an entity populates the field.
+    pub present: fn(&E) -> bool,
+}
+
+/// The metrics of one feed type, and how to find its entities in a feed.
+pub trait FieldRegistry: fmt::Debug + Default + Clone + Copy + 'static {
+    /// GTFS-RT message the metrics are counted on.
+    type Entity: 'static;
+
+    /// Feed type whose samples these metrics describe.
+    const FEED_TYPE: FeedType;
+
+    /// Version of the CSV columns; bump it when metrics are added, removed or renamed.
+    const SCHEMA_VERSION: u32;
+
+    /// Weight of uptime in the overall score.
+    const UPTIME_WEIGHT: f64;
+
+    /// Weight of service time (samples with at least one entity) in the overall score.
+    const SERVICE_TIME_WEIGHT: f64;
+
+    /// The metrics, in CSV column order.
+    fn metrics() -> &'static [Metric<Self::Entity>];
+
+    /// The entity of this feed type carried by `entity`, if any.
+    fn entity(entity: &FeedEntity) -> Option<&Self::Entity>;
+
+    /// The route an entity runs on, used to find its transit mode.
+    fn route_id(_entity: &Self::Entity) -> Option<&str> {
+        None
+    }
+
+    /// Weights of the graded metrics plus `uptime` and `service_time`, keyed by name.
+    fn weights() -> Vec<(&'static str, f64)> {
+        Self::metrics()
+            .iter()
+            .filter_map(|m| Some((m.name, m.weight?)))
+            .chain([
+                ("uptime", Self::UPTIME_WEIGHT),
+                ("service_time", Self::SERVICE_TIME_WEIGHT),
+            ])
+            .collect()
+    }
+}
+
+/// Per-sample counts of entities populating each metric of a [`FieldRegistry`].
+#[derive(Clone, PartialEq, Eq)]
+pub struct FieldCounts<F> {
+    counts: Vec<usize>,
+    registry: PhantomData<F>,
+}
+
+impl<F: FieldRegistry> FieldCounts<F> {
+    /// Counts the metrics `entity` populates.
+    pub fn record(&mut self, entity: &F::Entity) {
+        for (count, metric) in self.counts.iter_mut().zip(F::metrics()) {
+            if (metric.present)(entity) {
+                *count += 1;
+            }
+        }
+    }
+
+    /// The count for the metric named `name` (0 for an unknown name).
+    pub fn get(&self, name: &str) -> usize {
+        index_of::<F>(name).map_or(0, |i| self.counts[i])
+    }
+
+    /// Sets the count for the metric named `name`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the registry has no metric called `name`.
+    pub fn set(&mut self, name: &str, count: usize) {
+        let i = index_of::<F>(name)
+            .unwrap_or_else(|| panic!("no {} metric named '{}'", F::FEED_TYPE, name));
+        self.counts[i] = count;
+    }
+
+    /// Each metric with its count, in registry order.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static Metric<F::Entity>, usize)> + '_ {
+        F::metrics().iter().zip(self.counts.iter().copied())
+    }
+
+    /// Sets the count of the metric stored in CSV column `column`, returning
+    /// false when no metric uses that column.
+    pub(crate) fn set_column(&mut self, column: &str, count: usize) -> bool {
+        match F::metrics().iter().position(|m| m.column == column) {
+            Some(i) => {
+                self.counts[i] = count;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Category of the metric called `name` in `feed_type`'s registry.
+pub fn category_of(feed_type: FeedType, name: &str) -> Option<Category> {
+    fn find<F: FieldRegistry>(name: &str) -> Option<Category> {
+        F::metrics()
+            .iter()
+            .find(|m| m.name == name)
+            .map(|m| m.category)
+    }
+
+    match feed_type {
+        FeedType::VehiclePositions => find::<VehiclePositionFields>(name),
+        FeedType::TripUpdates => find::<TripUpdateFields>(name),
+        FeedType::ServiceAlerts => find::<AlertFields>(name),
+    }
+}
+
+fn index_of<F: FieldRegistry>(name: &str) -> Option<usize> {
+    F::metrics().iter().position(|m| m.name == name)
+}
+
+impl<F: FieldRegistry> Default for FieldCounts<F> {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; F::metrics().len()],
+            registry: PhantomData,
+        }
+    }
+}
+
+impl<F: FieldRegistry> fmt::Debug for FieldCounts<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.iter().map(|(m, count)| (m.name, count)))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn assert_unique<F: FieldRegistry>() {
+        let names: HashSet<_> = F::metrics().iter().map(|m| m.name).collect();
+        let columns: HashSet<_> = F::metrics().iter().map(|m| m.column).collect();
+        assert_eq!(names.len(), F::metrics().len(), "{}", F::FEED_TYPE);
+        assert_eq!(columns.len(), F::metrics().len(), "{}", F::FEED_TYPE);
+        assert!(F::metrics().iter().all(|m| m.column.starts_with("with_")));
+    }
+
+    #[test]
+    fn test_registries_have_unique_names_and_columns() {
+        assert_unique::<VehiclePositionFields>();
+        assert_unique::<TripUpdateFields>();
+        assert_unique::<AlertFields>();
+    }
+
+    #[test]
+    fn test_weights_skip_ungraded_metrics() {
+        let weights = VehiclePositionFields::weights();
+        assert!(weights.contains(&("stop_sequence", 3.0)));
+        assert!(weights.contains(&("uptime", 3.0)));
+        assert!(!weights.iter().any(|(name, _)| *name == "position"));
+    }
+
+    #[test]
+    fn test_category_of() {
+        assert_eq!(
+            category_of(FeedType::VehiclePositions, "bearing"),
+            Some(Category::Position)
+        );
+        assert_eq!(
+            category_of(FeedType::ServiceAlerts, "header_text"),
+            Some(Category::Text)
+        );
+        assert_eq!(category_of(FeedType::TripUpdates, "bearing"), None);
+    }
+
+    #[test]
+    fn test_counts_get_and_set() {
+        let mut counts = FieldCounts::<VehiclePositionFields>::default();
+        counts.set("bearing", 4);
+        assert_eq!(counts.get("bearing"), 4);
+        assert_eq!(counts.get("unknown"), 0);
+        assert!(counts.set_column("with_speed", 2));
+        assert!(!counts.set_column("vehicles", 2));
+        assert_eq!(counts.get("speed"), 2);
+    }
+}
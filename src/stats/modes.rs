@@ -0,0 +1,219 @@
+//! Per-sample entity counts by transit mode.
+//!
+//! Fields like `multi_carriage` only apply to some modes (see
+//! [`analyzers::mode`](crate::analyzers::mode)). A feed can mix modes, so each
+//! sample records how many of its entities ran on routes of each known
+//! [`Mode`], and how many of those populated each mode-specific field.
+//! Entities on routes of no known mode are left out; aggregation works them
+//! out from the sample's totals and gives them the feed's configured mode.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::analyzers::mode::{Mode, is_mode_field, mode_field_name};
+use crate::stats::fields::FieldRegistry;
+
+/// Entities of one mode, and how many populated each mode-specific field.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct ModeCount {
+    entities: usize,
+    fields: BTreeMap<&'static str, usize>,
+}
+
+/// Entities of a sample that populate a field, out of those it applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Applicable {
+    pub populated: usize,
+    pub entities: usize,
+    /// Whether any of the entities had a mode, from their route or the
+    /// feed's setting. When none did the field is judged on every entity.
+    pub mode_known: bool,
+}
+
+/// Counts of a sample's entities by the mode of their route.
+///
+/// Written to CSV as one `mode_counts` column, e.g.
+/// `bus=10;tram=4,multi_carriage=3,wheelchair_accessible=4`: each mode with
+/// its entity count, followed by the count of each mode-specific field.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ModeCounts {
+    modes: BTreeMap<Mode, ModeCount>,
+}
+
+impl ModeCounts {
+    /// Counts `entity`, running on a route of `mode`, and the mode-specific
+    /// fields it populates.
+    pub fn record<F: FieldRegistry>(&mut self, mode: Mode, entity: &F::Entity) {
+        let count = self.modes.entry(mode).or_default();
+        count.entities += 1;
+        for metric in F::metrics() {
+            if is_mode_field(metric.name) && (metric.present)(entity) {
+                *count.fields.entry(metric.name).or_default() += 1;
+            }
+        }
+    }
+
+    /// Returns true when no entity had a known mode.
+    pub fn is_empty(&self) -> bool {
+        self.modes.is_empty()
+    }
+
+    /// Entities of each known mode.
+    pub fn entities(&self) -> impl Iterator<Item = (Mode, usize)> + '_ {
+        self.modes
+            .iter()
+            .map(|(mode, count)| (*mode, count.entities))
+    }
+
+    /// Which of a sample's `entities` field `name` applies to and how many of
+    /// them populate it, given that `populated` entities populate it overall.
+    ///
+    /// Entities of no known mode are taken to be of mode `fallback`. When
+    /// there is no fallback they are left out, unless no entity has a known
+    /// mode, in which case the field is judged on every entity. `None` when
+    /// the field applies to no entity.
+    pub fn applicable(
+        &self,
+        name: &str,
+        populated: usize,
+        entities: usize,
+        fallback: Option<Mode>,
+    ) -> Option<Applicable> {
+        let mut applicable = Applicable {
+            populated: 0,
+            entities: 0,
+            mode_known: true,
+        };
+        let (mut known_populated, mut known_entities) = (0, 0);
+        for (mode, count) in &self.modes {
+            let with = count.fields.get(name).copied().unwrap_or(0);
+            known_populated += with;
+            known_entities += count.entities;
+            if mode.applies(name) {
+                applicable.populated += with;
+                applicable.entities += count.entities;
+            }
+        }
+
+        let unknown_applies = match fallback {
+            Some(mode) => mode.applies(name),
+            None if self.modes.is_empty() => {
+                applicable.mode_known = false;
+                true
+            }
+            None => false,
+        };
+        if unknown_applies {
+            applicable.populated += populated.saturating_sub(known_populated);
+            applicable.entities += entities.saturating_sub(known_entities);
+        }
+
+        (applicable.entities > 0).then_some(applicable)
+    }
+}
+
+impl fmt::Display for ModeCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (mode, count)) in self.modes.iter().enumerate() {
+            if i > 0 {
+                f.write_str(";")?;
+            }
+            write!(f, "{}={}", mode, count.entities)?;
+            for (name, with) in &count.fields {
+                write!(f, ",{}={}", name, with)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ModeCounts {
+    type Err = anyhow::Error;
+
+    /// Parses the `mode_counts` column. Fields no longer graded per mode are dropped.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut counts = ModeCounts::default();
+        for part in s.split(';').filter(|p| !p.is_empty()) {
+            let mut pairs = part.split(',').map(|pair| {
+                pair.split_once('=')
+                    .ok_or_else(|| anyhow::anyhow!("invalid mode count '{}'", pair))
+            });
+            let Some(first) = pairs.next() else { continue };
+            let (mode, entities) = first?;
+            let count = counts.modes.entry(mode.parse()?).or_default();
+            count.entities = entities.parse()?;
+            for pair in pairs {
+                let (name, with) = pair?;
+                if let Some(name) = mode_field_name(name) {
+                    count.fields.insert(name, with.parse()?);
+                }
+            }
+        }
+        Ok(counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts() -> ModeCounts {
+        "bus=6;tram=4,multi_carriage=3".parse().unwrap()
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let counts = counts();
+        assert_eq!(counts.to_string(), "bus=6;tram=4,multi_carriage=3");
+        assert_eq!(
+            counts.entities().collect::<Vec<_>>(),
+            vec![(Mode::Bus, 6), (Mode::Tram, 4)]
+        );
+        assert!("".parse::<ModeCounts>().unwrap().is_empty());
+        assert!("hovercraft=1".parse::<ModeCounts>().is_err());
+    }
+
+    #[test]
+    fn test_applicable_counts_only_modes_the_field_applies_to() {
+        // 12 vehicles: 6 buses, 4 trams, 2 on routes of no known mode
+        let counts = counts();
+        let tram_only = Applicable {
+            populated: 3,
+            entities: 4,
+            mode_known: true,
+        };
+        assert_eq!(
+            counts.applicable("multi_carriage", 4, 12, None),
+            Some(tram_only)
+        );
+        assert_eq!(
+            counts.applicable("multi_carriage", 4, 12, Some(Mode::Bus)),
+            Some(tram_only)
+        );
+        // The unknown vehicles are rail, and one of them reports carriages
+        assert_eq!(
+            counts.applicable("multi_carriage", 4, 12, Some(Mode::Rail)),
+            Some(Applicable {
+                populated: 4,
+                entities: 6,
+                mode_known: true,
+            })
+        );
+    }
+
+    #[test]
+    fn test_applicable_without_any_mode() {
+        let none = ModeCounts::default();
+        assert_eq!(
+            none.applicable("odometer", 1, 5, None),
+            Some(Applicable {
+                populated: 1,
+                entities: 5,
+                mode_known: false,
+            })
+        );
+        assert_eq!(none.applicable("odometer", 1, 5, Some(Mode::Bus)), None);
+        assert_eq!(counts().applicable("odometer", 0, 10, None), None);
+    }
+}
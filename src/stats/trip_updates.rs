@@ -0,0 +1,230 @@
+//! Field metrics for GTFS-RT TripUpdates feeds.
+//!
+//! [`TripUpdateFields`] lists every optional `TripUpdate` field the rater
+//! counts, mirroring [`VehiclePositionFields`](super::VehiclePositionFields)
+//! for vehicle positions.
+
+use super::fields::{Category, FieldRegistry, Metric};
+use crate::feed_type::FeedType;
+use crate::gtfs_rt::trip_update::StopTimeUpdate;
+use crate::gtfs_rt::{FeedEntity, TripUpdate};
+
+/// Field registry for trip updates.
+///
+/// Stop-time metrics (`stop_id`, `arrival`, ...) count trip updates where at
+/// least one `StopTimeUpdate` populated that field.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TripUpdateFields;
+
+/// Returns true when any of a trip update's stop-time updates satisfies `f`.
+fn any_stop_time(tu: &TripUpdate, f: fn(&StopTimeUpdate) -> bool) -> bool {
+    tu.stop_time_update.iter().any(f)
+}
+
+static METRICS: &[Metric<TripUpdate>] = &[
+    Metric {
+        name: "trip_id",
+        column: "with_trip_id",
+        category: Category::Trip,
+        weight: Some(3.0),
+        present: |tu| tu.trip.trip_id.is_some(),
+    },
+    Metric {
+        name: "route_id",
+        column: "with_route_id",
+        category: Category::Trip,
+        weight: Some(2.0),
+        present: |tu| tu.trip.route_id.is_some(),
+    },
+    Metric {
+        name: "direction_id",
+        column: "with_direction_id",
+        category: Category::Trip,
+        weight: Some(1.0),
+        present: |tu| tu.trip.direction_id.is_some(),
+    },
+    Metric {
+        name: "start_date",
+        column: "with_start_date",
+        category: Category::Trip,
+        weight: Some(1.0),
+        present: |tu| tu.trip.start_date.is_some(),
+    },
+    Metric {
+        name: "start_time",
+        column: "with_start_time",
+        category: Category::Trip,
+        weight: Some(0.0),
+        present: |tu| tu.trip.start_time.is_some(),
+    },
+    Metric {
+        name: "vehicle_id",
+        column: "with_vehicle_id",
+        category: Category::Vehicle,
+        weight: Some(1.0),
+        present: |tu| tu.vehicle.as_ref().is_some_and(|v| v.id.is_some()),
+    },
+    Metric {
+        name: "stop_time_updates",
+        column: "with_stop_time_updates",
+        category: Category::Stop,
+        weight: Some(3.0),
+        present: |tu| !tu.stop_time_update.is_empty(),
+    },
+    Metric {
+        name: "stop_id",
+        column: "with_stop_id",
+        category: Category::Stop,
+        weight: Some(2.0),
+        present: |tu| any_stop_time(tu, |u| u.stop_id.is_some()),
+    },
+    Metric {
+        name: "stop_sequence",
+        column: "with_stop_sequence",
+        category: Category::Stop,
+        weight: Some(2.0),
+        present: |tu| any_stop_time(tu, |u| u.stop_sequence.is_some()),
+    },
+    Metric {
+        name: "arrival",
+        column: "with_arrival",
+        category: Category::Stop,
+        weight: Some(2.0),
+        present: |tu| any_stop_time(tu, |u| u.arrival.is_some()),
+    },
+    Metric {
+        name: "departure",
+        column: "with_departure",
+        category: Category::Stop,
+        weight: Some(1.0),
+        present: |tu| any_stop_time(tu, |u| u.departure.is_some()),
+    },
+    Metric {
+        name: "uncertainty",
+        column: "with_uncertainty",
+        category: Category::Stop,
+        weight: Some(0.0),
+        present: |tu| {
+            any_stop_time(tu, |u| {
+                u.arrival.as_ref().is_some_and(|a| a.uncertainty.is_some())
+                    || u.departure
+                        .as_ref()
+                        .is_some_and(|d| d.uncertainty.is_some())
+            })
+        },
+    },
+    Metric {
+        name: "timestamp",
+        column: "with_timestamp",
+        category: Category::Timing,
+        weight: Some(1.0),
+        present: |tu| tu.timestamp.is_some(),
+    },
+    Metric {
+        name: "delay",
+        column: "with_delay",
+        category: Category::Timing,
+        weight: Some(0.0),
+        present: |tu| tu.delay.is_some(),
+    },
+];
+
+impl FieldRegistry for TripUpdateFields {
+    type Entity = TripUpdate;
+
+    const FEED_TYPE: FeedType = FeedType::TripUpdates;
+
+    /// Version 2 added the shapes, stops and trip_modifications entity counts.
+    /// Version 3 added the feed header timestamp.
+    /// Version 4 added the per-mode entity counts.
+    const SCHEMA_VERSION: u32 = 4;
+
+    const UPTIME_WEIGHT: f64 = 3.0;
+
+    const SERVICE_TIME_WEIGHT: f64 = 3.0;
+
+    fn metrics() -> &'static [Metric<TripUpdate>] {
+        METRICS
+    }
+
+    fn entity(entity: &FeedEntity) -> Option<&TripUpdate> {
+        entity.trip_update.as_ref()
+    }
+
+    fn route_id(entity: &TripUpdate) -> Option<&str> {
+        entity.trip.route_id.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs_rt::trip_update::StopTimeEvent;
+    use crate::gtfs_rt::{FeedHeader, FeedMessage, TripDescriptor};
+    use crate::stats::TripUpdateStats;
+
+    fn feed_with(entity: Vec<FeedEntity>) -> FeedMessage {
+        FeedMessage {
+            header: FeedHeader {
+                gtfs_realtime_version: "2.0".to_string(),
+                timestamp: Some(1234567890),
+                incrementality: None,
+                feed_version: None,
+            },
+            entity,
+        }
+    }
+
+    #[test]
+    fn test_from_feed_counts_trip_update_fields() {
+        let feed = feed_with(vec![FeedEntity {
+            id: "tu1".to_string(),
+            trip_update: Some(TripUpdate {
+                trip: TripDescriptor {
+                    trip_id: Some("trip-1".to_string()),
+                    route_id: Some("route-1".to_string()),
+                    ..Default::default()
+                },
+                stop_time_update: vec![StopTimeUpdate {
+                    stop_id: Some("stop-1".to_string()),
+                    arrival: Some(StopTimeEvent {
+                        time: Some(1234567900),
+                        uncertainty: Some(30),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }],
+                timestamp: Some(1234567890),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }]);
+
+        let stats = TripUpdateStats::from_feed(&feed);
+        assert_eq!(stats.trip_updates, 1);
+        assert_eq!(stats.fields.get("trip_id"), 1);
+        assert_eq!(stats.fields.get("route_id"), 1);
+        assert_eq!(stats.fields.get("direction_id"), 0);
+        assert_eq!(stats.fields.get("stop_time_updates"), 1);
+        assert_eq!(stats.fields.get("stop_id"), 1);
+        assert_eq!(stats.fields.get("arrival"), 1);
+        assert_eq!(stats.fields.get("departure"), 0);
+        assert_eq!(stats.fields.get("uncertainty"), 1);
+        assert_eq!(stats.fields.get("timestamp"), 1);
+    }
+
+    #[test]
+    fn test_from_feed_ignores_vehicle_entities() {
+        use crate::gtfs_rt::VehiclePosition;
+        let feed = feed_with(vec![FeedEntity {
+            id: "v1".to_string(),
+            vehicle: Some(VehiclePosition::default()),
+            ..Default::default()
+        }]);
+
+        let stats = TripUpdateStats::from_feed(&feed);
+        assert_eq!(stats.total_entities, 1);
+        assert_eq!(stats.vehicles, 1);
+        assert_eq!(stats.trip_updates, 0);
+    }
+}
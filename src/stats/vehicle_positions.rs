@@ -0,0 +1,199 @@
+//! Field metrics for GTFS-RT VehiclePositions feeds.
+//!
+//! [`VehiclePositionFields`] lists every optional `VehiclePosition` field the
+//! rater counts, with its weight in the overall score.
+
+use super::fields::{Category, FieldRegistry, Metric};
+use crate::feed_type::FeedType;
+use crate::gtfs_rt::{FeedEntity, VehiclePosition};
+
+/// Field registry for vehicle positions.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct VehiclePositionFields;
+
+static METRICS: &[Metric<VehiclePosition>] = &[
+    Metric {
+        name: "trip",
+        column: "with_trip",
+        category: Category::Trip,
+        weight: None,
+        present: |v| v.trip.is_some(),
+    },
+    Metric {
+        name: "trip_id",
+        column: "with_trip_id",
+        category: Category::Trip,
+        weight: Some(2.0),
+        present: |v| v.trip.as_ref().is_some_and(|t| t.trip_id.is_some()),
+    },
+    Metric {
+        name: "route_id",
+        column: "with_route_id",
+        category: Category::Trip,
+        weight: Some(3.0),
+        present: |v| v.trip.as_ref().is_some_and(|t| t.route_id.is_some()),
+    },
+    Metric {
+        name: "direction_id",
+        column: "with_direction_id",
+        category: Category::Trip,
+        weight: Some(3.0),
+        present: |v| v.trip.as_ref().is_some_and(|t| t.direction_id.is_some()),
+    },
+    Metric {
+        name: "vehicle_descriptor",
+        column: "with_vehicle_descriptor",
+        category: Category::Vehicle,
+        weight: None,
+        present: |v| v.vehicle.is_some(),
+    },
+    Metric {
+        name: "vehicle_id",
+        column: "with_vehicle_id",
+        category: Category::Vehicle,
+        weight: Some(0.0),
+        present: |v| v.vehicle.as_ref().is_some_and(|d| d.id.is_some()),
+    },
+    Metric {
+        name: "vehicle_label",
+        column: "with_vehicle_label",
+        category: Category::Vehicle,
+        weight: Some(0.0),
+        present: |v| v.vehicle.as_ref().is_some_and(|d| d.label.is_some()),
+    },
+    Metric {
+        name: "license_plate",
+        column: "with_license_plate",
+        category: Category::Vehicle,
+        weight: Some(0.0),
+        present: |v| {
+            v.vehicle
+                .as_ref()
+                .is_some_and(|d| d.license_plate.is_some())
+        },
+    },
+    Metric {
+        name: "wheelchair_accessible",
+        column: "with_wheelchair_accessible",
+        category: Category::Vehicle,
+        weight: Some(0.0),
+        // wheelchair_accessible = 0 means NO_VALUE (default); only count when
+        // the producer explicitly set a non-default value.
+        present: |v| {
+            v.vehicle
+                .as_ref()
+                .is_some_and(|d| d.wheelchair_accessible.is_some_and(|w| w != 0))
+        },
+    },
+    Metric {
+        name: "position",
+        column: "with_position",
+        category: Category::Position,
+        weight: None,
+        present: |v| v.position.is_some(),
+    },
+    Metric {
+        name: "bearing",
+        column: "with_bearing",
+        category: Category::Position,
+        weight: Some(0.0),
+        present: |v| v.position.as_ref().is_some_and(|p| p.bearing.is_some()),
+    },
+    Metric {
+        name: "speed",
+        column: "with_speed",
+        category: Category::Position,
+        weight: Some(0.0),
+        present: |v| v.position.as_ref().is_some_and(|p| p.speed.is_some()),
+    },
+    Metric {
+        name: "odometer",
+        column: "with_odometer",
+        category: Category::Position,
+        weight: Some(0.0),
+        present: |v| v.position.as_ref().is_some_and(|p| p.odometer.is_some()),
+    },
+    Metric {
+        name: "stop_sequence",
+        column: "with_current_stop_sequence",
+        category: Category::Stop,
+        weight: Some(3.0),
+        present: |v| v.current_stop_sequence.is_some(),
+    },
+    Metric {
+        name: "stop_id",
+        column: "with_stop_id",
+        category: Category::Stop,
+        weight: Some(3.0),
+        present: |v| v.stop_id.is_some(),
+    },
+    Metric {
+        name: "current_status",
+        column: "with_current_status",
+        category: Category::Stop,
+        weight: Some(1.0),
+        present: |v| v.current_status.is_some(),
+    },
+    Metric {
+        name: "timestamp",
+        column: "with_timestamp",
+        category: Category::Timing,
+        weight: Some(1.0),
+        present: |v| v.timestamp.is_some(),
+    },
+    Metric {
+        name: "congestion_level",
+        column: "with_congestion_level",
+        category: Category::Occupancy,
+        weight: Some(0.0),
+        present: |v| v.congestion_level.is_some(),
+    },
+    Metric {
+        name: "occupancy",
+        column: "with_occupancy",
+        category: Category::Occupancy,
+        weight: Some(1.0),
+        present: |v| v.occupancy_status.is_some(),
+    },
+    Metric {
+        name: "occupancy_percentage",
+        column: "with_occupancy_percentage",
+        category: Category::Occupancy,
+        weight: Some(1.0),
+        present: |v| v.occupancy_percentage.is_some(),
+    },
+    Metric {
+        name: "multi_carriage",
+        column: "with_multi_carriage_details",
+        category: Category::Occupancy,
+        weight: Some(0.0),
+        present: |v| !v.multi_carriage_details.is_empty(),
+    },
+];
+
+impl FieldRegistry for VehiclePositionFields {
+    type Entity = VehiclePosition;
+
+    const FEED_TYPE: FeedType = FeedType::VehiclePositions;
+
+    /// Version 2 added the catalog conformance columns.
+    /// Version 3 added the feed header timestamp.
+    /// Version 4 added the per-mode entity counts.
+    const SCHEMA_VERSION: u32 = 4;
+
+    const UPTIME_WEIGHT: f64 = 3.0;
+
+    const SERVICE_TIME_WEIGHT: f64 = 3.0;
+
+    fn metrics() -> &'static [Metric<VehiclePosition>] {
+        METRICS
+    }
+
+    fn entity(entity: &FeedEntity) -> Option<&VehiclePosition> {
+        entity.vehicle.as_ref()
+    }
+
+    fn route_id(entity: &VehiclePosition) -> Option<&str> {
+        entity.trip.as_ref()?.route_id.as_deref()
+    }
+}
@@ -0,0 +1,119 @@
+//! Where sampled rows are stored between collection and aggregation.
+//!
+//! The default [`StorageBackend::Csv`] writes one file per feed and day under
+//! `agency_id=*/date=*.csv`. [`StorageBackend::Sqlite`] keeps samples,
+//! consistency rows and computed aggregates in a single embedded database
+//! (see [`sqlite`]), which is easier to manage on a single node.
+
+pub mod sqlite;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use std::path::Path;
+
+/// File name of the SQLite database inside the output directory.
+pub const SQLITE_FILE: &str = "samples.sqlite";
+
+/// Storage backend for sample rows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum StorageBackend {
+    /// Per-feed daily CSV files.
+    Csv,
+    /// An embedded SQLite database.
+    Sqlite,
+}
+
+/// Path of the SQLite database for an output directory.
+pub fn sqlite_path(output_dir: &str) -> String {
+    Path::new(output_dir)
+        .join(SQLITE_FILE)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// A half-open `[since, until)` time range; a missing bound is unbounded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimeRange {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl TimeRange {
+    /// The whole UTC day `date`.
+    pub fn day(date: NaiveDate) -> Self {
+        let start = date.and_hms_opt(0, 0, 0).unwrap().and_utc();
+        Self {
+            since: Some(start),
+            until: Some(start + chrono::Duration::days(1)),
+        }
+    }
+
+    /// The UTC day this range covers exactly, if it is one whole day.
+    pub fn whole_day(&self) -> Option<NaiveDate> {
+        let date = self.since?.date_naive();
+        (*self == Self::day(date)).then_some(date)
+    }
+
+    /// Returns true when `ts` falls inside the range.
+    pub fn contains(&self, ts: DateTime<Utc>) -> bool {
+        self.since.is_none_or(|s| ts >= s) && self.until.is_none_or(|u| ts < u)
+    }
+
+    /// Returns true when any part of the UTC day `date` falls inside the range.
+    pub fn overlaps_day(&self, date: NaiveDate) -> bool {
+        let day = Self::day(date);
+        self.since.is_none_or(|s| s < day.until.unwrap())
+            && self.until.is_none_or(|u| u > day.since.unwrap())
+    }
+
+    /// Lower bound in epoch milliseconds, for range queries.
+    pub(crate) fn since_millis(&self) -> i64 {
+        self.since.map_or(i64::MIN, |s| s.timestamp_millis())
+    }
+
+    /// Upper bound in epoch milliseconds, for range queries.
+    pub(crate) fn until_millis(&self) -> i64 {
+        self.until.map_or(i64::MAX, |u| u.timestamp_millis())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_day_range() {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 2).unwrap();
+        let range = TimeRange::day(date);
+        let noon = date.and_hms_opt(12, 0, 0).unwrap().and_utc();
+        let next = date
+            .succ_opt()
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_utc();
+        assert!(range.contains(noon));
+        assert!(!range.contains(next));
+        assert!(TimeRange::default().contains(next));
+        assert_eq!(range.whole_day(), Some(date));
+        assert_eq!(TimeRange::default().whole_day(), None);
+    }
+
+    #[test]
+    fn test_overlaps_day() {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 2).unwrap();
+        let noon = date.and_hms_opt(12, 0, 0).unwrap().and_utc();
+        let midnight = date.and_hms_opt(0, 0, 0).unwrap().and_utc();
+        let since_noon = TimeRange {
+            since: Some(noon),
+            until: None,
+        };
+        assert!(since_noon.overlaps_day(date));
+        assert!(!since_noon.overlaps_day(date.pred_opt().unwrap()));
+        let until_midnight = TimeRange {
+            since: None,
+            until: Some(midnight),
+        };
+        assert!(!until_midnight.overlaps_day(date));
+        assert!(until_midnight.overlaps_day(date.pred_opt().unwrap()));
+    }
+}
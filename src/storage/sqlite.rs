@@ -0,0 +1,509 @@
+//! Embedded SQLite storage for samples, consistency rows and aggregates.
+//!
+//! Each sample row is stored as JSON next to indexed columns for its feed,
+//! feed type, timestamp and error, so aggregation can query a feed's rows for
+//! any time range and deserialize them into the analyzers' row types.
+
+use anyhow::{Result, bail};
+use chrono::{DateTime, NaiveDate, Utc};
+use rusqlite::{Connection, OpenFlags, OptionalExtension, params};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::TimeRange;
+use crate::feed_type::FeedType;
+use crate::stats::consistency::ConsistencyStats;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS samples (
+    id INTEGER PRIMARY KEY,
+    feed_type TEXT NOT NULL,
+    feed_id TEXT NOT NULL,
+    source TEXT,
+    timestamp INTEGER NOT NULL,
+    error_type TEXT,
+    error_message TEXT,
+    row TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS samples_by_feed ON samples (feed_type, feed_id, timestamp);
+CREATE INDEX IF NOT EXISTS samples_by_time ON samples (timestamp);
+CREATE INDEX IF NOT EXISTS sample_errors ON samples (error_type, timestamp)
+    WHERE error_type IS NOT NULL;
+
+CREATE TABLE IF NOT EXISTS consistency (
+    id INTEGER PRIMARY KEY,
+    agency_id TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    row TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS consistency_by_agency ON consistency (agency_id, timestamp);
+
+CREATE TABLE IF NOT EXISTS aggregates (
+    scope TEXT NOT NULL,
+    id TEXT NOT NULL,
+    since INTEGER NOT NULL,
+    until INTEGER NOT NULL,
+    computed_at INTEGER NOT NULL,
+    aggregate TEXT NOT NULL,
+    PRIMARY KEY (scope, id, since, until)
+);
+";
+
+/// In-memory databases opened so far, to give each a unique name.
+static IN_MEMORY_DATABASES: AtomicUsize = AtomicUsize::new(0);
+
+/// A SQLite database holding sampled rows and computed aggregates.
+///
+/// The connection is guarded by a mutex so one store can be shared between
+/// the sampler's feed tasks and the aggregation task. Long scans go through a
+/// separate [`SqliteReader`] instead, so they don't hold up the sampler.
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+    // Path or URI the database was opened from, for opening readers
+    path: String,
+}
+
+/// A read-only connection to a [`SqliteStore`]'s database.
+///
+/// With WAL, it reads a consistent snapshot while the store keeps writing.
+pub struct SqliteReader {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    /// Opens (or creates) the database at `path` and ensures the schema exists.
+    pub fn open(path: &str) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        Self::init(conn, path)
+    }
+
+    /// Opens a private in-memory database.
+    ///
+    /// It is shared-cache, under a name unique to this process, so that
+    /// readers can open it too.
+    pub fn open_in_memory() -> Result<Self> {
+        let n = IN_MEMORY_DATABASES.fetch_add(1, Ordering::Relaxed);
+        let uri = format!("file:gtfs-rt-rater-{}?mode=memory&cache=shared", n);
+        let conn = Connection::open_with_flags(&uri, OpenFlags::default())?;
+        Self::init(conn, &uri)
+    }
+
+    /// Opens a read-only connection to the same database, e.g. for aggregation.
+    pub fn reader(&self) -> Result<SqliteReader> {
+        let conn = Connection::open_with_flags(
+            &self.path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY
+                | OpenFlags::SQLITE_OPEN_URI
+                | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        Ok(SqliteReader {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn init(conn: Connection, path: &str) -> Result<Self> {
+        // WAL lets aggregation read while the sampler keeps writing
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.execute_batch(SCHEMA)?;
+        // Databases created before samples recorded their source URL
+        let has_source: bool = conn.query_row(
+            "SELECT COUNT(*) FROM pragma_table_info('samples') WHERE name = 'source'",
+            [],
+            |r| r.get(0),
+        )?;
+        if !has_source {
+            conn.execute("ALTER TABLE samples ADD COLUMN source TEXT", [])?;
+        }
+        Ok(Self {
+            conn: Mutex::new(conn),
+            path: path.to_string(),
+        })
+    }
+
+    /// Stores one sample row (any per-sample stats struct) for a feed, along
+    /// with the URL or path it was read from.
+    ///
+    /// The row's `timestamp`, `error_type` and `error_message` fields are
+    /// copied into indexed columns.
+    pub fn insert_sample<T: Serialize>(
+        &self,
+        feed_type: FeedType,
+        feed_id: &str,
+        source: Option<&str>,
+        row: &T,
+    ) -> Result<()> {
+        let value = serde_json::to_value(row)?;
+        let timestamp = row_timestamp(&value)?;
+        let error_type = value.get("error_type").and_then(|v| v.as_str());
+        let error_message = value.get("error_message").and_then(|v| v.as_str());
+
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO samples (feed_type, feed_id, source, timestamp, error_type, error_message, row)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                feed_type.catalog_code(),
+                feed_id,
+                source,
+                timestamp,
+                error_type,
+                error_message,
+                value.to_string()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Stores one cross-feed consistency row.
+    pub fn insert_consistency(&self, row: &ConsistencyStats) -> Result<()> {
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO consistency (agency_id, timestamp, row) VALUES (?1, ?2, ?3)",
+            params![
+                row.agency_id,
+                row.timestamp.timestamp_millis(),
+                serde_json::to_string(row)?
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Feed ids of `feed_type` with at least one sample in `range`.
+    pub fn feed_ids(&self, feed_type: FeedType, range: &TimeRange) -> Result<Vec<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT feed_id FROM samples
+             WHERE feed_type = ?1 AND timestamp >= ?2 AND timestamp < ?3
+             ORDER BY feed_id",
+        )?;
+        let ids = stmt
+            .query_map(
+                params![
+                    feed_type.catalog_code(),
+                    range.since_millis(),
+                    range.until_millis()
+                ],
+                |r| r.get(0),
+            )?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(ids)
+    }
+
+    /// A feed's sample rows in `range`, oldest first.
+    pub fn samples<R: DeserializeOwned>(
+        &self,
+        feed_type: FeedType,
+        feed_id: &str,
+        range: &TimeRange,
+    ) -> Result<Vec<R>> {
+        let mut rows = Vec::new();
+        self.for_each_sample(feed_type, feed_id, range, |row| rows.push(row))?;
+        Ok(rows)
+    }
+
+    /// Calls `f` with each of a feed's sample rows in `range`, oldest first,
+    /// without collecting them.
+    pub fn for_each_sample<R: DeserializeOwned>(
+        &self,
+        feed_type: FeedType,
+        feed_id: &str,
+        range: &TimeRange,
+        f: impl FnMut(R),
+    ) -> Result<()> {
+        each_sample(&self.conn.lock().unwrap(), feed_type, feed_id, range, f)
+    }
+
+    /// An agency's consistency rows in `range`, oldest first.
+    pub fn consistency<R: DeserializeOwned>(
+        &self,
+        agency_id: &str,
+        range: &TimeRange,
+    ) -> Result<Vec<R>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT row FROM consistency
+             WHERE agency_id = ?1 AND timestamp >= ?2 AND timestamp < ?3
+             ORDER BY timestamp",
+        )?;
+        let rows = stmt.query_map(
+            params![agency_id, range.since_millis(), range.until_millis()],
+            |r| r.get::<_, String>(0),
+        )?;
+        rows.map(|json| Ok(serde_json::from_str(&json?)?)).collect()
+    }
+
+    /// UTC days that have at least one sample.
+    pub fn sample_dates(&self) -> Result<BTreeSet<NaiveDate>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT date(timestamp / 1000, 'unixepoch') FROM samples ORDER BY 1",
+        )?;
+        let dates = stmt
+            .query_map([], |r| r.get::<_, String>(0))?
+            .filter_map(|d| d.ok())
+            .filter_map(|d| NaiveDate::parse_from_str(&d, "%Y-%m-%d").ok())
+            .collect();
+        Ok(dates)
+    }
+
+    /// Stores a computed aggregate, replacing any earlier one for the same
+    /// scope (a feed type code, or `agencies`), id and range.
+    pub fn save_aggregate<T: Serialize>(
+        &self,
+        scope: &str,
+        id: &str,
+        range: &TimeRange,
+        aggregate: &T,
+    ) -> Result<()> {
+        self.conn.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO aggregates (scope, id, since, until, computed_at, aggregate)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                scope,
+                id,
+                range.since_millis(),
+                range.until_millis(),
+                Utc::now().timestamp_millis(),
+                serde_json::to_string(aggregate)?
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The most recently computed aggregate JSON for a scope and id, if any.
+    pub fn latest_aggregate(&self, scope: &str, id: &str) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        let json = conn
+            .query_row(
+                "SELECT aggregate FROM aggregates WHERE scope = ?1 AND id = ?2
+                 ORDER BY computed_at DESC LIMIT 1",
+                params![scope, id],
+                |r| r.get(0),
+            )
+            .optional()?;
+        Ok(json)
+    }
+}
+
+/// Reads a serialized row's RFC 3339 `timestamp` as epoch milliseconds.
+fn row_timestamp(value: &serde_json::Value) -> Result<i64> {
+    let Some(ts) = value.get("timestamp").and_then(|v| v.as_str()) else {
+        bail!("sample row has no timestamp");
+    };
+    Ok(ts.parse::<DateTime<Utc>>()?.timestamp_millis())
+}
+
+impl SqliteReader {
+    /// Calls `f` with each of a feed's sample rows in `range`, oldest first,
+    /// without collecting them (see [`SqliteStore::for_each_sample`]).
+    pub fn for_each_sample<R: DeserializeOwned>(
+        &self,
+        feed_type: FeedType,
+        feed_id: &str,
+        range: &TimeRange,
+        f: impl FnMut(R),
+    ) -> Result<()> {
+        each_sample(&self.conn.lock().unwrap(), feed_type, feed_id, range, f)
+    }
+}
+
+/// Calls `f` with each of a feed's sample rows in `range` on `conn`, oldest first.
+fn each_sample<R: DeserializeOwned>(
+    conn: &Connection,
+    feed_type: FeedType,
+    feed_id: &str,
+    range: &TimeRange,
+    mut f: impl FnMut(R),
+) -> Result<()> {
+    let mut stmt = conn.prepare(
+        "SELECT row FROM samples
+             WHERE feed_type = ?1 AND feed_id = ?2 AND timestamp >= ?3 AND timestamp < ?4
+             ORDER BY timestamp",
+    )?;
+    let rows = stmt.query_map(
+        params![
+            feed_type.catalog_code(),
+            feed_id,
+            range.since_millis(),
+            range.until_millis()
+        ],
+        |r| r.get::<_, String>(0),
+    )?;
+    for json in rows {
+        f(serde_json::from_str(&json?)?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::FeedStats;
+    use chrono::Duration;
+
+    #[test]
+    fn test_samples_filtered_by_feed_and_range() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let now = Utc::now();
+
+        for (feed_id, minutes_ago) in [("a", 120), ("a", 10), ("b", 10)] {
+            let row = FeedStats {
+                timestamp: now - Duration::minutes(minutes_ago),
+                vehicles: 5,
+                ..Default::default()
+            };
+            store
+                .insert_sample(FeedType::VehiclePositions, feed_id, None, &row)
+                .unwrap();
+        }
+
+        let range = TimeRange {
+            since: Some(now - Duration::hours(1)),
+            until: None,
+        };
+        let ids = store.feed_ids(FeedType::VehiclePositions, &range).unwrap();
+        assert_eq!(ids, vec!["a", "b"]);
+        assert!(
+            store
+                .feed_ids(FeedType::TripUpdates, &range)
+                .unwrap()
+                .is_empty()
+        );
+
+        let rows: Vec<serde_json::Value> = store
+            .samples(FeedType::VehiclePositions, "a", &range)
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["vehicles"], 5);
+
+        // Rows read back straight into the analyzers' row type
+        let all: Vec<FeedStats> = store
+            .samples(FeedType::VehiclePositions, "a", &TimeRange::default())
+            .unwrap();
+        assert_eq!(all.len(), 2);
+        assert!(all[0].timestamp < all[1].timestamp);
+    }
+
+    #[test]
+    fn test_reader_scans_while_store_writes() {
+        let path = format!(
+            "{}/gtfs_rt_rater_test_sqlite_reader.db",
+            std::env::temp_dir().display()
+        );
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+        }
+        let store = SqliteStore::open(&path).unwrap();
+        let row = FeedStats {
+            timestamp: Utc::now(),
+            vehicles: 5,
+            ..Default::default()
+        };
+        for _ in 0..2 {
+            store
+                .insert_sample(FeedType::VehiclePositions, "a", None, &row)
+                .unwrap();
+        }
+
+        // The scan doesn't hold the store's connection, so the sampler can keep writing
+        let reader = store.reader().unwrap();
+        let mut scanned = 0;
+        reader
+            .for_each_sample(
+                FeedType::VehiclePositions,
+                "a",
+                &TimeRange::default(),
+                |_: FeedStats| {
+                    scanned += 1;
+                    store
+                        .insert_sample(FeedType::VehiclePositions, "b", None, &row)
+                        .unwrap();
+                },
+            )
+            .unwrap();
+        assert_eq!(scanned, 2);
+        assert_eq!(
+            store
+                .feed_ids(FeedType::VehiclePositions, &TimeRange::default())
+                .unwrap(),
+            vec!["a", "b"]
+        );
+
+        let memory = SqliteStore::open_in_memory().unwrap();
+        memory
+            .insert_sample(FeedType::VehiclePositions, "a", None, &row)
+            .unwrap();
+        let mut rows = 0;
+        memory
+            .reader()
+            .unwrap()
+            .for_each_sample(
+                FeedType::VehiclePositions,
+                "a",
+                &TimeRange::default(),
+                |_: FeedStats| rows += 1,
+            )
+            .unwrap();
+        assert_eq!(rows, 1);
+    }
+
+    #[test]
+    fn test_errors_are_indexed_columns() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let row = FeedStats::from_error("fetch_error", "timeout");
+        store
+            .insert_sample(FeedType::VehiclePositions, "a", None, &row)
+            .unwrap();
+
+        let conn = store.conn.lock().unwrap();
+        let error: String = conn
+            .query_row(
+                "SELECT error_type FROM samples WHERE error_type IS NOT NULL",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(error, "fetch_error");
+    }
+
+    #[test]
+    fn test_source_stored_apart_from_feed_id() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let row = FeedStats::default();
+        store
+            .insert_sample(
+                FeedType::VehiclePositions,
+                "mdb-1",
+                Some("https://example.com/vp.pb"),
+                &row,
+            )
+            .unwrap();
+
+        let conn = store.conn.lock().unwrap();
+        let (feed_id, source): (String, String) = conn
+            .query_row("SELECT feed_id, source FROM samples", [], |r| {
+                Ok((r.get(0)?, r.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(feed_id, "mdb-1");
+        assert_eq!(source, "https://example.com/vp.pb");
+    }
+
+    #[test]
+    fn test_save_aggregate_replaces_same_range() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let range = TimeRange::day(Utc::now().date_naive());
+        store
+            .save_aggregate("vp", "a", &range, &serde_json::json!({"score": 0.5}))
+            .unwrap();
+        store
+            .save_aggregate("vp", "a", &range, &serde_json::json!({"score": 0.9}))
+            .unwrap();
+
+        let latest = store.latest_aggregate("vp", "a").unwrap().unwrap();
+        assert!(latest.contains("0.9"));
+        assert!(store.latest_aggregate("vp", "b").unwrap().is_none());
+    }
+}
@@ -0,0 +1,536 @@
+//! Uploads of completed daily sample files.
+//!
+//! [`upload_day`] copies one day's files from the output directory to the
+//! upload store, retrying each object and verifying its checksum after the
+//! write. Objects already uploaded with the same contents are skipped, so
+//! re-running a day is cheap and safe. Every run writes an [`UploadManifest`]
+//! to `manifests/date={date}.json` listing what was uploaded; only the local
+//! files it confirms may be deleted afterwards.
+
+use anyhow::{Result, anyhow, bail};
+use chrono::{DateTime, NaiveDate, Utc};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use crate::analyzers::agency::AGENCY_SUBDIR;
+use crate::feed_type::FeedType;
+use crate::object_store::{ObjectInfo, ObjectStore, md5_hex};
+use crate::output::{
+    UploadFormat, consistency_csv_to_parquet, count_csv_rows, daily_file_date, parquet,
+    stats_csv_to_parquet,
+};
+use crate::stats::{AlertFields, TripUpdateFields, VehiclePositionFields};
+
+/// Converts the contents of a daily CSV to Parquet with the schema of its record type.
+type ToParquet = fn(&[u8]) -> Result<Vec<u8>>;
+
+/// How often, and how patiently, a failed object upload is retried.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Total attempts per object, including the first.
+    pub attempts: u32,
+    /// Delay before the first retry; doubled before each further one.
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 4,
+            base_delay: Duration::from_millis(500),
+        }
+    }
+}
+
+/// One object uploaded for a day.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Local file the object was uploaded from.
+    pub source_key: String,
+    /// Key of the uploaded object.
+    pub key: String,
+    /// Sample rows in the file.
+    pub rows: usize,
+    /// Size of the uploaded object in bytes.
+    pub bytes: u64,
+    /// Hex MD5 of the uploaded object.
+    pub md5: String,
+}
+
+/// Record of one day's upload, written next to the uploaded files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadManifest {
+    pub date: NaiveDate,
+    pub format: UploadFormat,
+    pub generated_at: DateTime<Utc>,
+    /// Objects confirmed uploaded, in upload order.
+    pub objects: Vec<ManifestEntry>,
+    /// Local files that could not be uploaded after every retry.
+    pub failed: Vec<String>,
+}
+
+impl UploadManifest {
+    /// Whether every file of the day was uploaded.
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    /// Local files confirmed uploaded, and so safe to delete.
+    pub fn uploaded_sources(&self) -> BTreeSet<String> {
+        self.objects.iter().map(|o| o.source_key.clone()).collect()
+    }
+}
+
+/// Key of the manifest for `date` in the upload store.
+pub fn manifest_key(date: NaiveDate) -> String {
+    format!("manifests/date={}.json", date.format("%Y-%m-%d"))
+}
+
+/// Writes `body` to `key`, then reads back its size and checksum, retrying
+/// the pair until they match or `policy` runs out of attempts.
+///
+/// An object already holding exactly `body` is left untouched, when the
+/// store's checksums can tell.
+pub async fn put_verified(
+    store: &dyn ObjectStore,
+    key: &str,
+    body: Vec<u8>,
+    content_type: Option<&str>,
+    policy: RetryPolicy,
+) -> Result<ObjectInfo> {
+    let expected = store.expected_info(&body);
+    // Without a checksum, an object of the same size may still hold other bytes
+    if expected.checksum.is_some()
+        && store
+            .head(key)
+            .await
+            .ok()
+            .flatten()
+            .is_some_and(|info| info.matches(&expected))
+    {
+        debug!(key, "Object already uploaded, skipping");
+        return Ok(expected);
+    }
+
+    let mut delay = policy.base_delay;
+    let mut attempt = 1;
+    loop {
+        let error = match store.put(key, body.clone(), content_type).await {
+            Ok(()) => match store.head(key).await {
+                Ok(Some(info)) if info.matches(&expected) => return Ok(expected),
+                Ok(Some(info)) => anyhow!(
+                    "checksum mismatch after upload: expected {:?}, found {:?}",
+                    expected,
+                    info
+                ),
+                Ok(None) => anyhow!("object missing after upload"),
+                Err(e) => e,
+            },
+            Err(e) => e,
+        };
+
+        if attempt >= policy.attempts {
+            return Err(error.context(format!(
+                "failed to upload {} after {} attempts",
+                key, attempt
+            )));
+        }
+        warn!(key, attempt, error = %error, retry_in = ?delay, "Upload failed, retrying");
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+/// A local file prepared for upload in the requested format.
+struct Prepared {
+    source_key: String,
+    key: String,
+    body: Vec<u8>,
+    rows: usize,
+}
+
+/// Converts a local daily file for upload, or returns `None` when `format`
+/// does not upload it.
+///
+/// With Parquet (`to_parquet` is given), a CSV is first converted to
+/// `date=*.parquet` in place, so a retried upload and the analyzer both pick
+/// up the Parquet file.
+async fn prepare(
+    files: &dyn ObjectStore,
+    key: &str,
+    format: UploadFormat,
+    to_parquet: Option<ToParquet>,
+) -> Result<Option<Prepared>> {
+    let Some(contents) = files.get(key).await? else {
+        return Ok(None);
+    };
+
+    let prepared = if key.ends_with(".parquet") {
+        if to_parquet.is_none() {
+            return Ok(None);
+        }
+        Prepared {
+            source_key: key.to_string(),
+            key: key.to_string(),
+            rows: parquet::row_count(contents.clone())?,
+            body: contents,
+        }
+    } else if let Some(to_parquet) = to_parquet {
+        let parquet_key = format!("{}.parquet", key.trim_end_matches(".csv"));
+        let rows = count_csv_rows(&contents)?;
+        let converted = to_parquet(&contents)?;
+        files.put(&parquet_key, converted.clone(), None).await?;
+        files.delete(key).await?;
+        Prepared {
+            source_key: parquet_key.clone(),
+            key: parquet_key,
+            body: converted,
+            rows,
+        }
+    } else if format == UploadFormat::Csv {
+        Prepared {
+            source_key: key.to_string(),
+            key: key.to_string(),
+            rows: count_csv_rows(&contents)?,
+            body: contents,
+        }
+    } else {
+        // Gzip compress the file
+        let rows = count_csv_rows(&contents)?;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&contents)?;
+        Prepared {
+            source_key: key.to_string(),
+            key: format!("{}.gz", key),
+            body: encoder.finish()?,
+            rows,
+        }
+    };
+    Ok(Some(prepared))
+}
+
+/// Uploads the files for `date` from `files` to `uploads` in the requested
+/// [`UploadFormat`] and writes the day's [`UploadManifest`].
+///
+/// Files keep their key in the output directory, so trip update and service
+/// alert feeds land under their feed type's key prefix (see [`FeedType::key`])
+/// and agency consistency files under `agencies/`.
+/// A file that still fails after every retry is listed in the manifest's
+/// `failed` instead of aborting the rest of the day.
+#[tracing::instrument(skip(files, uploads, policy), fields(date = %date, format = ?format))]
+pub async fn upload_day(
+    files: &dyn ObjectStore,
+    uploads: &dyn ObjectStore,
+    date: NaiveDate,
+    format: UploadFormat,
+    policy: RetryPolicy,
+) -> Result<UploadManifest> {
+    let date_str = date.format("%Y-%m-%d").to_string();
+    let mut manifest = UploadManifest {
+        date,
+        format,
+        generated_at: Utc::now(),
+        objects: Vec::new(),
+        failed: Vec::new(),
+    };
+
+    // Sample files of every feed type, then the agencies' consistency files
+    let parquet = format == UploadFormat::Parquet;
+    let mut day_files: Vec<(String, Option<ToParquet>)> = Vec::new();
+    for feed_type in FeedType::ALL {
+        let to_parquet: ToParquet = match feed_type {
+            FeedType::VehiclePositions => stats_csv_to_parquet::<VehiclePositionFields>,
+            FeedType::TripUpdates => stats_csv_to_parquet::<TripUpdateFields>,
+            FeedType::ServiceAlerts => stats_csv_to_parquet::<AlertFields>,
+        };
+        let to_parquet = parquet.then_some(to_parquet);
+
+        // Collect the agency_id=* keys for this day, including rotated CSVs
+        let keys = files.list(&feed_type.key("agency_id=")).await?;
+        day_files.extend(
+            keys.into_iter()
+                .filter(|key| {
+                    feed_type
+                        .parse_key(key)
+                        .and_then(|(_, file)| daily_file_date(file))
+                        .is_some_and(|d| d == date_str)
+                })
+                .map(|key| (key, to_parquet)),
+        );
+    }
+    let keys = files.list(&format!("{}/agency=", AGENCY_SUBDIR)).await?;
+    day_files.extend(
+        keys.into_iter()
+            .filter(|key| {
+                key.rsplit('/')
+                    .next()
+                    .and_then(daily_file_date)
+                    .is_some_and(|d| d == date_str)
+            })
+            .map(|key| {
+                (
+                    key,
+                    parquet.then_some(consistency_csv_to_parquet as ToParquet),
+                )
+            }),
+    );
+
+    for (key, to_parquet) in &day_files {
+        let prepared = match prepare(files, key, format, *to_parquet).await {
+            Ok(Some(prepared)) => prepared,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!(key = %key, error = %e, "Failed to prepare file for upload");
+                manifest.failed.push(key.clone());
+                continue;
+            }
+        };
+
+        let md5 = md5_hex(&prepared.body);
+        match put_verified(uploads, &prepared.key, prepared.body, None, policy).await {
+            Ok(info) => manifest.objects.push(ManifestEntry {
+                source_key: prepared.source_key,
+                key: prepared.key,
+                rows: prepared.rows,
+                bytes: info.size,
+                md5,
+            }),
+            Err(e) => {
+                warn!(key = %key, error = %e, "Giving up on file upload");
+                manifest.failed.push(prepared.source_key);
+            }
+        }
+    }
+
+    let body = serde_json::to_vec_pretty(&manifest)?;
+    put_verified(
+        uploads,
+        &manifest_key(date),
+        body,
+        Some("application/json"),
+        policy,
+    )
+    .await?;
+
+    info!(
+        uploaded = manifest.objects.len(),
+        failed = manifest.failed.len(),
+        date = %date_str,
+        "Upload complete"
+    );
+    if manifest.objects.is_empty() && !manifest.is_complete() {
+        bail!("no files for {} could be uploaded", date_str);
+    }
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::MemoryStore;
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+
+    /// Wraps a [`MemoryStore`], failing the first `failures` puts of each key
+    /// in `flaky` and every put of a key in `broken`.
+    #[derive(Default)]
+    struct FlakyStore {
+        inner: MemoryStore,
+        failures: usize,
+        flaky: Mutex<Vec<String>>,
+        broken: Vec<String>,
+        puts: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl ObjectStore for FlakyStore {
+        async fn put(&self, key: &str, body: Vec<u8>, content_type: Option<&str>) -> Result<()> {
+            self.puts.lock().unwrap().push(key.to_string());
+            if self.broken.iter().any(|k| k == key) {
+                bail!("connection reset");
+            }
+            {
+                let mut flaky = self.flaky.lock().unwrap();
+                if flaky.iter().filter(|k| *k == key).count() < self.failures {
+                    flaky.push(key.to_string());
+                    bail!("slow down");
+                }
+            }
+            self.inner.put(key, body, content_type).await
+        }
+
+        async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+            self.inner.get(key).await
+        }
+
+        async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+            self.inner.list(prefix).await
+        }
+
+        async fn delete(&self, key: &str) -> Result<()> {
+            self.inner.delete(key).await
+        }
+    }
+
+    fn fast() -> RetryPolicy {
+        RetryPolicy {
+            attempts: 3,
+            base_delay: Duration::from_millis(1),
+        }
+    }
+
+    fn date() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 1, 1).unwrap()
+    }
+
+    async fn day_files() -> MemoryStore {
+        let files = MemoryStore::new();
+        let csv = b"# schema_version=2\nvehicles\n1\n2\n".to_vec();
+        for key in [
+            "agency_id=a/date=2024-01-01.csv",
+            "agency_id=b/date=2024-01-01.csv",
+            "agency_id=a/date=2024-01-02.csv",
+            "agencies/agency=x/date=2024-01-01.csv",
+        ] {
+            files.put(key, csv.clone(), None).await.unwrap();
+        }
+        files
+    }
+
+    #[tokio::test]
+    async fn test_upload_day_retries_and_writes_manifest() {
+        let files = day_files().await;
+        let uploads = FlakyStore {
+            failures: 2,
+            ..Default::default()
+        };
+
+        let manifest = upload_day(&files, &uploads, date(), UploadFormat::Csv, fast())
+            .await
+            .unwrap();
+
+        assert!(manifest.is_complete());
+        assert_eq!(manifest.objects.len(), 3);
+        assert_eq!(manifest.objects[0].rows, 2);
+        assert_eq!(
+            manifest.objects[0].md5,
+            crate::object_store::md5_hex(
+                &files.get(&manifest.objects[0].key).await.unwrap().unwrap()
+            )
+        );
+        assert_eq!(
+            uploads.inner.keys(),
+            vec![
+                "agencies/agency=x/date=2024-01-01.csv",
+                "agency_id=a/date=2024-01-01.csv",
+                "agency_id=b/date=2024-01-01.csv",
+                "manifests/date=2024-01-01.json",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_upload_does_not_abort_day() {
+        let files = day_files().await;
+        let uploads = FlakyStore {
+            broken: vec!["agency_id=a/date=2024-01-01.csv".to_string()],
+            ..Default::default()
+        };
+
+        let manifest = upload_day(&files, &uploads, date(), UploadFormat::Csv, fast())
+            .await
+            .unwrap();
+
+        assert!(!manifest.is_complete());
+        assert_eq!(manifest.failed, vec!["agency_id=a/date=2024-01-01.csv"]);
+        assert!(
+            !manifest
+                .uploaded_sources()
+                .contains("agency_id=a/date=2024-01-01.csv")
+        );
+        assert!(
+            manifest
+                .uploaded_sources()
+                .contains("agency_id=b/date=2024-01-01.csv")
+        );
+        let attempts = uploads.puts.lock().unwrap();
+        assert_eq!(
+            attempts
+                .iter()
+                .filter(|k| *k == "agency_id=a/date=2024-01-01.csv")
+                .count(),
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parquet_converts_every_file_type() {
+        fn csv<T: Serialize>(row: &T) -> Vec<u8> {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            writer.serialize(row).unwrap();
+            writer.into_inner().unwrap()
+        }
+        let files = MemoryStore::new();
+        let sample = "agency_id=a/date=2024-01-01.csv";
+        for (key, body) in [
+            (
+                FeedType::VehiclePositions.key(sample),
+                csv(&crate::stats::FeedStats::default()),
+            ),
+            (
+                FeedType::TripUpdates.key(sample),
+                csv(&crate::stats::TripUpdateStats::default()),
+            ),
+            (
+                FeedType::ServiceAlerts.key(sample),
+                csv(&crate::stats::AlertStats::default()),
+            ),
+            (
+                "agencies/agency=x/date=2024-01-01.csv".to_string(),
+                csv(&crate::stats::consistency::ConsistencyStats::default()),
+            ),
+        ] {
+            files.put(&key, body, None).await.unwrap();
+        }
+        let uploads = MemoryStore::new();
+
+        let manifest = upload_day(&files, &uploads, date(), UploadFormat::Parquet, fast())
+            .await
+            .unwrap();
+
+        assert!(manifest.is_complete());
+        assert_eq!(manifest.objects.len(), 4);
+        for object in &manifest.objects {
+            assert!(object.key.ends_with(".parquet"), "{}", object.key);
+            assert_eq!(object.rows, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reupload_skips_identical_objects() {
+        let files = day_files().await;
+        let uploads = FlakyStore::default();
+
+        upload_day(&files, &uploads, date(), UploadFormat::CsvGzip, fast())
+            .await
+            .unwrap();
+        uploads.puts.lock().unwrap().clear();
+        let manifest = upload_day(&files, &uploads, date(), UploadFormat::CsvGzip, fast())
+            .await
+            .unwrap();
+
+        assert_eq!(manifest.objects.len(), 3);
+        assert!(manifest.objects[0].key.ends_with(".csv.gz"));
+        // Only the manifest, whose generated_at changed, is written again
+        assert_eq!(
+            *uploads.puts.lock().unwrap(),
+            vec!["manifests/date=2024-01-01.json"]
+        );
+    }
+}